@@ -0,0 +1,120 @@
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use planetx_server::bench::*;
+
+// enough maps to smooth out the per map cost, small enough to keep in memory
+const SAMPLE_MAPS: usize = 100_000;
+
+fn survey(
+    ss: &Sectors,
+    sector_type: SectorType,
+    start: usize,
+    end: usize,
+) -> (Operation, OperationResult) {
+    let cnt = ss.get_range_type_cnt(start, end, &sector_type);
+    (
+        Operation::Survey(SurveyOperatoin {
+            sector_type,
+            start,
+            end,
+        }),
+        OperationResult::Survey(cnt),
+    )
+}
+
+/// an expert bot filter narrowed by a few surveys consistent with the first map.
+fn expert_filter() -> ChoiceFilter {
+    let truth = enumerate(&MapType::Expert).next().unwrap();
+    let mut filter = ChoiceFilter::new(MapType::Expert, Seat::bot("bench"));
+    for (sector_type, start, end) in [
+        (SectorType::DwarfPlanet, 1, 9),
+        (SectorType::Comet, 3, 11),
+        (SectorType::Asteroid, 9, 17),
+    ] {
+        let (op, result) = survey(&truth, sector_type, start, end);
+        filter.add_operation(op, result);
+    }
+    filter
+}
+
+fn bench_enumerate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("enumerate");
+    group.sample_size(10);
+    group.bench_function("expert", |b| {
+        b.iter(|| enumerate(black_box(&MapType::Expert)).count())
+    });
+    group.bench_function("standard", |b| {
+        b.iter(|| enumerate(black_box(&MapType::Standard)).count())
+    });
+    group.finish();
+}
+
+fn bench_filter_op(c: &mut Criterion) {
+    let maps = enumerate(&MapType::Expert)
+        .take(SAMPLE_MAPS)
+        .collect::<Vec<_>>();
+    let research = |conn: ClueConnection| {
+        (
+            Operation::Research(ResearchOperation { index: ClueEnum::A }),
+            OperationResult::Research(Clue {
+                index: ClueEnum::A,
+                subject: SectorType::Comet,
+                object: SectorType::Asteroid,
+                conn,
+            }),
+        )
+    };
+    let cases = [
+        ("survey", survey(&maps[0], SectorType::Asteroid, 1, 9)),
+        (
+            "target",
+            (
+                Operation::Target(TargetOperation { index: 3 }),
+                OperationResult::Target(maps[0].data[2].r#type.clone()),
+            ),
+        ),
+        ("all_adjacent", research(ClueConnection::AllAdjacent)),
+        ("one_adjacent", research(ClueConnection::OneAdjacent)),
+        ("not_adjacent", research(ClueConnection::NotAdjacent)),
+        ("one_opposite", research(ClueConnection::OneOpposite)),
+        ("not_opposite", research(ClueConnection::NotOpposite)),
+        ("all_in_range", research(ClueConnection::AllInRange(3))),
+        ("not_in_range", research(ClueConnection::NotInRange(3))),
+    ];
+
+    let mut group = c.benchmark_group("filter_op");
+    for (name, (op, result)) in cases.iter() {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                maps.iter()
+                    .filter(|ss| filter_op(ss, black_box(op), black_box(result)))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_effect(c: &mut Criterion) {
+    let filter = expert_filter();
+    c.bench_function("effect_survey", |b| {
+        b.iter(|| {
+            filter.effect_survey(black_box(&SurveyOperatoin {
+                sector_type: SectorType::Nebula,
+                start: 1,
+                end: 9,
+            }))
+        })
+    });
+    c.bench_function("all_sector_possibilities", |b| {
+        b.iter_batched(
+            || filter.all.clone(),
+            AllSectorPossibilities::from,
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_enumerate, bench_filter_op, bench_effect);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+-- the tables of the first storage release, which created them without migrations
+CREATE TABLE IF NOT EXISTS snapshots (
+    room_id TEXT NOT NULL,
+    id BIGINT NOT NULL,
+    taken_at BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (room_id, id)
+);
+CREATE TABLE IF NOT EXISTS games (
+    room_id TEXT NOT NULL,
+    finished_at BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (room_id, finished_at)
+);
+CREATE TABLE IF NOT EXISTS stats (
+    user_id TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- the format of the json in `data`, the rows before it are version 1
+ALTER TABLE snapshots ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE games ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE stats ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
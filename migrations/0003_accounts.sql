@@ -0,0 +1,14 @@
+-- registered accounts, an identity verified by the login integration owns a user id
+CREATE TABLE accounts (
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_id TEXT NOT NULL UNIQUE,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (provider, subject)
+);
+-- the guest ids an account claimed, its own user id included
+CREATE TABLE account_claims (
+    guest_id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    claimed_at BIGINT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- the settings a player keeps across devices
+CREATE TABLE preferences (
+    user_id TEXT PRIMARY KEY,
+    version BIGINT NOT NULL,
+    data TEXT NOT NULL
+);
@@ -0,0 +1,52 @@
+-- every socket.io namespace keeps its own rooms, games, stats and preferences, the rows
+-- stored before belong to the default namespace. the accounts are shared by every namespace.
+CREATE TABLE snapshots_by_namespace (
+    namespace TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    id BIGINT NOT NULL,
+    taken_at BIGINT NOT NULL,
+    version BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (namespace, room_id, id)
+);
+INSERT INTO snapshots_by_namespace (namespace, room_id, id, taken_at, version, data)
+    SELECT '/xplanet', room_id, id, taken_at, version, data FROM snapshots;
+DROP TABLE snapshots;
+ALTER TABLE snapshots_by_namespace RENAME TO snapshots;
+
+CREATE TABLE games_by_namespace (
+    namespace TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    finished_at BIGINT NOT NULL,
+    version BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (namespace, room_id, finished_at)
+);
+INSERT INTO games_by_namespace (namespace, room_id, finished_at, version, data)
+    SELECT '/xplanet', room_id, finished_at, version, data FROM games;
+DROP TABLE games;
+ALTER TABLE games_by_namespace RENAME TO games;
+
+CREATE TABLE stats_by_namespace (
+    namespace TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (namespace, user_id)
+);
+INSERT INTO stats_by_namespace (namespace, user_id, version, data)
+    SELECT '/xplanet', user_id, version, data FROM stats;
+DROP TABLE stats;
+ALTER TABLE stats_by_namespace RENAME TO stats;
+
+CREATE TABLE preferences_by_namespace (
+    namespace TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (namespace, user_id)
+);
+INSERT INTO preferences_by_namespace (namespace, user_id, version, data)
+    SELECT '/xplanet', user_id, version, data FROM preferences;
+DROP TABLE preferences;
+ALTER TABLE preferences_by_namespace RENAME TO preferences;
@@ -0,0 +1,115 @@
+use futures_util::FutureExt;
+use rust_socketio::{
+    Payload,
+    asynchronous::{Client, ClientBuilder},
+};
+use serde::Serialize;
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+use planetx_server::protocol::{
+    LobbyQuery, Operation, RecommendOperation, RoomUserOperation, User,
+};
+
+use crate::ServerEvent;
+
+const NAMESPACE: &str = "/xplanet";
+
+/// thin wrapper of a socket.io connection to the `/xplanet` namespace.
+///
+/// every server event is decoded into a [`ServerEvent`] and pushed to the receiver
+/// returned by [`PlanetXClient::connect`], in the order it arrived.
+pub struct PlanetXClient {
+    client: Client,
+}
+
+impl PlanetXClient {
+    /// connect to the server at `url` and authenticate as `user`.
+    pub async fn connect(
+        url: &str,
+        user: &User,
+    ) -> anyhow::Result<(Self, UnboundedReceiver<ServerEvent>)> {
+        let (tx, rx) = unbounded_channel();
+        let client = ClientBuilder::new(url)
+            .namespace(NAMESPACE)
+            .on_any(move |event, payload, _client| {
+                let tx = tx.clone();
+                async move {
+                    let Payload::Text(values) = payload else {
+                        return;
+                    };
+                    let data = values.into_iter().next().unwrap_or_default();
+                    tx.send(ServerEvent::parse(&event.to_string(), data)).ok();
+                }
+                .boxed()
+            })
+            .connect()
+            .await?;
+        let client = PlanetXClient { client };
+        client.auth(user).await?;
+        Ok((client, rx))
+    }
+
+    async fn emit<T: Serialize>(&self, event: &str, data: &T) -> anyhow::Result<()> {
+        self.client.emit(event, serde_json::to_value(data)?).await?;
+        Ok(())
+    }
+
+    pub async fn auth(&self, user: &User) -> anyhow::Result<()> {
+        self.emit("auth", user).await
+    }
+
+    pub async fn room(&self, op: &RoomUserOperation) -> anyhow::Result<()> {
+        self.emit("room", op).await
+    }
+
+    pub async fn create_room(&self) -> anyhow::Result<()> {
+        self.room(&RoomUserOperation::Create).await
+    }
+
+    pub async fn join(&self, room_id: &str) -> anyhow::Result<()> {
+        self.room(&RoomUserOperation::Join(room_id.to_string()))
+            .await
+    }
+
+    pub async fn leave(&self, room_id: &str) -> anyhow::Result<()> {
+        self.room(&RoomUserOperation::Leave(room_id.to_string()))
+            .await
+    }
+
+    pub async fn prepare(&self, room_id: &str) -> anyhow::Result<()> {
+        self.room(&RoomUserOperation::Prepare(room_id.to_string()))
+            .await
+    }
+
+    /// play a move, the server answers with `op_result` or a `server_resp` error.
+    pub async fn act(&self, op: &Operation) -> anyhow::Result<()> {
+        self.emit("op", op).await
+    }
+
+    pub async fn recommend(&self, op: &RecommendOperation) -> anyhow::Result<()> {
+        self.emit("recommend", op).await
+    }
+
+    pub async fn lobby(&self, query: &LobbyQuery) -> anyhow::Result<()> {
+        self.emit("lobby", query).await
+    }
+
+    /// ask the server to resend everything this user is allowed to see of the current room.
+    pub async fn sync(&self) -> anyhow::Result<()> {
+        self.client.emit("sync", serde_json::Value::Null).await?;
+        Ok(())
+    }
+
+    /// ask for everything the seat knows in a single [`ServerEvent::CatchUp`].
+    pub async fn catch_up(&self) -> anyhow::Result<()> {
+        self.client
+            .emit("catch_up", serde_json::Value::Null)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> anyhow::Result<()> {
+        self.client.disconnect().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+use planetx_server::protocol::{
+    CatchUp, Clue, ClueSecret, GameStateResp, IdleKick, LobbyPage, OperationResult, ProjectedScore,
+    RecommendOperationResult, SecretToken, ServerResp, Token,
+};
+
+/// an event pushed by the server, decoded into the protocol types.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    ServerResp(ServerResp),
+    GameState(Box<GameStateResp>),
+    GameStart(Vec<ClueSecret>),
+    OpResult(OperationResult),
+    RecommendResult(RecommendOperationResult),
+    Token(Vec<Token>),
+    BoardTokens(Vec<SecretToken>),
+    XClue(Vec<Clue>),
+    StartingIn(Option<u64>),
+    IdleKick(IdleKick),
+    ProjectedScore(Vec<ProjectedScore>),
+    Lobby(LobbyPage),
+    /// None when the user is not seated in any room.
+    CatchUp(Option<Box<CatchUp>>),
+    /// events this client version does not decode, or failed to decode.
+    Other {
+        event: String,
+        data: Value,
+    },
+}
+
+impl ServerEvent {
+    pub fn parse(event: &str, data: Value) -> Self {
+        fn decode<T: serde::de::DeserializeOwned>(data: &Value) -> Option<T> {
+            serde_json::from_value(data.clone()).ok()
+        }
+        let parsed = match event {
+            "server_resp" => decode(&data).map(ServerEvent::ServerResp),
+            "game_state" => decode(&data).map(|gs| ServerEvent::GameState(Box::new(gs))),
+            "game_start" => decode(&data).map(ServerEvent::GameStart),
+            "op_result" => decode(&data).map(ServerEvent::OpResult),
+            "recommend_result" => decode(&data).map(ServerEvent::RecommendResult),
+            "token" => decode(&data).map(ServerEvent::Token),
+            "board_tokens" => decode(&data).map(ServerEvent::BoardTokens),
+            "xclue" => decode(&data).map(ServerEvent::XClue),
+            "starting_in" => decode(&data).map(ServerEvent::StartingIn),
+            "idle_kick" => decode(&data).map(ServerEvent::IdleKick),
+            "projected_score" => decode(&data).map(ServerEvent::ProjectedScore),
+            "lobby" => decode(&data).map(ServerEvent::Lobby),
+            "catch_up" => decode(&data).map(ServerEvent::CatchUp),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| ServerEvent::Other {
+            event: event.to_string(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        let data: Value = serde_json::from_str(r#"{"locate":true}"#).unwrap();
+        assert!(matches!(
+            ServerEvent::parse("op_result", data),
+            ServerEvent::OpResult(OperationResult::Locate(true))
+        ));
+
+        let data: Value = serde_json::from_str(
+            r#"{"id":"1234","status":"not_started","game_stage":"user_move","hint":null,"users":[],"start_index":1,"end_index":6,"map_seed":0,"map_type":"standard","game_result":null,"rules":{"idle_kick_turns":3,"x_bonus":null,"no_hints":false,"casting":false}}"#,
+        )
+        .unwrap();
+        let ServerEvent::GameState(gs) = ServerEvent::parse("game_state", data) else {
+            panic!("game_state not decoded");
+        };
+        assert_eq!(gs.id, "1234");
+
+        let data = Value::from(1);
+        assert!(matches!(
+            ServerEvent::parse("unknown", data),
+            ServerEvent::Other { .. }
+        ));
+    }
+}
@@ -0,0 +1,24 @@
+//! typed client of the planetx server `/xplanet` socket.io namespace.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use planetx_client::{PlanetXClient, ServerEvent, protocol::User};
+//!
+//! let user = User { id: "device-id".to_string(), name: "alice".to_string(), service: true };
+//! let (client, mut events) = PlanetXClient::connect("http://127.0.0.1:17878", &user).await?;
+//! client.join("1234").await?;
+//! while let Some(event) = events.recv().await {
+//!     if let ServerEvent::GameState(gs) = event {
+//!         println!("{:?}", gs.status);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod client;
+mod event;
+
+pub use client::*;
+pub use event::*;
+pub use planetx_server::protocol;
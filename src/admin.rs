@@ -0,0 +1,478 @@
+use salvo::{affix_state, http::StatusCode, prelude::*};
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use tracing::{info, warn};
+
+use crate::{
+    api::{bearer_matches, obtain_namespaces, obtain_state, room_error_status},
+    config::ServerConfig,
+    room::{AnnouncementKind, BugReportBundle, BugReportFiled, Maintenance, now_secs},
+    server_handler::broadcast_full_state,
+    server_state::{Namespaces, StateRef},
+};
+
+/// admin api under `/admin`, every request needs `Authorization: Bearer <admin_token>`.
+/// the `metrics/` reports need the `metrics` feature. it acts on the namespace it is served
+/// for, but the deletion of a user's data, which covers every namespace.
+pub fn router(state: StateRef, namespaces: Namespaces, io: SocketIo) -> Router {
+    let router = Router::with_path("admin")
+        .hoop(affix_state::inject(state).inject(namespaces).inject(io))
+        .hoop(admin_auth)
+        .push(Router::with_path("suspicious").get(suspicious))
+        .push(Router::with_path("dashboard").get(dashboard))
+        .push(Router::with_path("config/reload").post(reload_config))
+        .push(Router::with_path("moderation").get(moderation))
+        .push(
+            Router::with_path("maintenance")
+                .get(maintenance)
+                .put(start_maintenance)
+                .delete(end_maintenance),
+        )
+        .push(
+            Router::with_path("announcements")
+                .get(announcements)
+                .post(schedule_announcement),
+        )
+        .push(Router::with_path("announcements/{id}").delete(cancel_announcement))
+        .push(Router::with_path("rooms/{room_id}/snapshots").get(snapshots))
+        .push(Router::with_path("rooms/{room_id}/bug_report").post(file_bug_report))
+        .push(Router::with_path("rooms/{room_id}/caster_token").post(caster_token))
+        .push(Router::with_path("bug_reports").get(bug_reports))
+        .push(Router::with_path("bug_reports/{id}").get(bug_report))
+        .push(Router::with_path("rooms/{room_id}/snapshots/{snapshot_id}/restore").post(restore))
+        .push(Router::with_path("rooms/{room_id}/stored").get(stored_snapshots))
+        .push(
+            Router::with_path("rooms/{room_id}/stored/{snapshot_id}/restore").post(restore_stored),
+        )
+        .push(Router::with_path("users/search").get(search_users))
+        .push(
+            Router::with_path("users/{user_id}/data")
+                .get(user_data)
+                .delete(delete_user_data),
+        );
+    #[cfg(feature = "metrics")]
+    let router = router
+        .push(Router::with_path("metrics/broadcast").get(broadcast_metrics))
+        .push(Router::with_path("metrics/rejections").get(rejection_metrics))
+        .push(Router::with_path("metrics/bad_requests").get(bad_request_metrics))
+        .push(Router::with_path("metrics/pacing").get(pacing_metrics))
+        .push(Router::with_path("metrics/clues").get(clue_metrics))
+        .push(Router::with_path("metrics/shadow").get(shadow_metrics));
+    router
+}
+
+fn obtain_io(depot: &Depot) -> SocketIo {
+    depot
+        .obtain::<SocketIo>()
+        .expect("socket io injected by the admin router")
+        .clone()
+}
+
+#[handler]
+async fn admin_auth(req: &mut Request, depot: &mut Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
+    let token = obtain_state(depot).lock().await.config.admin_token.clone();
+    if !bearer_matches(req, token) {
+        res.status_code(StatusCode::UNAUTHORIZED);
+        ctrl.skip_rest();
+    }
+}
+
+/// players whose moves match the bot's best move implausibly often in competitive rooms.
+#[handler]
+async fn suspicious(depot: &mut Depot, res: &mut Response) {
+    let reports = obtain_state(depot).lock().await.suspicious_players();
+    res.render(Json(reports));
+}
+
+/// per room live stats for an ops ui.
+#[handler]
+async fn dashboard(depot: &mut Depot, res: &mut Response) {
+    let dashboard = obtain_state(depot).lock().await.dashboard();
+    res.render(Json(dashboard));
+}
+
+/// autosaved snapshots of a room, oldest first.
+#[handler]
+async fn snapshots(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    match obtain_state(depot).lock().await.room_snapshots(&room_id) {
+        Some(list) => res.render(Json(list)),
+        None => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+    }
+}
+
+/// bundle a room for a bug report, `?note=` is kept with it. answers the id of the report.
+#[handler]
+async fn file_bug_report(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let note = req.query::<String>("note");
+    match obtain_state(depot)
+        .lock()
+        .await
+        .file_bug_report(&room_id, None, note)
+    {
+        Ok(id) => res.render(Json(BugReportFiled { id })),
+        Err(_) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+    }
+}
+
+/// the token casters join the room with, the same one until the room is gone.
+#[handler]
+async fn caster_token(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    match obtain_state(depot)
+        .lock()
+        .await
+        .issue_caster_token(&room_id)
+    {
+        Ok(token) => res.render(Json(token)),
+        Err(e) => {
+            res.status_code(room_error_status(&e));
+        }
+    }
+}
+
+/// the kept bug reports without their bundles, latest first.
+#[handler]
+async fn bug_reports(depot: &mut Depot, res: &mut Response) {
+    let list = obtain_state(depot).lock().await.bug_reports.list();
+    res.render(Json(list));
+}
+
+/// the bundle of a bug report, to replay the room locally.
+#[handler]
+async fn bug_report(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    let (report, clue_weights) = {
+        let state = obtain_state(depot);
+        let state = state.lock().await;
+        let report = state.bug_reports.get(&id).cloned();
+        (report, state.config.clue_weights.clone())
+    };
+    let Some(report) = report else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    };
+    // solving the seed takes a while, off the state lock and the runtime
+    let bundle = tokio::task::spawn_blocking(move || BugReportBundle {
+        seed_report: report.seed_report(&clue_weights),
+        report,
+    })
+    .await;
+    match bundle {
+        Ok(bundle) => res.render(Json(bundle)),
+        Err(e) => {
+            warn!(?e, "bug report {} seed report failed", id);
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
+
+/// rewind a room to a snapshot and resend the full state to its clients.
+#[handler]
+async fn restore(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let Some(snapshot_id) = req.param::<u64>("snapshot_id") else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let io = obtain_io(depot);
+    let state = obtain_state(depot);
+    let mut state = state.lock().await;
+    if !state.restore_snapshot(&room_id, snapshot_id) {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    }
+    broadcast_full_state(&state.sink(&io), &state, &room_id).await;
+    res.status_code(StatusCode::NO_CONTENT);
+}
+
+/// snapshots of a room kept by the storage, oldest first. they outlive the room and restarts.
+#[handler]
+async fn stored_snapshots(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let storage = obtain_state(depot).lock().await.storage.clone();
+    match storage.load_snapshots(room_id).await {
+        Ok(stored) => res.render(Json(stored.into_iter().map(|s| s.info).collect::<Vec<_>>())),
+        Err(e) => {
+            warn!(?e, "load snapshots failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
+
+/// bring a room back from a stored snapshot, creating it again if it is gone.
+#[handler]
+async fn restore_stored(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let Some(snapshot_id) = req.param::<u64>("snapshot_id") else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let io = obtain_io(depot);
+    let state = obtain_state(depot);
+    let storage = state.lock().await.storage.clone();
+    let stored = match storage.load_snapshots(room_id.clone()).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            warn!(?e, "load snapshots failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+            return;
+        }
+    };
+    let Some(room) = stored.into_iter().find(|s| s.info.id == snapshot_id) else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    };
+    let mut state = state.lock().await;
+    state.restore_stored(&room_id, room);
+    broadcast_full_state(&state.sink(&io), &state, &room_id).await;
+    res.status_code(StatusCode::NO_CONTENT);
+}
+
+/// where the users matching `?q=` are: their connections, their seats and their last action.
+/// the id matches exactly, the name in part.
+#[handler]
+async fn search_users(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Some(query) = req.query::<String>("q").filter(|q| !q.is_empty()) else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let found = obtain_state(depot).lock().await.find_users(&query);
+    res.render(Json(found));
+}
+
+/// everything stored about a user, for a data access request.
+#[handler]
+async fn user_data(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let user_id = req.param::<String>("user_id").unwrap_or_default();
+    let state = obtain_state(depot);
+    let (live, preferences, storage) = {
+        let state = state.lock().await;
+        (
+            state.play_quality.get(&user_id).cloned(),
+            state.preferences.get(&user_id).cloned(),
+            state.storage.clone(),
+        )
+    };
+    match storage.load_user_data(user_id).await {
+        Ok(mut data) => {
+            // the stats and preferences of this server are newer than the stored ones
+            data.stats = live.or(data.stats);
+            data.preferences = preferences.or(data.preferences);
+            res.render(Json(data));
+        }
+        Err(e) => {
+            warn!(?e, "load user data failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
+
+/// forget a user in every namespace: stats, preferences and snapshots are deleted, finished
+/// games keep the results under an alias. games still running are left alone.
+#[handler]
+async fn delete_user_data(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let user_id = req.param::<String>("user_id").unwrap_or_default();
+    for state in obtain_namespaces(depot).0 {
+        let mut state = state.lock().await;
+        state.forget_stats(&user_id);
+        state.forget_preferences(&user_id);
+    }
+    let storage = obtain_state(depot).lock().await.storage.clone();
+    match storage.delete_user_data(user_id).await {
+        Ok(deletion) => res.render(Json(deletion)),
+        Err(e) => {
+            warn!(?e, "delete user data failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
+
+/// the latest chat mutes, blocks and filtered messages, oldest first. `?room_id=` keeps the
+/// actions of one room.
+#[handler]
+async fn moderation(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.query::<String>("room_id");
+    let state = obtain_state(depot);
+    let state = state.lock().await;
+    let entries = state
+        .moderation
+        .iter()
+        .filter(|e| room_id.is_none() || e.room_id == room_id)
+        .cloned()
+        .collect::<Vec<_>>();
+    res.render(Json(entries));
+}
+
+/// the maintenance mode, `null` when the server is not draining.
+#[handler]
+async fn maintenance(depot: &mut Depot, res: &mut Response) {
+    let maintenance = obtain_state(depot).lock().await.maintenance.clone();
+    res.render(Json(maintenance));
+}
+
+#[derive(Debug, Deserialize)]
+struct StartMaintenance {
+    message: String, // shown to the players, e.g. when the server is back
+}
+
+/// refuse new rooms and tell every connection, the running games go on.
+#[handler]
+async fn start_maintenance(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Ok(StartMaintenance { message }) = req.parse_json::<StartMaintenance>().await else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let io = obtain_io(depot);
+    let state = obtain_state(depot);
+    let mut state = state.lock().await;
+    let maintenance = Maintenance {
+        message,
+        since: now_secs(),
+    };
+    info!(?maintenance, "maintenance started");
+    state.sink(&io).to_all("maintenance", &maintenance).await;
+    state.maintenance = Some(maintenance.clone());
+    res.render(Json(maintenance));
+}
+
+#[handler]
+async fn end_maintenance(depot: &mut Depot, res: &mut Response) {
+    let io = obtain_io(depot);
+    let state = obtain_state(depot);
+    let mut state = state.lock().await;
+    if state.maintenance.take().is_some() {
+        info!("maintenance ended");
+        state
+            .sink(&io)
+            .to_all("maintenance", &None::<Maintenance>)
+            .await;
+    }
+    res.status_code(StatusCode::NO_CONTENT);
+}
+
+/// the announcements not sent yet, the earliest first.
+#[handler]
+async fn announcements(depot: &mut Depot, res: &mut Response) {
+    let scheduled = obtain_state(depot).lock().await.announcements.clone();
+    res.render(Json(scheduled));
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduleAnnouncement {
+    message: String,
+    #[serde(default)]
+    kind: AnnouncementKind,
+    at: Option<u64>, // unix secs, now when missing
+}
+
+/// queue a message to every connection. the tick sends the due ones, one per
+/// `announce_interval_secs`.
+#[handler]
+async fn schedule_announcement(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Ok(ScheduleAnnouncement { message, kind, at }) =
+        req.parse_json::<ScheduleAnnouncement>().await
+    else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let scheduled = obtain_state(depot)
+        .lock()
+        .await
+        .schedule_announcement(message, kind, at);
+    match scheduled {
+        Some(announcement) => {
+            info!(?announcement, "announcement scheduled");
+            res.status_code(StatusCode::CREATED);
+            res.render(Json(announcement));
+        }
+        None => {
+            res.status_code(StatusCode::TOO_MANY_REQUESTS);
+        }
+    }
+}
+
+#[handler]
+async fn cancel_announcement(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Some(id) = req.param::<u64>("id") else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    if obtain_state(depot).lock().await.cancel_announcement(id) {
+        res.status_code(StatusCode::NO_CONTENT);
+    } else {
+        res.status_code(StatusCode::NOT_FOUND);
+    }
+}
+
+/// read the config file again and apply the fields safe to change live, answer the names of
+/// the changed ones. an invalid file keeps the running config. only the namespace of the path is
+/// reloaded, a SIGHUP reloads every one.
+#[handler]
+async fn reload_config(depot: &mut Depot, res: &mut Response) {
+    let config = match ServerConfig::reload() {
+        Ok(config) => config,
+        Err(e) => {
+            warn!(?e, "config reload failed");
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(Text::Plain(e.to_string()));
+            return;
+        }
+    };
+    let changed = obtain_state(depot).lock().await.reload_config(config);
+    res.render(Json(changed));
+}
+
+/// how many room broadcasts the ticks queue and how many are left after coalescing.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn broadcast_metrics(depot: &mut Depot, res: &mut Response) {
+    let metrics = obtain_state(depot).lock().await.broadcast_metrics.clone();
+    res.render(Json(metrics));
+}
+
+/// percentiles of the turn, stage and game durations since the start.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn pacing_metrics(depot: &mut Depot, res: &mut Response) {
+    let report = obtain_state(depot).lock().await.pacing.report();
+    res.render(Json(report));
+}
+
+/// the clues the players researched by letter and connection, with the share of the maps they kept.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn clue_metrics(depot: &mut Depot, res: &mut Response) {
+    let report = obtain_state(depot).lock().await.clue_usage.report();
+    res.render(Json(report));
+}
+
+/// how often the shadow bot picked another move than the live bot, and the latest of those
+/// picks with how the game of the seat ended.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn shadow_metrics(depot: &mut Depot, res: &mut Response) {
+    let report = obtain_state(depot).lock().await.shadow.report();
+    res.render(Json(report));
+}
+
+/// the refused player operations by error, operation and client version, most frequent first.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn rejection_metrics(depot: &mut Depot, res: &mut Response) {
+    let report = obtain_state(depot).lock().await.rejections.report();
+    res.render(Json(report));
+}
+
+/// the inbound payloads that didn't deserialize by event, most frequent first.
+#[cfg(feature = "metrics")]
+#[handler]
+async fn bad_request_metrics(depot: &mut Depot, res: &mut Response) {
+    let report = obtain_state(depot).lock().await.bad_requests.report();
+    res.render(Json(report));
+}
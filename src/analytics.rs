@@ -0,0 +1,281 @@
+//! anonymized gameplay analytics, for balance decisions, apart from the operational logs.
+//!
+//! the events carry no user, room or seed, their time is rounded to the minute. they are
+//! queued by the state, rate limited, and shipped after the tick by `ship` to the sink picked
+//! by the `analytics` section of the config, nothing is recorded without one.
+
+use std::{collections::HashSet, io::Write, sync::Arc, time::Instant};
+
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{
+    map::{ClueConnection, MapType},
+    operation::{Operation, OperationKind},
+    ratelimit::RateBucket,
+    room::{GameStage, GameState, GameStateResp, OpError, ServerGameState, now_secs},
+};
+
+/// where the analytics go, e.g. `{"sink": "file", "path": "analytics.jsonl"}`. the file is
+/// the only sink, a collector agent tails it to ship the events further.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "sink", rename_all = "snake_case")]
+pub enum AnalyticsConfig {
+    /// json lines appended to the file, created if missing.
+    File { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AnalyticsEvent {
+    /// a player operation, `error` set when it was refused.
+    Action {
+        at: u64,
+        operation: OperationKind,
+        stage: GameStage,
+        map_type: MapType,
+        error: Option<String>,
+    },
+    GameEnded {
+        at: u64,
+        map_type: MapType,
+        players: usize,
+        bots: usize,
+        competitive: bool,
+        rounds: usize,
+        moves: usize,
+        duration_secs: u64,
+        clue_connections: Vec<&'static str>, // of the research clues dealt
+        researched: usize,                   // of the research clues
+    },
+    /// events over the rate, counted since the last batch.
+    Dropped { at: u64, count: u64 },
+}
+
+/// the analytics waiting for the next batch.
+#[derive(Debug)]
+pub struct Analytics {
+    enabled: bool,
+    queue: Vec<AnalyticsEvent>,
+    bucket: RateBucket,
+    dropped: u64,
+    ended: HashSet<String>, // rooms whose end is recorded
+}
+
+/// the connection without its range, the ranges are drawn after the connection.
+pub(crate) fn connection_kind(conn: &ClueConnection) -> &'static str {
+    match conn {
+        ClueConnection::AllAdjacent => "all_adjacent",
+        ClueConnection::OneAdjacent => "one_adjacent",
+        ClueConnection::NotAdjacent => "not_adjacent",
+        ClueConnection::OneOpposite => "one_opposite",
+        ClueConnection::NotOpposite => "not_opposite",
+        ClueConnection::AllInRange(_) => "all_in_range",
+        ClueConnection::NotInRange(_) => "not_in_range",
+    }
+}
+
+/// the time of an event, to the minute.
+fn minute(now: u64) -> u64 {
+    now - now % 60
+}
+
+impl Analytics {
+    pub fn new(enabled: bool, burst: usize) -> Self {
+        Analytics {
+            enabled,
+            queue: vec![],
+            bucket: RateBucket::new(burst as f64, Instant::now()),
+            dropped: 0,
+            ended: HashSet::new(),
+        }
+    }
+
+    /// queue the event if the rate allows it, else count it as dropped.
+    fn record(&mut self, event: AnalyticsEvent, rate: f64, burst: usize) {
+        if !self.enabled {
+            return;
+        }
+        if rate > 0.0 && !self.bucket.try_take(Instant::now(), rate, burst as f64) {
+            self.dropped += 1;
+            return;
+        }
+        self.queue.push(event);
+    }
+
+    /// an operation of a player, in the stage of its room when it was sent.
+    pub fn record_action(
+        &mut self,
+        (stage, map_type): (GameStage, MapType),
+        op: &Operation,
+        error: Option<&OpError>,
+        (rate, burst): (f64, usize),
+    ) {
+        let error = error.map(|e| {
+            serde_json::to_value(e)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_else(|| "other".to_string())
+        });
+        let event = AnalyticsEvent::Action {
+            at: minute(now_secs()),
+            operation: op.kind(),
+            stage,
+            map_type,
+            error,
+        };
+        self.record(event, rate, burst);
+    }
+
+    /// record the end of the finished games once, practice games aside.
+    pub fn observe_end(
+        &mut self,
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        (rate, burst): (f64, usize),
+    ) {
+        if !self.enabled || gs.status != GameState::End || gs.puzzle.is_some() {
+            return;
+        }
+        if !self.ended.insert(gs.id.clone()) {
+            return;
+        }
+        let now = now_secs();
+        let researched = ss
+            .journal
+            .iter()
+            .filter(|e| matches!(e.op, Operation::Research(_)))
+            .count();
+        let event = AnalyticsEvent::GameEnded {
+            at: minute(now),
+            map_type: gs.map_type.clone(),
+            players: gs.users.iter().filter(|u| !u.seat().is_bot()).count(),
+            bots: gs.users.iter().filter(|u| u.seat().is_bot()).count(),
+            competitive: gs.rules.is_competitive(),
+            rounds: gs.round,
+            moves: ss.journal.len(),
+            duration_secs: now.saturating_sub(gs.created_at),
+            clue_connections: ss
+                .research_clues
+                .iter()
+                .map(|c| connection_kind(&c.conn))
+                .collect(),
+            researched,
+        };
+        self.record(event, rate, burst);
+    }
+
+    /// forget the rooms gone, their end can't be seen again.
+    pub fn retain_rooms(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.ended.retain(|id| keep(id));
+    }
+
+    /// the queued events, and the count of the dropped ones since the last batch.
+    pub fn take(&mut self) -> Vec<AnalyticsEvent> {
+        let mut batch = std::mem::take(&mut self.queue);
+        if self.dropped > 0 {
+            batch.push(AnalyticsEvent::Dropped {
+                at: minute(now_secs()),
+                count: std::mem::take(&mut self.dropped),
+            });
+        }
+        batch
+    }
+}
+
+/// where a batch of analytics is sent.
+pub trait AnalyticsSink: Send + Sync {
+    fn send(&self, batch: Vec<AnalyticsEvent>) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+pub struct FileSink {
+    path: String,
+}
+
+impl AnalyticsSink for FileSink {
+    fn send(&self, batch: Vec<AnalyticsEvent>) -> BoxFuture<'_, anyhow::Result<()>> {
+        let path = self.path.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+                let mut lines = vec![];
+                for event in batch.iter() {
+                    serde_json::to_writer(&mut lines, event)?;
+                    lines.push(b'\n');
+                }
+                file.write_all(&lines)?;
+                anyhow::Ok(())
+            })
+            .await?
+        })
+    }
+}
+
+pub fn connect(config: &AnalyticsConfig) -> anyhow::Result<Arc<dyn AnalyticsSink>> {
+    match config {
+        AnalyticsConfig::File { path } => Ok(Arc::new(FileSink { path: path.clone() })),
+    }
+}
+
+/// send the batches in order, a batch that fails is logged and dropped.
+pub async fn ship(
+    sink: Arc<dyn AnalyticsSink>,
+    mut batches: mpsc::UnboundedReceiver<Vec<AnalyticsEvent>>,
+) {
+    while let Some(batch) = batches.recv().await {
+        if let Err(e) = sink.send(batch).await {
+            warn!(?e, "analytics batch dropped");
+        }
+    }
+    info!("analytics shipper stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::operation::TargetOperation;
+
+    #[test]
+    fn test_analytics_config() {
+        let config: AnalyticsConfig =
+            serde_json::from_str(r#"{"sink":"file","path":"analytics.jsonl"}"#).unwrap();
+        assert_eq!(
+            config,
+            AnalyticsConfig::File {
+                path: "analytics.jsonl".to_string()
+            }
+        );
+        let http = r#"{"sink":"http","url":"http://127.0.0.1:8686/planetx"}"#;
+        assert!(serde_json::from_str::<AnalyticsConfig>(http).is_err());
+    }
+
+    #[test]
+    fn test_analytics_rate() {
+        let gs = GameStateResp::new("1".to_string());
+        let at = || (gs.game_stage.clone(), gs.map_type.clone());
+        let op = Operation::Target(TargetOperation { index: 1 });
+
+        let mut off = Analytics::new(false, 2);
+        off.record_action(at(), &op, None, (1.0, 2));
+        assert!(off.take().is_empty());
+
+        let mut analytics = Analytics::new(true, 2);
+        for _ in 0..3 {
+            analytics.record_action(at(), &op, Some(&OpError::NotUsersTurn), (1.0, 2));
+        }
+        let batch = analytics.take();
+        assert_eq!(batch.len(), 3);
+        assert!(matches!(
+            &batch[0],
+            AnalyticsEvent::Action { error: Some(e), .. } if e == "not_users_turn"
+        ));
+        assert!(matches!(batch[2], AnalyticsEvent::Dropped { count: 1, .. }));
+        assert!(analytics.take().is_empty());
+    }
+}
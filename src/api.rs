@@ -0,0 +1,102 @@
+use salvo::{affix_state, http::StatusCode, prelude::*};
+use socketioxide::SocketIo;
+use tracing::warn;
+
+#[cfg(feature = "integrations")]
+use crate::control;
+#[cfg(any(feature = "admin", feature = "integrations"))]
+use crate::room::RoomError;
+use crate::server_state::{Namespaces, StateRef};
+
+/// public http api, for things players share outside the socket session.
+///
+/// `control/`, with the `integrations` feature, is the control plane for trusted integrations,
+/// see `control::router`.
+pub fn router(state: StateRef, namespaces: Namespaces, io: SocketIo) -> Router {
+    let router = Router::new()
+        .hoop(affix_state::inject(state).inject(namespaces).inject(io))
+        .push(Router::with_path("export/{room_id}").get(export_game));
+    #[cfg(feature = "integrations")]
+    let router = router.push(control::router());
+    router
+}
+
+pub(crate) fn obtain_state(depot: &Depot) -> StateRef {
+    depot
+        .obtain::<StateRef>()
+        .expect("state injected by the router")
+        .clone()
+}
+
+#[cfg(any(feature = "admin", feature = "integrations"))]
+pub(crate) fn obtain_namespaces(depot: &Depot) -> Namespaces {
+    depot
+        .obtain::<Namespaces>()
+        .expect("namespaces injected by the router")
+        .clone()
+}
+
+/// the request carries `Authorization: Bearer <token>`, always false without a token.
+#[cfg(any(feature = "admin", feature = "integrations"))]
+pub(crate) fn bearer_matches(req: &Request, token: Option<String>) -> bool {
+    token.is_some_and(|token| {
+        req.header::<String>("authorization")
+            .is_some_and(|h| h == format!("Bearer {token}"))
+    })
+}
+
+/// finished game as json, or a plain text summary with `?format=text`.
+/// a room gone from the server is looked up in the storage.
+#[handler]
+async fn export_game(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let (export, storage) = {
+        let state = obtain_state(depot);
+        let state = state.lock().await;
+        (state.export_game(&room_id), state.storage.clone())
+    };
+    let export = match export {
+        Some(export) => Some(export),
+        None => storage.load_game(room_id).await.unwrap_or_else(|e| {
+            warn!(?e, "load game failed");
+            None
+        }),
+    };
+    let Some(export) = export else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    };
+    match req.query::<String>("format").as_deref() {
+        Some("text") => res.render(Text::Plain(export.summary())),
+        _ => res.render(Json(export)),
+    }
+}
+
+#[cfg(any(feature = "admin", feature = "integrations"))]
+pub(crate) fn room_error_status(e: &RoomError) -> StatusCode {
+    match e {
+        RoomError::RoomNotFound
+        | RoomError::UserNotFoundInRoom
+        | RoomError::NoSuchTurn
+        | RoomError::PresetNotFound => StatusCode::NOT_FOUND,
+        RoomError::RoomStarted
+        | RoomError::RoomFull
+        | RoomError::GameNotRunning
+        | RoomError::NoAbortVote
+        | RoomError::DealFailed { .. }
+        | RoomError::GameNotEnded => StatusCode::CONFLICT,
+        RoomError::NotHost
+        | RoomError::CastingNotAllowed
+        | RoomError::CasterIsPlayer
+        | RoomError::CasterNotAuthorized
+        | RoomError::SeedHidden
+        | RoomError::CompetitiveRoom => StatusCode::FORBIDDEN,
+        RoomError::InvalidHandicap | RoomError::InvalidSaveCode => StatusCode::BAD_REQUEST,
+        RoomError::SaveCodesDisabled => StatusCode::NOT_IMPLEMENTED,
+        RoomError::SaveCodeExpired | RoomError::SaveCodeUsed => StatusCode::GONE,
+        RoomError::ReplayFailed => StatusCode::INTERNAL_SERVER_ERROR,
+        RoomError::ServerBusy { .. } | RoomError::Maintenance { .. } => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
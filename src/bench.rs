@@ -0,0 +1,18 @@
+//! entry points of the criterion benches in `benches/`, only built with the `bench` feature.
+
+pub use crate::ids::Seat;
+pub use crate::map::{
+    AllSectorPossibilities, ChoiceFilter, Clue, ClueConnection, ClueEnum, MapType, SectorType,
+    Sectors,
+};
+pub use crate::operation::{
+    Operation, OperationResult, ResearchOperation, SurveyOperatoin, TargetOperation,
+};
+
+pub fn enumerate(map_type: &MapType) -> impl Iterator<Item = Sectors> {
+    ChoiceFilter::enumerate(map_type)
+}
+
+pub fn filter_op(ss: &Sectors, op: &Operation, result: &OperationResult) -> bool {
+    ChoiceFilter::filter_op(ss, op, result)
+}
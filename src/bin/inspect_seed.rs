@@ -0,0 +1,53 @@
+//! print what a seed deals: the sectors, the clues and how far they narrow the possible maps.
+//!
+//! `inspect-seed <seed> [standard|expert] [--json] [--config <path>]`, the clue weights are
+//! read from the `clue_weights` of the server config at `path`, the built in ones without it.
+
+use planetx_server::{ClueWeights, inspect_seed, protocol::MapType};
+
+const USAGE: &str = "usage: inspect-seed <seed> [standard|expert] [--json] [--config <path>]";
+
+fn main() -> anyhow::Result<()> {
+    let mut json = false;
+    let mut config = None;
+    let mut positional = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?),
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let Some(seed) = positional.next() else {
+        anyhow::bail!(USAGE);
+    };
+    let seed = seed.parse::<u64>()?;
+    let map_type = match positional.next() {
+        Some(map_type) => serde_json::from_value::<MapType>(map_type.into())?,
+        None => MapType::Standard,
+    };
+    let clue_weights = match config {
+        Some(path) => {
+            let mut config: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            let weights = config.get_mut("clue_weights").map(serde_json::Value::take);
+            let weights = match weights {
+                Some(weights) => serde_json::from_value::<ClueWeights>(weights)?,
+                None => ClueWeights::default(),
+            };
+            weights.validate()?;
+            weights
+        }
+        None => ClueWeights::default(),
+    };
+
+    let report = inspect_seed(seed, map_type, &clue_weights)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    Ok(())
+}
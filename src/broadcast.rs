@@ -0,0 +1,127 @@
+#[cfg(feature = "metrics")]
+use serde::Serialize;
+
+use crate::{
+    map::SecretToken,
+    room::{GameStateResp, ServerGameState},
+    sink::EventSink,
+};
+
+/// room broadcasts queued during a tick, only the last state of each room is sent when flushed.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    game_states: Vec<GameStateResp>,
+    board_tokens: Vec<(String, Vec<SecretToken>)>, // room_id -> placed tokens
+    #[cfg(feature = "metrics")]
+    queued: usize,
+}
+
+impl Outbox {
+    pub fn game_state(&mut self, gs: &GameStateResp) {
+        #[cfg(feature = "metrics")]
+        {
+            self.queued += 1;
+        }
+        match self.game_states.iter_mut().find(|s| s.id == gs.id) {
+            Some(queued) => *queued = gs.clone(),
+            None => self.game_states.push(gs.clone()),
+        }
+    }
+
+    pub fn board_tokens(&mut self, room_id: &str, ss: &ServerGameState) {
+        #[cfg(feature = "metrics")]
+        {
+            self.queued += 1;
+        }
+        let tokens = board_tokens(ss);
+        match self.board_tokens.iter_mut().find(|(id, _)| id == room_id) {
+            Some((_, queued)) => *queued = tokens,
+            None => self.board_tokens.push((room_id.to_owned(), tokens)),
+        }
+    }
+
+    /// broadcasts that will actually be sent.
+    #[cfg(any(feature = "metrics", test))]
+    pub fn pending(&self) -> usize {
+        self.game_states.len() + self.board_tokens.len()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn record(&self, metrics: &mut BroadcastMetrics) {
+        metrics.record(self.queued, self.pending());
+    }
+
+    pub async fn flush(self, sink: &impl EventSink) {
+        for gs in self.game_states {
+            let state_event = gs.state_event();
+            sink.to_room(&gs.id, state_event.event(), &state_event)
+                .await;
+        }
+        for (room_id, tokens) in self.board_tokens {
+            sink.to_room(&room_id, "board_tokens", &tokens).await;
+        }
+    }
+}
+
+pub fn board_tokens(ss: &ServerGameState) -> Vec<SecretToken> {
+    ss.user_tokens
+        .iter()
+        .flat_map(|(_user_id, tokens)| tokens.iter())
+        .filter(|t| t.placed)
+        .map(|t| &t.secret)
+        .cloned()
+        .collect()
+}
+
+/// tick broadcast counters, the queue depth is the number of broadcasts queued in one tick.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BroadcastMetrics {
+    pub ticks: u64,
+    pub queued: u64,
+    pub sent: u64,
+    pub coalesced: u64, // broadcasts dropped for a later state of the same room
+    pub last_queue_depth: usize,
+    pub max_queue_depth: usize,
+}
+
+#[cfg(feature = "metrics")]
+impl BroadcastMetrics {
+    pub fn record(&mut self, queued: usize, sent: usize) {
+        self.ticks += 1;
+        self.queued += queued as u64;
+        self.sent += sent as u64;
+        self.coalesced += (queued - sent) as u64;
+        self.last_queue_depth = queued;
+        self.max_queue_depth = self.max_queue_depth.max(queued);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_outbox_coalesce() {
+        let mut outbox = Outbox::default();
+        let mut gs = GameStateResp::new("1234".to_string());
+        outbox.game_state(&gs);
+        gs.round = 2;
+        outbox.game_state(&gs);
+        outbox.game_state(&GameStateResp::new("5678".to_string()));
+        outbox.board_tokens("1234", &ServerGameState::placeholder());
+        outbox.board_tokens("1234", &ServerGameState::placeholder());
+        assert_eq!(outbox.pending(), 3);
+        assert_eq!(outbox.game_states[0].round, 2);
+
+        #[cfg(feature = "metrics")]
+        {
+            let mut metrics = BroadcastMetrics::default();
+            outbox.record(&mut metrics);
+            assert_eq!(metrics.coalesced, 2);
+            assert_eq!(metrics.max_queue_depth, 5);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    analytics::AnalyticsConfig, map::ClueWeights, recommendation::MoveWeights,
+    storage::StorageConfig,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+/// the namespace of the stable protocol, served when no other is configured.
+pub const DEFAULT_NAMESPACE: &str = "/xplanet";
+
+/// server wide tunables, loaded from the json file at `$PLANETX_CONFIG` (default `config.json`).
+/// every field is optional in the file, missing ones fall back to the defaults below.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// seconds between everyone being ready and the game actually starting, 0 to start at once.
+    pub start_countdown_secs: u64,
+    /// seconds a ready flag lasts while the room is still waiting for others, 0 to keep forever.
+    pub ready_timeout_secs: u64,
+    /// seconds a waiting player has to act before the server plays the turn for them, 0 to wait forever.
+    pub turn_timeout_secs: u64,
+    /// bearer token of the admin api, the admin api is disabled without it.
+    pub admin_token: Option<String>,
+    /// bearer token of the control plane api, for trusted integrations acting on behalf of
+    /// players. the control plane is disabled without it.
+    pub control_token: Option<String>,
+    /// key signing the save codes of running games, the servers sharing it resume each other's
+    /// codes. save codes are disabled without it, or without the `integrations` feature.
+    pub save_code_secret: Option<String>,
+    /// rated moves needed before a player can be reported as implausible.
+    pub anticheat_min_samples: usize,
+    /// rate of bot-best moves from which a player is reported as implausible.
+    pub anticheat_best_move_rate: f64,
+    /// autosave snapshots kept per room for the admin restore, 0 to disable.
+    pub snapshot_keep: usize,
+    /// requests per second a service account may send, 0 to not limit them.
+    pub service_ops_per_sec: f64,
+    /// requests a service account may send at once before the rate applies.
+    pub service_burst: usize,
+    /// rooms the server holds at most, 0 for no limit.
+    pub max_rooms: usize,
+    /// games running at the same time at most, 0 for no limit.
+    pub max_games: usize,
+    /// authenticated connections that may still create or join rooms, 0 for no limit.
+    pub max_connections: usize,
+    /// retry hint in seconds sent along a refused create or join.
+    pub busy_retry_secs: u64,
+    /// build the map enumeration tables before accepting traffic, slower startup but no
+    /// cold first game.
+    pub warm_up: bool,
+    /// where snapshots, finished games and player stats are kept, e.g.
+    /// `{"backend": "sqlite", "url": "sqlite://planetx.db"}`. in memory by default.
+    pub storage: StorageConfig,
+    /// words masked in the room chat, case insensitive.
+    pub chat_banned_words: Vec<String>,
+    /// reactions per second a player may send, 0 to not limit them.
+    pub reactions_per_sec: f64,
+    /// reactions a player may send at once before the rate applies.
+    pub reaction_burst: usize,
+    /// seconds a running game nobody is connected to is kept aside for its players to come
+    /// back, 0 to keep such rooms in play and drop the empty ones at once.
+    pub dormant_ttl_secs: u64,
+    /// seconds a socket that sent a `ping` may stay silent before it is dropped as if it
+    /// disconnected, 0 to not reap. sockets that never pinged are left to socket.io.
+    pub heartbeat_timeout_secs: u64,
+    /// seconds the turn timer of a running game brought back after a restart holds, from the
+    /// restart, for its players to reconnect. the players back meanwhile are sent the room again.
+    pub restore_grace_secs: u64,
+    /// share of the players that has to be exceeded to abort a running game by vote.
+    pub abort_vote_ratio: f64,
+    /// seconds an abort vote stays open.
+    pub abort_vote_secs: u64,
+    /// seconds between two announcements, the due ones wait their turn.
+    pub announce_interval_secs: u64,
+    /// check after every accepted operation that the acting player's choice filter still admits
+    /// the true map, log a dump of the ones that don't and panic in debug builds. on in tests.
+    pub choice_self_check: bool,
+    /// how often the clue generator draws each connection, per map type and for the x clues,
+    /// e.g. `{"expert": {"all_adjacent": 100, ...}}`. the built in table by default.
+    pub clue_weights: ClueWeights,
+    /// socket.io namespaces served, e.g. `["/xplanet", "/xplanet-beta"]`, each with its own
+    /// rooms and players. the http api, `/ws` and `/sse` of a namespace are served under its
+    /// name, e.g. `/xplanet-beta/ws`, the first namespace is served at the root as well.
+    pub namespaces: Vec<String>,
+    /// weights of a shadow bot picking a move next to the live bot at every server played turn,
+    /// the live move is played and the other pick logged when they differ. off when None or
+    /// without the `metrics` feature.
+    pub shadow_bot: Option<MoveWeights>,
+    /// log the map, the clues, the tokens and the private results in full instead of redacted.
+    /// for development, release builds redact them anyway.
+    pub log_secrets: bool,
+    /// where the anonymized gameplay analytics are sent, e.g.
+    /// `{"sink": "file", "path": "analytics.jsonl"}`. none are recorded when None.
+    pub analytics: Option<AnalyticsConfig>,
+    /// analytics events per second recorded at most, the ones over it are only counted. 0 to not
+    /// limit them.
+    pub analytics_events_per_sec: f64,
+    /// analytics events recorded at once before the rate applies.
+    pub analytics_burst: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            start_countdown_secs: 5,
+            ready_timeout_secs: 300,
+            turn_timeout_secs: 120,
+            admin_token: None,
+            control_token: None,
+            save_code_secret: None,
+            anticheat_min_samples: 30,
+            anticheat_best_move_rate: 0.8,
+            snapshot_keep: 10,
+            service_ops_per_sec: 2.0,
+            service_burst: 10,
+            max_rooms: 0,
+            max_games: 0,
+            max_connections: 0,
+            busy_retry_secs: 30,
+            warm_up: false,
+            storage: StorageConfig::default(),
+            chat_banned_words: vec![],
+            reactions_per_sec: 1.0,
+            reaction_burst: 3,
+            dormant_ttl_secs: 1800,
+            heartbeat_timeout_secs: 45,
+            restore_grace_secs: 120,
+            abort_vote_ratio: 0.5,
+            abort_vote_secs: 60,
+            announce_interval_secs: 60,
+            choice_self_check: cfg!(test),
+            clue_weights: ClueWeights::default(),
+            namespaces: vec![DEFAULT_NAMESPACE.to_string()],
+            shadow_bot: None,
+            log_secrets: false,
+            analytics: None,
+            analytics_events_per_sec: 50.0,
+            analytics_burst: 200,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn path() -> String {
+        std::env::var("PLANETX_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+    }
+
+    pub fn load() -> Self {
+        let path = Self::path();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<ServerConfig>(&content) {
+                Ok(mut config) => {
+                    if let Err(e) = config.clue_weights.validate() {
+                        warn!("invalid config {path}: {e}, use default clue weights");
+                        config.clue_weights = ClueWeights::default();
+                    }
+                    info!(?config, "config loaded from {path}");
+                    config
+                }
+                Err(e) => {
+                    warn!("invalid config {path}: {e}, use default config");
+                    Self::default()
+                }
+            },
+            Err(_) => {
+                info!("no config file at {path}, use default config");
+                Self::default()
+            }
+        }
+    }
+
+    /// the config file read again for a reload, an unreadable or invalid file is an error so
+    /// the running config is kept.
+    pub fn reload() -> anyhow::Result<Self> {
+        let path = Self::path();
+        let content = std::fs::read_to_string(&path)?;
+        let config = serde_json::from_str::<ServerConfig>(&content)?;
+        config.clue_weights.validate()?;
+        Ok(config)
+    }
+
+    /// the configured namespaces that start with a `/`, once each, the default one if none does.
+    pub fn namespaces(&self) -> Vec<String> {
+        let mut namespaces = vec![];
+        for namespace in self.namespaces.iter() {
+            if !namespace.starts_with('/') || namespaces.contains(namespace) {
+                warn!("invalid or repeated namespace {namespace}, skipped");
+                continue;
+            }
+            namespaces.push(namespace.clone());
+        }
+        if namespaces.is_empty() {
+            namespaces.push(DEFAULT_NAMESPACE.to_string());
+        }
+        namespaces
+    }
+
+    /// take the fields that are safe to change while games run, return the names of the ones
+    /// that changed. `storage`, `warm_up`, `namespaces` and `analytics` only apply at startup.
+    pub fn apply_live(&mut self, new: ServerConfig) -> Vec<&'static str> {
+        let mut changed = vec![];
+        macro_rules! live {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if self.$field != new.$field {
+                        self.$field = new.$field;
+                        changed.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+        live!(
+            start_countdown_secs,
+            ready_timeout_secs,
+            turn_timeout_secs,
+            admin_token,
+            control_token,
+            save_code_secret,
+            anticheat_min_samples,
+            anticheat_best_move_rate,
+            snapshot_keep,
+            service_ops_per_sec,
+            service_burst,
+            max_rooms,
+            max_games,
+            max_connections,
+            busy_retry_secs,
+            chat_banned_words,
+            reactions_per_sec,
+            reaction_burst,
+            dormant_ttl_secs,
+            heartbeat_timeout_secs,
+            restore_grace_secs,
+            abort_vote_ratio,
+            abort_vote_secs,
+            announce_interval_secs,
+            choice_self_check,
+            clue_weights,
+            shadow_bot,
+            log_secrets,
+            analytics_events_per_sec,
+            analytics_burst,
+        );
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_config_default_fields() {
+        let config: ServerConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.start_countdown_secs, 5);
+
+        let config: ServerConfig = serde_json::from_str(r#"{"start_countdown_secs":0}"#).unwrap();
+        assert_eq!(config.start_countdown_secs, 0);
+    }
+
+    #[test]
+    fn test_apply_live() {
+        let mut config = ServerConfig::default();
+        let new: ServerConfig = serde_json::from_str(
+            r#"{"turn_timeout_secs":60,"warm_up":true,"storage":{"backend":"sqlite","url":"x"}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.apply_live(new), vec!["turn_timeout_secs"]);
+        assert_eq!(config.turn_timeout_secs, 60);
+        assert!(!config.warm_up);
+        assert!(matches!(config.storage, StorageConfig::Memory));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        assert_eq!(ServerConfig::default().namespaces(), vec!["/xplanet"]);
+        let config: ServerConfig = serde_json::from_str(
+            r#"{"namespaces":["/xplanet","beta","/xplanet-beta","/xplanet"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.namespaces(), vec!["/xplanet", "/xplanet-beta"]);
+        let config: ServerConfig = serde_json::from_str(r#"{"namespaces":[]}"#).unwrap();
+        assert_eq!(config.namespaces(), vec!["/xplanet"]);
+    }
+
+    #[test]
+    fn test_clue_weights() {
+        let config: ServerConfig =
+            serde_json::from_str(r#"{"clue_weights":{"expert":{"all_adjacent":0,"one_adjacent":1,"not_adjacent":1,"one_opposite":1,"not_opposite":1,"all_in_range":1,"not_in_range":1,"all_in_range_span":[3,9],"not_in_range_span":[2,2]}}}"#)
+                .unwrap();
+        assert_eq!(
+            config.clue_weights.standard,
+            ClueWeights::default().standard
+        );
+        assert!(config.clue_weights.validate().is_ok());
+
+        let mut weights = config.clue_weights.clone();
+        weights.standard.all_in_range_span = [3, 9]; // wider than half the standard board
+        assert!(weights.validate().is_err());
+        let mut weights = config.clue_weights;
+        weights.x_clue.not_in_range_span = [4, 3];
+        assert!(weights.validate().is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use salvo::{http::StatusCode, prelude::*};
+use serde::Deserialize;
+use socketioxide::SocketIo;
+use tracing::warn;
+
+use crate::{
+    api::{bearer_matches, obtain_namespaces, obtain_state, room_error_status},
+    room::{LobbyQuery, RoomUserOperation, ServerResp},
+    server_state::User,
+    sink::{Detached, EventSink},
+    storage::ClaimError,
+};
+
+/// the control plane of the http api for trusted integrations (e.g. a chat bot setting up
+/// games), under `control/`. every request needs `Authorization: Bearer <control_token>`:
+/// - `POST control/rooms/ops` with `{"user": User, "op": RoomUserOperation}` applies a room
+///   operation for the user, validated as on the socket. answers the changed rooms, or the
+///   `ServerResp::RoomErrors` with a 4xx/503 status.
+/// - `GET control/rooms/{room_id}` answers the public `GameStateResp` of a room.
+/// - `POST control/lobby` with a `LobbyQuery` answers a `LobbyPage`.
+/// - `POST control/accounts/claim` with `{"provider", "subject", "guest_id"}` attaches a guest
+///   id to the account of an identity the integration verified (e.g. `email` and the address,
+///   or an oauth provider and its subject), merging its stats and finished games. answers the
+///   `Account`, whose `user_id` the client plays as from then on, or 409 if another account
+///   claimed the guest id. the accounts are shared by every namespace, the merge happens in
+///   each. guests without account keep playing as before.
+/// - `GET control/accounts/{provider}/{subject}` answers the `Account`.
+pub fn router() -> Router {
+    Router::with_path("control")
+        .hoop(control_auth)
+        .push(Router::with_path("rooms/ops").post(room_op))
+        .push(Router::with_path("rooms/{room_id}").get(room_state))
+        .push(Router::with_path("lobby").post(lobby))
+        .push(Router::with_path("accounts/claim").post(claim_guest))
+        .push(Router::with_path("accounts/{provider}/{subject}").get(account))
+}
+
+#[handler]
+async fn control_auth(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let token = obtain_state(depot)
+        .lock()
+        .await
+        .config
+        .control_token
+        .clone();
+    if !bearer_matches(req, token) {
+        res.status_code(StatusCode::UNAUTHORIZED);
+        ctrl.skip_rest();
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ControlRoomOp {
+    user: User,
+    op: RoomUserOperation,
+}
+
+/// a room operation on behalf of a player, the room members see the change on their socket.
+#[handler]
+async fn room_op(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Ok(ControlRoomOp { user, op }) = req.parse_json::<ControlRoomOp>().await else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let io = depot
+        .obtain::<SocketIo>()
+        .expect("socket io injected by the api router")
+        .clone();
+    let state = obtain_state(depot);
+    let mut state = state.lock().await;
+    match state.handle_room_op(&Detached, user, op) {
+        Ok(resp) => {
+            for gs in resp.iter() {
+                state.touch_room(&gs.id);
+                let state_event = gs.state_event();
+                let sink = state.sink(&io);
+                sink.to_room(&gs.id, state_event.event(), &state_event)
+                    .await;
+            }
+            res.render(Json(resp));
+        }
+        Err(e) => {
+            res.status_code(room_error_status(&e));
+            res.render(Json(ServerResp::RoomErrors(e)));
+        }
+    }
+}
+
+#[handler]
+async fn room_state(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let state = obtain_state(depot);
+    let state = state.lock().await;
+    match state.state_data.get(&room_id) {
+        Some((gs, _)) => res.render(Json(gs)),
+        None => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+    }
+}
+
+#[handler]
+async fn lobby(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Ok(query) = req.parse_json::<LobbyQuery>().await else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let page = obtain_state(depot).lock().await.query_lobby(&query);
+    res.render(Json(page));
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaimGuest {
+    provider: String,
+    subject: String,
+    guest_id: String,
+}
+
+#[handler]
+async fn claim_guest(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let Ok(claim) = req.parse_json::<ClaimGuest>().await else {
+        res.status_code(StatusCode::BAD_REQUEST);
+        return;
+    };
+    let state = obtain_state(depot);
+    let store = state.lock().await.storage.clone();
+    let guest_id = claim.guest_id.clone();
+    match store
+        .claim_guest(claim.provider, claim.subject, claim.guest_id)
+        .await
+    {
+        Ok(account) => {
+            // the account is the same player in every namespace
+            for state in obtain_namespaces(depot).0 {
+                let mut state = state.lock().await;
+                state.merge_stats(&guest_id, &account.user_id);
+                state.merge_preferences(&guest_id, &account.user_id);
+            }
+            res.render(Json(account));
+        }
+        Err(ClaimError::Taken) => {
+            res.status_code(StatusCode::CONFLICT);
+        }
+        Err(ClaimError::Storage(e)) => {
+            warn!(?e, "claim guest failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
+
+#[handler]
+async fn account(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let provider = req.param::<String>("provider").unwrap_or_default();
+    let subject = req.param::<String>("subject").unwrap_or_default();
+    let store = obtain_state(depot).lock().await.storage.clone();
+    match store.load_account(provider, subject).await {
+        Ok(Some(account)) => res.render(Json(account)),
+        Ok(None) => {
+            res.status_code(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            warn!(?e, "load account failed");
+            res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::room::RoomError;
+
+    #[test]
+    fn test_control_room_op_body() {
+        let body = r#"{"user":{"id":"u1","name":"discord"},"op":{"join":"1234"}}"#;
+        let ControlRoomOp { user, op } = serde_json::from_str(body).unwrap();
+        assert_eq!(user.id, "u1");
+        assert!(matches!(op, RoomUserOperation::Join(id) if id == "1234"));
+        assert_eq!(
+            room_error_status(&RoomError::ServerBusy {
+                retry_after_secs: 30
+            }),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
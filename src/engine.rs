@@ -0,0 +1,1186 @@
+//! the game flow of a room, driven by the server tick. every phase takes the room state and
+//! returns the events the tick has to send, so the transitions run without sockets.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+use tracing::info;
+
+#[cfg(feature = "metrics")]
+use crate::recommendation::{MoveWeights, best_move_with};
+use crate::{
+    broadcast::Outbox,
+    map::{
+        ChoiceFilter, Clue, ClueGenerator, ClueSecret, ClueWeights, Map, MapType, SectorIndex,
+        SectorType, SkyWindow, Token,
+    },
+    operation::{Operation, OperationResult, ResearchOperation},
+    recommendation::{BestMoveInfo, best_move},
+    redact::Secret,
+    room::{
+        ConferenceResult, GameStage, GameState, GameStateResp, PacingSummary, Penalty,
+        PenaltyReason, RoomError, ServerGameState, ServerResp, Solvability, TheoryVerdict,
+        TokenTransition, UserLocationSequence, UserState, YourTurn,
+    },
+    scoring::compute_results,
+    server_state::User,
+    sink::EventSink,
+};
+
+/// what a phase asks the tick to send, in order.
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    GameState,               // the room state changed, coalesced per tick
+    BoardTokens,             // the public board changed, coalesced per tick
+    StartingIn(Option<u64>), // countdown seconds, None when it was aborted
+    GameStart(Vec<ClueSecret>),
+    XClue(Vec<Clue>),
+    Tokens(HashMap<String, Vec<Token>>), // user_id -> private tokens
+    HandicapReveals(HashMap<String, Vec<(usize, SectorType)>>), // user_id -> revealed sectors
+    YourTurn(Vec<(String, YourTurn)>),   // user_id -> turn, to the waiting humans
+    Conference(ConferenceResult),
+    Pacing(PacingSummary),   // at the start of a conference
+    Penalties(Vec<Penalty>), // after the conference that caused them
+    DealFailed(RoomError),   // the game couldn't start, the room is back in the lobby
+}
+
+/// the moves the server plays for the waiting seats, once every waiting seat is server driven.
+/// `timed_out` tells the idle humans whose turn the server takes over.
+pub fn bot_moves(
+    gs: &GameStateResp,
+    ss: &ServerGameState,
+    timed_out: impl Fn(&str) -> bool,
+) -> Vec<(User, Operation)> {
+    let GameState::Wait(waiting) = &gs.status else {
+        return vec![];
+    };
+    let server_driven =
+        |id: &String| timed_out(id) || gs.users.iter().any(|u| u.id == *id && u.is_server_driven());
+    // wait until every human in the waiting list has acted
+    if waiting.is_empty() || !waiting.iter().all(server_driven) {
+        return vec![];
+    }
+    info!("bot at room: {} for {:?}", gs.id, waiting);
+
+    waiting
+        .iter()
+        .filter_map(|seat_id| {
+            let seat_state = gs.users.iter().find(|u| u.id == *seat_id)?;
+            let tokens = ss.user_tokens.get(seat_id)?;
+            let choices = ss.choices.get(seat_id)?;
+            let info = best_move_info(gs, ss)?;
+            let op = best_move(info, ss.research_clues.clone(), seat_state, tokens, choices);
+            Some((seat_state.seat().user(&seat_state.name), op))
+        })
+        .collect()
+}
+
+/// the move a bot with other weights would play for the seat, to compare with `bot_moves`.
+#[cfg(feature = "metrics")]
+pub fn shadow_move(
+    gs: &GameStateResp,
+    ss: &ServerGameState,
+    seat_id: &str,
+    weights: &MoveWeights,
+) -> Option<Operation> {
+    let seat_state = gs.users.iter().find(|u| u.id == seat_id)?;
+    let tokens = ss.user_tokens.get(seat_id)?;
+    let choices = ss.choices.get(seat_id)?;
+    let info = best_move_info(gs, ss)?;
+    Some(best_move_with(
+        weights,
+        info,
+        ss.research_clues.clone(),
+        seat_state,
+        tokens,
+        choices,
+    ))
+}
+
+fn best_move_info(gs: &GameStateResp, ss: &ServerGameState) -> Option<BestMoveInfo> {
+    let (Ok(start_index), Ok(end_index)) = (
+        SectorIndex::new(gs.start_index, gs.map_type.sector_count()),
+        SectorIndex::new(gs.end_index, gs.map_type.sector_count()),
+    ) else {
+        tracing::error!("bot error: broken indexes at room {}", gs.id);
+        return None;
+    };
+    Some(BestMoveInfo {
+        stage: gs.game_stage.clone(),
+        map_type: gs.map_type.clone(),
+        start_index,
+        end_index,
+        revealed_sectors: ss.revealed_sector_indexs.clone(),
+    })
+}
+
+/// count down once everyone in the room is ready, then deal the map, the clues and the tokens,
+/// the clue connections drawn by `clue_weights`.
+pub fn start_game(
+    gs: &mut GameStateResp,
+    ss: &mut ServerGameState,
+    countdown: Duration,
+    clue_weights: &ClueWeights,
+    now: Instant,
+) -> Vec<EngineEvent> {
+    let map_type = gs.map_type.clone();
+    start_game_dealt_by(gs, ss, countdown, now, |seed| {
+        deal(seed, map_type.clone(), clue_weights)
+    })
+}
+
+/// `start_game`, the game of a seed dealt by `deal`.
+fn start_game_dealt_by(
+    gs: &mut GameStateResp,
+    ss: &mut ServerGameState,
+    countdown: Duration,
+    now: Instant,
+    deal: impl FnMut(u64) -> anyhow::Result<(Map, Vec<Clue>, Vec<Clue>)>,
+) -> Vec<EngineEvent> {
+    let mut events = vec![];
+    if gs.status != GameState::NotStarted {
+        return events;
+    }
+    let all_ready = gs.users.iter().all(|u| u.ready);
+    if gs.start_countdown.is_some() && !all_ready {
+        // someone un-readied during the countdown, abort it
+        gs.start_countdown = None;
+        events.push(EngineEvent::StartingIn(None));
+    }
+    if !all_ready {
+        return events;
+    }
+    let deadline = *gs.start_countdown.get_or_insert(now + countdown);
+    if deadline > now {
+        let left = (deadline - now).as_secs_f64().ceil() as u64;
+        events.push(EngineEvent::StartingIn(Some(left)));
+        return events;
+    }
+    gs.start_countdown = None;
+    // drawn once the settings are locked, the players see it with the results
+    let drawn_seed = gs.rules.server_seed.then(|| rand::random::<u32>() as u64);
+    let first_seed = drawn_seed.unwrap_or(gs.map_seed);
+    let dealt = reroll(first_seed, MAX_SEED_REROLLS, deal);
+    let (seed, (map, research_clues, x_clues)) = match dealt {
+        Ok(dealt) => dealt,
+        Err(tries) => {
+            tracing::error!(
+                "room {}: no seed from {first_seed} dealt a game in {tries} tries",
+                gs.id
+            );
+            // back to the lobby, unready so the countdown doesn't run into the same seed again.
+            // the bots never ready themselves, they stay ready
+            gs.users
+                .iter_mut()
+                .filter(|u| !u.seat().is_bot())
+                .for_each(|u| u.ready = false);
+            gs.hint =
+                Some("The game couldn't be dealt, change the seed and ready again".to_string());
+            events.push(EngineEvent::GameState);
+            events.push(EngineEvent::DealFailed(RoomError::DealFailed {
+                seed: if drawn_seed.is_some() { 0 } else { first_seed },
+                tries,
+            }));
+            return events;
+        }
+    };
+    if seed != first_seed {
+        tracing::warn!(
+            "room {}: seed {first_seed} failed to deal, rerolled to {seed}",
+            gs.id
+        );
+    }
+    let hidden_seed = drawn_seed.map(|_| seed);
+    gs.map_seed = if hidden_seed.is_some() { 0 } else { seed };
+    gs.users.shuffle(&mut SmallRng::seed_from_u64(seed));
+    gs.hint = Some(match seed != first_seed && hidden_seed.is_none() {
+        true => format!("Seed {first_seed} couldn't be dealt, game is starting with seed {seed}"),
+        false => "Game is starting".to_string(),
+    });
+    events.extend(lay_out(
+        gs,
+        ss,
+        (map, research_clues, x_clues),
+        seed,
+        hidden_seed,
+    ));
+    events
+}
+
+/// seat the players of the room, in their order, at a dealt game and start it.
+pub fn lay_out(
+    gs: &mut GameStateResp,
+    ss: &mut ServerGameState,
+    (map, research_clues, x_clues): (Map, Vec<Clue>, Vec<Clue>),
+    seed: u64,
+    hidden_seed: Option<u64>,
+) -> Vec<EngineEvent> {
+    let mut events = vec![];
+    gs.status = GameState::Starting;
+    gs.set_window(SkyWindow::first(&gs.map_type));
+    gs.round = 1;
+    let mut user_tokens = HashMap::new();
+    let mut choices = HashMap::new();
+    for (index, user) in gs.users.iter_mut().enumerate() {
+        user.location =
+            UserLocationSequence::new(gs.start_index, index + 1, gs.map_type.sector_count());
+        let tokens = gs.map_type.generate_tokens(user.id.clone(), index + 1);
+        user_tokens.insert(user.id.clone(), tokens);
+        choices.insert(
+            user.id.clone(),
+            ChoiceFilter::new(gs.map_type.clone(), user.seat()),
+        );
+    }
+
+    events.push(EngineEvent::GameState);
+    info!(map = ?Secret(&map), "map generated");
+    gs.solvability = Some(Solvability::new(&gs.map_type, &research_clues, &x_clues));
+    let mut server_game_state = ServerGameState {
+        map,
+        research_clues,
+        x_clues,
+        user_tokens,
+        terminator_location: None,
+        revealed_sector_indexs: vec![],
+        choices,
+        located_order: vec![],
+        handicap_reveals: HashMap::new(),
+        journal: vec![],
+        hidden_seed,
+        conferences: vec![],
+    };
+    server_game_state.reveal_handicap_sectors(&gs.users, seed);
+    events.push(EngineEvent::HandicapReveals(
+        server_game_state.handicap_reveals.clone(),
+    ));
+    events.push(EngineEvent::GameStart(server_game_state.clue_secret()));
+    // distrubute tokens emiting to users
+    events.push(player_tokens(gs, &server_game_state));
+
+    *ss = server_game_state;
+
+    gs.status = GameState::AutoMove;
+    gs.hint = Some("Game started".to_string());
+    events.push(EngineEvent::GameState);
+    events
+}
+
+/// the map and the research and x clues of a seed.
+pub fn deal(
+    seed: u64,
+    map_type: MapType,
+    clue_weights: &ClueWeights,
+) -> anyhow::Result<(Map, Vec<Clue>, Vec<Clue>)> {
+    let map = Map::new(SmallRng::seed_from_u64(seed), map_type)?;
+    let (research_clues, x_clues) =
+        ClueGenerator::new(seed, map.sectors.clone(), map.r#type.clone())
+            .with_weights(clue_weights.clone())
+            .generate_clues()?;
+    Ok((map, research_clues, x_clues))
+}
+
+/// seeds tried after the one of the room, when its map or clues can't be dealt.
+const MAX_SEED_REROLLS: u64 = 8;
+
+/// deal from `seed`, then from the seeds after it, the seed dealt with. the tries made when none did.
+fn reroll<T>(
+    seed: u64,
+    rerolls: u64,
+    mut deal: impl FnMut(u64) -> anyhow::Result<T>,
+) -> Result<(u64, T), u64> {
+    for seed in (0..=rerolls).map(|i| seed.wrapping_add(i)) {
+        match deal(seed) {
+            Ok(dealt) => return Ok((seed, dealt)),
+            Err(e) => tracing::warn!("seed {seed} failed to deal: {e:?}"),
+        }
+    }
+    Err(rerolls + 1)
+}
+
+/// run the automatic phases of a started game, in the order of one tick.
+pub fn advance(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    let before = gs.status.clone();
+    let mut events = auto_move(gs, ss);
+    events.extend(meeting_check(gs, ss));
+    events.extend(meeting_publish(gs, ss));
+    events.extend(meeting_proposal(gs, ss));
+    events.extend(last_move(gs, ss));
+    if gs.status != before {
+        // the think time of the waited players counts from their `your_turn`
+        if matches!(gs.status, GameState::Wait(_)) {
+            gs.wait_since = Some(std::time::Instant::now());
+        }
+        events.extend(your_turn(gs));
+    }
+    events
+}
+
+/// steps `settle` runs at most, a meeting takes four.
+const MAX_SETTLE_STEPS: usize = 8;
+
+/// run the automatic phases at once until the room waits again, after an operation ended a wait.
+pub fn settle(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    let mut events = vec![];
+    for _ in 0..MAX_SETTLE_STEPS {
+        if gs.status != GameState::AutoMove {
+            break;
+        }
+        let step = advance(gs, ss);
+        if step.is_empty() {
+            break;
+        }
+        events.extend(step);
+    }
+    events
+}
+
+/// tell the players the room now waits for, bots need no telling.
+pub fn your_turn(gs: &GameStateResp) -> Option<EngineEvent> {
+    let GameState::Wait(waiting) = &gs.status else {
+        return None;
+    };
+    let turns = gs
+        .users
+        .iter()
+        .filter(|u| !u.seat().is_bot() && waiting.contains(&u.id))
+        .filter_map(|u| {
+            let legal = gs.legal_actions(&u.id)?;
+            let turn = YourTurn {
+                room_id: gs.id.clone(),
+                stage: legal.stage,
+                operations: legal.operations,
+            };
+            Some((u.id.clone(), turn))
+        })
+        .collect::<Vec<_>>();
+    (!turns.is_empty()).then_some(EngineEvent::YourTurn(turns))
+}
+
+/// send the events of a tick: the room events first, then the coalesced room states, then
+/// the private data that follows them. the room states and boards must be already queued.
+pub async fn send(sink: &impl EventSink, outbox: Outbox, events: Vec<(String, EngineEvent)>) {
+    for (room_id, event) in &events {
+        match event {
+            EngineEvent::StartingIn(left) => sink.to_room(room_id, "starting_in", left).await,
+            EngineEvent::GameStart(clues) => sink.to_room(room_id, "game_start", clues).await,
+            EngineEvent::XClue(clues) => sink.to_room(room_id, "xclue", clues).await,
+            EngineEvent::Conference(result) => {
+                sink.to_room(room_id, "conference_result", result).await
+            }
+            EngineEvent::Pacing(summary) => sink.to_room(room_id, "pacing_summary", summary).await,
+            EngineEvent::DealFailed(e) => {
+                let resp = ServerResp::RoomErrors(e.clone());
+                sink.to_room(room_id, "server_resp", &resp).await
+            }
+            EngineEvent::Penalties(penalties) => {
+                for penalty in penalties {
+                    sink.to_room(room_id, "penalty", penalty).await;
+                }
+            }
+            _ => {}
+        }
+    }
+    outbox.flush(sink).await;
+    for (_room_id, event) in &events {
+        match event {
+            EngineEvent::Tokens(tokens) => {
+                for (user_id, tokens) in tokens {
+                    if sink.to_user(user_id, "token", tokens) == 0 {
+                        tracing::error!("user not connected, user_id: {}", user_id);
+                    }
+                }
+            }
+            EngineEvent::HandicapReveals(reveals) => {
+                for (user_id, reveals) in reveals {
+                    sink.to_user(user_id, "handicap_reveal", reveals);
+                }
+            }
+            EngineEvent::YourTurn(turns) => {
+                for (user_id, turn) in turns {
+                    sink.to_user(user_id, "your_turn", turn);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// move on to the next point of the track: a player's turn, a meeting or an x clue.
+pub fn auto_move(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    if gs.status != GameState::AutoMove || gs.game_stage != GameStage::UserMove {
+        return vec![];
+    }
+    // find the first point from gs.start_index, move to it.
+    let Some(next_point) = find_next_point(gs, false) else {
+        return no_more_points(gs, ss);
+    };
+    move_window(gs, next_point.index);
+    let mut events = vec![];
+    match next_point.r#type {
+        PointType::User(id) => {
+            let name = user_name(gs, &id);
+            gs.status = GameState::Wait(vec![id]);
+            gs.game_stage = GameStage::UserMove;
+            gs.hint = Some(format!("{} should move", name));
+        }
+        PointType::Meeting => {
+            info!("should start a meeting");
+            gs.status = GameState::Wait(gs.users.iter().map(|u| u.id.clone()).collect());
+            gs.game_stage = GameStage::MeetingProposal;
+            gs.hint = Some("Meeting proposal, Everyone should move".to_string());
+            events.push(EngineEvent::Pacing(gs.pacing_summary()));
+        }
+        PointType::XClue => {
+            info!("should broadcast xclue");
+            let index = gs
+                .map_type
+                .xclue_points()
+                .iter()
+                .position(|(i, c)| *i == next_point.index && *c == next_point.child_index)
+                .unwrap_or(0);
+            let xclue = ss.x_clues.get(index).map_or(vec![], |x| vec![x.clone()]);
+            events.push(EngineEvent::XClue(xclue.clone()));
+            let Some(second_point) = find_next_point(gs, true) else {
+                events.extend(no_more_points(gs, ss));
+                return events;
+            };
+            gs.hint = Some("X clue time".to_string());
+            move_window(gs, second_point.index);
+            gs.game_stage = GameStage::UserMove;
+            gs.status = GameState::AutoMove;
+
+            for clue in xclue.iter() {
+                for (_user_id, filter) in ss.choices.iter_mut() {
+                    filter.add_operation(
+                        Operation::Research(ResearchOperation {
+                            index: clue.index.clone(),
+                        }),
+                        OperationResult::Research(clue.clone()),
+                    );
+                }
+            }
+        }
+    }
+    events.push(EngineEvent::GameState);
+    events
+}
+
+/// check the theories due at this meeting, then the ones on sectors revealed by it.
+pub fn meeting_check(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    if gs.status != GameState::AutoMove || gs.game_stage != GameStage::MeetingCheck {
+        return vec![];
+    }
+    let mut verdicts = vec![];
+    let mut transitions = vec![];
+    let mut checked_tokens = ss
+        .user_tokens
+        .iter_mut()
+        .flat_map(|(user_id, tokens)| {
+            tokens
+                .iter_mut()
+                .filter(|t| t.any_ready_checked())
+                .map(|t| (user_id.clone(), t))
+        })
+        .collect::<Vec<(String, &mut Token)>>();
+    // we need to sort the tokens by sector_index, and then check them one by one
+    checked_tokens.sort_by_key(|(_user_id, token)| token.secret.sector_index);
+    for (user_id, token) in checked_tokens {
+        let from = token.secret.state.meeting_index();
+        if check_theory(gs, &ss.map, &user_id, token, &mut verdicts) {
+            ss.revealed_sector_indexs.push(token.secret.sector_index);
+        }
+        transitions.push(TokenTransition::new(token, from));
+    }
+    // next checked tokens
+    let mut double_check_tokens = ss
+        .user_tokens
+        .iter_mut()
+        .flat_map(|(user_id, tokens)| {
+            tokens
+                .iter_mut()
+                .filter(|t| {
+                    t.secret.r#type.is_none()
+                        && t.placed
+                        && ss.revealed_sector_indexs.contains(&t.secret.sector_index)
+                })
+                .map(|t| (user_id.clone(), t))
+        })
+        .collect::<Vec<(String, &mut Token)>>();
+    double_check_tokens.sort_by_key(|(_user_id, token)| token.secret.sector_index);
+    for (user_id, token) in double_check_tokens {
+        let from = token.secret.state.meeting_index();
+        check_theory(gs, &ss.map, &user_id, token, &mut verdicts);
+        transitions.push(TokenTransition::new(token, from));
+    }
+    info!("meeting check result: {:?}", verdicts);
+    let penalties = verdicts
+        .iter()
+        .filter(|v| v.penalty_steps > 0)
+        .map(|v| Penalty {
+            room_id: gs.id.clone(),
+            user_id: v.user_id.clone(),
+            name: v.name.clone(),
+            reason: PenaltyReason::WrongTheory,
+            sector_index: v.sector_index,
+            steps: v.penalty_steps,
+        })
+        .collect::<Vec<_>>();
+    let conference = ConferenceResult {
+        room_id: gs.id.clone(),
+        stage: GameStage::MeetingCheck,
+        transitions,
+        verdicts,
+    };
+    ss.conferences.push(conference.clone());
+    let conference = EngineEvent::Conference(conference);
+
+    // no one need to publish, go to next user
+    // make waiting next user move
+    gs.status = GameState::AutoMove;
+    gs.game_stage = GameStage::UserMove;
+    gs.hint = Some("Push forward".to_string());
+    // need to find next user to move
+    let Some(second_point) = find_next_point(gs, true) else {
+        let mut events = vec![conference, EngineEvent::Penalties(penalties)];
+        events.extend(no_more_points(gs, ss));
+        return events;
+    };
+    move_window(gs, second_point.index);
+
+    // update tokens to choices
+    for (user_id, tokens) in ss.user_tokens.iter() {
+        let Some(choice) = ss.choices.get_mut(user_id) else {
+            continue;
+        };
+        let placed = tokens
+            .iter()
+            .filter(|t| t.placed && t.secret.r#type.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        choice.update_tokens(&placed);
+    }
+    vec![
+        conference,
+        EngineEvent::Penalties(penalties),
+        EngineEvent::GameState,
+        EngineEvent::BoardTokens,
+    ]
+}
+
+/// wait for each player to publish, in track order, then push the published theories forward.
+pub fn meeting_publish(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    // check publish first then proposal, we could update tokens after proposal
+    if gs.status != GameState::AutoMove || gs.game_stage != GameStage::MeetingPublish {
+        return vec![];
+    }
+    let next_publisher = user_ids_in_track_order(gs).into_iter().find(|id| {
+        ss.user_tokens
+            .get(id)
+            .is_some_and(|tokens| tokens.iter().any(|t| t.any_ready_published()))
+    });
+    if let Some(id) = next_publisher {
+        let name = user_name(gs, &id);
+        gs.status = GameState::Wait(vec![id]);
+        gs.hint = Some(format!("{} should publish", name));
+        return vec![EngineEvent::GameState, EngineEvent::BoardTokens];
+    }
+
+    // push tokens forword at any none revealed sector
+    // first we need to get revealed sector index
+    let revealed_sectors = ss
+        .user_tokens
+        .iter()
+        .flat_map(|(_user_id, tokens)| {
+            tokens
+                .iter()
+                .filter_map(|t| t.is_revealed_checked().then_some(t.secret.sector_index))
+        })
+        .collect::<Vec<_>>();
+    let mut transitions = vec![];
+    for token in ss.user_tokens.values_mut().flatten() {
+        let from = token.secret.state.meeting_index();
+        token.push_at_meeting(&revealed_sectors);
+        if token.secret.state.meeting_index() != from {
+            transitions.push(TokenTransition::new(token, from));
+        }
+    }
+    // the tokens move together, keep the order stable across the players
+    transitions.sort_by_key(|t| (t.sector_index, t.user_index));
+    let mut events = vec![];
+    if !transitions.is_empty() {
+        let conference = ConferenceResult {
+            room_id: gs.id.clone(),
+            stage: GameStage::MeetingPublish,
+            transitions,
+            verdicts: vec![],
+        };
+        ss.conferences.push(conference.clone());
+        events.push(EngineEvent::Conference(conference));
+    }
+
+    // check if need to go to meeting check phase
+    gs.status = GameState::AutoMove;
+    if ss
+        .user_tokens
+        .iter()
+        .any(|(_user_id, tokens)| tokens.iter().any(|t| t.any_ready_checked()))
+    {
+        gs.game_stage = GameStage::MeetingCheck;
+        gs.hint = Some("Push forward triggle Meeting check, Wait Checking...".to_string());
+    } else {
+        // no one need to publish, go to next user
+        gs.game_stage = GameStage::UserMove;
+        gs.hint = Some("Push forward".to_string());
+        // need to find next user to move
+        let Some(second_point) = find_next_point(gs, true) else {
+            events.extend(no_more_points(gs, ss));
+            return events;
+        };
+        move_window(gs, second_point.index);
+    }
+    events.extend([EngineEvent::GameState, EngineEvent::BoardTokens]);
+    events
+}
+
+/// everyone proposed, gather the tokens for the publish round.
+pub fn meeting_proposal(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    if gs.status != GameState::AutoMove || gs.game_stage != GameStage::MeetingProposal {
+        return vec![];
+    }
+    info!("server MeetingPublish");
+    gs.game_stage = GameStage::MeetingPublish;
+    gs.hint = Some("Gathering all tokens, ready for Meeting publish".to_string());
+    vec![
+        EngineEvent::GameState,
+        EngineEvent::BoardTokens,
+        player_tokens(gs, ss),
+    ]
+}
+
+/// the private tokens of the human seats, the bot seats have no one to send them to.
+fn player_tokens(gs: &GameStateResp, ss: &ServerGameState) -> EngineEvent {
+    let tokens = ss
+        .user_tokens
+        .iter()
+        .filter(|(id, _)| gs.seat_of(id).is_some_and(|s| !s.is_bot()))
+        .map(|(id, tokens)| (id.clone(), tokens.clone()))
+        .collect();
+    EngineEvent::Tokens(tokens)
+}
+
+/// everyone behind the player who located x gets one last move, then the game is scored.
+pub fn last_move(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    if gs.status != GameState::AutoMove || gs.game_stage != GameStage::LastMove {
+        return vec![];
+    }
+    let next_mover = user_ids_in_track_order(gs)
+        .into_iter()
+        .find(|id| gs.users.iter().any(|u| u.id == *id && u.last_move));
+    if let Some(id) = next_mover {
+        let name = user_name(gs, &id);
+        gs.status = GameState::Wait(vec![id]);
+        gs.hint = Some(format!("{} should make last move", name));
+        return vec![EngineEvent::GameState, EngineEvent::BoardTokens];
+    }
+
+    // no one need to move, end the game
+    gs.status = GameState::End;
+    gs.game_stage = GameStage::GameEnd;
+    gs.hint = Some("Game Over!".to_string());
+
+    // reveal all tokens
+    ss.user_tokens.iter_mut().for_each(|(_user_id, tokens)| {
+        tokens.iter_mut().for_each(|t| {
+            if t.reveal_in_the_end()
+                && !ss
+                    .map
+                    .meeting_check(t.secret.sector_index, &t.r#type)
+                    .unwrap_or(false)
+            {
+                // wrong, move to 4
+                t.disprove();
+            }
+        });
+    });
+
+    let results = compute_results(gs, ss);
+    info!("game result: {:?}", results);
+    gs.game_result = Some(results);
+    reveal_seed(gs, ss);
+    vec![EngineEvent::GameState, EngineEvent::BoardTokens]
+}
+
+/// reveal a theory, a wrong one moves its owner 1 step. true if the theory was right.
+fn check_theory(
+    gs: &mut GameStateResp,
+    map: &Map,
+    user_id: &str,
+    token: &mut Token,
+    verdicts: &mut Vec<TheoryVerdict>,
+) -> bool {
+    let all_users_location = gs
+        .users
+        .iter()
+        .map(|u| u.location.clone())
+        .collect::<Vec<_>>();
+    let Some(user) = gs.users.iter_mut().find(|u| u.id == user_id) else {
+        tracing::error!("user not found: {user_id}");
+        return false;
+    };
+    let right = map
+        .meeting_check(token.secret.sector_index, &token.r#type)
+        .unwrap_or(false);
+    let penalty_steps = if right {
+        // right, reveal the token
+        token.verify();
+        0
+    } else {
+        // punish the user move 1 step, token reveal and move outside the map
+        token.disprove();
+        user.location = user.location.next(1, &all_users_location);
+        1
+    };
+    verdicts.push(TheoryVerdict {
+        user_id: user_id.to_owned(),
+        name: user.name.clone(),
+        sector_index: token.secret.sector_index,
+        claimed: token.r#type.clone(),
+        right,
+        penalty_steps,
+    });
+    right
+}
+
+fn no_more_points(gs: &mut GameStateResp, ss: &mut ServerGameState) -> Vec<EngineEvent> {
+    gs.status = GameState::End;
+    gs.hint = Some("No more points".to_string());
+    reveal_seed(gs, ss);
+    vec![EngineEvent::GameState]
+}
+
+/// the seed of a `server_seed` room is public once the game ended.
+fn reveal_seed(gs: &mut GameStateResp, ss: &mut ServerGameState) {
+    if let Some(seed) = ss.hidden_seed.take() {
+        gs.map_seed = seed;
+    }
+}
+
+/// the visible half of the track starts at `index`, passing the end of the track is a new round.
+fn move_window(gs: &mut GameStateResp, index: usize) {
+    let mut window = gs.window();
+    if window.advance(index) {
+        gs.round += 1;
+    }
+    gs.set_window(window);
+}
+
+fn user_name(gs: &GameStateResp, id: &str) -> String {
+    gs.users
+        .iter()
+        .find(|u| u.id == id)
+        .map(|u| u.name.clone())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+fn find_next_point(gs: &GameStateResp, next_next: bool) -> Option<PointInfo> {
+    let index = if next_next { 1 } else { 0 };
+    let mut all_points: Vec<PointInfo> = gs
+        .users
+        .iter()
+        .map(Into::into)
+        .chain(
+            gs.map_type
+                .meeting_points()
+                .into_iter()
+                .map(|(index, child_index)| PointInfo {
+                    r#type: PointType::Meeting,
+                    index,
+                    child_index,
+                    round: 0, // not used
+                }),
+        )
+        .chain(
+            gs.map_type
+                .xclue_points()
+                .into_iter()
+                .map(|(index, child_index)| PointInfo {
+                    r#type: PointType::XClue,
+                    index,
+                    child_index,
+                    round: 1, // not used
+                }),
+        )
+        .collect::<Vec<_>>();
+    // sort by start_index, index, child_index
+    all_points.sort_by(|a, b| {
+        a.index
+            .cmp(&b.index)
+            .then_with(|| a.child_index.cmp(&b.child_index))
+    });
+    info!(?all_points, "all points");
+
+    all_points
+        .iter()
+        .cycle()
+        .skip_while(|p| p.index < gs.start_index || (gs.round > 1 && p.round == 1))
+        .nth(index)
+        .cloned()
+}
+
+/// the players from the one furthest behind on the track.
+pub fn user_ids_in_track_order(gs: &GameStateResp) -> Vec<String> {
+    let mut all_user_points: Vec<PointInfo> = gs.users.iter().map(Into::into).collect::<Vec<_>>();
+    all_user_points.sort_by(|a, b| {
+        // round , then index, then child_index
+        a.round.cmp(&b.round).then_with(|| {
+            if a.index == b.index {
+                a.child_index.cmp(&b.child_index)
+            } else {
+                a.index.cmp(&b.index)
+            }
+        })
+    });
+    info!(?all_user_points, "all user points");
+    all_user_points
+        .into_iter()
+        .filter_map(|p| match p.r#type {
+            PointType::User(id) => Some(id),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct PointInfo {
+    r#type: PointType,
+    index: usize,
+    child_index: usize,
+    round: usize,
+}
+
+#[derive(Debug, Clone)]
+enum PointType {
+    User(String),
+    Meeting,
+    XClue,
+}
+
+impl From<&UserState> for PointInfo {
+    fn from(user: &UserState) -> Self {
+        PointInfo {
+            r#type: PointType::User(user.id.clone()),
+            index: user.location.index,
+            child_index: user.location.child_index,
+            round: user.location.round,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::sink::{RecordingSink, Sent};
+
+    fn ready_room() -> (GameStateResp, ServerGameState) {
+        let mut gs = GameStateResp::new("1234".to_string());
+        gs.map_seed = 42;
+        for id in ["u1", "u2"] {
+            let user = User::test(id);
+            let mut user_state = UserState::placeholder(&user, gs.users.len() + 1, false);
+            user_state.ready = true;
+            gs.users.push(user_state);
+        }
+        (gs, ServerGameState::placeholder())
+    }
+
+    #[test]
+    fn test_start_countdown() {
+        let (mut gs, mut ss) = ready_room();
+        let now = Instant::now();
+        let events = start_game(
+            &mut gs,
+            &mut ss,
+            Duration::from_secs(5),
+            &ClueWeights::default(),
+            now,
+        );
+        assert!(matches!(events[..], [EngineEvent::StartingIn(Some(5))]));
+        assert_eq!(gs.status, GameState::NotStarted);
+
+        gs.users[0].ready = false;
+        let events = start_game(
+            &mut gs,
+            &mut ss,
+            Duration::from_secs(5),
+            &ClueWeights::default(),
+            now,
+        );
+        assert!(matches!(events[..], [EngineEvent::StartingIn(None)]));
+        assert!(gs.start_countdown.is_none());
+    }
+
+    #[test]
+    fn test_deal_failed_keeps_bots_ready() {
+        let (mut gs, mut ss) = ready_room();
+        gs.users.push(UserState::bot("1234", Default::default(), 3));
+        let events =
+            start_game_dealt_by(&mut gs, &mut ss, Duration::ZERO, Instant::now(), |seed| {
+                Err(anyhow::anyhow!("can't deal {seed}"))
+            });
+        assert!(matches!(
+            events[..],
+            [EngineEvent::GameState, EngineEvent::DealFailed(_)]
+        ));
+        assert_eq!(gs.status, GameState::NotStarted);
+        let ready = gs.users.iter().map(|u| u.ready).collect::<Vec<_>>();
+        assert_eq!(ready, vec![false, false, true]);
+
+        // the humans ready again on a new seed, the room starts
+        gs.map_seed = 7;
+        gs.users[..2].iter_mut().for_each(|u| u.ready = true);
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        assert_eq!(gs.status, GameState::AutoMove);
+    }
+
+    #[test]
+    fn test_server_seed() {
+        let (mut gs, mut ss) = ready_room();
+        gs.rules.server_seed = true;
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        assert_eq!(gs.status, GameState::AutoMove);
+        assert_eq!(gs.map_seed, 0);
+        let seed = ss.hidden_seed.unwrap();
+
+        no_more_points(&mut gs, &mut ss);
+        assert_eq!(gs.status, GameState::End);
+        assert_eq!(gs.map_seed, seed);
+        assert!(ss.hidden_seed.is_none());
+    }
+
+    #[test]
+    fn test_reroll() {
+        let fail_below = |seed: u64| match seed >= 12 {
+            true => Ok(seed * 2),
+            false => Err(anyhow::anyhow!("can't deal {seed}")),
+        };
+        assert_eq!(reroll(12, 8, fail_below), Ok((12, 24)));
+        assert_eq!(reroll(10, 8, fail_below), Ok((12, 24)));
+        assert_eq!(reroll(1, 8, fail_below), Err(9));
+        // wraps round instead of overflowing
+        let mut tried = vec![];
+        let never = |seed: u64| -> anyhow::Result<()> {
+            tried.push(seed);
+            Err(anyhow::anyhow!("can't deal {seed}"))
+        };
+        assert_eq!(reroll(u64::MAX, 1, never), Err(2));
+        assert_eq!(tried, vec![u64::MAX, 0]);
+    }
+
+    #[test]
+    fn test_start_and_first_move() {
+        let (mut gs, mut ss) = ready_room();
+        let events = start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        assert_eq!(gs.status, GameState::AutoMove);
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, EngineEvent::GameStart(clues) if !clues.is_empty()))
+        );
+        assert!(
+            events
+                .iter()
+                .any(|e| matches!(e, EngineEvent::Tokens(tokens) if tokens.len() == 2))
+        );
+        assert_eq!(ss.choices.len(), 2);
+
+        let events = advance(&mut gs, &mut ss);
+        let [EngineEvent::GameState, EngineEvent::YourTurn(turns)] = &events[..] else {
+            panic!("the first player should be told");
+        };
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].0, gs.users[0].id);
+        assert_eq!(turns[0].1.operations.len(), 5);
+        assert_eq!(gs.game_stage, GameStage::UserMove);
+        let GameState::Wait(waiting) = &gs.status else {
+            panic!("first player should be waited for");
+        };
+        assert_eq!(waiting, &vec![gs.users[0].id.clone()]);
+        // timed from the `your_turn`, not from the first tick that checks the deadline
+        assert!(gs.wait_since.is_some());
+
+        // the waiting seat is human and still on time
+        assert!(bot_moves(&gs, &ss, |_| false).is_empty());
+        assert_eq!(bot_moves(&gs, &ss, |_| true).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_order() {
+        let (mut gs, mut ss) = ready_room();
+        gs.users[1].id = "bot-1234".to_string();
+        gs.users[1].is_bot = true;
+        let events = start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        let mut outbox = Outbox::default();
+        let mut room_events = vec![];
+        for event in events {
+            match event {
+                EngineEvent::GameState => outbox.game_state(&gs),
+                EngineEvent::BoardTokens => outbox.board_tokens(&gs.id, &ss),
+                event => room_events.push((gs.id.clone(), event)),
+            }
+        }
+
+        let sink = RecordingSink::default();
+        send(&sink, outbox, room_events).await;
+        // the bot gets no tokens, the coalesced state goes out once
+        assert_eq!(sink.events(), vec!["game_start", "game_state", "token"]);
+        assert!(sink.sent.lock().unwrap().contains(&Sent::User(
+            "u1".to_string(),
+            "token",
+            serde_json::to_value(&ss.user_tokens["u1"]).unwrap()
+        )));
+    }
+
+    #[test]
+    fn test_meeting_proposal_gathers_tokens() {
+        let (mut gs, mut ss) = ready_room();
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        gs.game_stage = GameStage::MeetingProposal;
+        let events = meeting_proposal(&mut gs, &mut ss);
+        assert_eq!(gs.game_stage, GameStage::MeetingPublish);
+        assert!(matches!(
+            events[..],
+            [
+                EngineEvent::GameState,
+                EngineEvent::BoardTokens,
+                EngineEvent::Tokens(_)
+            ]
+        ));
+
+        // nothing to publish nor to check, the game goes on
+        let events = meeting_publish(&mut gs, &mut ss);
+        assert_eq!(gs.game_stage, GameStage::UserMove);
+        assert_eq!(gs.status, GameState::AutoMove);
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn test_settle_meeting() {
+        let (mut gs, mut ss) = ready_room();
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        gs.game_stage = GameStage::MeetingProposal;
+
+        // nothing proposed: gather, push forward, then wait for the next point of the track
+        let events = settle(&mut gs, &mut ss);
+        assert_ne!(gs.game_stage, GameStage::MeetingPublish);
+        assert!(matches!(gs.status, GameState::Wait(_)));
+        assert!(events.iter().any(|e| matches!(e, EngineEvent::Tokens(_))));
+        assert!(matches!(events.last(), Some(EngineEvent::YourTurn(_))));
+        assert!(settle(&mut gs, &mut ss).is_empty());
+    }
+
+    #[test]
+    fn test_meeting_publish_transitions() {
+        let (mut gs, mut ss) = ready_room();
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        let token = &mut ss.user_tokens.get_mut("u1").unwrap()[0];
+        token.set_to_be_placed().set_published(5).unwrap();
+        gs.game_stage = GameStage::MeetingPublish;
+
+        let events = meeting_publish(&mut gs, &mut ss);
+        let [
+            EngineEvent::Conference(result),
+            EngineEvent::GameState,
+            EngineEvent::BoardTokens,
+        ] = &events[..]
+        else {
+            panic!("the pushed theory should be told first");
+        };
+        assert_eq!(result.stage, GameStage::MeetingPublish);
+        assert_eq!(result.transitions.len(), 1);
+        let transition = &result.transitions[0];
+        assert_eq!((transition.sector_index, transition.from), (5, 3));
+        assert_eq!((transition.to, transition.revealed.clone()), (2, None));
+        assert!(result.verdicts.is_empty());
+    }
+
+    #[test]
+    fn test_meeting_check_verdicts() {
+        let (mut gs, mut ss) = ready_room();
+        start_game(
+            &mut gs,
+            &mut ss,
+            Duration::ZERO,
+            &ClueWeights::default(),
+            Instant::now(),
+        );
+        let token = &mut ss.user_tokens.get_mut("u1").unwrap()[0];
+        token.set_to_be_placed().set_published(1).unwrap();
+        for _ in 0..3 {
+            token.push_at_meeting(&[]);
+        }
+        let claimed = token.r#type.clone();
+        gs.game_stage = GameStage::MeetingCheck;
+
+        let events = meeting_check(&mut gs, &mut ss);
+        let Some(EngineEvent::Conference(result)) = events.first() else {
+            panic!("the checked theory should be told first");
+        };
+        let [verdict] = &result.verdicts[..] else {
+            panic!("one theory was due");
+        };
+        assert_eq!((verdict.user_id.as_str(), verdict.sector_index), ("u1", 1));
+        assert_eq!(verdict.claimed, claimed);
+        assert_eq!(verdict.penalty_steps, if verdict.right { 0 } else { 1 });
+        assert_eq!(result.transitions[0].revealed, Some(claimed));
+        let Some(EngineEvent::Penalties(penalties)) = events.get(1) else {
+            panic!("the penalties follow the conference");
+        };
+        assert_eq!(penalties.len(), if verdict.right { 0 } else { 1 });
+    }
+}
@@ -0,0 +1,144 @@
+use std::borrow::Borrow;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use crate::server_state::User;
+
+macro_rules! string_id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        // the maps keyed by the id are looked up with a plain `&str`
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+string_id!(
+    /// id of a room, the digits the players share to join it.
+    RoomId
+);
+
+string_id!(
+    /// id of a player, or of the bot seat of a room.
+    UserId
+);
+
+/// who plays a seat of a room, the one place that knows how bot ids are made.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Seat {
+    Human(UserId),
+    Bot(UserId),
+}
+
+impl Seat {
+    /// the bot seat of a room, one per room.
+    pub fn bot(room_id: &str) -> Self {
+        Seat::Bot(UserId(format!("bot-{room_id}")))
+    }
+
+    /// a seat of the room, as the room state flags it.
+    pub fn of(id: &str, is_bot: bool) -> Self {
+        if is_bot {
+            Seat::Bot(id.into())
+        } else {
+            Seat::Human(id.into())
+        }
+    }
+
+    pub fn id(&self) -> &UserId {
+        match self {
+            Seat::Human(id) | Seat::Bot(id) => id,
+        }
+    }
+
+    pub fn is_bot(&self) -> bool {
+        matches!(self, Seat::Bot(_))
+    }
+
+    /// the user acting for the seat, the server plays the bot seats as such a user.
+    pub fn user(&self, name: &str) -> User {
+        User {
+            id: self.id().to_string(),
+            name: name.to_string(),
+            service: false,
+            client_version: None,
+            capabilities: Default::default(),
+            appearance: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        let room_id = RoomId::from("1234");
+        assert_eq!(serde_json::to_string(&room_id).unwrap(), r#""1234""#);
+        let back: RoomId = serde_json::from_str(r#""1234""#).unwrap();
+        assert_eq!(back, room_id);
+
+        let rooms = std::collections::HashMap::from([(room_id.clone(), 1)]);
+        assert_eq!(rooms.get("1234"), Some(&1));
+
+        let bot = Seat::bot(&room_id);
+        assert!(bot.is_bot());
+        assert_eq!(bot, Seat::of(bot.id(), true));
+        assert_eq!(bot.user("protocol").id, bot.id().as_str());
+        assert!(!Seat::of("u1", false).is_bot());
+    }
+}
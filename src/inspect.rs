@@ -0,0 +1,150 @@
+//! what a seed deals, for triaging the reports of broken clues offline:
+//! `cargo run --bin inspect-seed -- <seed> [standard|expert] [--json] [--config <path>]`.
+
+use std::collections::HashSet;
+
+use rand::{SeedableRng, rngs::SmallRng};
+use serde::Serialize;
+
+use crate::{
+    map::{ChoiceFilter, Clue, ClueGenerator, ClueWeights, Map, MapType, Sector, SectorType},
+    operation::{Operation, OperationResult, ResearchOperation},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SeedReport {
+    pub seed: u64,
+    pub map_type: MapType,
+    pub sectors: Vec<Sector>,
+    pub clues: Vec<ClueReport>, // the research clues, then the x clues
+    pub solver: SolverStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClueReport {
+    pub clue: Clue,
+    /// share of the possible maps the clue alone leaves, 1 for a clue that tells nothing.
+    pub difficulty: f64,
+}
+
+/// the possible maps of the map type, narrowed by the clues of the seed.
+#[derive(Debug, Clone, Serialize)]
+pub struct SolverStats {
+    pub maps: usize,
+    pub after_research: usize, // left by the research clues
+    pub after_all: usize,      // left by every clue, the true map among them
+    pub x_candidates: usize,   // sectors x can still be in after every clue
+}
+
+/// deal the map and the clues of the seed as a game does, and run them through the solver.
+pub fn inspect_seed(
+    seed: u64,
+    map_type: MapType,
+    clue_weights: &ClueWeights,
+) -> anyhow::Result<SeedReport> {
+    let map = Map::new(SmallRng::seed_from_u64(seed), map_type.clone())?;
+    let (research_clues, x_clues) = ClueGenerator::new(seed, map.sectors.clone(), map_type.clone())
+        .with_weights(clue_weights.clone())
+        .generate_clues()?;
+    let research = research_clues.len();
+    let clues = research_clues
+        .into_iter()
+        .chain(x_clues)
+        .collect::<Vec<_>>();
+    let ops = clues
+        .iter()
+        .map(|clue| {
+            let op = Operation::Research(ResearchOperation {
+                index: clue.index.clone(),
+            });
+            (op, OperationResult::Research(clue.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    let mut maps = 0;
+    let mut admitted = vec![0; clues.len()];
+    let (mut after_research, mut after_all) = (0, 0);
+    let mut x_candidates = HashSet::new();
+    for sectors in ChoiceFilter::enumerate(&map_type) {
+        maps += 1;
+        let passes = ops
+            .iter()
+            .map(|(op, opr)| ChoiceFilter::filter_op(&sectors, op, opr))
+            .collect::<Vec<_>>();
+        for (count, pass) in admitted.iter_mut().zip(&passes) {
+            *count += *pass as usize;
+        }
+        if passes[..research].iter().all(|p| *p) {
+            after_research += 1;
+            if passes[research..].iter().all(|p| *p) {
+                after_all += 1;
+                x_candidates.extend(
+                    sectors
+                        .data
+                        .iter()
+                        .filter(|s| s.r#type == SectorType::X)
+                        .map(|s| s.index),
+                );
+            }
+        }
+    }
+
+    let clues = clues
+        .into_iter()
+        .zip(admitted)
+        .map(|(clue, count)| ClueReport {
+            clue,
+            difficulty: count as f64 / maps.max(1) as f64,
+        })
+        .collect();
+    Ok(SeedReport {
+        seed,
+        map_type,
+        sectors: map.sectors.data,
+        clues,
+        solver: SolverStats {
+            maps,
+            after_research,
+            after_all,
+            x_candidates: x_candidates.len(),
+        },
+    })
+}
+
+impl std::fmt::Display for SeedReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "seed {} ({:?})", self.seed, self.map_type)?;
+        for sector in self.sectors.iter() {
+            writeln!(f, "  {sector}")?;
+        }
+        writeln!(f, "clues:")?;
+        for report in self.clues.iter() {
+            let clue = &report.clue;
+            writeln!(f, "  {:?} {clue} ({:.3})", clue.index, report.difficulty)?;
+        }
+        let solver = &self.solver;
+        writeln!(
+            f,
+            "solver: {} maps, {} after the research clues, {} after every clue, x in {} sector(s)",
+            solver.maps, solver.after_research, solver.after_all, solver.x_candidates
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_inspect_seed() {
+        let report = inspect_seed(42, MapType::Standard, &ClueWeights::default()).unwrap();
+        assert_eq!(report.sectors.len(), MapType::Standard.sector_count());
+        assert!(report.clues.iter().all(|c| c.difficulty > 0.0));
+        let solver = &report.solver;
+        // the true map always passes its own clues
+        assert!(solver.after_all >= 1 && solver.x_candidates >= 1);
+        assert!(solver.after_all <= solver.after_research && solver.after_research <= solver.maps);
+        assert!(report.to_string().starts_with("seed 42"));
+    }
+}
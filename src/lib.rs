@@ -0,0 +1,194 @@
+#[cfg(feature = "admin")]
+mod admin;
+mod analytics;
+mod api;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod broadcast;
+mod config;
+#[cfg(feature = "integrations")]
+mod control;
+mod engine;
+mod ids;
+mod inspect;
+mod map;
+mod operation;
+mod ratelimit;
+mod recommendation;
+mod redact;
+mod room;
+mod scoring;
+mod self_test;
+mod server_handler;
+mod server_state;
+mod simulation;
+mod sink;
+mod storage;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod ws;
+
+use salvo::{Listener, Router, Server, conn::TcpListener, handler, prelude::TowerLayerCompat};
+use server_handler::{handle_on_connect, register_state_manager};
+use server_state::{Namespaces, StateRef};
+use socketioxide::{SocketIo, extract::SocketRef};
+
+pub use inspect::{ClueReport, SeedReport, SolverStats, inspect_seed};
+pub use map::{ClueWeights, ConnectionWeights};
+pub use self_test::self_test;
+pub use simulation::{SimulationConfig, SimulationReport, simulate_games};
+
+/// the wire types of the `/xplanet` namespace, shared with the client crate.
+///
+/// scripted clients (third party bots) authenticate with `User::service` set, then
+/// join and play like any browser client. they read their seat with one `catch_up`
+/// request instead of the `sync` event stream, and are answered `ServerResp::RateLimited`
+/// once they send requests faster than `service_ops_per_sec` allows.
+///
+/// a room waiting for its game is sent as `room_lobby_state`, the seats and the settings only,
+/// `game_state` is sent once the game started. a dealt game carries its `Solvability`, its
+/// difficulty badge is shown in the lobby list and kept with the finished game.
+///
+/// a player can send `bug_report` for their room, the server bundles what it takes to replay the
+/// room and answers the id of the bundle for the admins.
+///
+/// the `puzzle` room operation opens a solo room at a decision of a seat of a finished game the
+/// player played, the server plays the other seats and keeps no results of it.
+///
+/// the host of a running room can ask for its `save_code`, a signed code of the seed and the
+/// moves played. the `resume` room operation deals the game again from it and plays it back, on
+/// any server sharing the save code secret. a server resumes a code once and for a week after
+/// it was issued, competitive rooms have no save code.
+///
+/// the `preferences` event reads or replaces the small settings the server keeps for a user,
+/// so they follow the player to another device. a host keeps named room presets there, the
+/// `from_preset` room operation creates a room with the map, rules and bot of one.
+///
+/// the server may serve more namespaces next to `/xplanet`, e.g. `/xplanet-beta`, each with its
+/// own rooms and players, see `namespaces` in the config.
+///
+/// clients without socket.io connect to `/ws` and exchange the same events as json `Frame`s,
+/// spectators can follow a room over server-sent events at `/sse/{room_id}`, a running game starts
+/// with a `spectator_summary` of the public events they missed.
+///
+/// players of a room talk over the `chat` event, the host can mute a player of the room and
+/// anyone can block a user, whose messages they stop receiving. the `reaction` event relays an
+/// `Emote` to the whole room, a few per player and second.
+///
+/// an `op` may be a `batch` of a few moves along the track, played in order as one turn and
+/// answered with the result of each. every step is checked before any is played, a step refused
+/// plays none of them. a locate can only be the last step.
+///
+/// a waiting player may `pass`: in the user move stage it costs as much time as a research, in a
+/// meeting it proposes or publishes nothing more, for the last move it gives the move up.
+///
+/// the server times each move of a player from its `your_turn` to the `op`, the time is kept in
+/// the journal of the game and its export, and shown as `last_think_ms` of the player in
+/// `game_state`. the clients' own timers are never read.
+///
+/// `game_state` carries the `board_checksum` of the public board, the one the client holds from
+/// `game_state` and `board_tokens`, see `public_board` for how it is hashed. a client whose
+/// board hashes otherwise missed an event and sends `sync`.
+///
+/// a client advertising `Capabilities::SYNC_BUNDLE` is answered one `sync_bundle` to its `sync`
+/// in a running game, the clues, the state, its results, tokens and the board at once, instead
+/// of the events one by one.
+///
+/// an `op` or `room` payload the server can't read is answered `ServerResp::BadRequest` with
+/// the event and the parser's reason, over `/ws` every event is.
+///
+/// the events added since are only sent to the clients advertising them in
+/// `User::capabilities`, older clients keep receiving the events they know.
+pub mod protocol {
+    pub use crate::ids::{RoomId, Seat, UserId};
+    pub use crate::map::{
+        Clue, ClueConnection, ClueEnum, ClueSecret, MapType, SecretToken, Sector, SectorType,
+        TheoryState, Token,
+    };
+    pub use crate::operation::{
+        DoPublishOperation, LocateMiss, LocateOperation, Operation, OperationKind, OperationResult,
+        PassResult, ReadyPublishOperation, ResearchOperation, SurveyOperatoin, TargetOperation,
+    };
+    pub use crate::recommendation::{RecommendOperation, RecommendOperationResult};
+    pub use crate::room::{
+        AbortBallot, AbortVote, Announcement, AnnouncementKind, Appearance, BotFlair, BotPersona,
+        BotPersonaInfo, BugReportFiled, BugReportRequest, CatchUp, ChatError, ChatMessage,
+        ChatOperation, ChatSend, ChatTarget, ConferenceResult, DifficultyBadge, EditRoomInfo,
+        Emote, FailedLocatePolicy, GameStage, GameState, GameStateResp, Handicap, HandicapInfo,
+        IdleKick, IdleKickAction, LegalActions, LobbyPage, LobbyPlayer, LobbyQuery, LobbyRoom,
+        LobbySort, LobbyStatus, Maintenance, Notifications, OpError, PacingSummary, Penalty,
+        PenaltyReason, PlayerPacing, Preferences, PreferencesOperation, PuzzleInfo, PuzzleOrigin,
+        Reaction, ReactionSend, RecommendError, RoomError, RoomLobbyState, RoomPreset, RoomRules,
+        RoomUserOperation, SaveCodeIssued, SaveCodeRequest, ServerResp, Solvability,
+        SpectatorSummary, SyncBundle, TheoryVerdict, TokenTransition, TurnDeadline, TurnTimeout,
+        UserLocationSequence, UserResultSummary, UserState, YourTurn,
+    };
+    pub use crate::scoring::{ProjectedScore, XBonusCurve};
+    pub use crate::server_state::User;
+    pub use crate::sink::{Capabilities, Frame};
+}
+
+#[handler]
+async fn hello() -> &'static str {
+    "Hello Salvo!"
+}
+
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::ServerConfig::load();
+    redact::set_log_secrets(config.log_secrets);
+    if config.warm_up {
+        let started = std::time::Instant::now();
+        let seen = tokio::task::spawn_blocking(map::ChoiceFilter::warm_up).await?;
+        tracing::info!(?seen, "warm up done in {:?}", started.elapsed());
+    }
+    let (layer, io) = SocketIo::builder().build_layer();
+
+    let layer = tower::ServiceBuilder::new()
+        .layer(tower_http::cors::CorsLayer::permissive())
+        .layer(layer);
+
+    // every namespace plays on its own state, stored apart in the same storage
+    let storage = storage::connect(&config.storage).await?;
+    let mut states = vec![];
+    for namespace in config.namespaces() {
+        let state =
+            server_state::create_state(config.clone(), &namespace, storage.as_ref()).await?;
+        let connect_state = state.clone();
+        io.ns(namespace.clone(), move |io: SocketIo, socket: SocketRef| {
+            handle_on_connect(io, socket, connect_state.clone())
+        });
+        register_state_manager(state.clone(), io.clone());
+        #[cfg(unix)]
+        server_handler::register_reload_on_hangup(state.clone());
+        tracing::info!("serving namespace {namespace}");
+        states.push((namespace, state));
+    }
+    let namespaces = Namespaces(states.iter().map(|(_, state)| state.clone()).collect());
+
+    let layer = layer.compat();
+    let mut router = Router::new().push(Router::with_path("/socket.io").hoop(layer).goal(hello));
+    // the http routes of a namespace are under its name, e.g. `/xplanet-beta/admin`, the first
+    // namespace serves them at the root too
+    for (index, (namespace, state)) in states.into_iter().enumerate() {
+        if index == 0 {
+            router = router.push(http_router(state.clone(), &namespaces, &io));
+        }
+        let name = namespace.trim_start_matches('/');
+        router = router.push(Router::with_path(name).push(http_router(state, &namespaces, &io)));
+    }
+    let acceptor = TcpListener::new("127.0.0.1:17878").bind().await;
+    Server::new(acceptor).serve(router).await;
+
+    Ok(())
+}
+
+/// the admin, api and websocket routes playing on the state of a namespace.
+fn http_router(state: StateRef, namespaces: &Namespaces, io: &SocketIo) -> Router {
+    let router = Router::new();
+    #[cfg(feature = "admin")]
+    let router = router.push(admin::router(state.clone(), namespaces.clone(), io.clone()));
+    router
+        .push(ws::router(state.clone(), io.clone()))
+        .push(api::router(state, namespaces.clone(), io.clone()))
+}
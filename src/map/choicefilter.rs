@@ -1,14 +1,19 @@
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 use itertools::Itertools;
 use tracing::info;
 
+use crate::ids::Seat;
 use crate::operation::{
     LocateOperation, Operation, OperationResult, ResearchOperation, SurveyOperatoin,
     TargetOperation,
 };
 
-use super::{Clue, ClueConnection, MapType, SectorType, Sectors, Token, enumerator::MapEnumerator};
+use super::{
+    Clue, ClueConnection, MapType, SectorType, Sectors, TheoryState, Token,
+    enumerator::MapEnumerator,
+};
 
 static MAX_CACHED_COUNT: usize = 100000;
 static MAX_CACHED_COUNT_FOR_BOT: usize = 500000;
@@ -16,22 +21,50 @@ static MAX_CACHED_COUNT_FOR_BOT: usize = 500000;
 #[derive(Debug, Clone)]
 pub struct ChoiceFilter {
     map_type: MapType,
-    id: String,
+    seat: Seat, // a bot seat caches more maps
     pub all: Vec<Sectors>,
     ops: Vec<(Operation, OperationResult)>,
     tokens: Vec<Token>,
     pub initialized: bool,
+    stats: Option<SectorStats>, // streamed from the last pass when the maps are too many to cache
+    survey_cache: SurveyCache,
+}
+
+type SurveyKey = (usize, usize, SectorType); // start, end, type
+type SurveyDistribution = HashMap<usize, usize>; // survey result -> maps
+
+/// survey count distributions of the possible maps, valid until `all` changes.
+#[derive(Debug, Default)]
+struct SurveyCache(Mutex<HashMap<SurveyKey, SurveyDistribution>>);
+
+impl Clone for SurveyCache {
+    // a copy of the filter is mutated on its own, start it empty
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl SurveyCache {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SurveyKey, SurveyDistribution>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn clear(&self) {
+        self.lock().clear();
+    }
 }
 
 impl ChoiceFilter {
-    pub fn new(map_type: MapType, id: String) -> Self {
+    pub fn new(map_type: MapType, seat: Seat) -> Self {
         Self {
             map_type,
-            id,
+            seat,
             all: vec![],
             ops: vec![],
             tokens: vec![],
             initialized: false,
+            stats: None,
+            survey_cache: SurveyCache::default(),
         }
         // if !id.starts_with("bot-") {
         // } else {
@@ -48,16 +81,107 @@ impl ChoiceFilter {
         // }
     }
 
-    fn is_bot(&self) -> bool {
-        self.id.starts_with("bot-")
+    /// build the enumerator tables and run one full enumeration of each map type through
+    /// the filter, so the first game does not pay for it. returns the maps seen per type.
+    pub fn warm_up() -> Vec<(MapType, usize)> {
+        [MapType::Standard, MapType::Expert]
+            .into_iter()
+            .map(|map_type| {
+                let survey = Operation::Survey(SurveyOperatoin {
+                    sector_type: SectorType::Comet,
+                    start: 1,
+                    end: map_type.sector_count() / 2,
+                });
+                let seen = Self::enumerate(&map_type)
+                    .filter(|ss| Self::filter_op(ss, &survey, &OperationResult::Survey(1)))
+                    .count();
+                (map_type, seen)
+            })
+            .collect()
+    }
+
+    /// every map of the type, unfiltered.
+    pub fn enumerate(map_type: &MapType) -> impl Iterator<Item = Sectors> {
+        MapEnumerator::shared().gen_sec(map_type)
+    }
+
+    /// drop the cached possibilities, keeping the ops and tokens to rebuild them later.
+    pub fn compact(&self, tokens: &[Token]) -> Self {
+        Self {
+            map_type: self.map_type.clone(),
+            seat: self.seat.clone(),
+            all: vec![],
+            ops: self.ops.clone(),
+            tokens: tokens.to_vec(),
+            initialized: false,
+            stats: None,
+            survey_cache: SurveyCache::default(),
+        }
+    }
+
+    /// replay a compacted filter, the result is the same as the filter it came from.
+    pub fn rebuild(&self) -> Self {
+        Self::replay(
+            self.map_type.clone(),
+            self.seat.clone(),
+            &self.tokens,
+            self.ops.clone(),
+        )
+    }
+
+    /// the filter of a player who placed `tokens` and made `ops`.
+    pub fn replay(
+        map_type: MapType,
+        seat: Seat,
+        tokens: &[Token],
+        ops: Vec<(Operation, OperationResult)>,
+    ) -> Self {
+        let mut filter = Self::new(map_type, seat);
+        filter.update_tokens(tokens);
+        for (op, result) in ops {
+            filter.add_operation(op, result);
+        }
+        filter
+    }
+
+    pub fn operations(&self) -> &[(Operation, OperationResult)] {
+        &self.ops
+    }
+
+    /// whether `map` is still one of the candidates, false means the filter and the rules disagree.
+    pub fn admits(&self, map: &Sectors) -> bool {
+        let same = |ss: &Sectors| {
+            ss.data
+                .iter()
+                .map(|s| &s.r#type)
+                .eq(map.data.iter().map(|s| &s.r#type))
+        };
+        if self.initialized {
+            return self.all.iter().any(same);
+        }
+        self.ops
+            .iter()
+            .all(|(op, opr)| Self::filter_op(map, op, opr))
+            && self.tokens.iter().all(|t| Self::filter_token(map, t))
     }
 
     pub fn len(&self) -> usize {
         self.all.len()
     }
 
+    /// maps still possible, from the cache or the last streamed pass. None before any pass.
+    pub fn count(&self) -> Option<usize> {
+        if self.initialized {
+            return Some(self.all.len());
+        }
+        self.stats.as_ref().map(|s| s.total)
+    }
+
     pub fn all_possibilities(&self) -> AllSectorPossibilities {
-        AllSectorPossibilities::from(self.all.clone())
+        match &self.stats {
+            Some(stats) if !self.initialized => AllSectorPossibilities::from(stats),
+            _ => AllSectorPossibilities::from(self.all.clone()),
+        }
     }
 
     pub fn update_tokens(&mut self, token: &[Token]) {
@@ -65,17 +189,22 @@ impl ChoiceFilter {
         if !self.initialized {
             // cached tokens if not enough operations to start filtering
             self.tokens = token.to_vec();
+            // the streamed stats do not know the new tokens, wait for the next pass
+            self.stats = None;
             return;
         }
         self.all
             .retain(|ss| token.iter().all(|t| Self::filter_token(ss, t)));
+        self.survey_cache.clear();
     }
 
     pub fn add_operation(&mut self, op: Operation, result: OperationResult) {
+        self.survey_cache.clear();
         // not initialized
         if !self.initialized {
             self.ops.push((op, result));
-            if matches!(self.map_type, MapType::Expert) && self.ops.len() < 3 && !self.is_bot() {
+            if matches!(self.map_type, MapType::Expert) && self.ops.len() < 3 && !self.seat.is_bot()
+            {
                 // expert map, no need to filter
                 return;
             }
@@ -83,7 +212,7 @@ impl ChoiceFilter {
             //     return;
             // }
             // at least 2 operations
-            let m = MapEnumerator::new();
+            let m = MapEnumerator::shared();
             let iter = || {
                 m.gen_sec(&self.map_type).filter(|ss| {
                     self.ops
@@ -92,9 +221,12 @@ impl ChoiceFilter {
                         && self.tokens.iter().all(|t| Self::filter_token(ss, t))
                 })
             };
-            let cnt = iter().count();
-            if cnt
-                <= if self.is_bot() {
+            let stats = iter().fold(SectorStats::default(), |mut stats, ss| {
+                stats.add(&ss);
+                stats
+            });
+            if stats.total
+                <= if self.seat.is_bot() {
                     MAX_CACHED_COUNT_FOR_BOT
                 } else {
                     MAX_CACHED_COUNT
@@ -102,12 +234,15 @@ impl ChoiceFilter {
             {
                 self.all = iter().collect();
                 self.initialized = true;
+                self.stats = None;
+            } else {
+                self.stats = Some(stats);
             }
         } else {
             self.all.retain(|ss| Self::filter_op(ss, &op, &result));
             self.ops.push((op, result));
         }
-        info!("{}: choices: {}", self.id, self.all.len());
+        info!("{}: choices: {}", self.seat.id(), self.all.len());
     }
 
     fn filter_token(ss: &Sectors, token: &Token) -> bool {
@@ -117,14 +252,14 @@ impl ChoiceFilter {
         if token.secret.r#type.is_none() {
             return true;
         }
-        if token.secret.meeting_index == 4 {
+        if token.secret.state == TheoryState::Disproved {
             ss.data[token.secret.sector_index - 1].r#type != token.r#type
         } else {
             ss.data[token.secret.sector_index - 1].r#type == token.r#type
         }
     }
 
-    fn filter_op(ss: &Sectors, op: &Operation, opr: &OperationResult) -> bool {
+    pub(crate) fn filter_op(ss: &Sectors, op: &Operation, opr: &OperationResult) -> bool {
         match (op, opr) {
             (
                 Operation::Survey(SurveyOperatoin {
@@ -135,13 +270,7 @@ impl ChoiceFilter {
                 OperationResult::Survey(cnt),
             ) => ss.get_range_type_cnt(*start, *end, sector_type) == *cnt,
             (Operation::Target(TargetOperation { index }), OperationResult::Target(r#type)) => {
-                match r#type {
-                    SectorType::Space => {
-                        ss.data[*index - 1].r#type == SectorType::Space
-                            || ss.data[*index - 1].r#type == SectorType::X
-                    }
-                    _ => ss.data[*index - 1].r#type == *r#type,
-                }
+                ss.data[*index - 1].r#type.observed() == *r#type
             }
             (Operation::Research(_), OperationResult::Research(clue)) => match clue.conn {
                 ClueConnection::AllAdjacent => {
@@ -207,6 +336,25 @@ impl ChoiceFilter {
                     true
                 }
             }
+            (
+                Operation::Locate(LocateOperation {
+                    index,
+                    pre_sector_type,
+                    next_sector_type,
+                }),
+                OperationResult::LocateMiss(miss),
+            ) => {
+                let x_there = ss.data[*index - 1].r#type == SectorType::X;
+                match miss.adjacent_wrong {
+                    Some(true) => {
+                        x_there
+                            && (ss.prev(*index).r#type != *pre_sector_type
+                                || ss.next(*index).r#type != *next_sector_type)
+                    }
+                    Some(false) => !x_there,
+                    None => true,
+                }
+            }
             (Operation::ReadyPublish(_), OperationResult::ReadyPublish(_)) => true,
             (Operation::DoPublish(_), OperationResult::DoPublish(_)) => true,
             _ => true,
@@ -215,29 +363,10 @@ impl ChoiceFilter {
 
     pub fn can_locate(&self) -> bool {
         // can locate if all the possibilities of x are in the same sector and the adjacent sectors is only one type
-        self.initialized
-            && self
-                .all
-                .iter()
-                .map(|s| {
-                    // first find only x sectors
-                    let x_index = s
-                        .data
-                        .iter()
-                        .filter(|x| x.r#type == SectorType::X)
-                        .map(|x| x.index)
-                        .next()
-                        .unwrap();
-                    // then find the adjacent sectors
-                    let mut adjacent = vec![];
-                    for i in 1..=s.data.len() {
-                        if s.prev(i).r#type == SectorType::X || s.next(i).r#type == SectorType::X {
-                            adjacent.push(s.data[i - 1].r#type.clone());
-                        }
-                    }
-                    (adjacent, x_index)
-                })
-                .all_equal()
+        if !self.initialized {
+            return self.stats.as_ref().is_some_and(|s| s.can_locate());
+        }
+        self.all.iter().map(x_surroundings).all_equal()
     }
 
     // try to locate the x sector
@@ -285,29 +414,33 @@ impl ChoiceFilter {
     }
 
     pub fn effect_survey(&self, survey: &SurveyOperatoin) -> f64 {
-        // if not initialized, return 0
+        // if not initialized, use the distribution streamed from the last pass
         if !self.initialized {
-            return 0.0;
+            return self
+                .stats
+                .as_ref()
+                .and_then(|stats| Some((stats.survey_distribution(survey)?, stats.total)))
+                .map_or(0.0, |(cnt, total)| survey_effect(&cnt, total));
         }
         // get all possible result of the survey, that is the number of surver.type between start and end
         // for example, current 1000 possibilities, 200 of them are count = 2, 300 of them are count = 3, 500 of them are count = 1.
         // the effect of the survey is 0.2 * 0.2 + 0.3 * 0.3 + 0.5 * 0.5 = 0.38
-        let mut cnt = HashMap::new();
-        for s in self.all.iter() {
-            let count = s.get_range_type_cnt(survey.start, survey.end, &survey.sector_type);
-            *cnt.entry(count).or_insert(0) += 1;
-        }
-        let total = self.all.len() as f64;
-        let mut res = 0.0;
-        for (_count, v) in cnt.iter() {
-            let rate = *v as f64 / total;
-            res += rate * rate;
-        }
-        1.0 - res
+        let mut cache = self.survey_cache.lock();
+        let cnt = cache
+            .entry((survey.start, survey.end, survey.sector_type.clone()))
+            .or_insert_with(|| {
+                let mut cnt = HashMap::new();
+                for s in self.all.iter() {
+                    let count = s.get_range_type_cnt(survey.start, survey.end, &survey.sector_type);
+                    *cnt.entry(count).or_insert(0) += 1;
+                }
+                cnt
+            });
+        survey_effect(cnt, self.all.len())
     }
 
     pub fn effect_target(&self, index: usize) -> f64 {
-        if !self.initialized {
+        if self.count().is_none() {
             return 0.0;
         }
 
@@ -315,12 +448,7 @@ impl ChoiceFilter {
 
         let mut sec_rates = HashMap::<SectorType, f64>::new();
         for p in all_possibilities.0[index - 1].possibilities.iter() {
-            match &p.sector_type {
-                SectorType::X | SectorType::Space => {
-                    *sec_rates.entry(SectorType::Space).or_insert(0.0) += p.rate
-                }
-                rest => *sec_rates.entry(rest.clone()).or_insert(0.0) += p.rate,
-            }
+            *sec_rates.entry(p.sector_type.observed()).or_insert(0.0) += p.rate;
         }
         let mut res = 0.0;
         for (_k, v) in sec_rates.iter() {
@@ -351,6 +479,130 @@ impl ChoiceFilter {
     }
 }
 
+/// 1 - the chance two of the maps give the same survey result.
+fn survey_effect(cnt: &SurveyDistribution, total: usize) -> f64 {
+    let mut res = 0.0;
+    for (_count, v) in cnt.iter() {
+        let rate = *v as f64 / total as f64;
+        res += rate * rate;
+    }
+    1.0 - res
+}
+
+/// the x index and the types next to it, equal for every map iff x can be located.
+fn x_surroundings(s: &Sectors) -> (Vec<SectorType>, usize) {
+    // first find only x sectors
+    let x_index = s
+        .data
+        .iter()
+        .filter(|x| x.r#type == SectorType::X)
+        .map(|x| x.index)
+        .next()
+        .unwrap();
+    // then find the adjacent sectors
+    let mut adjacent = vec![];
+    for i in 1..=s.data.len() {
+        if s.prev(i).r#type == SectorType::X || s.next(i).r#type == SectorType::X {
+            adjacent.push(s.data[i - 1].r#type.clone());
+        }
+    }
+    (adjacent, x_index)
+}
+
+/// per sector type counts over the possible maps, accumulated during the enumeration pass
+/// so the common recommendations work without caching the maps.
+#[derive(Debug, Clone, Default)]
+pub struct SectorStats {
+    pub total: usize,
+    counts: Vec<HashMap<SectorType, usize>>, // 0-based sector index -> type -> maps
+    x_surroundings: Option<(Vec<SectorType>, usize)>, // of the first map
+    x_mixed: bool,                           // some map disagrees with the first one
+    surveys: Vec<usize>, // flat (start, span, type, result) -> maps, see `survey_slot`
+}
+
+/// the types a survey can count, as observed.
+const SURVEY_TYPES: [SectorType; 5] = [
+    SectorType::Comet,
+    SectorType::Asteroid,
+    SectorType::DwarfPlanet,
+    SectorType::Nebula,
+    SectorType::Space,
+];
+
+impl SectorStats {
+    pub fn add(&mut self, ss: &Sectors) {
+        if self.counts.is_empty() {
+            self.counts = vec![HashMap::new(); ss.data.len()];
+            self.surveys = vec![0; Self::survey_slot(ss.data.len(), ss.data.len(), 0, 0, 0)];
+        }
+        self.add_surveys(ss);
+        for (i, s) in ss.data.iter().enumerate() {
+            *self.counts[i].entry(s.r#type.clone()).or_insert(0) += 1;
+        }
+        if !self.x_mixed {
+            let surroundings = x_surroundings(ss);
+            match &self.x_surroundings {
+                Some(first) => self.x_mixed = *first != surroundings,
+                None => self.x_surroundings = Some(surroundings),
+            }
+        }
+        self.total += 1;
+    }
+
+    pub fn can_locate(&self) -> bool {
+        self.total > 0 && !self.x_mixed
+    }
+
+    /// index of the maps counting `result` sectors of the type in the range from the 0-based
+    /// `start`, `span` sectors past it. a survey spans at most half the sky.
+    fn survey_slot(n: usize, start: usize, span: usize, r#type: usize, result: usize) -> usize {
+        let half = n / 2;
+        ((start * half + span) * SURVEY_TYPES.len() + r#type) * (half + 1) + result
+    }
+
+    /// count the result of every survey on the map, widening the range from each start.
+    fn add_surveys(&mut self, ss: &Sectors) {
+        let n = ss.data.len();
+        let types = ss
+            .data
+            .iter()
+            .map(|s| SURVEY_TYPES.iter().position(|t| *t == s.r#type.observed()))
+            .collect::<Vec<_>>();
+        for start in 0..n {
+            let mut found = [0; SURVEY_TYPES.len()];
+            for span in 0..n / 2 {
+                if let Some(t) = types[(start + span) % n] {
+                    found[t] += 1;
+                }
+                for (t, result) in found.iter().enumerate() {
+                    self.surveys[Self::survey_slot(n, start, span, t, *result)] += 1;
+                }
+            }
+        }
+    }
+
+    /// the survey results over the maps, None for a survey wider than half the sky.
+    fn survey_distribution(&self, survey: &SurveyOperatoin) -> Option<SurveyDistribution> {
+        let n = self.counts.len();
+        let r#type = SURVEY_TYPES.iter().position(|t| *t == survey.sector_type)?;
+        if survey.start == 0 || survey.start > n || survey.end == 0 || survey.end > n {
+            return None;
+        }
+        let span = (survey.end + n - survey.start) % n;
+        if span >= n / 2 {
+            return None;
+        }
+        let distribution = (0..=n / 2)
+            .map(|result| {
+                let slot = Self::survey_slot(n, survey.start - 1, span, r#type, result);
+                (result, self.surveys[slot])
+            })
+            .filter(|(_, maps)| *maps > 0)
+            .collect();
+        Some(distribution)
+    }
+}
+
 #[derive(Debug)]
 pub struct SectorPossibility {
     pub sector_type: SectorType,
@@ -399,6 +651,31 @@ impl From<Vec<Sectors>> for AllSectorPossibilities {
     }
 }
 
+impl From<&SectorStats> for AllSectorPossibilities {
+    fn from(stats: &SectorStats) -> Self {
+        let res = stats
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, rates)| {
+                let mut possibilities = rates
+                    .iter()
+                    .map(|(k, v)| SectorPossibility {
+                        sector_type: k.clone(),
+                        rate: *v as f64 / stats.total as f64,
+                    })
+                    .collect::<Vec<SectorPossibility>>();
+                possibilities.sort_by(|a, b| b.rate.partial_cmp(&a.rate).unwrap());
+                SectorPossibilities {
+                    index: i + 1,
+                    possibilities,
+                }
+            })
+            .collect();
+        Self(res)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -411,7 +688,7 @@ mod tests {
 
     #[test]
     fn test_filter2() {
-        let mut cf = ChoiceFilter::new(MapType::Expert, "test".to_string());
+        let mut cf = ChoiceFilter::new(MapType::Expert, Seat::of("test", false));
         macro_rules! survey {
             ($t:expr, $start:expr, $end:expr, $cnt:expr) => {
                 cf.add_operation(
@@ -481,7 +758,7 @@ mod tests {
                     user_id: "xxx".to_owned(),
                     user_index: 1,
                     sector_index: 17,
-                    meeting_index: 3,
+                    state: TheoryState::Verified { steps_left: 3 },
                     r#type: Some(SectorType::Asteroid),
                 },
                 r#type: SectorType::Asteroid,
@@ -492,7 +769,7 @@ mod tests {
                     user_id: "xxx".to_owned(),
                     user_index: 1,
                     sector_index: 11,
-                    meeting_index: 4,
+                    state: TheoryState::Disproved,
                     r#type: Some(SectorType::DwarfPlanet),
                 },
                 r#type: SectorType::DwarfPlanet,
@@ -521,4 +798,140 @@ mod tests {
         //     );
         // }
     }
+
+    #[test]
+    fn test_streamed_stats() {
+        let maps = ChoiceFilter::enumerate(&MapType::Standard)
+            .take(500)
+            .collect::<Vec<_>>();
+        let mut stats = SectorStats::default();
+        maps.iter().for_each(|ss| stats.add(ss));
+        assert_eq!(stats.total, 500);
+
+        let streamed = AllSectorPossibilities::from(&stats);
+        let cached = AllSectorPossibilities::from(maps.clone());
+        for (s, c) in streamed.0.iter().zip(cached.0.iter()) {
+            assert_eq!(s.index, c.index);
+            let rate = |p: &SectorPossibilities, t: &SectorType| {
+                p.possibilities
+                    .iter()
+                    .find(|x| x.sector_type == *t)
+                    .map_or(0.0, |x| x.rate)
+            };
+            for p in c.possibilities.iter() {
+                assert!((rate(s, &p.sector_type) - p.rate).abs() < 1e-9);
+            }
+        }
+        assert_eq!(
+            stats.can_locate(),
+            maps.iter().map(x_surroundings).all_equal()
+        );
+
+        let mut single = SectorStats::default();
+        single.add(&maps[0]);
+        assert!(single.can_locate());
+    }
+
+    #[test]
+    fn test_streamed_survey_effect() {
+        let maps = ChoiceFilter::enumerate(&MapType::Standard)
+            .take(2000)
+            .collect::<Vec<_>>();
+        let mut stats = SectorStats::default();
+        maps.iter().for_each(|ss| stats.add(ss));
+        // too many maps to cache, only the stats of the pass are kept
+        let streamed = ChoiceFilter {
+            stats: Some(stats),
+            ..ChoiceFilter::new(MapType::Standard, Seat::of("u1", false))
+        };
+        let cached = ChoiceFilter {
+            all: maps,
+            initialized: true,
+            ..ChoiceFilter::new(MapType::Standard, Seat::of("u1", false))
+        };
+        // `start == end` is the whole sky to `in_range`, a survey covers two sectors or more
+        for (start, span, sector_type) in (1..=12)
+            .cartesian_product(1..6)
+            .cartesian_product(SURVEY_TYPES)
+            .map(|((start, span), t)| (start, span, t))
+        {
+            let survey = SurveyOperatoin {
+                sector_type,
+                start,
+                end: (start + span - 1) % 12 + 1,
+            };
+            let effect = streamed.effect_survey(&survey);
+            assert!(
+                (effect - cached.effect_survey(&survey)).abs() < 1e-9,
+                "{survey:?}"
+            );
+        }
+        // wider than the window
+        let wide = SurveyOperatoin {
+            sector_type: SectorType::Comet,
+            start: 1,
+            end: 7,
+        };
+        assert_eq!(streamed.effect_survey(&wide), 0.0);
+    }
+
+    #[test]
+    fn test_survey_cache() {
+        let mut cf = ChoiceFilter::new(MapType::Standard, Seat::bot("test"));
+        cf.add_operation(
+            Operation::Survey(SurveyOperatoin {
+                sector_type: SectorType::Comet,
+                start: 1,
+                end: 6,
+            }),
+            OperationResult::Survey(1),
+        );
+        assert!(cf.initialized);
+        let survey = SurveyOperatoin {
+            sector_type: SectorType::Asteroid,
+            start: 2,
+            end: 7,
+        };
+        let effect = cf.effect_survey(&survey);
+        assert_eq!(cf.survey_cache.lock().len(), 1);
+        assert_eq!(cf.effect_survey(&survey), effect);
+        assert_eq!(cf.survey_cache.lock().len(), 1);
+
+        cf.add_operation(
+            Operation::Target(TargetOperation { index: 3 }),
+            OperationResult::Target(SectorType::Space),
+        );
+        assert!(cf.survey_cache.lock().is_empty());
+    }
+
+    #[test]
+    fn test_admits() {
+        use rand::{SeedableRng, rngs::SmallRng};
+
+        let map = crate::map::Map::new(SmallRng::seed_from_u64(7), MapType::Standard).unwrap();
+        let survey = |cnt| {
+            (
+                Operation::Survey(SurveyOperatoin {
+                    sector_type: SectorType::Asteroid,
+                    start: 1,
+                    end: 6,
+                }),
+                OperationResult::Survey(cnt),
+            )
+        };
+        let truth = map.survey_sector(1, 6, &SectorType::Asteroid);
+        let mut cf = ChoiceFilter::new(MapType::Standard, Seat::bot("1"));
+        let (op, result) = survey(truth);
+        cf.add_operation(op, result);
+        assert!(cf.initialized);
+        assert!(cf.admits(&map.sectors));
+
+        let lie = ChoiceFilter::replay(
+            MapType::Standard,
+            Seat::of("u1", false),
+            &[],
+            vec![survey(truth + 1)],
+        );
+        assert!(!lie.admits(&map.sectors));
+    }
 }
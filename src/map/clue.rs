@@ -1,6 +1,6 @@
 use core::panic;
 
-use rand::{Rng, SeedableRng, rngs::SmallRng};
+use rand::{Rng, SeedableRng, rngs::SmallRng, seq::SliceRandom};
 use serde::{Deserialize, Serialize};
 
 use super::{
@@ -8,7 +8,7 @@ use super::{
     model::{SectorType, Sectors},
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Clue {
     pub index: ClueEnum,
@@ -67,7 +67,7 @@ pub enum ClueEnum {
     X1,
     X2,
 }
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct ClueSecret {
     pub index: ClueEnum,
@@ -108,11 +108,125 @@ pub enum ClueConnection {
 
 // map Z5D6 is very special.
 
+/// how often each connection is drawn, for the research clues of each map type and the x clues.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct ClueWeights {
+    pub standard: ConnectionWeights,
+    pub expert: ConnectionWeights,
+    pub x_clue: ConnectionWeights,
+}
+
+/// relative weights of the connections, and the ranges the range connections are drawn from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ConnectionWeights {
+    pub all_adjacent: u32,
+    pub one_adjacent: u32,
+    pub not_adjacent: u32,
+    pub one_opposite: u32,
+    pub not_opposite: u32,
+    pub all_in_range: u32,
+    pub not_in_range: u32,
+    pub all_in_range_span: [usize; 2], // least and most sectors, both included
+    pub not_in_range_span: [usize; 2],
+}
+
+impl Default for ClueWeights {
+    fn default() -> Self {
+        let easy = ConnectionWeights::default();
+        ClueWeights {
+            standard: easy.clone(),
+            expert: ConnectionWeights {
+                all_in_range_span: [4, 6],
+                not_in_range_span: [2, 3],
+                ..easy.clone()
+            },
+            x_clue: easy,
+        }
+    }
+}
+
+impl Default for ConnectionWeights {
+    fn default() -> Self {
+        ConnectionWeights {
+            all_adjacent: 200,
+            one_adjacent: 10,
+            not_adjacent: 16,
+            one_opposite: 10,
+            not_opposite: 12,
+            all_in_range: 7,
+            not_in_range: 64,
+            all_in_range_span: [2, 4],
+            not_in_range_span: [3, 4],
+        }
+    }
+}
+
+impl ClueWeights {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, weights, map_type) in [
+            ("standard", &self.standard, MapType::Standard),
+            ("expert", &self.expert, MapType::Expert),
+            ("x_clue", &self.x_clue, MapType::Standard),
+        ] {
+            weights
+                .validate(&map_type)
+                .map_err(|e| anyhow::anyhow!("clue weights {name}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    fn for_clue(&self, map_type: &MapType, is_x: bool) -> &ConnectionWeights {
+        match (is_x, map_type) {
+            (true, _) => &self.x_clue,
+            (false, MapType::Standard) => &self.standard,
+            (false, MapType::Expert) => &self.expert,
+        }
+    }
+}
+
+impl ConnectionWeights {
+    /// the spans have to fit the smaller board they may be used on, half of it at most.
+    fn validate(&self, map_type: &MapType) -> anyhow::Result<()> {
+        if self.weighted().iter().all(|(weight, _)| *weight == 0) {
+            anyhow::bail!("every weight is 0");
+        }
+        let max = map_type.sector_count() / 2;
+        for [least, most] in [self.all_in_range_span, self.not_in_range_span] {
+            if least == 0 || least > most || most > max {
+                anyhow::bail!("span {least}..={most} not within 1..={max}");
+            }
+        }
+        Ok(())
+    }
+
+    /// the connections by weight, the range ones with the least of their span.
+    fn weighted(&self) -> [(u32, ClueConnection); 7] {
+        [
+            (self.all_adjacent, ClueConnection::AllAdjacent),
+            (self.one_adjacent, ClueConnection::OneAdjacent),
+            (self.not_adjacent, ClueConnection::NotAdjacent),
+            (self.one_opposite, ClueConnection::OneOpposite),
+            (self.not_opposite, ClueConnection::NotOpposite),
+            (
+                self.all_in_range,
+                ClueConnection::AllInRange(self.all_in_range_span[0]),
+            ),
+            (
+                self.not_in_range,
+                ClueConnection::NotInRange(self.not_in_range_span[0]),
+            ),
+        ]
+    }
+}
+
 pub struct ClueGenerator {
     seed: u64,
     rng: SmallRng,
     sectors: Sectors,
     map_type: MapType,
+    weights: ClueWeights,
 }
 
 impl ClueGenerator {
@@ -122,9 +236,16 @@ impl ClueGenerator {
             rng: SmallRng::seed_from_u64(seed),
             sectors,
             map_type,
+            weights: ClueWeights::default(),
         }
     }
 
+    /// draw the connections by these weights rather than the default ones.
+    pub fn with_weights(mut self, weights: ClueWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
     pub fn generate_clues(&mut self) -> anyhow::Result<(Vec<Clue>, Vec<Clue>)> {
         let mut res = vec![];
 
@@ -136,7 +257,7 @@ impl ClueGenerator {
                 3 => ClueEnum::D,
                 4 => ClueEnum::E,
                 5 => ClueEnum::F,
-                _ => panic!("clue index out of range"),
+                _ => return Err(anyhow::anyhow!("clue index out of range")),
             };
             let subject = self.get_rand_type(false, false);
             let object = self.get_rand_type(true, false);
@@ -153,36 +274,108 @@ impl ClueGenerator {
             });
         }
         let mut xres = vec![];
-        let mut cnt = 0;
-        while xres.is_empty() || !check_x_space_only(&res, &xres, &self.sectors).is_empty() {
-            xres.clear();
-            while xres.len() < self.map_type.xclue_points().len() {
-                let index = match xres.len() {
-                    0 => ClueEnum::X1,
-                    1 => ClueEnum::X2,
-                    _ => panic!("clue index out of range"),
-                };
-                let subject = SectorType::X;
-                let object = self.get_rand_type(true, false);
-
-                let conn = self.get_rand_conn(true);
-                if !self.check_clue(&xres, &subject, &object, &conn) {
-                    continue;
+        while xres.len() < self.map_type.xclue_points().len() {
+            let index = match xres.len() {
+                0 => ClueEnum::X1,
+                1 => ClueEnum::X2,
+                _ => return Err(anyhow::anyhow!("x clue index out of range")),
+            };
+            let subject = SectorType::X;
+            let object = self.get_rand_type(true, false);
+
+            let conn = self.get_rand_conn(true);
+            if !self.check_clue(&xres, &subject, &object, &conn) {
+                continue;
+            }
+            xres.push(Clue {
+                index,
+                subject,
+                object,
+                conn,
+            });
+        }
+        self.repair_x_clues(&res, &mut xres)?;
+
+        Ok((res, xres))
+    }
+
+    /// swap the x clues one at a time for the candidate leaving the fewest other spaces x could be in,
+    /// until x is pinned down. only fails when no candidate narrows it any further.
+    fn repair_x_clues(&mut self, clues: &[Clue], xclues: &mut [Clue]) -> anyhow::Result<()> {
+        let mut ambiguous = check_x_space_only(clues, xclues, &self.sectors).len();
+        while ambiguous > 0 {
+            let mut best = None;
+            for slot in 0..xclues.len() {
+                let others = xclues
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, c)| (i != slot).then_some(c.clone()))
+                    .collect::<Vec<_>>();
+                for candidate in self.x_clue_candidates(&others, &xclues[slot].index) {
+                    let mut tried = xclues.to_vec();
+                    tried[slot] = candidate;
+                    let left = check_x_space_only(clues, &tried, &self.sectors).len();
+                    if best.as_ref().is_none_or(|(l, _)| left < *l) {
+                        best = Some((left, tried));
+                    }
+                    if left == 0 {
+                        break;
+                    }
+                }
+                if best.as_ref().is_some_and(|(l, _)| *l == 0) {
+                    break;
                 }
-                xres.push(Clue {
-                    index,
-                    subject,
-                    object,
-                    conn,
-                });
             }
-            cnt += 1;
-            if cnt > 100 {
-                return Err(anyhow::anyhow!("x clue too much try"));
+            match best {
+                Some((left, tried)) if left < ambiguous => {
+                    xclues.clone_from_slice(&tried);
+                    ambiguous = left;
+                }
+                _ => return Err(anyhow::anyhow!("x clue can't be repaired")),
             }
         }
+        Ok(())
+    }
 
-        Ok((res, xres))
+    /// every x clue true of the map that fits with the others, in a random order.
+    fn x_clue_candidates(&mut self, others: &[Clue], index: &ClueEnum) -> Vec<Clue> {
+        let objects = [
+            SectorType::Comet,
+            SectorType::Asteroid,
+            SectorType::DwarfPlanet,
+            SectorType::Nebula,
+            SectorType::Space,
+        ];
+        let weights = &self.weights.x_clue;
+        let [all_least, all_most] = weights.all_in_range_span;
+        let [not_least, not_most] = weights.not_in_range_span;
+        let conns = weights
+            .weighted()
+            .into_iter()
+            .filter(|(weight, _)| *weight > 0)
+            .flat_map(|(_, conn)| match conn {
+                ClueConnection::AllInRange(_) => (all_least..=all_most)
+                    .map(ClueConnection::AllInRange)
+                    .collect(),
+                ClueConnection::NotInRange(_) => (not_least..=not_most)
+                    .map(ClueConnection::NotInRange)
+                    .collect(),
+                conn => vec![conn],
+            })
+            .collect::<Vec<_>>();
+        let mut candidates = objects
+            .iter()
+            .flat_map(|object| conns.iter().map(move |conn| (object, conn)))
+            .filter(|(object, conn)| self.check_clue(others, &SectorType::X, object, conn))
+            .map(|(object, conn)| Clue {
+                index: index.clone(),
+                subject: SectorType::X,
+                object: object.clone(),
+                conn: conn.clone(),
+            })
+            .collect::<Vec<_>>();
+        candidates.shuffle(&mut self.rng);
+        candidates
     }
 
     fn get_rand_type(&mut self, allow_space: bool, allow_x: bool) -> SectorType {
@@ -199,26 +392,18 @@ impl ClueGenerator {
     }
 
     fn get_rand_conn(&mut self, is_x: bool) -> ClueConnection {
-        let easy = matches!(self.map_type, MapType::Standard) || is_x;
-
-        let distributions = [
-            (200, ClueConnection::AllAdjacent),
-            (10, ClueConnection::OneAdjacent),
-            (16, ClueConnection::NotAdjacent),
-            (10, ClueConnection::OneOpposite),
-            (12, ClueConnection::NotOpposite),
-            (
-                7,
-                ClueConnection::AllInRange(self.rng.random_range(if easy { 2..=4 } else { 4..=6 })),
-            ),
-            (
-                64,
-                ClueConnection::NotInRange(self.rng.random_range(if easy { 3..=4 } else { 2..=3 })),
-            ),
-        ];
+        let weights = self.weights.for_clue(&self.map_type, is_x);
+        let [all_least, all_most] = weights.all_in_range_span;
+        let [not_least, not_most] = weights.not_in_range_span;
+
+        let mut distributions = weights.weighted();
+        distributions[5].1 =
+            ClueConnection::AllInRange(self.rng.random_range(all_least..=all_most));
+        distributions[6].1 =
+            ClueConnection::NotInRange(self.rng.random_range(not_least..=not_most));
 
         // 计算总和
-        let sum: i32 = distributions.iter().map(|(weight, _)| *weight).sum();
+        let sum: u32 = distributions.iter().map(|(weight, _)| *weight).sum();
 
         // 生成随机数
         let mut r = self.rng.random_range(0..sum);
@@ -290,7 +475,8 @@ impl ClueGenerator {
         }
 
         if *subject == SectorType::Space {
-            panic!("no possible check algorithm");
+            // no possible check algorithm
+            return false;
         }
 
         match conn {
@@ -656,4 +842,42 @@ mod tests {
             Vec::<usize>::new()
         );
     }
+
+    #[test]
+    fn test_repair_x_clues() {
+        #[rustfmt::skip]
+        let s = Sectors{ data: vec![
+            Sector { index: 1, r#type: SectorType::Asteroid },
+            Sector { index: 2, r#type: SectorType::X },
+            Sector { index: 3, r#type: SectorType::Nebula },
+            Sector { index: 4, r#type: SectorType::Space },
+            Sector { index: 5, r#type: SectorType::Space },
+            Sector { index: 6, r#type: SectorType::DwarfPlanet },
+        ]};
+        let mut xclues = vec![Clue {
+            index: ClueEnum::X1,
+            subject: SectorType::X,
+            object: SectorType::DwarfPlanet,
+            conn: ClueConnection::NotAdjacent,
+        }];
+        assert_eq!(check_x_space_only(&[], &xclues, &s), vec![4]);
+        let mut cg = ClueGenerator::new(0, s.clone(), MapType::Standard);
+        cg.repair_x_clues(&[], &mut xclues).unwrap();
+        assert_eq!(xclues[0].index, ClueEnum::X1);
+        assert!(check_x_space_only(&[], &xclues, &s).is_empty());
+
+        // a few deals can't be pinned down by any x clue, the engine rerolls their seed
+        let mut unrepaired = 0;
+        for seed in 0..50 {
+            let map = Map::new(SmallRng::seed_from_u64(seed), MapType::Expert).unwrap();
+            let mut cg = ClueGenerator::new(seed, map.sectors.clone(), map.r#type.clone());
+            match cg.generate_clues() {
+                Ok((clues, xclues)) => {
+                    assert!(check_x_space_only(&clues, &xclues, &map.sectors).is_empty())
+                }
+                Err(_) => unrepaired += 1,
+            }
+        }
+        assert!(unrepaired < 5, "{unrepaired} of 50 seeds");
+    }
 }
@@ -1,5 +1,6 @@
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 
 use super::{MapType, Sector, SectorType, Sectors};
 
@@ -8,6 +9,8 @@ type Position = usize;
 const PRIMES_EXPERT: [Position; 7] = [1, 2, 4, 6, 10, 12, 16]; // 0-based positions for 2,3,5,7,11,13,17
 const PRIMES_STANDARD: [Position; 5] = [1, 2, 4, 6, 10]; // 0-based positions for 2,3,5,7,11
 
+static SHARED: OnceLock<MapEnumerator> = OnceLock::new();
+
 pub struct MapEnumerator {
     predef_d_e_standard: HashMap<Vec<Position>, Vec<([Position; 2], Vec<Position>)>>,
     predef_d_e_expert: HashMap<Vec<Position>, Vec<([Position; 2], Vec<Position>)>>,
@@ -24,6 +27,11 @@ impl MapEnumerator {
         }
     }
 
+    /// the tables never change once built, every filter shares one copy.
+    pub fn shared() -> &'static Self {
+        SHARED.get_or_init(Self::new)
+    }
+
     pub fn gen_sec(&self, map_type: &MapType) -> impl Iterator<Item = Sectors> {
         generate_c(map_type).flat_map(move |c| {
             generate_f(&c, map_type).flat_map(move |f| {
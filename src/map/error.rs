@@ -0,0 +1,28 @@
+/// errors of the map helpers on indexes and tokens coming from clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+    IndexOutOfRange { index: usize, max: usize },
+    TokenNotReady,
+}
+
+impl std::fmt::Display for MapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapError::IndexOutOfRange { index, max } => {
+                write!(f, "sector index {} out of range 1..={}", index, max)
+            }
+            MapError::TokenNotReady => write!(f, "token is not ready to be published"),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+/// check a 1-based sector index.
+pub fn check_index(index: usize, max: usize) -> Result<usize, MapError> {
+    if 0 < index && index <= max {
+        Ok(index)
+    } else {
+        Err(MapError::IndexOutOfRange { index, max })
+    }
+}
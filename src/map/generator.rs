@@ -40,6 +40,13 @@ impl MapGenerator {
             MapType::Standard => BEGINNER_TYPES,
             MapType::Expert => MASTER_TYPES,
         };
+        // only support 1 or 2 for now. Stupid limit generate method as nebula can take the Space at second time. no more.
+        if sector_types
+            .iter()
+            .any(|(t, count)| *t == SectorType::Nebula && *count > 2)
+        {
+            return Err(anyhow::anyhow!("Too many Nebula sectors."));
+        }
 
         while self.temp.len() < sector_types.len() {
             let mut ok = false;
@@ -70,7 +77,10 @@ impl MapGenerator {
                     self.temp.clear();
                     self.rest_index = (1..=self.map_type.sector_count()).collect();
                 } else {
-                    let prev = &sector_types[self.temp.len() - 1].0;
+                    let Some(prev) = self.temp.len().checked_sub(1).map(|i| &sector_types[i].0)
+                    else {
+                        return Err(anyhow::anyhow!("Failed to generate {:?}.", sector_type));
+                    };
                     println!("Failed to generate {:?}, try again back", sector_type);
                     println!("current rest index: {:?}", self.rest_index);
                     println!("prev: {:?}", prev);
@@ -103,7 +113,6 @@ impl MapGenerator {
                 SectorType::Asteroid => self.generate_asteroid_sector(),
                 SectorType::DwarfPlanet => self.generate_dwarf_planet_sector(),
                 SectorType::Nebula => {
-                    assert!(count <= 2); // only support 1 or 2 for now. Stupid limit generate method as nebula can take the Space at second time. no more.
                     self.generate_nebula_sector(sectors.last().map(|s: &Sector| s.index))
                 }
                 SectorType::X => self.generate_x_sector(),
@@ -2,9 +2,11 @@
 mod choicefilter;
 mod clue;
 mod enumerator;
+mod error;
 mod generator;
 mod model;
 
 pub use clue::*;
+pub use error::*;
 pub use model::*;
 pub use choicefilter::*;
@@ -1,7 +1,7 @@
 use rand::{distr::StandardUniform, rngs::SmallRng};
 use serde::{Deserialize, Serialize};
 
-use super::generator::MapGenerator;
+use super::{MapError, check_index, generator::MapGenerator};
 
 #[derive(Clone, Debug)]
 pub struct Map {
@@ -86,19 +86,14 @@ impl Sectors {
         self.data
             .iter()
             .filter(|s| {
-                in_range(st, ed, s.index, self.data.len())
-                    && match object {
-                        SectorType::Space => {
-                            s.r#type == SectorType::Space || s.r#type == SectorType::X
-                        }
-                        _ => s.r#type == *object,
-                    }
+                in_range(st, ed, s.index, self.data.len()) && s.r#type.observed() == *object
             })
             .count()
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub struct Sector {
     pub index: usize, // 1-based index.
     pub r#type: SectorType,
@@ -110,7 +105,7 @@ impl std::fmt::Display for Sector {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MapType {
     Standard, // 12 secotrs.
@@ -185,14 +180,91 @@ impl std::fmt::Display for SectorType {
     }
 }
 
+impl SectorType {
+    /// what a target or a survey sees of a sector of this type, the one rule for x: it looks
+    /// like empty space. only the clues, the locate and the theory checks tell x apart.
+    pub fn observed(&self) -> SectorType {
+        match self {
+            SectorType::X => SectorType::Space,
+            rest => rest.clone(),
+        }
+    }
+}
+
+const PUBLISHED_STEPS: usize = 3; // meetings a published theory waits before it is checked
+const DISPROVED_MEETING_INDEX: usize = 4;
+
+/// where a theory stands, sent as `meeting_index` on the wire: 0 unplaced, 1-3 submitted,
+/// 4 disproved. a verified theory keeps the index it was verified at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TheoryState {
+    Unplaced,                        // not published on a sector yet
+    Submitted { steps_left: usize }, // published, checked at the meeting once it reaches 0
+    Verified { steps_left: usize },  // revealed right, the steps left order the first publishers
+    Disproved,                       // revealed wrong
+}
+
+impl TheoryState {
+    pub fn meeting_index(&self) -> usize {
+        match self {
+            TheoryState::Unplaced => 0,
+            TheoryState::Submitted { steps_left } | TheoryState::Verified { steps_left } => {
+                *steps_left
+            }
+            TheoryState::Disproved => DISPROVED_MEETING_INDEX,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
+#[serde(into = "SecretTokenWire", from = "SecretTokenWire")]
 pub struct SecretToken {
     pub user_id: String,
-    pub user_index: usize,          // game sequence 1, 2, 3, 4
-    pub sector_index: usize,        // 0 for init, 1-12/1-18 is set.
-    pub meeting_index: usize,       // 0 for known, 1,2, 3 is just published, // 4 for wrong guess
-    pub r#type: Option<SectorType>, // 0/-1 is Some, 123 is None
+    pub user_index: usize,   // game sequence 1, 2, 3, 4
+    pub sector_index: usize, // 0 for init, 1-12/1-18 is set.
+    pub state: TheoryState,
+    pub r#type: Option<SectorType>, // Some once verified or disproved
+}
+
+/// the wire layout of `SecretToken`, unchanged for the clients.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct SecretTokenWire {
+    user_id: String,
+    user_index: usize,
+    sector_index: usize,
+    meeting_index: usize,
+    r#type: Option<SectorType>,
+}
+
+impl From<SecretToken> for SecretTokenWire {
+    fn from(token: SecretToken) -> Self {
+        SecretTokenWire {
+            user_id: token.user_id,
+            user_index: token.user_index,
+            sector_index: token.sector_index,
+            meeting_index: token.state.meeting_index(),
+            r#type: token.r#type,
+        }
+    }
+}
+
+impl From<SecretTokenWire> for SecretToken {
+    fn from(wire: SecretTokenWire) -> Self {
+        let state = match (wire.meeting_index, &wire.r#type) {
+            (DISPROVED_MEETING_INDEX, _) => TheoryState::Disproved,
+            (steps_left, Some(_)) => TheoryState::Verified { steps_left },
+            (_, None) if wire.sector_index == 0 => TheoryState::Unplaced,
+            (steps_left, None) => TheoryState::Submitted { steps_left },
+        };
+        SecretToken {
+            user_id: wire.user_id,
+            user_index: wire.user_index,
+            sector_index: wire.sector_index,
+            state,
+            r#type: wire.r#type,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -210,9 +282,9 @@ impl Token {
             secret: SecretToken {
                 user_id: user_id.to_owned(),
                 user_index,
-                sector_index: 0,  // not used yet
-                meeting_index: 0, // not used yet
-                r#type: None,     // not used yet
+                sector_index: 0, // not used yet
+                state: TheoryState::Unplaced,
+                r#type: None, // not used yet
             },
             r#type,
         }
@@ -222,7 +294,7 @@ impl Token {
         self.r#type == r#type && self.is_success_located_any()
     }
     pub fn is_success_located_any(&self) -> bool {
-        self.placed && self.secret.meeting_index != 4 && self.secret.r#type.is_some()
+        self.placed && matches!(self.secret.state, TheoryState::Verified { .. })
     }
 
     pub fn is_not_used(&self, r#type: &SectorType) -> bool {
@@ -234,7 +306,7 @@ impl Token {
     }
 
     pub fn is_revealed_checked(&self) -> bool {
-        self.placed && self.secret.r#type.is_some() && self.secret.meeting_index == 0
+        self.placed && self.secret.state == TheoryState::Verified { steps_left: 0 }
     }
 
     pub fn any_ready_published(&self) -> bool {
@@ -247,33 +319,52 @@ impl Token {
     }
 
     pub fn any_ready_checked(&self) -> bool {
-        self.placed && self.secret.meeting_index == 0 && self.secret.r#type.is_none()
+        // a token picked for publishing but never published is checked, and fails, as well
+        self.placed
+            && matches!(
+                self.secret.state,
+                TheoryState::Unplaced | TheoryState::Submitted { steps_left: 0 }
+            )
     }
 
-    pub fn set_published(&mut self, sector_index: usize) {
-        assert!(self.placed && self.secret.sector_index == 0);
+    pub fn set_published(&mut self, sector_index: usize) -> Result<(), MapError> {
+        if !self.placed || self.secret.sector_index != 0 {
+            return Err(MapError::TokenNotReady);
+        }
         self.secret.sector_index = sector_index;
-        self.secret.meeting_index = 3;
+        self.secret.state = TheoryState::Submitted {
+            steps_left: PUBLISHED_STEPS,
+        };
+        Ok(())
     }
 
     pub fn push_at_meeting(&mut self, revealed_sectors: &[usize]) {
-        if self.placed
-            && self.secret.sector_index != 0
-            && self.secret.meeting_index > 0
-            && self.secret.meeting_index <= 3
-            && self.secret.r#type.is_none()
-            && !revealed_sectors.contains(&self.secret.sector_index)
-        {
-            self.secret.meeting_index -= 1;
-            // if self.secret.meeting_index == 0 {
-            //     self.secret.r#type = Some(self.r#type.clone());
-            // }
+        if !self.placed || revealed_sectors.contains(&self.secret.sector_index) {
+            return;
+        }
+        if let TheoryState::Submitted { steps_left } = &mut self.secret.state {
+            *steps_left = steps_left.saturating_sub(1);
         }
     }
 
+    /// the theory is right, reveal it.
+    pub fn verify(&mut self) {
+        self.secret.r#type = Some(self.r#type.clone());
+        self.secret.state = TheoryState::Verified {
+            steps_left: self.secret.state.meeting_index(),
+        };
+    }
+
+    /// the theory is wrong, reveal it.
+    pub fn disprove(&mut self) {
+        self.secret.r#type = Some(self.r#type.clone());
+        self.secret.state = TheoryState::Disproved;
+    }
+
+    /// reveal the unchecked theories as right, the caller disproves the wrong ones.
     pub fn reveal_in_the_end(&mut self) -> bool {
         if self.placed && self.secret.r#type.is_none() {
-            self.secret.r#type = Some(self.r#type.clone());
+            self.verify();
             return true;
         }
         false
@@ -303,11 +394,9 @@ impl Map {
         self.sectors.get_range_type_cnt(st, ed, object)
     }
 
-    pub fn target_sector(&self, index: usize) -> SectorType {
-        match &self.sectors.data[index - 1].r#type {
-            SectorType::X => SectorType::Space,
-            rest => rest.clone(),
-        }
+    pub fn target_sector(&self, index: usize) -> Result<SectorType, MapError> {
+        let index = check_index(index, self.size())?;
+        Ok(self.sectors.data[index - 1].r#type.observed())
     }
 
     pub fn locate_x(
@@ -315,18 +404,20 @@ impl Map {
         index: usize,
         pre_sector_type: &SectorType,
         next_sector_type: &SectorType,
-    ) -> bool {
+    ) -> Result<bool, MapError> {
+        let index = check_index(index, self.size())?;
         let sector = &self.sectors.data[index - 1];
         let next_sector = self.sectors.next(index);
         let pre_sector = self.sectors.prev(index);
-        sector.r#type == SectorType::X
+        Ok(sector.r#type == SectorType::X
             && pre_sector.r#type == *pre_sector_type
-            && next_sector.r#type == *next_sector_type
+            && next_sector.r#type == *next_sector_type)
     }
 
-    pub fn meeting_check(&self, index: usize, target_type: &SectorType) -> bool {
+    pub fn meeting_check(&self, index: usize, target_type: &SectorType) -> Result<bool, MapError> {
+        let index = check_index(index, self.size())?;
         let sector = &self.sectors.data[index - 1];
-        sector.r#type == *target_type
+        Ok(sector.r#type == *target_type)
     }
 }
 
@@ -343,6 +434,146 @@ impl rand::distr::Distribution<SectorType> for StandardUniform {
     }
 }
 
+/// a checked 1-based sector index of a board, moving round it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorIndex {
+    value: usize, // 1-based index
+    max: usize,   // 12 or 18
+}
+
+impl SectorIndex {
+    pub fn new(value: usize, max: usize) -> Result<Self, MapError> {
+        let value = check_index(value, max)?;
+        Ok(Self { value, max })
+    }
+    pub fn as_usize(&self) -> usize {
+        self.value
+    }
+    pub fn next(&self) -> Self {
+        let value = if self.value == self.max {
+            1
+        } else {
+            self.value + 1
+        };
+        Self {
+            value,
+            max: self.max,
+        }
+    }
+    pub fn prev(&self) -> Self {
+        let value = if self.value == 1 {
+            self.max
+        } else {
+            self.value - 1
+        };
+        Self {
+            value,
+            max: self.max,
+        }
+    }
+    pub fn dis(&self, other: &Self) -> usize {
+        let dis = if self.value > other.value {
+            self.value - other.value + 1
+        } else {
+            other.value - self.value + 1
+        };
+        if dis > self.max / 2 {
+            self.max - dis + 2
+        } else {
+            dis
+        }
+    }
+}
+
+/// the sectors from `start` to `end` going round the board, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorRange {
+    pub start: SectorIndex,
+    pub end: SectorIndex,
+}
+
+impl SectorRange {
+    pub fn new(start: usize, end: usize, max: usize) -> Result<Self, MapError> {
+        Ok(Self {
+            start: SectorIndex::new(start, max)?,
+            end: SectorIndex::new(end, max)?,
+        })
+    }
+
+    /// steps from the start to the end, 0 for a single sector.
+    pub fn span(&self) -> usize {
+        (self.end.value + self.start.max - self.start.value) % self.start.max
+    }
+}
+
+/// the visible half of the track, `start..=end` going round the board, the players can only
+/// survey, target and locate inside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkyWindow {
+    pub start: usize,
+    pub end: usize,
+    size: usize, // sectors of the board, 12 or 18
+}
+
+impl SkyWindow {
+    /// the window of a new game, the first half of the track.
+    pub fn first(map_type: &MapType) -> Self {
+        Self::starting_at(1, map_type)
+    }
+
+    /// the window from `start` over half of the track, wrapping past the last sector.
+    pub fn starting_at(start: usize, map_type: &MapType) -> Self {
+        let size = map_type.sector_count();
+        SkyWindow {
+            start,
+            end: (start + size / 2 - 2) % size + 1,
+            size,
+        }
+    }
+
+    /// the window as the room stores it.
+    pub fn from_indexes(start: usize, end: usize, map_type: &MapType) -> Self {
+        SkyWindow {
+            start,
+            end,
+            size: map_type.sector_count(),
+        }
+    }
+
+    /// the window one sector further.
+    pub fn next(&self) -> Self {
+        SkyWindow {
+            start: self.start % self.size + 1,
+            end: self.end % self.size + 1,
+            size: self.size,
+        }
+    }
+
+    /// move the window to start at `index`, true if it went past the end of the track, i.e. a
+    /// new round begins.
+    pub fn advance(&mut self, index: usize) -> bool {
+        let wrapped = index < self.start;
+        self.start = index;
+        self.end = (index + self.size / 2 - 2) % self.size + 1;
+        wrapped
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        in_range(self.start, self.end, index, self.size)
+    }
+
+    /// `start..=end` lies inside the window, in the order of the track.
+    pub fn contains_range(&self, start: usize, end: usize) -> bool {
+        validate_index_in_range(self.start, self.end, start, Some(end), self.size)
+    }
+
+    /// the sectors of the window, in track order.
+    pub fn indexes(&self) -> impl Iterator<Item = usize> {
+        let (start, size) = (self.start, self.size);
+        (0..size / 2).map(move |offset| (start + offset - 1) % size + 1)
+    }
+}
+
 pub fn validate_index_in_range(
     start: usize,
     end: usize,
@@ -350,9 +581,6 @@ pub fn validate_index_in_range(
     input_ed: Option<usize>,
     max: usize,
 ) -> bool {
-    assert!(0 < start && start <= max);
-    assert!(0 < end && end <= max);
-
     // is a circle from 1 to max, the input should be in the range of start to end.
     // the input_end can be None, which means the input is a single point.
     // or the input_end can be Some, which means the input is a range, so the input_st should be earlier than input_ed.
@@ -361,10 +589,14 @@ pub fn validate_index_in_range(
             .is_none_or(|ed| in_range(start, end, ed, max) && in_range(input_st, end, ed, max))
 }
 
+/// false for any index outside of 1..=max.
 pub fn in_range(start: usize, end: usize, input: usize, max: usize) -> bool {
-    assert!(0 < start && start <= max);
-    assert!(0 < end && end <= max);
-
+    if [start, end, input]
+        .iter()
+        .any(|i| check_index(*i, max).is_err())
+    {
+        return false;
+    }
     if start < end {
         start <= input && input <= end
     } else {
@@ -389,5 +621,181 @@ mod tests {
         assert!(validate_index_in_range(11, 1, 13, None, 18));
         assert!(validate_index_in_range(11, 1, 13, Some(14), 18));
         assert!(!validate_index_in_range(11, 1, 13, Some(12), 18));
+        assert!(!validate_index_in_range(1, 9, 0, None, 18));
+        assert!(!validate_index_in_range(1, 9, 3, Some(19), 18));
+    }
+
+    #[test]
+    fn test_theory_state_transitions() {
+        let mut token = Token::new(SectorType::Comet, "u1", 1);
+        assert_eq!(token.secret.state, TheoryState::Unplaced);
+        assert_eq!(token.set_published(2), Err(MapError::TokenNotReady));
+
+        token.set_to_be_placed();
+        assert!(token.any_ready_checked());
+        token.set_published(2).unwrap();
+        assert_eq!(token.secret.state, TheoryState::Submitted { steps_left: 3 });
+        assert!(!token.any_ready_checked());
+
+        // a revealed sector holds the theory back
+        token.push_at_meeting(&[2]);
+        assert_eq!(token.secret.state, TheoryState::Submitted { steps_left: 3 });
+        for _ in 0..5 {
+            token.push_at_meeting(&[]);
+        }
+        assert_eq!(token.secret.state, TheoryState::Submitted { steps_left: 0 });
+        assert!(token.any_ready_checked());
+
+        let mut wrong = token.clone();
+        token.verify();
+        assert_eq!(token.secret.state, TheoryState::Verified { steps_left: 0 });
+        assert!(token.is_revealed_checked());
+        assert!(token.is_success_located(SectorType::Comet));
+        assert!(!token.reveal_in_the_end());
+
+        wrong.disprove();
+        assert_eq!(wrong.secret.state, TheoryState::Disproved);
+        assert!(!wrong.is_success_located_any());
+    }
+
+    #[test]
+    fn test_secret_token_wire() {
+        let mut token = Token::new(SectorType::Comet, "u1", 1);
+        token.set_to_be_placed();
+        let mut states = vec![token.secret.clone()];
+        token.set_published(5).unwrap();
+        states.push(token.secret.clone());
+        token.push_at_meeting(&[]);
+        let mut verified = token.clone();
+        verified.verify();
+        states.push(verified.secret);
+        token.disprove();
+        states.push(token.secret.clone());
+
+        let meeting_indexes = [0, 3, 2, 4];
+        for (secret, meeting_index) in states.into_iter().zip(meeting_indexes) {
+            let json = serde_json::to_value(&secret).unwrap();
+            assert_eq!(json["meeting_index"], meeting_index);
+            let back: SecretToken = serde_json::from_value(json).unwrap();
+            assert_eq!(back.state, secret.state);
+        }
+    }
+
+    #[test]
+    fn test_sector_range() {
+        let span = |start, end| SectorRange::new(start, end, 12).map(|r| r.span());
+        assert_eq!(span(3, 3), Ok(0));
+        assert_eq!(span(1, 6), Ok(5));
+        assert_eq!(span(10, 3), Ok(5));
+        assert_eq!(span(12, 1), Ok(1));
+        assert_eq!(
+            span(0, 3),
+            Err(MapError::IndexOutOfRange { index: 0, max: 12 })
+        );
+        assert!(span(3, 13).is_err());
+
+        let index = SectorIndex::new(12, 12).unwrap();
+        assert_eq!(index.next().as_usize(), 1);
+        assert_eq!(index.next().prev(), index);
+    }
+
+    #[test]
+    fn test_sky_window() {
+        let standard = SkyWindow::first(&MapType::Standard);
+        assert_eq!((standard.start, standard.end), (1, 6));
+        let expert = SkyWindow::first(&MapType::Expert);
+        assert_eq!((expert.start, expert.end), (1, 9));
+
+        for map_type in [MapType::Standard, MapType::Expert] {
+            let size = map_type.sector_count();
+            let mut window = SkyWindow::first(&map_type);
+            for _ in 0..size {
+                let next = window.next();
+                assert_eq!(next, SkyWindow::starting_at(next.start, &map_type));
+                assert_eq!(window.indexes().count(), size / 2);
+                assert!(window.indexes().all(|i| window.contains(i)));
+                assert!(window.contains_range(window.start, window.end));
+                assert!(!window.contains(window.end % size + 1));
+                window = next;
+            }
+            assert_eq!(window, SkyWindow::first(&map_type));
+        }
+
+        // wrapping past the last sector
+        let mut window = SkyWindow::starting_at(10, &MapType::Standard);
+        assert_eq!(window.end, 3);
+        assert_eq!(
+            window.indexes().collect::<Vec<_>>(),
+            vec![10, 11, 12, 1, 2, 3]
+        );
+        assert!(window.contains_range(11, 2));
+        assert!(!window.contains_range(2, 11));
+        assert!(!window.advance(12));
+        assert_eq!((window.start, window.end), (12, 5));
+        assert!(window.advance(3));
+        assert_eq!((window.start, window.end), (3, 8));
+
+        let window = SkyWindow::starting_at(16, &MapType::Expert);
+        assert_eq!(window.end, 6);
+        assert_eq!(window, SkyWindow::from_indexes(16, 6, &MapType::Expert));
+    }
+
+    #[test]
+    fn test_x_observed_as_space() {
+        use crate::map::ChoiceFilter;
+        use crate::operation::{Operation, OperationResult, TargetOperation};
+
+        let types = [
+            SectorType::X,
+            SectorType::Space,
+            SectorType::Asteroid,
+            SectorType::Space,
+        ];
+        let map = Map {
+            r#type: MapType::Standard,
+            sectors: Sectors {
+                data: types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| Sector {
+                        index: i + 1,
+                        r#type: t.clone(),
+                    })
+                    .collect(),
+            },
+        };
+        // target
+        assert_eq!(map.target_sector(1), Ok(SectorType::Space));
+        assert_eq!(map.target_sector(3), Ok(SectorType::Asteroid));
+        // survey
+        assert_eq!(map.survey_sector(1, 4, &SectorType::Space), 3);
+        assert_eq!(map.survey_sector(1, 4, &SectorType::X), 0);
+        // the filter keeps the maps the target saw
+        let target = |index, seen| {
+            ChoiceFilter::filter_op(
+                &map.sectors,
+                &Operation::Target(TargetOperation { index }),
+                &OperationResult::Target(seen),
+            )
+        };
+        assert!(target(1, SectorType::Space));
+        assert!(!target(1, SectorType::X));
+        assert!(target(2, SectorType::Space));
+        // a theory is checked against the true type
+        assert_eq!(map.meeting_check(1, &SectorType::Space), Ok(false));
+        assert_eq!(map.meeting_check(1, &SectorType::X), Ok(true));
+    }
+
+    #[test]
+    fn test_index_out_of_map() {
+        let map = Map::place_holder();
+        assert_eq!(
+            map.target_sector(1),
+            Err(MapError::IndexOutOfRange { index: 1, max: 0 })
+        );
+        assert!(
+            map.locate_x(0, &SectorType::Comet, &SectorType::Space)
+                .is_err()
+        );
     }
 }
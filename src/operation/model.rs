@@ -1,8 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de::Error as _};
 
-use crate::map::{Clue, ClueEnum, SectorType};
+use crate::map::{Clue, ClueEnum, MapType, SectorIndex, SectorType};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Operation {
     Survey(SurveyOperatoin),
@@ -11,54 +11,126 @@ pub enum Operation {
     Locate(LocateOperation),
     ReadyPublish(ReadyPublishOperation),
     DoPublish(DoPublishOperation),
+    /// operations played one after the other in one submission, all or none of them.
+    Batch(Vec<Operation>),
+    /// give up the turn, see `PASS_STEPS` for what it costs.
+    Pass,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// steps a batch may hold at most.
+pub const MAX_BATCH_STEPS: usize = 4;
+
+/// time a pass moves the player on the track in the user move stage, as much as a research.
+/// passing in a meeting or for the last move costs no time.
+pub const PASS_STEPS: usize = 1;
+
+/// the type of an operation, without its arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Survey,
+    Target,
+    Research,
+    Locate,
+    ReadyPublish,
+    DoPublish,
+    Batch,
+    Pass,
+}
+
+impl Operation {
+    pub fn kind(&self) -> OperationKind {
+        match self {
+            Operation::Survey(_) => OperationKind::Survey,
+            Operation::Target(_) => OperationKind::Target,
+            Operation::Research(_) => OperationKind::Research,
+            Operation::Locate(_) => OperationKind::Locate,
+            Operation::ReadyPublish(_) => OperationKind::ReadyPublish,
+            Operation::DoPublish(_) => OperationKind::DoPublish,
+            Operation::Batch(_) => OperationKind::Batch,
+            Operation::Pass => OperationKind::Pass,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SurveyOperatoin {
     pub sector_type: SectorType,
+    #[serde(deserialize_with = "sector_index")]
     pub start: usize,
+    #[serde(deserialize_with = "sector_index")]
     pub end: usize,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TargetOperation {
+    #[serde(deserialize_with = "sector_index")]
     pub index: usize,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResearchOperation {
     pub index: ClueEnum,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LocateOperation {
+    #[serde(deserialize_with = "sector_index")]
     pub index: usize,
     pub pre_sector_type: SectorType,
     pub next_sector_type: SectorType,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ReadyPublishOperation {
     pub sectors: Vec<SectorType>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DoPublishOperation {
+    #[serde(deserialize_with = "sector_index")]
     pub index: usize,
     pub sector_type: SectorType,
 }
 
+/// a sector index of the largest board, the board of the room is checked once the operation is
+/// played.
+fn sector_index<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let index = usize::deserialize(deserializer)?;
+    SectorIndex::new(index, MapType::Expert.sector_count())
+        .map(|index| index.as_usize())
+        .map_err(D::Error::custom)
+}
+
 // result
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum OperationResult {
     Survey(usize),
     Target(SectorType),
     Research(Clue), // ABCDEFX1X2
     Locate(bool),
+    LocateMiss(LocateMiss), // a missed locate, in rooms whose rules tell more than `false`
     ReadyPublish(usize),
     DoPublish((usize, SectorType)), // index
+    Batch(Vec<OperationResult>),    // of each step, in order
+    Pass(PassResult),
+}
+
+/// what a pass gave up.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PassResult {
+    pub steps: usize,     // time moved on the track
+    pub withdrawn: usize, // tokens picked for the meeting and taken back unpublished
+    pub last_move: bool,  // the last move of the player, given up
+}
+
+/// what the failed locate rule of the room reveals about a miss.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LocateMiss {
+    pub adjacent_wrong: Option<bool>, // Some when revealed: x is at the index, a neighbor is wrong
+    pub extra_time: usize,
 }
 
 #[cfg(test)]
@@ -78,6 +150,17 @@ mod tests {
         let json_str = serde_json::to_string(&survey).unwrap();
         println!("{}", json_str);
         assert!(json_str.contains(r#""survey":{"sector_type":"space","start":1,"end":2}"#));
+        assert_eq!(
+            serde_json::from_str::<Operation>(&json_str).unwrap(),
+            survey
+        );
+
+        for index in [0, 19] {
+            let target = format!(r#"{{"target":{{"index":{index}}}}}"#);
+            assert!(serde_json::from_str::<Operation>(&target).is_err());
+        }
+        let survey = r#"{"survey":{"sector_type":"space","start":18,"end":0}}"#;
+        assert!(serde_json::from_str::<Operation>(survey).is_err());
     }
 
     #[test]
@@ -104,5 +187,29 @@ mod tests {
         let res_str = serde_json::to_string(&locate).unwrap();
         println!("{}", res_str);
         assert_eq!(res_str, r#"{"locate":true}"#);
+
+        let miss = OperationResult::LocateMiss(LocateMiss {
+            adjacent_wrong: Some(true),
+            extra_time: 0,
+        });
+        let res_str = serde_json::to_string(&miss).unwrap();
+        assert_eq!(
+            res_str,
+            r#"{"locate_miss":{"adjacent_wrong":true,"extra_time":0}}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&Operation::Pass).unwrap(),
+            r#""pass""#
+        );
+        let pass = OperationResult::Pass(PassResult {
+            steps: 1,
+            withdrawn: 0,
+            last_move: false,
+        });
+        assert_eq!(
+            serde_json::to_string(&pass).unwrap(),
+            r#"{"pass":{"steps":1,"withdrawn":0,"last_move":false}}"#
+        );
     }
 }
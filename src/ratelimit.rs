@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+/// token bucket of one service account, refilled at `rate` per second up to `burst`.
+#[derive(Debug, Clone)]
+pub struct RateBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateBucket {
+    pub fn new(burst: f64, now: Instant) -> Self {
+        RateBucket {
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    pub fn try_take(&mut self, now: Instant, rate: f64, burst: f64) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_rate_bucket() {
+        let start = Instant::now();
+        let mut bucket = RateBucket::new(2.0, start);
+        assert!(bucket.try_take(start, 1.0, 2.0));
+        assert!(bucket.try_take(start, 1.0, 2.0));
+        assert!(!bucket.try_take(start, 1.0, 2.0));
+
+        let later = start + std::time::Duration::from_millis(1500);
+        assert!(bucket.try_take(later, 1.0, 2.0));
+        assert!(!bucket.try_take(later, 1.0, 2.0));
+
+        // refill is capped at the burst
+        let much_later = later + std::time::Duration::from_secs(60);
+        assert!(bucket.try_take(much_later, 1.0, 2.0));
+        assert!(bucket.try_take(much_later, 1.0, 2.0));
+        assert!(!bucket.try_take(much_later, 1.0, 2.0));
+    }
+}
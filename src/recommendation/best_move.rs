@@ -1,12 +1,14 @@
 use crate::{
-    map::{ChoiceFilter, Clue, ClueConnection, ClueEnum, MapType, SectorType, Token},
+    map::{ChoiceFilter, Clue, ClueConnection, ClueEnum, MapType, SectorIndex, SectorType, Token},
     operation::{
         DoPublishOperation, Operation, ReadyPublishOperation, ResearchOperation, SurveyOperatoin,
         TargetOperation,
     },
+    redact::Secret,
     room::{GameStage, UserState},
 };
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
 enum CandidateOperation {
@@ -18,49 +20,6 @@ enum CandidateOperation {
     DoPublish,
 }
 
-// todo maybe make this a more low level struct, refactor other codes
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct SectorIndex {
-    value: usize, // 1-based index
-    max: usize,   // 12 or 18
-}
-
-impl SectorIndex {
-    pub fn new(value: usize, max: usize) -> Self {
-        if value > max {
-            panic!("SectorIndex out of range");
-        }
-        Self { value, max }
-    }
-    pub fn as_usize(&self) -> usize {
-        self.value
-    }
-    pub fn next(&self) -> Self {
-        if self.value == self.max {
-            return Self::new(1, self.max);
-        }
-        Self::new(self.value + 1, self.max)
-    }
-    pub fn prev(&self) -> Self {
-        if self.value == 1 {
-            return Self::new(self.max, self.max);
-        }
-        Self::new(self.value - 1, self.max)
-    }
-    pub fn dis(&self, other: &Self) -> usize {
-        let dis = if self.value > other.value {
-            self.value - other.value + 1
-        } else {
-            other.value - self.value + 1
-        };
-        if dis > self.max / 2 {
-            self.max - dis + 2
-        } else {
-            dis
-        }
-    }
-}
-
 pub struct BestMoveInfo {
     pub stage: GameStage,
     pub map_type: MapType,
@@ -69,12 +28,53 @@ pub struct BestMoveInfo {
     pub revealed_sectors: Vec<usize>,
 }
 
+/// how the bot weighs its candidate moves, the defaults are the live bot. a shadow bot runs
+/// other weights next to it to compare their picks on real games.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct MoveWeights {
+    pub effect: f64,      // weight of the narrowing of the possible maps against the cost
+    pub survey_cost: f64, // scale of the cost of each kind of move
+    pub target_cost: f64,
+    pub research_cost: f64,
+}
+
+impl Default for MoveWeights {
+    fn default() -> Self {
+        MoveWeights {
+            effect: 10.0,
+            survey_cost: 1.0,
+            target_cost: 1.0,
+            research_cost: 1.0,
+        }
+    }
+}
+
 pub fn best_move(
     info: BestMoveInfo,
     clues: Vec<Clue>, // should not used the conn field
     user_state: &UserState,
     tokens: &[Token],
     choice_filter: &ChoiceFilter,
+) -> Operation {
+    best_move_with(
+        &MoveWeights::default(),
+        info,
+        clues,
+        user_state,
+        tokens,
+        choice_filter,
+    )
+}
+
+/// the best move by the given weights.
+pub fn best_move_with(
+    weights: &MoveWeights,
+    info: BestMoveInfo,
+    clues: Vec<Clue>,
+    user_state: &UserState,
+    tokens: &[Token],
+    choice_filter: &ChoiceFilter,
 ) -> Operation {
     let mut candidate_operations = vec![];
 
@@ -116,7 +116,7 @@ pub fn best_move(
         })
         .flatten()
         .collect();
-    moves.sort_by(|a, b| b.weight().partial_cmp(&a.weight()).unwrap());
+    moves.sort_by(|a, b| b.weight(weights).partial_cmp(&a.weight(weights)).unwrap());
     if moves.is_empty() {
         error!("No moves available");
         // todo
@@ -125,7 +125,7 @@ pub fn best_move(
     for m in &moves {
         info!(
             "- w{:.4}|s{:2}|e{:.5}|c{}|{:?}",
-            m.weight(),
+            m.weight(weights),
             m.score,
             m.filter_effect,
             m.cost,
@@ -136,6 +136,41 @@ pub fn best_move(
     return moves[0].op.clone();
 }
 
+/// how the move ranks among the bot's candidates in the user move stage,
+/// 1.0 for the bot's best move and 0.0 for its worst. None if the move is not a candidate.
+pub fn rank_move(
+    info: BestMoveInfo,
+    clues: &[Clue],
+    user_state: &UserState,
+    tokens: &[Token],
+    choice_filter: &ChoiceFilter,
+    op: &Operation,
+) -> Option<f64> {
+    if info.stage != GameStage::UserMove || !choice_filter.initialized {
+        return None;
+    }
+    let mut candidate_operations = vec![CandidateOperation::Survey];
+    if can_research(user_state) {
+        candidate_operations.push(CandidateOperation::Research);
+    }
+    if can_target(user_state) {
+        candidate_operations.push(CandidateOperation::Target);
+    }
+    let moves: Vec<_> = candidate_operations
+        .into_iter()
+        .flat_map(|c_op| {
+            map_candidate_operations(c_op, &info, clues, user_state, tokens, choice_filter)
+        })
+        .collect();
+    if moves.len() < 2 {
+        return None;
+    }
+    let weights = MoveWeights::default();
+    let weight = moves.iter().find(|m| m.op == *op)?.weight(&weights);
+    let better = moves.iter().filter(|m| m.weight(&weights) > weight).count();
+    Some(1.0 - better as f64 / (moves.len() - 1) as f64)
+}
+
 fn can_research(user_state: &UserState) -> bool {
     if user_state
         .moves
@@ -168,10 +203,16 @@ struct PossibleMove {
 }
 
 impl PossibleMove {
-    fn weight(&self) -> f64 {
+    fn weight(&self, weights: &MoveWeights) -> f64 {
         // [0-20]
-        let effect = self.score + self.filter_effect * 10.0;
-        (effect + 1.0) / self.cost as f64
+        let effect = self.score + self.filter_effect * weights.effect;
+        let cost_scale = match self.op {
+            Operation::Survey(_) => weights.survey_cost,
+            Operation::Target(_) => weights.target_cost,
+            Operation::Research(_) => weights.research_cost,
+            _ => 1.0,
+        };
+        (effect + 1.0) / (self.cost as f64 * cost_scale)
     }
 }
 
@@ -384,16 +425,19 @@ fn best_shot(
         .filter_map(|x| usable_token(x).then_some(x.r#type.clone()))
         .unique()
         .collect::<Vec<_>>();
-    info!("possible sector tokens: {:?}", possible_sector_tokens);
+    info!(
+        "possible sector tokens: {:?}",
+        Secret(&possible_sector_tokens)
+    );
     let guessed_sectors = tokens
         .iter()
         .filter_map(|x| {
-            (x.placed && (x.secret.meeting_index != 4 || x.secret.r#type.is_some()))
-                .then_some(x.secret.sector_index)
+            // a disproved theory is revealed, so every placed token marks a guessed sector
+            x.placed.then_some(x.secret.sector_index)
         })
         .unique()
         .collect::<Vec<_>>();
-    info!("guessed sectors: {:?}", guessed_sectors);
+    info!("guessed sectors: {:?}", Secret(&guessed_sectors));
     let mut best_shot = all_possibilities
         .0
         .into_iter()
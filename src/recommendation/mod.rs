@@ -1,13 +1,23 @@
 mod best_move;
 pub use best_move::*;
+mod quality;
+pub use quality::*;
+#[cfg(feature = "metrics")]
+mod shadow;
+#[cfg(feature = "metrics")]
+pub use shadow::*;
 
 use serde::{Deserialize, Serialize};
 
+use crate::room::LegalActions;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RecommendOperation {
     Count,
     CanLocate,
+    /// the operations the caller may send now, answered even when hints are disabled.
+    LegalActions,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,4 +25,5 @@ pub enum RecommendOperation {
 pub enum RecommendOperationResult {
     Count(usize),
     CanLocate(bool),
+    LegalActions(LegalActions),
 }
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+
+/// how close a player's moves are to the bot's evaluation, collected in competitive rooms.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayQuality {
+    pub samples: usize,
+    pub best_moves: usize, // moves the bot would rank first
+    pub accuracy_sum: f64,
+    #[serde(default)]
+    pub competitive_games: usize, // finished competitive games the samples come from
+}
+
+impl PlayQuality {
+    pub fn record(&mut self, accuracy: f64) {
+        self.samples += 1;
+        if accuracy >= 1.0 {
+            self.best_moves += 1;
+        }
+        self.accuracy_sum += accuracy;
+    }
+
+    /// add the moves of another id of the same player.
+    #[cfg(feature = "integrations")]
+    pub fn merge(&mut self, other: &PlayQuality) {
+        self.samples += other.samples;
+        self.best_moves += other.best_moves;
+        self.accuracy_sum += other.accuracy_sum;
+        self.competitive_games += other.competitive_games;
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn mean_accuracy(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.accuracy_sum / self.samples as f64
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn best_move_rate(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.best_moves as f64 / self.samples as f64
+    }
+
+    /// consistently matching the bot's top choice is not how humans play.
+    #[cfg(feature = "admin")]
+    pub fn is_suspicious(&self, min_samples: usize, max_best_move_rate: f64) -> bool {
+        self.samples >= min_samples && self.best_move_rate() >= max_best_move_rate
+    }
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct QualityReport {
+    pub user_id: String,
+    pub samples: usize,
+    pub mean_accuracy: f64,
+    pub best_move_rate: f64,
+    pub competitive_games: usize,
+}
+
+#[cfg(feature = "admin")]
+impl QualityReport {
+    pub fn new(user_id: &str, quality: &PlayQuality) -> Self {
+        QualityReport {
+            user_id: user_id.to_string(),
+            samples: quality.samples,
+            mean_accuracy: quality.mean_accuracy(),
+            best_move_rate: quality.best_move_rate(),
+            competitive_games: quality.competitive_games,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "admin"))]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_play_quality() {
+        let mut q = PlayQuality::default();
+        assert!(!q.is_suspicious(0, 0.5));
+        q.record(1.0);
+        q.record(0.5);
+        q.record(1.0);
+        q.record(0.0);
+        assert_eq!(q.samples, 4);
+        assert_eq!(q.best_move_rate(), 0.5);
+        assert_eq!(q.mean_accuracy(), 0.625);
+        assert!(q.is_suspicious(4, 0.5));
+        assert!(!q.is_suspicious(5, 0.5));
+        assert!(!q.is_suspicious(4, 0.6));
+    }
+}
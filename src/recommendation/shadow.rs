@@ -0,0 +1,163 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    operation::Operation,
+    room::{GameStage, GameStateResp},
+};
+
+/// settled divergences kept for the report, the oldest are dropped.
+const MAX_SETTLED: usize = 200;
+
+/// the turns a shadow bot picked another move than the live bot, waiting for the end of their
+/// game to know how the live pick turned out.
+#[derive(Debug, Default)]
+pub struct ShadowLog {
+    turns: u64,
+    divergences: u64,
+    pending: HashMap<String, Vec<Divergence>>, // room_id -> divergences of the running game
+    settled: VecDeque<Divergence>,             // latest last
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Divergence {
+    pub room_id: String,
+    pub seat_id: String,
+    pub round: usize,
+    pub stage: GameStage,
+    pub live: Operation,   // played
+    pub shadow: Operation, // the shadow bot's pick instead
+    pub maps_before: Option<usize>,
+    pub maps_after: Option<usize>, // left by the live move
+    pub final_score: Option<usize>,
+    pub final_rank: Option<usize>, // 1 for the winner
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ShadowReport {
+    pub turns: u64,
+    pub divergences: u64,
+    pub pending: usize,
+    pub latest: Vec<Divergence>, // settled, latest first
+}
+
+impl ShadowLog {
+    /// a bot turn both bots picked a move for, the live one played.
+    pub fn record(&mut self, divergence: Divergence) {
+        self.turns += 1;
+        if divergence.live == divergence.shadow {
+            return;
+        }
+        self.divergences += 1;
+        info!(
+            "shadow bot diverged at room {} for {}: live {:?}, shadow {:?}",
+            divergence.room_id, divergence.seat_id, divergence.live, divergence.shadow
+        );
+        self.pending
+            .entry(divergence.room_id.clone())
+            .or_default()
+            .push(divergence);
+    }
+
+    /// settle the divergences of the finished games with the final score and rank of the seat.
+    pub fn settle(&mut self, gs: &GameStateResp) {
+        let Some(results) = &gs.game_result else {
+            return;
+        };
+        let Some(divergences) = self.pending.remove(&gs.id) else {
+            return;
+        };
+        for mut divergence in divergences {
+            let result = results.iter().find(|r| r.id == divergence.seat_id);
+            divergence.final_rank = result.map(|r| r.rank);
+            divergence.final_score = result.map(|r| r.sum);
+            info!(
+                "shadow bot divergence settled at room {}: {:?} ended rank {:?}",
+                divergence.room_id, divergence.live, divergence.final_rank
+            );
+            if self.settled.len() >= MAX_SETTLED {
+                self.settled.pop_front();
+            }
+            self.settled.push_back(divergence);
+        }
+    }
+
+    /// forget the divergences of the rooms that are gone before their end.
+    pub fn retain_rooms(&mut self, alive: impl Fn(&str) -> bool) {
+        self.pending.retain(|id, _| alive(id));
+    }
+
+    pub fn report(&self) -> ShadowReport {
+        ShadowReport {
+            turns: self.turns,
+            divergences: self.divergences,
+            pending: self.pending.values().map(Vec::len).sum(),
+            latest: self.settled.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        operation::{ResearchOperation, TargetOperation},
+        room::UserResultSummary,
+    };
+
+    #[test]
+    fn test_shadow_log() {
+        let divergence = |shadow| Divergence {
+            room_id: "1234".to_string(),
+            seat_id: "bot".to_string(),
+            round: 1,
+            stage: GameStage::UserMove,
+            live: Operation::Target(TargetOperation { index: 2 }),
+            shadow,
+            maps_before: Some(10),
+            maps_after: Some(4),
+            final_score: None,
+            final_rank: None,
+        };
+        let mut log = ShadowLog::default();
+        log.record(divergence(Operation::Target(TargetOperation { index: 2 })));
+        log.record(divergence(Operation::Research(ResearchOperation {
+            index: crate::map::ClueEnum::A,
+        })));
+        assert_eq!(log.report().pending, 1);
+
+        let mut gs = GameStateResp::new("1234".to_string());
+        log.settle(&gs);
+        assert_eq!(log.report().pending, 1);
+        let result = |id: &str, rank, sum| UserResultSummary {
+            id: id.to_string(),
+            name: id.to_string(),
+            rank,
+            sum,
+            first: 0,
+            comet: 0,
+            asteroid: 0,
+            dwarf_planet: 0,
+            nebula: 0,
+            x: 0,
+            bonus: 0,
+            step: 0,
+            bot: None,
+        };
+        gs.game_result = Some(vec![result("u1", 1, 30), result("bot", 2, 20)]);
+        log.settle(&gs);
+
+        let report = log.report();
+        assert_eq!(
+            (report.turns, report.divergences, report.pending),
+            (2, 1, 0)
+        );
+        assert_eq!(report.latest[0].final_rank, Some(2));
+        assert_eq!(report.latest[0].final_score, Some(20));
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// log the secret values in full, see `log_secrets` in the config.
+static LOG_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// turn the full logging of the secret values on or off, it stays off in release builds.
+pub fn set_log_secrets(on: bool) {
+    LOG_SECRETS.store(on && cfg!(debug_assertions), Ordering::Relaxed);
+}
+
+/// a value a player must not learn from the logs, e.g. the map, the clues, the tokens or the
+/// results of another player's operations. logged as its type unless `log_secrets` is on.
+pub struct Secret<'a, T: ?Sized>(pub &'a T);
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Secret<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_SECRETS.load(Ordering::Relaxed) {
+            return self.0.fmt(f);
+        }
+        // the type without its path nor its arguments, e.g. `Vec`
+        let name = std::any::type_name::<T>();
+        let name = name.split('<').next().unwrap_or(name);
+        write!(f, "<redacted {}>", name.rsplit("::").next().unwrap_or(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_secret() {
+        let sectors = vec![1, 2, 3];
+        set_log_secrets(false);
+        let logged = format!("{:?}", Secret(&sectors));
+        assert!(!logged.contains('2'));
+        assert_eq!(logged, "<redacted Vec>");
+    }
+}
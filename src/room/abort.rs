@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{map::SkyWindow, server_state::User};
+
+use super::{GameState, GameStateResp, ServerGameState, UserState};
+
+/// a player votes on aborting the running game, the first yes calls the vote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AbortBallot {
+    pub room_id: String,
+    pub agree: bool,
+}
+
+/// an open vote to abort the game, public in `game_state`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct AbortVote {
+    pub caller: String,
+    pub agree: Vec<String>,  // user ids
+    pub reject: Vec<String>, // user ids
+    pub deadline: u64,       // unix secs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbortOutcome {
+    Pending,
+    Passed,
+    Failed,
+}
+
+impl AbortVote {
+    pub fn new(caller: &str, deadline: u64) -> Self {
+        AbortVote {
+            caller: caller.to_string(),
+            agree: vec![],
+            reject: vec![],
+            deadline,
+        }
+    }
+
+    /// a player may change their mind until the vote is settled.
+    pub fn cast(&mut self, user_id: &str, agree: bool) {
+        self.agree.retain(|id| id != user_id);
+        self.reject.retain(|id| id != user_id);
+        let side = if agree {
+            &mut self.agree
+        } else {
+            &mut self.reject
+        };
+        side.push(user_id.to_string());
+    }
+
+    pub fn has_voted(&self, user_id: &str) -> bool {
+        self.agree
+            .iter()
+            .chain(self.reject.iter())
+            .any(|id| id == user_id)
+    }
+
+    /// passed once more than `ratio` of the voters agree, failed once that can't happen anymore
+    /// or at the deadline.
+    pub fn outcome(&self, voters: usize, ratio: f64, now: u64) -> AbortOutcome {
+        let needed = ((voters as f64 * ratio).floor() as usize + 1).clamp(1, voters.max(1));
+        if self.agree.len() >= needed {
+            AbortOutcome::Passed
+        } else if voters.saturating_sub(self.reject.len()) < needed || now >= self.deadline {
+            AbortOutcome::Failed
+        } else {
+            AbortOutcome::Pending
+        }
+    }
+}
+
+impl GameStateResp {
+    /// settle the open abort vote, return true if the room changed. a passed vote ends the
+    /// game unscored.
+    pub fn settle_abort_vote(&mut self, ss: &mut ServerGameState, ratio: f64, now: u64) -> bool {
+        let Some(vote) = &self.abort_vote else {
+            return false;
+        };
+        // the idle seats handed over to the bot don't hold the vote back
+        let voters = self
+            .users
+            .iter()
+            .filter(|u| !u.seat().is_bot() && (!u.bot_controlled || vote.has_voted(&u.id)))
+            .count();
+        match vote.outcome(voters, ratio, now) {
+            AbortOutcome::Pending => false,
+            AbortOutcome::Failed => {
+                self.abort_vote = None;
+                true
+            }
+            AbortOutcome::Passed => {
+                self.return_to_lobby();
+                *ss = ServerGameState::placeholder();
+                self.hint = Some("The game was aborted by vote".to_string());
+                true
+            }
+        }
+    }
+
+    /// back to `NotStarted` with the same players, settings and handicaps, only the bots ready.
+    pub fn return_to_lobby(&mut self) {
+        let mut room = GameStateResp::new(self.id.clone());
+        room.map_type = self.map_type.clone();
+        room.map_seed = self.map_seed;
+        room.set_window(SkyWindow::first(&room.map_type));
+        room.rules = self.rules.clone();
+        room.created_at = self.created_at;
+        room.users = self
+            .users
+            .iter()
+            .enumerate()
+            .map(|(i, u)| {
+                let user = User {
+                    id: u.id.clone(),
+                    name: u.name.clone(),
+                    service: false,
+                    client_version: None,
+                    capabilities: Default::default(),
+                    appearance: u.appearance.clone(),
+                };
+                UserState {
+                    handicap: u.handicap.clone(),
+                    cast_consent: u.cast_consent,
+                    persona: u.persona,
+                    disconnected_at: u.disconnected_at,
+                    ..UserState::placeholder(&user, i + 1, u.seat().is_bot())
+                }
+            })
+            .collect();
+        *self = room;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !matches!(self.status, GameState::NotStarted | GameState::End)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_abort_vote_outcome() {
+        let mut vote = AbortVote::new("u1", 100);
+        vote.cast("u1", true);
+        assert_eq!(vote.outcome(4, 0.5, 10), AbortOutcome::Pending);
+        vote.cast("u2", true);
+        assert_eq!(vote.outcome(4, 0.5, 10), AbortOutcome::Pending);
+        assert_eq!(vote.outcome(3, 0.5, 10), AbortOutcome::Passed);
+        assert_eq!(vote.outcome(4, 0.5, 100), AbortOutcome::Failed);
+        vote.cast("u3", false);
+        vote.cast("u4", false);
+        assert_eq!(vote.outcome(4, 0.5, 10), AbortOutcome::Failed);
+        // a lone player aborts at once
+        let mut vote = AbortVote::new("u1", 100);
+        vote.cast("u1", true);
+        assert_eq!(vote.outcome(1, 0.5, 10), AbortOutcome::Passed);
+    }
+
+    #[test]
+    fn test_abort_returns_to_lobby() {
+        use crate::{map::MapType, room::BotPersona};
+
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_type = MapType::Expert;
+        for (i, id) in ["u1", "u2"].iter().enumerate() {
+            let user = User::test(id);
+            gs.users.push(UserState::placeholder(&user, i + 1, false));
+        }
+        gs.users.push(UserState::bot("1", BotPersona::default(), 3));
+        gs.users[0].handicap.bonus_points = 10;
+        gs.users.iter_mut().for_each(|u| u.ready = true);
+        gs.status = GameState::AutoMove;
+        gs.set_window(SkyWindow::starting_at(7, &MapType::Expert));
+        let mut vote = AbortVote::new("u1", 100);
+        vote.cast("u1", true);
+        vote.cast("u2", true);
+        gs.abort_vote = Some(vote);
+
+        let mut ss = ServerGameState::placeholder();
+        assert!(gs.settle_abort_vote(&mut ss, 0.5, 10));
+        assert_eq!(gs.status, GameState::NotStarted);
+        assert!(gs.abort_vote.is_none());
+        let first = SkyWindow::first(&MapType::Expert);
+        assert_eq!((gs.start_index, gs.end_index), (first.start, first.end));
+        // the humans ready again, the bot seat stays ready
+        let ready = gs.users.iter().map(|u| u.ready).collect::<Vec<_>>();
+        assert_eq!(ready, vec![false, false, true]);
+        assert_eq!(gs.users[0].handicap.bonus_points, 10);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "admin")]
+use tracing::warn;
+
+#[cfg(feature = "admin")]
+use crate::{
+    inspect::{SeedReport, inspect_seed},
+    map::ClueWeights,
+};
+use crate::{
+    map::MapType,
+    room::{
+        GameStage, GameState, GameStateResp, JournalEntry, ServerGameState, StoredRoom, now_secs,
+    },
+};
+
+/// bug reports kept for the admin api, the oldest are dropped.
+const MAX_BUG_REPORTS: usize = 100;
+
+/// stage transitions kept per room, the oldest are dropped.
+const MAX_STAGE_TRANSITIONS: usize = 500;
+
+/// longest note a player can attach to a report, longer ones are cut.
+pub const MAX_BUG_NOTE_LEN: usize = 1000;
+
+/// a player asking for a bug report of their room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BugReportRequest {
+    pub room_id: String,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// the id to give along with the report, the admins retrieve the bundle with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BugReportFiled {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StageTransition {
+    pub at: u64, // unix secs, as seen by the tick
+    pub status: GameState,
+    pub stage: GameStage,
+    pub round: usize,
+    pub moves: usize, // journal length
+}
+
+/// the statuses and stages each room went through, for the bug reports.
+#[derive(Debug, Default)]
+pub struct StageLog {
+    rooms: HashMap<String, VecDeque<StageTransition>>, // room_id -> transitions, latest last
+}
+
+impl StageLog {
+    /// look at a room after it may have changed, a transition is logged when its status, stage
+    /// or round did.
+    pub fn observe(&mut self, gs: &GameStateResp, ss: &ServerGameState, at: u64) {
+        let log = self.rooms.entry(gs.id.clone()).or_default();
+        let unchanged = log.back().is_some_and(|t| {
+            t.status == gs.status && t.stage == gs.game_stage && t.round == gs.round
+        });
+        if unchanged {
+            return;
+        }
+        if log.len() >= MAX_STAGE_TRANSITIONS {
+            log.pop_front();
+        }
+        log.push_back(StageTransition {
+            at,
+            status: gs.status.clone(),
+            stage: gs.game_stage.clone(),
+            round: gs.round,
+            moves: ss.journal.len(),
+        });
+    }
+
+    pub fn retain_rooms(&mut self, alive: impl Fn(&str) -> bool) {
+        self.rooms.retain(|id, _| alive(id));
+    }
+
+    pub fn transitions(&self, room_id: &str) -> Vec<StageTransition> {
+        self.rooms
+            .get(room_id)
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// everything needed to replay a room locally: its seed dealt again, every accepted operation,
+/// the stages it went through and its state now and at its autosaved snapshots.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BugReport {
+    pub id: String,
+    pub created_at: u64,
+    pub room_id: String,
+    pub reporter: Option<String>, // user id, None when filed by an admin
+    pub note: Option<String>,
+    pub map_type: MapType,
+    pub seed: u64,   // the seed the room dealt, a hidden one included
+    pub dealt: bool, // false before the deal
+    pub journal: Vec<JournalEntry>,
+    pub stages: Vec<StageTransition>,
+    pub game_state: GameStateResp,
+    pub snapshots: Vec<StoredRoom>, // oldest first
+}
+
+/// a bug report as the admins fetch it, with the seed report computed on the fetch.
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BugReportBundle {
+    #[serde(flatten)]
+    pub report: BugReport,
+    pub seed_report: Option<SeedReport>, // None before the deal
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BugReportInfo {
+    pub id: String,
+    pub created_at: u64,
+    pub room_id: String,
+    pub reporter: Option<String>,
+    pub note: Option<String>,
+}
+
+impl BugReport {
+    pub fn new(
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        stages: Vec<StageTransition>,
+        snapshots: Vec<StoredRoom>,
+        reporter: Option<String>,
+        note: Option<String>,
+    ) -> Self {
+        BugReport {
+            id: uuid::Uuid::new_v4().to_string(),
+            created_at: now_secs(),
+            room_id: gs.id.clone(),
+            reporter,
+            note: note.map(|note| note.chars().take(MAX_BUG_NOTE_LEN).collect()),
+            map_type: gs.map_type.clone(),
+            seed: ss.hidden_seed.unwrap_or(gs.map_seed),
+            dealt: !ss.research_clues.is_empty(),
+            journal: ss.journal.clone(),
+            stages,
+            game_state: gs.clone(),
+            snapshots,
+        }
+    }
+
+    /// deal the seed again and solve it, slow: call it off the state lock. the clues are dealt
+    /// with the weights of now, they differ if the weights changed since the room was dealt.
+    #[cfg(feature = "admin")]
+    pub fn seed_report(&self, clue_weights: &ClueWeights) -> Option<SeedReport> {
+        if !self.dealt {
+            return None;
+        }
+        inspect_seed(self.seed, self.map_type.clone(), clue_weights)
+            .inspect_err(|e| {
+                let (room_id, seed) = (&self.room_id, self.seed);
+                warn!("room {}: seed {} doesn't deal: {}", room_id, seed, e)
+            })
+            .ok()
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn info(&self) -> BugReportInfo {
+        BugReportInfo {
+            id: self.id.clone(),
+            created_at: self.created_at,
+            room_id: self.room_id.clone(),
+            reporter: self.reporter.clone(),
+            note: self.note.clone(),
+        }
+    }
+}
+
+/// the filed bug reports, latest last.
+#[derive(Debug, Default)]
+pub struct BugReports {
+    reports: VecDeque<BugReport>,
+}
+
+impl BugReports {
+    /// keep the report and return its id. a player reporting a room again replaces their
+    /// earlier report of it.
+    pub fn file(&mut self, report: BugReport) -> String {
+        if report.reporter.is_some() {
+            self.reports
+                .retain(|r| r.room_id != report.room_id || r.reporter != report.reporter);
+        }
+        if self.reports.len() >= MAX_BUG_REPORTS {
+            self.reports.pop_front();
+        }
+        let id = report.id.clone();
+        self.reports.push_back(report);
+        id
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn get(&self, id: &str) -> Option<&BugReport> {
+        self.reports.iter().find(|r| r.id == id)
+    }
+
+    /// latest first.
+    #[cfg(feature = "admin")]
+    pub fn list(&self) -> Vec<BugReportInfo> {
+        self.reports.iter().rev().map(BugReport::info).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_stage_log() {
+        let mut gs = GameStateResp::new("1234".to_string());
+        let ss = ServerGameState::placeholder();
+        let mut log = StageLog::default();
+        log.observe(&gs, &ss, 1);
+        log.observe(&gs, &ss, 2);
+        gs.game_stage = GameStage::MeetingProposal;
+        log.observe(&gs, &ss, 3);
+        let transitions = log.transitions("1234");
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(transitions[1].stage, GameStage::MeetingProposal);
+        assert_eq!(transitions[1].at, 3);
+
+        log.retain_rooms(|_| false);
+        assert!(log.transitions("1234").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_bug_reports() {
+        let gs = GameStateResp::new("1234".to_string());
+        let ss = ServerGameState::placeholder();
+        let report = |reporter: Option<&str>| {
+            let reporter = reporter.map(str::to_string);
+            BugReport::new(&gs, &ss, vec![], vec![], reporter, None)
+        };
+        let mut reports = BugReports::default();
+        let first = reports.file(report(Some("u1")));
+        let second = reports.file(report(Some("u1")));
+        reports.file(report(None));
+        reports.file(report(None));
+        assert!(reports.get(&first).is_none());
+        let second = reports.get(&second).unwrap();
+        assert!(second.seed_report(&ClueWeights::default()).is_none());
+        assert_eq!(reports.list().len(), 3);
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{Clue, Sector, Token},
+    room::{GameStateResp, JournalEntry, ServerGameState},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CastOperation {
+    Join(CastJoin),
+    Leave(String),
+}
+
+/// joining a room as a caster needs the caster token an admin issued for the room,
+/// or the admin token itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CastJoin {
+    pub room_id: String,
+    pub token: String,
+}
+
+/// answer of the admin api to a caster token request.
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CasterToken {
+    pub room_id: String,
+    pub token: String,
+}
+
+/// full picture of a running game for commentary clients, never sent to players.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CasterView {
+    pub game_state: GameStateResp,
+    pub sectors: Vec<Sector>,
+    pub research_clues: Vec<Clue>,
+    pub x_clues: Vec<Clue>,
+    pub players: Vec<PlayerKnowledge>,
+}
+
+/// what a player knows privately, as far as the server can tell.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PlayerKnowledge {
+    pub id: String,
+    pub name: String,
+    pub tokens: Vec<Token>,
+    pub moves: Vec<JournalEntry>,
+    pub remaining_maps: usize, // maps still consistent with what the player has seen
+    pub can_locate: bool,
+}
+
+impl CasterView {
+    pub fn new(gs: &GameStateResp, ss: &ServerGameState) -> Self {
+        let players = gs
+            .users
+            .iter()
+            .map(|u| {
+                let choices = ss.choices.get(&u.id);
+                PlayerKnowledge {
+                    id: u.id.clone(),
+                    name: u.name.clone(),
+                    tokens: ss.user_tokens.get(&u.id).cloned().unwrap_or_default(),
+                    moves: ss
+                        .journal
+                        .iter()
+                        .filter(|e| e.user_id == u.id)
+                        .cloned()
+                        .collect(),
+                    remaining_maps: choices.and_then(|c| c.count()).unwrap_or(0),
+                    can_locate: choices.is_some_and(|c| c.can_locate()),
+                }
+            })
+            .collect();
+        CasterView {
+            game_state: gs.clone(),
+            sectors: ss.map.sectors.data.clone(),
+            research_clues: ss.research_clues.clone(),
+            x_clues: ss.x_clues.clone(),
+            players,
+        }
+    }
+
+    /// socket.io room of the casters of a game room.
+    pub fn channel(room_id: &str) -> String {
+        format!("caster-{room_id}")
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast::board_tokens,
+    map::{Clue, ClueSecret, SecretToken, SectorType, Token},
+    operation::OperationResult,
+    room::{GameStateResp, ServerGameState},
+    sink::Capabilities,
+};
+
+/// everything a seat legitimately knows, in one message for scripted clients.
+/// the same information a browser client pieces together from the `sync` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct CatchUp {
+    pub game_state: GameStateResp,
+    pub seat: usize, // index of the user in `game_state.users`
+    pub research_clues: Vec<ClueSecret>,
+    pub x_clues: Vec<Clue>,            // only the revealed ones
+    pub results: Vec<OperationResult>, // results of the user's own moves, in order
+    pub tokens: Vec<Token>,
+    pub board_tokens: Vec<SecretToken>,
+    pub handicap_reveals: Vec<(usize, SectorType)>,
+}
+
+impl CatchUp {
+    pub fn new(gs: &GameStateResp, ss: &ServerGameState, user_id: &str) -> Option<Self> {
+        let seat = gs.users.iter().position(|u| u.id == user_id)?;
+        Some(CatchUp {
+            game_state: gs.clone(),
+            seat,
+            research_clues: ss.clue_secret(),
+            x_clues: revealed_x_clues(gs, ss),
+            results: gs.users[seat].moves_result.clone(),
+            tokens: ss.user_tokens.get(user_id).cloned().unwrap_or_default(),
+            board_tokens: board_tokens(ss),
+            handicap_reveals: ss
+                .handicap_reveals
+                .get(user_id)
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// the answer to `sync` of a running game, for the clients advertising `SYNC_BUNDLE`: what the
+/// `game_start`, `game_state`, `op_result`, `xclue`, `handicap_reveal`, `token` and
+/// `board_tokens` events carry, applied at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SyncBundle {
+    pub clue_secrets: Vec<ClueSecret>,
+    pub game_state: GameStateResp,
+    pub op_results: Vec<OperationResult>, // of the user's own moves, in order
+    pub x_clues: Vec<Clue>,               // only the revealed ones
+    pub handicap_reveals: Vec<(usize, SectorType)>,
+    pub tokens: Vec<Token>,
+    pub board_tokens: Vec<SecretToken>,
+}
+
+impl SyncBundle {
+    pub fn new(
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        user_id: &str,
+        capabilities: Capabilities,
+    ) -> Option<Self> {
+        let user = gs.users.iter().find(|u| u.id == user_id)?;
+        Some(SyncBundle {
+            clue_secrets: ss.clue_secret(),
+            game_state: gs.clone(),
+            op_results: user
+                .moves_result
+                .iter()
+                .map(|r| capabilities.op_result(r.clone()))
+                .collect(),
+            x_clues: revealed_x_clues(gs, ss),
+            handicap_reveals: ss
+                .handicap_reveals
+                .get(user_id)
+                .cloned()
+                .unwrap_or_default(),
+            tokens: ss.user_tokens.get(user_id).cloned().unwrap_or_default(),
+            board_tokens: board_tokens(ss),
+        })
+    }
+}
+
+/// x clues whose reveal point has been passed.
+pub fn revealed_x_clues(gs: &GameStateResp, ss: &ServerGameState) -> Vec<Clue> {
+    gs.map_type
+        .xclue_points()
+        .iter()
+        .zip(ss.x_clues.iter())
+        .filter(|((index, _), _)| gs.round > 1 || gs.start_index > *index)
+        .map(|(_, clue)| clue.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{room::UserState, server_state::User};
+
+    #[test]
+    fn test_catch_up_seat() {
+        let mut gs = GameStateResp::empty();
+        let ss = ServerGameState::placeholder();
+        assert!(CatchUp::new(&gs, &ss, "u2").is_none());
+
+        for (i, id) in ["u1", "u2"].iter().enumerate() {
+            let user = User {
+                service: true,
+                ..User::test(id)
+            };
+            gs.users.push(UserState::placeholder(&user, i + 1, false));
+        }
+        let catch_up = CatchUp::new(&gs, &ss, "u2").unwrap();
+        assert_eq!(catch_up.seat, 1);
+        assert!(catch_up.x_clues.is_empty());
+    }
+
+    #[test]
+    fn test_sync_bundle() {
+        let mut gs = GameStateResp::empty();
+        let ss = ServerGameState::placeholder();
+        let user = User {
+            capabilities: Capabilities::SYNC_BUNDLE,
+            ..User::test("u1")
+        };
+        let mut seat = UserState::placeholder(&user, 1, false);
+        let miss = OperationResult::LocateMiss(crate::operation::LocateMiss {
+            adjacent_wrong: None,
+            extra_time: 2,
+        });
+        seat.moves_result.push(miss);
+        gs.users.push(seat);
+        assert!(SyncBundle::new(&gs, &ss, "u2", user.capabilities).is_none());
+
+        // the results read as the client understands them
+        let bundle = SyncBundle::new(&gs, &ss, "u1", user.capabilities).unwrap();
+        assert_eq!(bundle.op_results, vec![OperationResult::Locate(false)]);
+        assert!(bundle.tokens.is_empty() && bundle.board_tokens.is_empty());
+    }
+}
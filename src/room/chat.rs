@@ -0,0 +1,181 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+/// characters a chat message may have at most.
+pub const MAX_CHAT_LEN: usize = 280;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatOperation {
+    Send(ChatSend),
+    /// the host silences a player of the room, until unmuted or the room is gone.
+    Mute(ChatTarget),
+    Unmute(ChatTarget),
+    /// hide the messages of a user in every room, user_id.
+    Block(String),
+    Unblock(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ChatSend {
+    pub room_id: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ChatTarget {
+    pub room_id: String,
+    pub user_id: String,
+}
+
+/// a message as delivered, after the filters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ChatMessage {
+    pub room_id: String,
+    pub user_id: String,
+    pub name: String,
+    pub text: String,
+    pub sent_at: u64,
+}
+
+/// the emotes a player can react with, the clients pick their pictures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Emote {
+    ThumbsUp,
+    Clap,
+    Laugh,
+    Think,
+    Wow,
+    Sad,
+    GoodGame,
+}
+
+/// a reaction sent by a player of the room, aimed at another player or at everyone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ReactionSend {
+    pub room_id: String,
+    pub emote: Emote,
+    #[serde(default)]
+    pub target: Option<String>, // user_id
+}
+
+/// a reaction as relayed to the room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Reaction {
+    pub room_id: String,
+    pub user_id: String,
+    pub emote: Emote,
+    pub target: Option<String>,
+    pub sent_at: u64,
+}
+
+/// the chat state of a room.
+#[derive(Debug, Clone, Default)]
+pub struct RoomChat {
+    pub muted: HashSet<String>, // user ids
+}
+
+/// a server-side hook every message goes through before delivery, in order.
+pub trait ChatFilter: Send + Sync {
+    fn check(&self, text: &str) -> FilterVerdict;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterVerdict {
+    Pass,
+    Replace(String),
+    Reject,
+}
+
+/// masks the words of the config list, whatever their case and surrounding punctuation.
+#[derive(Debug, Clone)]
+pub struct WordFilter {
+    words: HashSet<String>, // lowercase
+}
+
+impl WordFilter {
+    pub fn new(words: &[String]) -> Self {
+        WordFilter {
+            words: words.iter().map(|w| w.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl ChatFilter for WordFilter {
+    fn check(&self, text: &str) -> FilterVerdict {
+        let (mut masked, mut kept) = (false, false);
+        let words = text
+            .split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+                if self.words.contains(&bare.to_lowercase()) {
+                    masked = true;
+                    "*".repeat(word.chars().count())
+                } else {
+                    kept |= !word.is_empty();
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+        match (masked, kept) {
+            (false, _) => FilterVerdict::Pass,
+            (true, true) => FilterVerdict::Replace(words.join(" ")),
+            // nothing but banned words is not worth sending as stars
+            (true, false) => FilterVerdict::Reject,
+        }
+    }
+}
+
+/// a moderation action, kept for the admin api.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ModerationEntry {
+    pub at: u64,
+    pub room_id: Option<String>, // None for the block list
+    pub actor: String,           // user_id, or `filter`
+    pub target: String,          // user_id
+    pub action: ModerationAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Mute,
+    Unmute,
+    Block,
+    Unblock,
+    Masked,
+    Rejected,
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_word_filter() {
+        let filter = WordFilter::new(&["Darn".to_string()]);
+        assert_eq!(filter.check("good game"), FilterVerdict::Pass);
+        assert_eq!(
+            filter.check("darn, DARN it"),
+            FilterVerdict::Replace("***** **** it".to_string())
+        );
+        assert_eq!(filter.check("DARN darn!"), FilterVerdict::Reject);
+    }
+
+    #[test]
+    fn test_reaction_serde() {
+        let send: ReactionSend =
+            serde_json::from_str(r#"{"room_id":"r1","emote":"good_game"}"#).unwrap();
+        assert_eq!(send.emote, Emote::GoodGame);
+        assert!(send.target.is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use sha2::{Digest, Sha256};
+
+use crate::room::{GameStateResp, ServerGameState};
+
+/// the public board as the clients hold it from `game_state` and `board_tokens`, one line each:
+///
+/// ```text
+/// stage user_move
+/// window 1-6
+/// seat <user id> <location index>.<child index> <moves>
+/// token <user index> <sector index> <meeting index>
+/// ```
+///
+/// the seats in the room order, the placed tokens by user and sector index.
+pub fn public_board(gs: &GameStateResp, ss: &ServerGameState) -> String {
+    let stage = serde_json::to_value(&gs.game_stage)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!("stage {stage}"),
+        format!("window {}-{}", gs.start_index, gs.end_index),
+    ];
+    for u in gs.users.iter() {
+        let (index, child) = (u.location.index, u.location.child_index);
+        lines.push(format!("seat {} {index}.{child} {}", u.id, u.moves.len()));
+    }
+    let mut tokens = ss
+        .user_tokens
+        .values()
+        .flatten()
+        .filter(|t| t.placed)
+        .map(|t| &t.secret)
+        .collect::<Vec<_>>();
+    tokens.sort_by_key(|t| (t.user_index, t.sector_index));
+    for t in tokens {
+        let meeting = t.state.meeting_index();
+        lines.push(format!(
+            "token {} {} {meeting}",
+            t.user_index, t.sector_index
+        ));
+    }
+    lines.join("\n")
+}
+
+impl GameStateResp {
+    /// `board_checksum`: the first 8 bytes of the sha256 of the `public_board`, in hex. a client
+    /// whose own board hashes otherwise missed an event and should `sync`.
+    pub fn stamp_checksum(&mut self, ss: &ServerGameState) {
+        let digest = Sha256::digest(public_board(self, ss).as_bytes());
+        self.board_checksum = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        map::{SectorType, Token},
+        room::UserState,
+        server_state::User,
+    };
+
+    #[test]
+    fn test_board_checksum() {
+        let mut gs = GameStateResp::new("1234".to_string());
+        let mut ss = ServerGameState::placeholder();
+        let user = User::test("u1");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        let token = Token::new(SectorType::Comet, "u1", 1);
+        ss.user_tokens.insert("u1".to_string(), vec![token]);
+        assert_eq!(
+            public_board(&gs, &ss),
+            "stage user_move\nwindow 1-6\nseat u1 1.1 0"
+        );
+
+        gs.stamp_checksum(&ss);
+        let before = gs.board_checksum.clone();
+        assert_eq!(before.len(), 16);
+        gs.stamp_checksum(&ss);
+        assert_eq!(gs.board_checksum, before);
+
+        // a token placed changes the board
+        ss.user_tokens.get_mut("u1").unwrap()[0].placed = true;
+        gs.stamp_checksum(&ss);
+        assert_ne!(gs.board_checksum, before);
+    }
+}
@@ -0,0 +1,134 @@
+use serde::Serialize;
+
+use crate::room::{GameStage, GameState, GameStateResp, ServerGameState, UserState};
+
+/// live stats of every room for the admin dashboard.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Dashboard {
+    pub connections: usize,
+    pub rooms: Vec<RoomStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RoomStats {
+    pub id: String,
+    pub uptime_secs: u64,
+    pub status: GameState,
+    pub game_stage: GameStage,
+    pub round: usize,
+    pub start_index: usize,
+    pub last_activity: u64, // unix secs of the last accepted player action
+    pub players: Vec<SeatStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SeatStats {
+    pub id: String,
+    pub name: String,
+    pub is_bot: bool,
+    pub bot_controlled: bool,
+    pub connected: bool,
+    pub choice_filter: Option<usize>, // maps left in the seat's filter, None before the game starts
+}
+
+/// where a user is on the server, for the admin user search.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UserWhereabouts {
+    pub id: String,
+    pub name: String,
+    pub sockets: Vec<String>,  // socket.io ids connected as the user
+    pub plain_connected: bool, // over `/ws` or sse
+    pub seats: Vec<UserSeat>,
+    pub last_action: Option<u64>, // unix secs of the last accepted operation, since the start
+}
+
+/// a room the user is seated in.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct UserSeat {
+    pub room_id: String,
+    pub status: GameState,
+    pub game_stage: GameStage,
+    pub dormant: bool,
+    pub waited_for: bool,
+    pub seat: SeatStats,
+}
+
+impl UserSeat {
+    pub fn new(
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        user_id: &str,
+        dormant: bool,
+    ) -> Option<Self> {
+        let user = gs.users.iter().find(|u| u.id == user_id)?;
+        Some(UserSeat {
+            room_id: gs.id.clone(),
+            status: gs.status.clone(),
+            game_stage: gs.game_stage.clone(),
+            dormant,
+            waited_for: gs.check_waiting(user_id),
+            seat: SeatStats::new(user, ss),
+        })
+    }
+}
+
+impl SeatStats {
+    pub fn new(u: &UserState, ss: &ServerGameState) -> Self {
+        SeatStats {
+            id: u.id.clone(),
+            name: u.name.clone(),
+            is_bot: u.seat().is_bot(),
+            bot_controlled: u.bot_controlled,
+            connected: u.seat().is_bot() || u.disconnected_at.is_none(),
+            choice_filter: ss.choices.get(&u.id).and_then(|c| c.count()),
+        }
+    }
+}
+
+impl RoomStats {
+    pub fn new(gs: &GameStateResp, ss: &ServerGameState, now: u64) -> Self {
+        let players = gs.users.iter().map(|u| SeatStats::new(u, ss)).collect();
+        RoomStats {
+            id: gs.id.clone(),
+            uptime_secs: now.saturating_sub(gs.created_at),
+            status: gs.status.clone(),
+            game_stage: gs.game_stage.clone(),
+            round: gs.round,
+            start_index: gs.start_index,
+            last_activity: gs.last_activity,
+            players,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::server_state::User;
+
+    #[test]
+    fn test_room_stats() {
+        let mut gs = GameStateResp::empty();
+        gs.created_at = 100;
+        let user = User::test("u1");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        let ss = ServerGameState::placeholder();
+
+        let stats = RoomStats::new(&gs, &ss, 160);
+        assert_eq!(stats.uptime_secs, 60);
+        assert_eq!(stats.players.len(), 1);
+        assert!(stats.players[0].connected);
+        assert_eq!(stats.players[0].choice_filter, None);
+
+        gs.status = GameState::Wait(vec!["u1".to_string()]);
+        let seat = UserSeat::new(&gs, &ss, "u1", false).unwrap();
+        assert!(seat.waited_for && !seat.dormant);
+        assert!(UserSeat::new(&gs, &ss, "u2", false).is_none());
+    }
+}
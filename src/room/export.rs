@@ -0,0 +1,243 @@
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{Clue, MapType, Sector, Token},
+    operation::Operation,
+    room::{
+        GameStateResp, Handicap, JournalEntry, RoomRules, ServerGameState, Solvability,
+        UserResultSummary,
+    },
+};
+
+pub const EXPORT_VERSION: u32 = 1;
+
+#[cfg(feature = "admin")]
+const ANONYMOUS_NAME: &str = "anonymous";
+
+/// self-contained record of a finished game, built from the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct GameExport {
+    pub version: u32,
+    pub room_id: String,
+    pub map_type: MapType,
+    pub map_seed: u64,
+    pub rules: RoomRules,
+    #[serde(default)]
+    pub competitive: bool, // played without hints, its moves were rated
+    pub sectors: Vec<Sector>,
+    pub research_clues: Vec<Clue>,
+    pub x_clues: Vec<Clue>,
+    #[serde(default)]
+    pub solvability: Option<Solvability>,
+    pub players: Vec<PlayerExport>,
+    pub journal: Vec<JournalEntry>,
+    pub results: Vec<UserResultSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PlayerExport {
+    pub id: String,
+    pub name: String,
+    pub is_bot: bool,
+    pub handicap: Handicap,
+    pub tokens: Vec<Token>,
+    pub stats: PlayerStats,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PlayerStats {
+    pub surveys: usize,
+    pub targets: usize,
+    pub researches: usize,
+    pub locates: usize,
+    pub publishes: usize,
+    #[serde(default)]
+    pub passes: usize,
+    #[serde(default)]
+    pub think_ms: u64, // summed over the turns the server timed
+    pub final_step: usize,
+}
+
+impl PlayerStats {
+    fn record(&mut self, op: &Operation) {
+        match op {
+            Operation::Survey(_) => self.surveys += 1,
+            Operation::Target(_) => self.targets += 1,
+            Operation::Research(_) => self.researches += 1,
+            Operation::Locate(_) => self.locates += 1,
+            Operation::ReadyPublish(_) | Operation::DoPublish(_) => self.publishes += 1,
+            Operation::Batch(steps) => steps.iter().for_each(|step| self.record(step)),
+            Operation::Pass => self.passes += 1,
+        }
+    }
+}
+
+impl GameExport {
+    pub fn new(gs: &GameStateResp, ss: &ServerGameState) -> Self {
+        let players = gs
+            .users
+            .iter()
+            .map(|u| {
+                let mut stats = PlayerStats {
+                    final_step: u.location.index,
+                    ..Default::default()
+                };
+                ss.journal
+                    .iter()
+                    .filter(|e| e.user_id == u.id)
+                    .for_each(|e| {
+                        stats.record(&e.op);
+                        stats.think_ms += e.think_ms.unwrap_or(0);
+                    });
+                PlayerExport {
+                    id: u.id.clone(),
+                    name: u.name.clone(),
+                    is_bot: u.seat().is_bot(),
+                    handicap: u.handicap.clone(),
+                    tokens: ss.user_tokens.get(&u.id).cloned().unwrap_or_default(),
+                    stats,
+                }
+            })
+            .collect();
+        GameExport {
+            version: EXPORT_VERSION,
+            room_id: gs.id.clone(),
+            map_type: gs.map_type.clone(),
+            map_seed: gs.map_seed,
+            rules: gs.rules.clone(),
+            competitive: gs.rules.is_competitive(),
+            sectors: ss.map.sectors.data.clone(),
+            research_clues: ss.research_clues.clone(),
+            x_clues: ss.x_clues.clone(),
+            solvability: gs.solvability.clone(),
+            players,
+            journal: ss.journal.clone(),
+            results: gs.game_result.clone().unwrap_or_default(),
+        }
+    }
+
+    /// move the game of a player to another id, false if they didn't play the game.
+    pub fn rename_player(&mut self, user_id: &str, new_id: &str) -> bool {
+        let Some(player) = self.players.iter_mut().find(|p| p.id == user_id) else {
+            return false;
+        };
+        player.id = new_id.to_string();
+        for token in player.tokens.iter_mut() {
+            token.secret.user_id = new_id.to_string();
+        }
+        for entry in self.journal.iter_mut().filter(|e| e.user_id == user_id) {
+            entry.user_id = new_id.to_string();
+        }
+        for result in self.results.iter_mut().filter(|r| r.id == user_id) {
+            result.id = new_id.to_string();
+        }
+        true
+    }
+
+    /// drop the link to a player, their results stay under an alias of their seat.
+    /// false if they didn't play the game.
+    #[cfg(feature = "admin")]
+    pub fn anonymize(&mut self, user_id: &str) -> bool {
+        let Some(seat) = self.players.iter().position(|p| p.id == user_id) else {
+            return false;
+        };
+        let alias = format!("anonymous-{}", seat + 1);
+        self.rename_player(user_id, &alias);
+        self.players[seat].name = ANONYMOUS_NAME.to_string();
+        for result in self.results.iter_mut().filter(|r| r.id == alias) {
+            result.name = ANONYMOUS_NAME.to_string();
+        }
+        true
+    }
+
+    /// plain text summary for sharing outside the client.
+    pub fn summary(&self) -> String {
+        let mut s = String::new();
+        writeln!(
+            s,
+            "Room {} ({:?}, seed {})",
+            self.room_id, self.map_type, self.map_seed
+        )
+        .ok();
+        let map = self
+            .sectors
+            .iter()
+            .map(|sector| sector.r#type.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(s, "Map: {}", map).ok();
+        for clue in self.research_clues.iter().chain(self.x_clues.iter()) {
+            writeln!(s, "Clue: {}", clue).ok();
+        }
+        if let Some(solvability) = &self.solvability {
+            writeln!(s, "Difficulty: {:?}", solvability.badge).ok();
+        }
+        for r in &self.results {
+            writeln!(
+                s,
+                "{}: {} points (x {}, bonus {}, step {})",
+                r.name, r.sum, r.x, r.bonus, r.step
+            )
+            .ok();
+        }
+        for p in &self.players {
+            let st = &p.stats;
+            writeln!(
+                s,
+                "{}: {} surveys, {} targets, {} researches, {} locates, {} publishes",
+                p.name, st.surveys, st.targets, st.researches, st.locates, st.publishes
+            )
+            .ok();
+        }
+        writeln!(s, "Moves: {}", self.journal.len()).ok();
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_player_stats() {
+        let mut stats = PlayerStats::default();
+        let ops: Vec<Operation> = serde_json::from_str(
+            r#"[{"survey":{"sector_type":"comet","start":1,"end":3}},{"research":{"index":"A"}}]"#,
+        )
+        .unwrap();
+        ops.iter().for_each(|op| stats.record(op));
+        assert_eq!(stats.surveys, 1);
+        assert_eq!(stats.researches, 1);
+        assert_eq!(stats.targets, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_anonymize() {
+        let mut gs = GameStateResp::empty();
+        gs.users = vec![
+            crate::room::UserState::placeholder(&user("u1"), 0, false),
+            crate::room::UserState::placeholder(&user("u2"), 1, false),
+        ];
+        let mut export = GameExport::new(&gs, &ServerGameState::placeholder());
+        assert!(export.anonymize("u2"));
+        assert!(!export.anonymize("u3"));
+        assert_eq!(export.players[0].id, "u1");
+        assert_eq!(export.players[1].id, "anonymous-2");
+        assert_eq!(export.players[1].name, ANONYMOUS_NAME);
+    }
+
+    #[cfg(feature = "admin")]
+    fn user(id: &str) -> crate::server_state::User {
+        crate::server_state::User {
+            name: format!("name of {id}"),
+            ..crate::server_state::User::test(id)
+        }
+    }
+}
@@ -1,15 +1,23 @@
 use std::collections::HashMap;
 
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    map::{ChoiceFilter, Clue, ClueSecret, Map, MapType, SecretToken, SectorType, Token},
-    operation::{Operation, OperationResult},
-    room::OpError,
+    ids::Seat,
+    map::{
+        ChoiceFilter, Clue, ClueSecret, Map, MapType, SecretToken, SectorRange, SectorType,
+        SkyWindow, Token,
+    },
+    operation::{Operation, OperationKind, OperationResult, TargetOperation},
+    room::{
+        AbortVote, BotFlair, BotPersona, ConferenceResult, OpError, PacingSummary, PlayerPacing,
+        PuzzleOrigin, RoomRules, Solvability,
+    },
     server_state::User,
 };
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct GameStateResp {
     pub id: String, // some rand id for each room. first 4 chars of uuid.
@@ -24,9 +32,28 @@ pub struct GameStateResp {
     pub map_seed: u64,
     pub map_type: MapType,
     pub game_result: Option<Vec<UserResultSummary>>,
+    pub rules: RoomRules,
+    #[serde(default)]
+    pub abort_vote: Option<AbortVote>,
+    #[serde(default)]
+    pub solvability: Option<Solvability>, // once the game is dealt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub puzzle: Option<PuzzleOrigin>, // a practice room, replaying a finished game
+    #[serde(default)]
+    pub board_checksum: String, // of the public board, see `board_checksum`. empty until dealt
+    #[serde(skip)]
+    pub created_at: u64,
+    #[serde(skip)]
+    pub last_activity: u64, // unix secs of the last accepted player action
+    #[serde(skip)]
+    pub start_countdown: Option<std::time::Instant>,
+    #[serde(skip)]
+    pub wait_since: Option<std::time::Instant>,
+    #[serde(skip)]
+    pub deadline_warned: Option<u64>, // secs left of the last turn deadline warning
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum GameStage {
     UserMove,
@@ -38,6 +65,61 @@ pub enum GameStage {
 }
 
 impl GameStateResp {
+    /// the legal actions of a player of the room, from the checks `handle_action_op` enforces.
+    pub fn legal_actions(&self, user_id: &str) -> Option<LegalActions> {
+        let user = self.users.iter().find(|u| u.id == user_id)?;
+        let operations = if self.check_waiting(user_id) {
+            let stage = &self.game_stage;
+            let user_may = |kind: &OperationKind| match kind {
+                OperationKind::Target => user.targets_left() > 0,
+                OperationKind::Research => !user.research_blocked(),
+                OperationKind::Locate => *stage != GameStage::LastMove || user.can_locate,
+                _ => true,
+            };
+            stage
+                .operations()
+                .iter()
+                .copied()
+                .filter(user_may)
+                .collect()
+        } else {
+            vec![]
+        };
+        Some(LegalActions {
+            stage: self.game_stage.clone(),
+            operations,
+            targets_left: user.targets_left(),
+            research_blocked: user.research_blocked(),
+            survey_window: (self.start_index, self.end_index),
+        })
+    }
+}
+
+impl GameStage {
+    /// the operations a waiting player may send in the stage.
+    pub fn operations(&self) -> &'static [OperationKind] {
+        use OperationKind::*;
+        match self {
+            GameStage::UserMove => &[Survey, Target, Research, Locate, Pass],
+            GameStage::MeetingProposal => &[ReadyPublish, Pass],
+            GameStage::MeetingPublish => &[DoPublish, Pass],
+            GameStage::LastMove => &[DoPublish, Locate, Pass],
+            GameStage::MeetingCheck | GameStage::GameEnd => &[],
+        }
+    }
+}
+
+impl GameStateResp {
+    /// the part of the track the players can survey, target and locate in.
+    pub fn window(&self) -> SkyWindow {
+        SkyWindow::from_indexes(self.start_index, self.end_index, &self.map_type)
+    }
+
+    pub fn set_window(&mut self, window: SkyWindow) {
+        self.start_index = window.start;
+        self.end_index = window.end;
+    }
+
     pub fn new(id: String) -> Self {
         GameStateResp {
             id,
@@ -51,6 +133,16 @@ impl GameStateResp {
             map_seed: rand::random::<u32>() as u64,
             map_type: MapType::Standard,
             game_result: None,
+            rules: RoomRules::default(),
+            abort_vote: None,
+            solvability: None,
+            puzzle: None,
+            board_checksum: String::new(),
+            created_at: super::now_secs(),
+            last_activity: super::now_secs(),
+            start_countdown: None,
+            wait_since: None,
+            deadline_warned: None,
         }
     }
 
@@ -67,9 +159,23 @@ impl GameStateResp {
             map_seed: 0,
             map_type: MapType::Standard,
             game_result: None,
+            rules: RoomRules::default(),
+            abort_vote: None,
+            solvability: None,
+            puzzle: None,
+            board_checksum: String::new(),
+            created_at: 0,
+            last_activity: 0,
+            start_countdown: None,
+            wait_since: None,
+            deadline_warned: None,
         }
     }
 
+    pub fn check_waiting(&self, user_id: &str) -> bool {
+        matches!(&self.status, GameState::Wait(waiting_list) if waiting_list.iter().any(|id| id == user_id))
+    }
+
     pub fn check_waiting_for(&mut self, user_id: &str) -> bool {
         // if status is Wating, and user_id is in the waiting list, return true and delete it from the list.
         if let GameState::Wait(ref mut waiting_list) = self.status {
@@ -77,6 +183,8 @@ impl GameStateResp {
                 waiting_list.remove(index);
                 if waiting_list.is_empty() {
                     self.status = GameState::AutoMove;
+                    self.wait_since = None;
+                    self.deadline_warned = None;
                 }
                 return true;
             }
@@ -84,6 +192,40 @@ impl GameStateResp {
         false
     }
 
+    /// casting is enabled and every human in the room agreed to it.
+    pub fn casting_allowed(&self) -> bool {
+        self.rules.casting
+            && self
+                .users
+                .iter()
+                .all(|u| u.seat().is_bot() || u.cast_consent)
+    }
+
+    pub fn pacing_summary(&self) -> PacingSummary {
+        let sector_count = self.map_type.sector_count();
+        PacingSummary {
+            room_id: self.id.clone(),
+            players: self.users.iter().map(|u| u.pacing(sector_count)).collect(),
+        }
+    }
+
+    /// the first human in the room, who can change the room settings.
+    pub fn host_id(&self) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|u| !u.seat().is_bot())
+            .map(|u| u.id.as_str())
+    }
+
+    /// the seat of a member of the room. the stats, hints and notifications skip the bot seats
+    /// through it, whatever their id.
+    pub fn seat_of(&self, user_id: &str) -> Option<Seat> {
+        self.users
+            .iter()
+            .find(|u| u.id == user_id)
+            .map(UserState::seat)
+    }
+
     pub fn user_move(&mut self, user_id: &str, delta: usize) -> Result<(), OpError> {
         let all = self
             .users
@@ -101,7 +243,7 @@ impl GameStateResp {
     }
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum GameState {
     NotStarted,
@@ -111,7 +253,7 @@ pub enum GameState {
     End,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct UserState {
     pub id: String,
@@ -121,10 +263,33 @@ pub struct UserState {
     pub last_move: bool,
     pub can_locate: bool,
     pub moves: Vec<Operation>,
+    #[serde(default)]
+    pub targets_remaining: usize, // `targets_left`, kept by `sync_limits`
+    #[serde(default)]
+    pub research_locked: bool, // `research_blocked`, kept by `sync_limits`
     #[serde(skip)]
     pub moves_result: Vec<OperationResult>,
     pub used_token: Vec<SecretToken>,
-    pub is_bot: bool,
+    pub is_bot: bool, // sent to the clients, the server reads it through `seat()`
+    pub bot_controlled: bool, // an idle player handed over to the bot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona: Option<BotPersona>, // of the bot seat
+    pub handicap: Handicap,
+    pub cast_consent: bool, // agreed to casters seeing the private state
+    #[serde(default)]
+    pub appearance: Appearance,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_think_ms: Option<u64>, // of the last move, as the server timed it
+    #[serde(skip)]
+    pub missed_turns: usize,
+    #[serde(skip)]
+    pub think_time: std::time::Duration, // summed over the timed turns
+    #[serde(skip)]
+    pub timed_turns: usize,
+    #[serde(skip)]
+    pub ready_at: Option<std::time::Instant>,
+    #[serde(skip)]
+    pub disconnected_at: Option<std::time::Instant>,
 }
 
 impl UserState {
@@ -137,17 +302,187 @@ impl UserState {
             last_move: true,
             can_locate: true,
             moves: vec![],
+            targets_remaining: MAX_TARGETS,
+            research_locked: false,
             moves_result: vec![],
             used_token: vec![],
             is_bot,
+            bot_controlled: false,
+            persona: None,
+            handicap: Handicap::default(),
+            cast_consent: false,
+            appearance: user.appearance.clone().validated(),
+            last_think_ms: None,
+            missed_turns: 0,
+            think_time: std::time::Duration::ZERO,
+            timed_turns: 0,
+            ready_at: None,
+            disconnected_at: None,
+        }
+    }
+
+    /// the bot seat of a room, the one way the server seats a bot.
+    pub fn bot(room_id: &str, persona: BotPersona, child_index: usize) -> Self {
+        let mut user = Seat::bot(room_id).user(persona.name());
+        user.appearance.avatar = Some(persona.avatar().to_string());
+        UserState {
+            persona: Some(persona),
+            ..Self::placeholder(&user, child_index, true)
+        }
+    }
+
+    /// switch the bot seat to another persona.
+    pub fn set_persona(&mut self, persona: BotPersona) {
+        self.name = persona.name().to_string();
+        self.appearance.avatar = Some(persona.avatar().to_string());
+        self.persona = Some(persona);
+    }
+
+    pub fn seat(&self) -> Seat {
+        Seat::of(&self.id, self.is_bot)
+    }
+
+    /// the server makes the moves for this seat.
+    pub fn is_server_driven(&self) -> bool {
+        self.seat().is_bot() || self.bot_controlled
+    }
+
+    /// targets the player may still use, `MAX_TARGETS` per game.
+    pub fn targets_left(&self) -> usize {
+        let used = self
+            .moves
+            .iter()
+            .filter(|op| matches!(op, Operation::Target(_)))
+            .count();
+        MAX_TARGETS.saturating_sub(used)
+    }
+
+    /// how the player paced the game so far, for the room.
+    pub fn pacing(&self, sector_count: usize) -> PlayerPacing {
+        let sectors_surveyed = self
+            .moves
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Survey(s) => SectorRange::new(s.start, s.end, sector_count).ok(),
+                _ => None,
+            })
+            .map(|range| range.span() + 1)
+            .sum();
+        PlayerPacing {
+            user_id: self.id.clone(),
+            name: self.name.clone(),
+            turns: self.moves.len(),
+            avg_think_secs: (self.timed_turns > 0)
+                .then(|| self.think_time.as_secs_f64() / self.timed_turns as f64),
+            sectors_surveyed,
+        }
+    }
+
+    /// a research can't follow another research.
+    pub fn research_blocked(&self) -> bool {
+        self.moves
+            .last()
+            .is_some_and(|op| matches!(op, Operation::Research(_)))
+    }
+
+    /// the public `targets_remaining` and `research_locked` again, once the moves changed.
+    pub fn sync_limits(&mut self) {
+        self.targets_remaining = self.targets_left();
+        self.research_locked = self.research_blocked();
+    }
+}
+
+/// targets a player can use in a game.
+pub const MAX_TARGETS: usize = 2;
+
+/// what a player may do now, by the rules. not a hint, the server refuses anything else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LegalActions {
+    pub stage: GameStage,
+    pub operations: Vec<OperationKind>, // empty when not the player's turn
+    pub targets_left: usize,
+    pub research_blocked: bool,
+    pub survey_window: (usize, usize), // start and end index
+}
+
+/// colors a player can pick from, the clients share the palette.
+pub const PALETTE_SIZE: u8 = 8;
+/// characters of an avatar id at most.
+const MAX_AVATAR_LEN: usize = 32;
+
+/// how the clients draw a player, chosen by the player and kept across devices.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct Appearance {
+    pub color: Option<u8>,      // index in the palette
+    pub avatar: Option<String>, // `[a-z0-9_-]` id of a picture the clients ship
+}
+
+impl Appearance {
+    /// drop what the server doesn't accept, the clients fall back to their default.
+    pub fn validated(self) -> Self {
+        let avatar = self.avatar.filter(|a| {
+            !a.is_empty()
+                && a.len() <= MAX_AVATAR_LEN
+                && a.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+        });
+        Appearance {
+            color: self.color.filter(|c| *c < PALETTE_SIZE),
+            avatar,
         }
     }
 }
 
+impl GameStateResp {
+    /// give the seat its color, or the first one free when another player of the room has it.
+    pub fn assign_color(&mut self, user_id: &str) {
+        let taken = |c: u8| {
+            self.users
+                .iter()
+                .any(|u| u.id != user_id && u.appearance.color == Some(c))
+        };
+        let wanted = self
+            .users
+            .iter()
+            .find(|u| u.id == user_id)
+            .and_then(|u| u.appearance.color)
+            .filter(|c| !taken(*c));
+        let color = wanted.or_else(|| (0..PALETTE_SIZE).find(|c| !taken(*c)));
+        if let Some(u) = self.users.iter_mut().find(|u| u.id == user_id) {
+            u.appearance.color = color;
+        }
+    }
+}
+
+/// advantages given by the host to a weaker player, public to the room.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct Handicap {
+    pub revealed_sectors: usize, // sectors revealed to the player at the start
+    pub survey_discount: usize,  // time saved on each survey, a survey always costs at least 1
+    pub bonus_points: usize,     // added to the final score
+}
+
+impl Handicap {
+    pub fn is_valid(&self) -> bool {
+        self.revealed_sectors <= 3 && self.survey_discount <= 2 && self.bonus_points <= 20
+    }
+
+    pub fn survey_cost(&self, base: usize) -> usize {
+        base.saturating_sub(self.survey_discount).max(1)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserResultSummary {
     pub id: String,
     pub name: String,
+    /// 1 for the winners, the players tied after every tie break share a rank and the next
+    /// rank counts them, e.g. 1, 1, 3. 0 in the results kept before the ranks.
+    #[serde(default)]
+    pub rank: usize,
     pub sum: usize,
     pub first: usize,
     pub comet: usize,        // 彗星得分
@@ -155,7 +490,10 @@ pub struct UserResultSummary {
     pub dwarf_planet: usize, // 矮行星得分
     pub nebula: usize,       // 星云得分
     pub x: usize,            // x clue
+    pub bonus: usize,        // handicap bonus points
     pub step: usize,         // 终局位置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot: Option<BotFlair>, // the persona of a bot seat
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -222,6 +560,24 @@ pub struct ServerGameState {
     pub terminator_location: Option<UserLocationSequence>,
     pub revealed_sector_indexs: Vec<usize>,
     pub choices: HashMap<String, ChoiceFilter>,
+    pub located_order: Vec<String>, // user ids in the order they located x
+    pub handicap_reveals: HashMap<String, Vec<(usize, SectorType)>>, // user_id -> revealed sectors
+    pub journal: Vec<JournalEntry>, // every accepted operation
+    pub hidden_seed: Option<u64>,   // the seed of a `server_seed` room, public at the end
+    pub conferences: Vec<ConferenceResult>, // the meetings held so far, public
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct JournalEntry {
+    pub user_id: String,
+    pub round: usize,
+    pub stage: GameStage,
+    pub op: Operation,
+    pub result: OperationResult,
+    // from the `your_turn` to the operation, timed by the server. None for bots and untimed turns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub think_ms: Option<u64>,
 }
 
 impl ServerGameState {
@@ -234,6 +590,71 @@ impl ServerGameState {
             terminator_location: None,
             revealed_sector_indexs: vec![],
             choices: HashMap::new(),
+            located_order: vec![],
+            handicap_reveals: HashMap::new(),
+            journal: vec![],
+            hidden_seed: None,
+            conferences: vec![],
+        }
+    }
+
+    /// the json dump of a player's operations, tokens and the true map, once the player's
+    /// filter no longer admits the true map. None while the filter holds it.
+    pub fn choice_divergence(&self, user_id: &str) -> Option<String> {
+        let choices = self.choices.get(user_id)?;
+        if choices.admits(&self.map.sectors) {
+            return None;
+        }
+        let dump = serde_json::json!({
+            "user_id": user_id,
+            "map": self.map.sectors.data,
+            "tokens": self.user_tokens.get(user_id),
+            "ops": choices.operations(),
+        });
+        Some(dump.to_string())
+    }
+
+    /// the proposals of a meeting are made at once, journal them in track order rather than in
+    /// the order they arrived.
+    pub fn order_proposals(&mut self, track_order: &[String]) {
+        let start = self
+            .journal
+            .iter()
+            .rposition(|e| e.stage != GameStage::MeetingProposal)
+            .map_or(0, |i| i + 1);
+        self.journal[start..].sort_by_key(|e| track_order.iter().position(|id| *id == e.user_id));
+    }
+
+    /// reveal random non-x sectors to the players with a handicap, as if they targeted them.
+    pub fn reveal_handicap_sectors(&mut self, users: &[UserState], seed: u64) {
+        for (index, user) in users.iter().enumerate() {
+            if user.handicap.revealed_sectors == 0 {
+                continue;
+            }
+            let mut rng = SmallRng::seed_from_u64(seed.wrapping_add(index as u64 + 1));
+            let mut candidates = self
+                .map
+                .sectors
+                .data
+                .iter()
+                .filter(|s| s.r#type != SectorType::X)
+                .map(|s| s.index)
+                .collect::<Vec<_>>();
+            candidates.shuffle(&mut rng);
+            let reveals = candidates
+                .into_iter()
+                .take(user.handicap.revealed_sectors)
+                .filter_map(|i| Some((i, self.map.target_sector(i).ok()?)))
+                .collect::<Vec<_>>();
+            if let Some(choice) = self.choices.get_mut(&user.id) {
+                for (i, r#type) in &reveals {
+                    choice.add_operation(
+                        Operation::Target(TargetOperation { index: *i }),
+                        OperationResult::Target(r#type.clone()),
+                    );
+                }
+            }
+            self.handicap_reveals.insert(user.id.clone(), reveals);
         }
     }
 
@@ -287,11 +708,25 @@ impl ServerGameState {
             .iter_mut()
             .find(|t| t.is_ready_published(r#type))
             .ok_or(OpError::TokenNotEnough)?
-            .set_published(index);
+            .set_published(index)?;
         *tokens = edited_tokens;
         Ok(())
     }
 
+    /// take back the tokens the player picked for the meeting and didn't publish, return how many.
+    pub fn withdraw_ready_tokens(&mut self, user_id: &str) -> Result<usize, OpError> {
+        let tokens = self
+            .user_tokens
+            .get_mut(user_id)
+            .ok_or(OpError::UserNotFoundInRoom)?;
+        let mut withdrawn = 0;
+        for token in tokens.iter_mut().filter(|t| t.any_ready_published()) {
+            token.placed = false;
+            withdrawn += 1;
+        }
+        Ok(withdrawn)
+    }
+
     pub fn last_move_publish_token(
         &mut self,
         user_id: &str,
@@ -310,7 +745,7 @@ impl ServerGameState {
             .find(|t| !t.placed && t.r#type == *r#type)
             .ok_or(OpError::TokenNotEnough)?
             .set_to_be_placed()
-            .set_published(index);
+            .set_published(index)?;
         // *tokens = edited_tokens;
         Ok(())
     }
@@ -321,20 +756,100 @@ mod tests {
     #[allow(unused_imports)]
     use super::*;
 
+    #[test]
+    fn test_seat_of() {
+        let mut gs = GameStateResp::new("1234".to_string());
+        let user = Seat::Human("bot-1234".into()).user("a human");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        gs.users
+            .push(UserState::bot("5678", BotPersona::Protocol, 2));
+        // the seat is told by the room, not by the id
+        assert_eq!(gs.seat_of("bot-1234"), Some(Seat::Human("bot-1234".into())));
+        assert_eq!(gs.seat_of("bot-5678"), Some(Seat::bot("5678")));
+        assert_eq!(gs.seat_of("u2"), None);
+    }
+
+    #[test]
+    fn test_appearance() {
+        let appearance = Appearance {
+            color: Some(PALETTE_SIZE),
+            avatar: Some("Robot!".to_string()),
+        };
+        assert_eq!(appearance.validated(), Appearance::default());
+
+        let mut gs = GameStateResp::new("1".to_string());
+        for id in ["u1", "u2"] {
+            let user = User {
+                appearance: Appearance {
+                    color: Some(3),
+                    avatar: Some("robot_2".to_string()),
+                },
+                ..User::test(id)
+            };
+            gs.users.push(UserState::placeholder(&user, 1, false));
+            gs.assign_color(id);
+        }
+        assert_eq!(gs.users[0].appearance.color, Some(3));
+        assert_eq!(gs.users[1].appearance.color, Some(0));
+        assert_eq!(gs.users[1].appearance.avatar.as_deref(), Some("robot_2"));
+    }
+
     #[test]
     fn test_game_state_serde() {
         let mut gs = GameStateResp::empty();
         let json = serde_json::to_string(&gs).unwrap();
         assert_eq!(
             json,
-            r#"{"id":"","status":"not_started","game_stage":"user_move","hint":null,"users":[],"start_index":1,"end_index":6,"map_seed":0,"map_type":"standard"}"#
+            r#"{"id":"","status":"not_started","game_stage":"user_move","hint":null,"users":[],"start_index":1,"end_index":6,"map_seed":0,"map_type":"standard","game_result":null,"rules":{"idle_kick_turns":3,"x_bonus":null,"no_hints":false,"casting":false,"failed_locate":"reveal_nothing","server_seed":false},"abort_vote":null,"solvability":null,"board_checksum":""}"#
         );
 
         gs.status = GameState::Wait(vec!["1234".to_string()]);
         let json = serde_json::to_string(&gs).unwrap();
         assert_eq!(
             json,
-            r#"{"id":"","status":{"wait":["1234"]},"game_stage":"user_move","hint":null,"users":[],"start_index":1,"end_index":6,"map_seed":0,"map_type":"standard"}"#
+            r#"{"id":"","status":{"wait":["1234"]},"game_stage":"user_move","hint":null,"users":[],"start_index":1,"end_index":6,"map_seed":0,"map_type":"standard","game_result":null,"rules":{"idle_kick_turns":3,"x_bonus":null,"no_hints":false,"casting":false,"failed_locate":"reveal_nothing","server_seed":false},"abort_vote":null,"solvability":null,"board_checksum":""}"#
         );
     }
+
+    #[test]
+    fn test_pacing_summary() {
+        use crate::operation::{ResearchOperation, SurveyOperatoin};
+
+        let mut gs = GameStateResp::empty();
+        let user = User::test("u1");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        let seat = &mut gs.users[0];
+        seat.moves = vec![
+            Operation::Survey(SurveyOperatoin {
+                sector_type: SectorType::Space,
+                start: 11,
+                end: 2,
+            }),
+            Operation::Research(ResearchOperation {
+                index: crate::map::ClueEnum::A,
+            }),
+        ];
+        seat.think_time = std::time::Duration::from_secs(30);
+        seat.timed_turns = 3;
+
+        let summary = gs.pacing_summary();
+        assert_eq!(summary.players.len(), 1);
+        let pacing = &summary.players[0];
+        assert_eq!(pacing.turns, 2);
+        assert_eq!(pacing.sectors_surveyed, 4);
+        assert_eq!(pacing.avg_think_secs, Some(10.0));
+    }
+
+    #[test]
+    fn test_casting_allowed() {
+        let mut gs = GameStateResp::empty();
+        let user = User::test("u1");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        assert!(!gs.casting_allowed());
+
+        gs.rules.casting = true;
+        assert!(!gs.casting_allowed());
+        gs.users[0].cast_consent = true;
+        assert!(gs.casting_allowed());
+    }
 }
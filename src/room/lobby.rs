@@ -0,0 +1,296 @@
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map::MapType;
+
+use super::{Appearance, DifficultyBadge, GameState, GameStateResp, Handicap, RoomRules};
+
+const ROOM_CAPACITY: usize = 4;
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct LobbyQuery {
+    pub open_seats_only: bool,
+    pub map_type: Option<MapType>,
+    pub status: Option<LobbyStatus>,
+    pub friends: Vec<String>, // user ids, only rooms with at least one of them if friends_only
+    pub friends_only: bool,
+    pub sort: LobbySort,
+    pub offset: usize,
+    pub limit: usize, // 0 means default page size
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyStatus {
+    Waiting,
+    Playing,
+    Finished,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbySort {
+    #[default]
+    RecentlyCreated,
+    OpenSeats,
+    FriendsFirst,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LobbyRoom {
+    pub id: String,
+    pub map_type: MapType,
+    pub status: LobbyStatus,
+    pub players: usize,
+    pub open_seats: usize,
+    pub has_bot: bool,
+    pub friends: Vec<String>,
+    pub created_at: u64,
+    pub difficulty: Option<DifficultyBadge>, // once the game is dealt
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LobbyPage {
+    pub total: usize,
+    pub offset: usize,
+    pub rooms: Vec<LobbyRoom>,
+}
+
+/// a room before its game, sent as `room_lobby_state`: the seats and the settings, none of the
+/// map window and stages `game_state` carries for a running game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RoomLobbyState {
+    pub id: String,
+    pub status: GameState, // not started, or counting down to the start
+    pub host: Option<String>,
+    pub players: Vec<LobbyPlayer>,
+    pub map_type: MapType,
+    pub map_seed: u64,
+    pub rules: RoomRules,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LobbyPlayer {
+    pub id: String,
+    pub name: String,
+    pub ready: bool,
+    pub is_bot: bool,
+    pub handicap: Handicap,
+    pub appearance: Appearance,
+}
+
+/// the state event a room sends, by whether its game started.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RoomStateEvent<'a> {
+    Lobby(RoomLobbyState),
+    Game(&'a GameStateResp),
+}
+
+impl RoomStateEvent<'_> {
+    pub fn event(&self) -> &'static str {
+        match self {
+            RoomStateEvent::Lobby(_) => "room_lobby_state",
+            RoomStateEvent::Game(_) => "game_state",
+        }
+    }
+}
+
+impl GameStateResp {
+    pub fn in_lobby(&self) -> bool {
+        matches!(self.status, GameState::NotStarted | GameState::Starting)
+    }
+
+    pub fn lobby_state(&self) -> RoomLobbyState {
+        RoomLobbyState {
+            id: self.id.clone(),
+            status: self.status.clone(),
+            host: self.host_id().map(str::to_string),
+            players: self
+                .users
+                .iter()
+                .map(|u| LobbyPlayer {
+                    id: u.id.clone(),
+                    name: u.name.clone(),
+                    ready: u.ready,
+                    is_bot: u.seat().is_bot(),
+                    handicap: u.handicap.clone(),
+                    appearance: u.appearance.clone(),
+                })
+                .collect(),
+            map_type: self.map_type.clone(),
+            map_seed: self.map_seed,
+            rules: self.rules.clone(),
+        }
+    }
+
+    /// `room_lobby_state` before the game, `game_state` once it started.
+    pub fn state_event(&self) -> RoomStateEvent<'_> {
+        match self.in_lobby() {
+            true => RoomStateEvent::Lobby(self.lobby_state()),
+            false => RoomStateEvent::Game(self),
+        }
+    }
+}
+
+impl From<&GameState> for LobbyStatus {
+    fn from(status: &GameState) -> Self {
+        match status {
+            GameState::NotStarted => LobbyStatus::Waiting,
+            GameState::End => LobbyStatus::Finished,
+            _ => LobbyStatus::Playing,
+        }
+    }
+}
+
+impl LobbyRoom {
+    fn new(gs: &GameStateResp, friends: &[String]) -> Self {
+        LobbyRoom {
+            id: gs.id.clone(),
+            map_type: gs.map_type.clone(),
+            status: (&gs.status).into(),
+            players: gs.users.len(),
+            open_seats: ROOM_CAPACITY.saturating_sub(gs.users.len()),
+            has_bot: gs.users.iter().any(|u| u.seat().is_bot()),
+            friends: gs
+                .users
+                .iter()
+                .filter(|u| friends.contains(&u.id))
+                .map(|u| u.id.clone())
+                .collect(),
+            created_at: gs.created_at,
+            difficulty: gs.solvability.as_ref().map(|s| s.badge.clone()),
+        }
+    }
+}
+
+impl LobbyQuery {
+    pub fn apply<'a>(&self, rooms: impl Iterator<Item = &'a GameStateResp>) -> LobbyPage {
+        let mut rooms = rooms
+            .map(|gs| LobbyRoom::new(gs, &self.friends))
+            .filter(|r| !self.open_seats_only || r.open_seats > 0)
+            .filter(|r| self.map_type.as_ref().is_none_or(|t| *t == r.map_type))
+            .filter(|r| self.status.as_ref().is_none_or(|s| *s == r.status))
+            .filter(|r| !self.friends_only || !r.friends.is_empty())
+            .collect::<Vec<_>>();
+
+        // newest first as the base order, then stable sort by the requested key.
+        rooms.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        match self.sort {
+            LobbySort::RecentlyCreated => {}
+            LobbySort::OpenSeats => rooms.sort_by_key(|r| Reverse(r.open_seats)),
+            LobbySort::FriendsFirst => rooms.sort_by_key(|r| Reverse(r.friends.len())),
+        }
+
+        let limit = match self.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+        let total = rooms.len();
+        LobbyPage {
+            total,
+            offset: self.offset,
+            rooms: rooms.into_iter().skip(self.offset).take(limit).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{room::UserState, server_state::User};
+
+    fn room(id: &str, users: &[&str], created_at: u64) -> GameStateResp {
+        let mut gs = GameStateResp::new(id.to_string());
+        gs.created_at = created_at;
+        for (i, u) in users.iter().enumerate() {
+            let user = User::test(u);
+            gs.users.push(UserState::placeholder(&user, i + 1, false));
+        }
+        gs
+    }
+
+    #[test]
+    fn test_lobby_query() {
+        let mut full = room("0001", &["a", "b", "c", "d"], 1);
+        full.status = GameState::AutoMove;
+        let mut expert = room("0002", &["e"], 2);
+        expert.map_type = MapType::Expert;
+        let rooms = [full, expert, room("0003", &["f", "g"], 3)];
+
+        let page = LobbyQuery::default().apply(rooms.iter());
+        assert_eq!(page.total, 3);
+        assert_eq!(
+            page.rooms.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            vec!["0003", "0002", "0001"]
+        );
+
+        let page = LobbyQuery {
+            open_seats_only: true,
+            sort: LobbySort::OpenSeats,
+            ..Default::default()
+        }
+        .apply(rooms.iter());
+        assert_eq!(
+            page.rooms.iter().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+            vec!["0002", "0003"]
+        );
+
+        let page = LobbyQuery {
+            map_type: Some(MapType::Standard),
+            status: Some(LobbyStatus::Waiting),
+            ..Default::default()
+        }
+        .apply(rooms.iter());
+        assert_eq!(page.total, 1);
+        assert_eq!(page.rooms[0].id, "0003");
+
+        let page = LobbyQuery {
+            friends: vec!["c".to_string()],
+            friends_only: true,
+            ..Default::default()
+        }
+        .apply(rooms.iter());
+        assert_eq!(page.total, 1);
+        assert_eq!(page.rooms[0].friends, vec!["c".to_string()]);
+
+        let page = LobbyQuery {
+            offset: 1,
+            limit: 1,
+            ..Default::default()
+        }
+        .apply(rooms.iter());
+        assert_eq!(page.total, 3);
+        assert_eq!(page.rooms.len(), 1);
+        assert_eq!(page.rooms[0].id, "0002");
+    }
+
+    #[test]
+    fn test_state_event() {
+        let mut gs = room("0001", &["a", "b"], 1);
+        gs.users[0].ready = true;
+        let state_event = gs.state_event();
+        assert_eq!(state_event.event(), "room_lobby_state");
+        let wire = serde_json::to_value(&state_event).unwrap();
+        assert_eq!(wire["host"], "a");
+        assert_eq!(wire["players"][0]["ready"], true);
+        assert!(wire.get("start_index").is_none() && wire.get("game_stage").is_none());
+
+        gs.status = GameState::AutoMove;
+        let state_event = gs.state_event();
+        assert_eq!(state_event.event(), "game_state");
+        assert_eq!(
+            serde_json::to_value(&state_event).unwrap()["start_index"],
+            1
+        );
+    }
+}
@@ -1,12 +1,53 @@
+mod abort;
+pub use abort::*;
+mod bug_report;
+pub use bug_report::*;
+mod caster;
+pub use caster::*;
+mod catch_up;
+pub use catch_up::*;
+mod chat;
+pub use chat::*;
+mod checksum;
+#[cfg(feature = "admin")]
+mod dashboard;
+#[cfg(feature = "admin")]
+pub use dashboard::*;
+mod export;
+pub use export::*;
 mod game_state;
 pub use game_state::*;
+mod lobby;
+pub use lobby::*;
+mod preferences;
+pub use preferences::*;
+mod puzzle;
+pub use puzzle::*;
+mod rules;
+pub use rules::*;
+mod save_code;
+pub use save_code::*;
 mod server_resp;
 pub use server_resp::*;
+mod snapshot;
+pub use snapshot::*;
+mod spectator;
+pub use spectator::*;
+mod solvability;
+pub use solvability::*;
+mod theater;
+pub use theater::*;
 
 use serde::{Deserialize, Serialize};
 
 use crate::map::MapType;
 
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RoomUserOperation {
@@ -17,6 +58,18 @@ pub enum RoomUserOperation {
     Prepare(String),
     Unprepare(String),
     SwitchBot(String),
+    /// pick the persona of the room bot, switching the bot on if the room has none.
+    BotPersona(BotPersonaInfo),
+    SetHandicap(HandicapInfo),
+    ConsentCast(String),
+    WithdrawCast(String),
+    AbortVote(AbortBallot),
+    /// open a solo practice room at a decision of a seat of a game the user played.
+    Puzzle(PuzzleInfo),
+    /// deal again the game of a save code and play it up to where it was saved, for its host.
+    Resume(String),
+    /// create a room with the settings of a preset of the user's preferences, by its name.
+    FromPreset(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +78,91 @@ pub struct EditRoomInfo {
     pub room_id: String,
     pub map_type: MapType,
     pub map_seed: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<RoomRules>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BotPersonaInfo {
+    pub room_id: String,
+    pub persona: BotPersona,
+}
+
+/// the name a bot seat is shown with, the bot plays the same whatever its persona.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotPersona {
+    #[default]
+    Protocol,
+    Navigator,
+    Stargazer,
+    Cartographer,
+}
+
+impl BotPersona {
+    /// the pool a bot switched on without a persona is drawn from.
+    pub const ALL: [BotPersona; 4] = [
+        BotPersona::Protocol,
+        BotPersona::Navigator,
+        BotPersona::Stargazer,
+        BotPersona::Cartographer,
+    ];
+
+    pub fn random() -> Self {
+        Self::ALL[rand::random_range(0..Self::ALL.len())]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BotPersona::Protocol => "protocol",
+            BotPersona::Navigator => "navigator",
+            BotPersona::Stargazer => "stargazer",
+            BotPersona::Cartographer => "cartographer",
+        }
+    }
+
+    /// id of the picture the clients ship for the persona.
+    pub fn avatar(self) -> &'static str {
+        match self {
+            BotPersona::Protocol => "bot-protocol",
+            BotPersona::Navigator => "bot-navigator",
+            BotPersona::Stargazer => "bot-stargazer",
+            BotPersona::Cartographer => "bot-cartographer",
+        }
+    }
+
+    /// how hard the bot behind the persona plays, every persona runs the same bot for now.
+    pub fn difficulty(self) -> &'static str {
+        "standard"
+    }
+
+    pub fn flair(self) -> BotFlair {
+        BotFlair {
+            persona: self,
+            name: self.name().to_string(),
+            avatar: self.avatar().to_string(),
+            difficulty: self.difficulty().to_string(),
+        }
+    }
+}
+
+/// the bot a result was played against, for the histories and the leaderboards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BotFlair {
+    pub persona: BotPersona,
+    pub name: String,
+    pub avatar: String,
+    pub difficulty: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct HandicapInfo {
+    pub room_id: String,
+    pub user_id: String,
+    pub handicap: Handicap,
 }
 
 #[cfg(test)]
@@ -40,6 +178,7 @@ mod tests {
             room_id: "123".to_string(),
             map_type: MapType::Expert,
             map_seed: 123,
+            rules: None,
         });
 
         let str = serde_json::to_string(&create).unwrap();
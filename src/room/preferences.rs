@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use crate::map::{MapType, SkyWindow};
+
+use super::{BotPersona, GameStateResp, PALETTE_SIZE, RoomRules};
+
+/// characters of a locale tag at most, e.g. `zh-Hans-CN`.
+const MAX_LOCALE_LEN: usize = 35;
+
+/// room presets a player may keep, and characters of their names at most.
+pub const MAX_PRESETS: usize = 8;
+const MAX_PRESET_NAME_LEN: usize = 32;
+
+/// read or replace the preferences of the player, both answered with the `preferences` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreferencesOperation {
+    Get,
+    Set(Preferences),
+}
+
+/// small settings of a player the server keeps, so they follow the player to another device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct Preferences {
+    pub locale: Option<String>, // language tag, the clients fall back to the system one
+    pub hints_opt_out: bool,    // never offer the recommend hints, even where the room allows
+    pub notifications: Notifications,
+    pub color: Option<u8>, // preferred index in the palette, as `Appearance::color`
+    pub presets: Vec<RoomPreset>,
+}
+
+/// the settings of a room a host saved by name, `RoomUserOperation::FromPreset` creates a room
+/// with them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RoomPreset {
+    pub name: String,
+    pub map_type: MapType,
+    #[serde(default)]
+    pub rules: RoomRules, // the timers, hint policy and scoring overrides
+    #[serde(default)]
+    pub bot: Option<BotPersona>, // the room bot, None for no bot
+}
+
+impl RoomPreset {
+    /// the settings on a room not started yet, the bot is seated by the caller.
+    pub fn apply(&self, gs: &mut GameStateResp) {
+        gs.map_type = self.map_type.clone();
+        gs.rules = self.rules.clone();
+        if gs.rules.server_seed {
+            gs.map_seed = 0;
+        }
+        gs.set_window(SkyWindow::first(&gs.map_type));
+    }
+}
+
+/// what the clients notify the player of while they are in the background.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct Notifications {
+    pub your_turn: bool,
+    pub chat: bool,
+    pub announcements: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            your_turn: true,
+            chat: true,
+            announcements: true,
+        }
+    }
+}
+
+impl Preferences {
+    /// drop what the server doesn't accept, the clients fall back to their default.
+    pub fn validated(self) -> Self {
+        let locale = self.locale.filter(|l| {
+            !l.is_empty()
+                && l.len() <= MAX_LOCALE_LEN
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+        let mut presets: Vec<RoomPreset> = vec![];
+        for preset in self.presets {
+            let name = preset.name.trim();
+            if name.is_empty()
+                || name.chars().count() > MAX_PRESET_NAME_LEN
+                || presets.iter().any(|p| p.name == name)
+            {
+                continue;
+            }
+            presets.push(RoomPreset {
+                name: name.to_string(),
+                ..preset
+            });
+        }
+        presets.truncate(MAX_PRESETS);
+        Preferences {
+            locale,
+            color: self.color.filter(|c| *c < PALETTE_SIZE),
+            presets,
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_preferences() {
+        let op: PreferencesOperation = serde_json::from_str(
+            r#"{"set":{"locale":"zh-CN","notifications":{"chat":false},"color":9}}"#,
+        )
+        .unwrap();
+        let PreferencesOperation::Set(preferences) = op else {
+            panic!("not a set");
+        };
+        let preferences = preferences.validated();
+        assert_eq!(preferences.locale.as_deref(), Some("zh-CN"));
+        assert!(!preferences.notifications.chat && preferences.notifications.your_turn);
+        assert_eq!(preferences.color, None);
+
+        let preferences = Preferences {
+            locale: Some("<script>".to_string()),
+            color: Some(2),
+            ..Default::default()
+        }
+        .validated();
+        assert_eq!(preferences.locale, None);
+        assert_eq!(preferences.color, Some(2));
+    }
+
+    #[test]
+    fn test_presets() {
+        let preferences: Preferences = serde_json::from_str(
+            r#"{"presets":[
+                {"name":" quick ","map_type":"standard","bot":"navigator"},
+                {"name":"quick","map_type":"expert"},
+                {"name":"","map_type":"expert"}
+            ]}"#,
+        )
+        .unwrap();
+        let preferences = preferences.validated();
+        assert_eq!(preferences.presets.len(), 1);
+        let preset = &preferences.presets[0];
+        assert_eq!(preset.name, "quick");
+        assert_eq!(preset.bot, Some(BotPersona::Navigator));
+
+        let mut gs = GameStateResp::new("1".to_string());
+        let preset = RoomPreset {
+            name: "expert".to_string(),
+            map_type: MapType::Expert,
+            rules: RoomRules {
+                no_hints: true,
+                server_seed: true,
+                ..Default::default()
+            },
+            bot: None,
+        };
+        preset.apply(&mut gs);
+        assert_eq!(gs.map_type, MapType::Expert);
+        assert!(gs.rules.no_hints);
+        assert_eq!(gs.map_seed, 0);
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ServerConfig,
+    engine,
+    ids::Seat,
+    map::{Map, Sectors},
+    operation::Operation,
+    room::{GameExport, GameState, GameStateResp, RoomError, ServerGameState, UserState},
+    server_state::{State, User},
+};
+
+/// auto moves run at most before a replayed operation, the engine waits on a seat long before.
+const MAX_ADVANCES: usize = 100;
+
+/// retry the decision of a seat of a finished game in a solo room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PuzzleInfo {
+    pub room_id: String, // of the finished game
+    pub seat_id: String, // the seat to play, the asking player's or another
+    pub turn: usize,     // moves of the seat already played, 0 for its first decision
+}
+
+/// where a practice room comes from, its results are not kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PuzzleOrigin {
+    pub room_id: String,
+    pub seat_id: String,
+    pub turn: usize,
+}
+
+/// the finished game played again from its deal up to the decision of the seat, in a room where
+/// `user` plays the seat and the server the others. `room_id` is the id of the new room.
+pub fn build_puzzle(
+    export: &GameExport,
+    info: &PuzzleInfo,
+    room_id: &str,
+    user: &User,
+) -> Result<(GameStateResp, ServerGameState), RoomError> {
+    if !export.players.iter().any(|p| p.id == info.seat_id) {
+        return Err(RoomError::UserNotFoundInRoom);
+    }
+    let played = export
+        .journal
+        .iter()
+        .filter(|e| e.user_id == info.seat_id)
+        .count();
+    if info.turn >= played {
+        return Err(RoomError::NoSuchTurn);
+    }
+
+    // the other seats get ids of the new room, so their players are not seated in it
+    let mut export = export.clone();
+    let others = export
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.id != info.seat_id)
+        .map(|(i, p)| (p.id.clone(), p.is_bot, i))
+        .collect::<Vec<_>>();
+    for (id, is_bot, i) in others {
+        let new_id = match is_bot {
+            true => Seat::bot(room_id).id().to_string(),
+            false => format!("{room_id}-seat-{}", i + 1),
+        };
+        export.rename_player(&id, &new_id);
+    }
+    export.rename_player(&info.seat_id, &user.id);
+
+    let mut gs = GameStateResp::new(room_id.to_string());
+    gs.map_type = export.map_type.clone();
+    gs.map_seed = export.map_seed;
+    gs.rules = export.rules.clone();
+    // a practice game is not rated
+    gs.rules.no_hints = false;
+    gs.users = export
+        .players
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            if p.id == user.id {
+                // the seat is the user's, a bot's seat included
+                return UserState {
+                    handicap: p.handicap.clone(),
+                    ..UserState::placeholder(user, i + 1, false)
+                };
+            }
+            let player = Seat::of(&p.id, p.is_bot).user(&p.name);
+            UserState {
+                handicap: p.handicap.clone(),
+                bot_controlled: !p.is_bot,
+                ..UserState::placeholder(&player, i + 1, p.is_bot)
+            }
+        })
+        .collect();
+    let map = Map {
+        r#type: export.map_type.clone(),
+        sectors: Sectors {
+            data: export.sectors.clone(),
+        },
+    };
+    let dealt = (map, export.research_clues.clone(), export.x_clues.clone());
+    let mut ss = ServerGameState::placeholder();
+    engine::lay_out(&mut gs, &mut ss, dealt, export.map_seed, None);
+
+    // the moves before the decision, the seat's earlier ones and everyone's around them
+    let cut = export
+        .journal
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.user_id == user.id)
+        .nth(info.turn)
+        .map_or(export.journal.len(), |(i, _)| i);
+    let moves = export.journal[..cut]
+        .iter()
+        .map(|e| (e.user_id.as_str(), &e.op));
+    let (mut gs, mut ss) = replay(gs, ss, moves)?;
+    if !advance_to(&mut gs, &mut ss, &user.id) {
+        return Err(RoomError::ReplayFailed);
+    }
+    gs.hint = Some(format!("Practice from room {}", export.room_id));
+    gs.puzzle = Some(PuzzleOrigin {
+        room_id: export.room_id.clone(),
+        seat_id: info.seat_id.clone(),
+        turn: info.turn,
+    });
+    Ok((gs, ss))
+}
+
+/// play the moves again on a room laid out from its deal, the auto moves between them included.
+pub fn replay<'a>(
+    gs: GameStateResp,
+    ss: ServerGameState,
+    moves: impl IntoIterator<Item = (&'a str, &'a Operation)>,
+) -> Result<(GameStateResp, ServerGameState), RoomError> {
+    let room_id = gs.id.clone();
+    let mut scratch = State::new(ServerConfig::default());
+    scratch.state_data.insert(room_id.clone(), (gs, ss));
+    for (user_id, op) in moves {
+        let (gs, ss) = scratch.get_state(&room_id).ok_or(RoomError::RoomNotFound)?;
+        if !advance_to(gs, ss, user_id) {
+            return Err(RoomError::ReplayFailed);
+        }
+        let player = gs
+            .users
+            .iter()
+            .find(|u| u.id == user_id)
+            .map(|u| u.seat().user(&u.name))
+            .ok_or(RoomError::ReplayFailed)?;
+        if let Err(e) = scratch.handle_action_op(player, op) {
+            tracing::warn!("room {}: replayed {:?} refused: {:?}", room_id, op, e);
+            return Err(RoomError::ReplayFailed);
+        }
+    }
+    scratch
+        .state_data
+        .remove(&room_id)
+        .ok_or(RoomError::RoomNotFound)
+}
+
+/// run the auto moves until the room waits on the seat, false if it never does.
+fn advance_to(gs: &mut GameStateResp, ss: &mut ServerGameState, user_id: &str) -> bool {
+    for _ in 0..MAX_ADVANCES {
+        if gs.check_waiting(user_id) {
+            return true;
+        }
+        if gs.status == GameState::End {
+            return false;
+        }
+        engine::advance(gs, ss);
+    }
+    false
+}
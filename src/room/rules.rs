@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::scoring::XBonusCurve;
+
+/// per room rule options, chosen by the room members before the game starts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", default)]
+pub struct RoomRules {
+    /// consecutive timed out turns before the seat is handed over to the bot, 0 to never hand over.
+    /// it also enables removing long disconnected users from a room that has not started.
+    pub idle_kick_turns: usize,
+    /// override of the x locate bonus, None for the default curve.
+    pub x_bonus: Option<XBonusCurve>,
+    /// competitive room, the recommend namespace is disabled for its members.
+    pub no_hints: bool,
+    /// casters may watch the true map and every player's knowledge, once every player consents.
+    pub casting: bool,
+    /// what a missed locate tells the player, and what else it costs.
+    pub failed_locate: FailedLocatePolicy,
+    /// the server draws the seed at the start and keeps it secret until the end of the game,
+    /// the seed the host sets is ignored.
+    pub server_seed: bool,
+}
+
+/// the official variants of a missed locate, outside of the last moves.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailedLocatePolicy {
+    #[default]
+    RevealNothing,
+    RevealAdjacent, // tell whether x is at the index and an adjacent guess is wrong
+    TimePenalty(usize), // extra time on top of the locate
+}
+
+impl Default for RoomRules {
+    fn default() -> Self {
+        RoomRules {
+            idle_kick_turns: 3,
+            x_bonus: None,
+            no_hints: false,
+            casting: false,
+            failed_locate: FailedLocatePolicy::RevealNothing,
+            server_seed: false,
+        }
+    }
+}
+
+impl RoomRules {
+    pub fn is_competitive(&self) -> bool {
+        self.no_hints
+    }
+}
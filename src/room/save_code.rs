@@ -0,0 +1,269 @@
+#[cfg(feature = "integrations")]
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+#[cfg(feature = "integrations")]
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "integrations")]
+use sha2::Sha256;
+
+#[cfg(feature = "integrations")]
+use crate::{
+    engine,
+    ids::Seat,
+    map::{ClueWeights, MapType},
+    operation::Operation,
+    room::{
+        BotPersona, GameState, GameStateResp, Handicap, RoomError, RoomRules, ServerGameState,
+        UserState, now_secs, replay,
+    },
+};
+
+#[cfg(feature = "integrations")]
+pub const SAVE_CODE_VERSION: u32 = 2;
+
+/// seconds a save code can be resumed after it was issued.
+#[cfg(feature = "integrations")]
+pub const SAVE_CODE_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// a host asking for the save code of their running room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SaveCodeRequest {
+    pub room_id: String,
+}
+
+/// the code to keep, the host sends it back in a `resume` room operation on any server sharing
+/// the secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SaveCodeIssued {
+    pub room_id: String,
+    pub code: String,
+}
+
+/// a running game as its seed and the moves played, enough to deal it again and play it back.
+#[cfg(feature = "integrations")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SaveGame {
+    pub version: u32,
+    pub nonce: String, // a code is resumed once, a server keeps the nonces it resumed
+    pub issued_at: u64,
+    pub room_id: String,
+    pub map_type: MapType,
+    pub seed: u64,
+    pub clue_weights: ClueWeights, // of the deal, the server resuming may weigh otherwise
+    pub rules: RoomRules,
+    pub seats: Vec<SavedSeat>,          // in the order of the game
+    pub moves: Vec<(usize, Operation)>, // seat index and operation, in the order played
+}
+
+#[cfg(feature = "integrations")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SavedSeat {
+    pub id: String,
+    pub name: String,
+    pub is_bot: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona: Option<BotPersona>,
+    #[serde(default)]
+    pub handicap: Handicap,
+}
+
+#[cfg(feature = "integrations")]
+impl SaveGame {
+    /// the save of a running room. a hidden seed is not given away, the code can be read, and
+    /// a competitive game is not saved, it could be played again from any of its turns.
+    pub fn new(
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        clue_weights: &ClueWeights,
+    ) -> Result<Self, RoomError> {
+        if !matches!(gs.status, GameState::Wait(_) | GameState::AutoMove) {
+            return Err(RoomError::GameNotRunning);
+        }
+        if ss.hidden_seed.is_some() {
+            return Err(RoomError::SeedHidden);
+        }
+        if gs.rules.is_competitive() {
+            return Err(RoomError::CompetitiveRoom);
+        }
+        let seats = gs
+            .users
+            .iter()
+            .map(|u| SavedSeat {
+                id: u.id.clone(),
+                name: u.name.clone(),
+                is_bot: u.seat().is_bot(),
+                persona: u.persona,
+                handicap: u.handicap.clone(),
+            })
+            .collect();
+        let moves = ss
+            .journal
+            .iter()
+            .map(|e| {
+                let seat = gs.users.iter().position(|u| u.id == e.user_id)?;
+                Some((seat, e.op.clone()))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(RoomError::ReplayFailed)?;
+        Ok(SaveGame {
+            version: SAVE_CODE_VERSION,
+            nonce: uuid::Uuid::new_v4().to_string(),
+            issued_at: now_secs(),
+            room_id: gs.id.clone(),
+            map_type: gs.map_type.clone(),
+            seed: gs.map_seed,
+            clue_weights: clue_weights.clone(),
+            rules: gs.rules.clone(),
+            seats,
+            moves,
+        })
+    }
+
+    /// the save as `<payload>.<signature>`, both url safe base64.
+    pub fn encode(&self, secret: &str) -> String {
+        let json = serde_json::to_vec(self).expect("a save game serializes");
+        let payload = URL_SAFE_NO_PAD.encode(json);
+        let signature = URL_SAFE_NO_PAD.encode(mac(secret, &payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// the save of a code signed with `secret`.
+    pub fn decode(code: &str, secret: &str) -> Result<Self, RoomError> {
+        let (payload, signature) = code
+            .trim()
+            .split_once('.')
+            .ok_or(RoomError::InvalidSaveCode)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| RoomError::InvalidSaveCode)?;
+        mac(secret, payload)
+            .verify_slice(&signature)
+            .map_err(|_| RoomError::InvalidSaveCode)?;
+        let json = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| RoomError::InvalidSaveCode)?;
+        let save: SaveGame =
+            serde_json::from_slice(&json).map_err(|_| RoomError::InvalidSaveCode)?;
+        if save.version != SAVE_CODE_VERSION {
+            return Err(RoomError::InvalidSaveCode);
+        }
+        Ok(save)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now > self.issued_at + SAVE_CODE_TTL_SECS
+    }
+
+    /// the player the code was issued for, the one who may resume it.
+    pub fn host_id(&self) -> Option<&str> {
+        self.seats.iter().find(|s| !s.is_bot).map(|s| s.id.as_str())
+    }
+
+    /// the game dealt again in room `room_id` and played up to where it was saved.
+    pub fn resume(&self, room_id: &str) -> Result<(GameStateResp, ServerGameState), RoomError> {
+        if self.moves.iter().any(|(seat, _)| *seat >= self.seats.len()) {
+            return Err(RoomError::InvalidSaveCode);
+        }
+        let dealt =
+            engine::deal(self.seed, self.map_type.clone(), &self.clue_weights).map_err(|e| {
+                tracing::warn!(
+                    "save of room {}: seed {} doesn't deal: {}",
+                    self.room_id,
+                    self.seed,
+                    e
+                );
+                RoomError::DealFailed {
+                    seed: self.seed,
+                    tries: 1,
+                }
+            })?;
+
+        let mut gs = GameStateResp::new(room_id.to_string());
+        gs.map_type = self.map_type.clone();
+        gs.map_seed = self.seed;
+        gs.rules = self.rules.clone();
+        gs.users = self
+            .seats
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let user = match s.is_bot {
+                    true => UserState::bot(room_id, s.persona.unwrap_or_default(), i + 1),
+                    false => {
+                        let player = Seat::of(&s.id, false).user(&s.name);
+                        UserState::placeholder(&player, i + 1, false)
+                    }
+                };
+                UserState {
+                    handicap: s.handicap.clone(),
+                    ..user
+                }
+            })
+            .collect();
+        let mut ss = ServerGameState::placeholder();
+        engine::lay_out(&mut gs, &mut ss, dealt, self.seed, None);
+
+        let ids = gs.users.iter().map(|u| u.id.clone()).collect::<Vec<_>>();
+        let moves = self
+            .moves
+            .iter()
+            .map(|(seat, op)| (ids[*seat].as_str(), op));
+        let (mut gs, ss) = replay(gs, ss, moves)?;
+        gs.hint = Some(format!("Game of room {} resumed", self.room_id));
+        Ok((gs, ss))
+    }
+}
+
+#[cfg(feature = "integrations")]
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(all(test, feature = "integrations"))]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_save_code_signature() {
+        let save = SaveGame {
+            version: SAVE_CODE_VERSION,
+            nonce: "n1".to_string(),
+            issued_at: 1000,
+            room_id: "1234".to_string(),
+            map_type: MapType::Standard,
+            seed: 42,
+            clue_weights: ClueWeights::default(),
+            rules: RoomRules::default(),
+            seats: vec![],
+            moves: vec![],
+        };
+        let code = save.encode("secret");
+        let decoded = SaveGame::decode(&code, "secret").unwrap();
+        assert_eq!(decoded.room_id, "1234");
+        assert_eq!(decoded.seed, 42);
+        assert!(!decoded.is_expired(1000 + SAVE_CODE_TTL_SECS));
+        assert!(decoded.is_expired(1001 + SAVE_CODE_TTL_SECS));
+
+        assert!(matches!(
+            SaveGame::decode(&code, "other"),
+            Err(RoomError::InvalidSaveCode)
+        ));
+        let (payload, signature) = code.split_once('.').unwrap();
+        let forged = SaveGame {
+            seed: 43,
+            ..decoded
+        }
+        .encode("secret");
+        let forged = format!("{}.{}", forged.split_once('.').unwrap().0, signature);
+        assert!(SaveGame::decode(&forged, "secret").is_err());
+        assert!(SaveGame::decode(payload, "secret").is_err());
+    }
+}
@@ -1,5 +1,14 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    map::{MapError, SectorType, Token},
+    operation::OperationKind,
+    room::GameStage,
+};
+
+/// longest reason of a bad request, the rest of a parser message is cut.
+const MAX_REASON_LEN: usize = 200;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ServerResp {
@@ -8,6 +17,10 @@ pub enum ServerResp {
     RoomErrors(RoomError),
     OpErrors(OpError),
     RecommendErrors(RecommendError),
+    TheaterErrors(TheaterError),
+    ChatErrors(ChatError),
+    RateLimited, // a service account sent requests faster than the server allows
+    BadRequest { event: String, reason: String }, // the payload of `event` didn't deserialize
 }
 
 impl ServerResp {
@@ -18,6 +31,169 @@ impl ServerResp {
     pub fn rejoin_room(room_id: String) -> Self {
         Self::RejoinRoom(room_id)
     }
+
+    pub fn bad_request(event: &str, reason: impl std::fmt::Display) -> Self {
+        Self::BadRequest {
+            event: event.to_string(),
+            reason: reason.to_string().chars().take(MAX_REASON_LEN).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct IdleKick {
+    pub user_id: String,
+    pub name: String,
+    pub action: IdleKickAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleKickAction {
+    BotControl,
+    Removed,
+}
+
+/// the server drains before a deploy: running games go on, new rooms are refused. sent to
+/// every connection as the `maintenance` event, `null` once it is over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Maintenance {
+    pub message: String,
+    pub since: u64,
+}
+
+/// a message of the operators to every connection, sent as the `announcement` event once due.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Announcement {
+    pub id: u64,
+    pub message: String,
+    pub kind: AnnouncementKind,
+    pub at: u64, // unix secs it is due
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementKind {
+    #[default]
+    Info,
+    MaintenanceWindow,
+    NewVersion,
+}
+
+/// private to a waiting player, their turn times out soon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TurnDeadline {
+    pub room_id: String,
+    pub secs_left: u64,
+}
+
+/// private to the players the room starts waiting for, with the operations they may legally send.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct YourTurn {
+    pub room_id: String,
+    pub stage: GameStage,
+    pub operations: Vec<OperationKind>,
+}
+
+/// to the room, how the theories moved at a meeting. `board_tokens` only carries the end state,
+/// the transitions are in the order the server made them so the clients can animate them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ConferenceResult {
+    pub room_id: String,
+    pub stage: GameStage, // MeetingPublish pushes theories forward, MeetingCheck reveals them
+    pub transitions: Vec<TokenTransition>,
+    #[serde(default)]
+    pub verdicts: Vec<TheoryVerdict>, // the checked theories, empty when nothing was checked
+}
+
+/// to the room at the start of each conference, how the players paced the game so far. the
+/// players are in seat order, the summary ranks nobody.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PacingSummary {
+    pub room_id: String,
+    pub players: Vec<PlayerPacing>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PlayerPacing {
+    pub user_id: String,
+    pub name: String,
+    pub turns: usize,                // operations played, the publishes aside
+    pub avg_think_secs: Option<f64>, // None for the bots and before the first timed turn
+    pub sectors_surveyed: usize,
+}
+
+/// a theory checked at a meeting, the clients phrase it in their language.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TheoryVerdict {
+    pub user_id: String,
+    pub name: String,
+    pub sector_index: usize,
+    pub claimed: SectorType,
+    pub right: bool,
+    pub penalty_steps: usize, // the owner of a wrong theory moves forward
+}
+
+/// one theory of the board moving between `meeting_index` slots.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenTransition {
+    pub user_id: String,
+    pub user_index: usize,
+    pub sector_index: usize,
+    pub from: usize,
+    pub to: usize,
+    pub revealed: Option<SectorType>, // Some once checked, right or wrong
+}
+
+impl TokenTransition {
+    /// the token as it is now, coming from the slot `from`.
+    pub fn new(token: &Token, from: usize) -> Self {
+        TokenTransition {
+            user_id: token.secret.user_id.clone(),
+            user_index: token.secret.user_index,
+            sector_index: token.secret.sector_index,
+            from,
+            to: token.secret.state.meeting_index(),
+            revealed: token.secret.r#type.clone(),
+        }
+    }
+}
+
+/// to the room, a player moved forward for a mistake rather than by the time of an operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Penalty {
+    pub room_id: String,
+    pub user_id: String,
+    pub name: String,
+    pub reason: PenaltyReason,
+    pub sector_index: usize, // of the wrong theory or the missed x
+    pub steps: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PenaltyReason {
+    WrongTheory,
+    FailedLocate,
+}
+
+/// to the room, the server plays the turn of a waiting player.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TurnTimeout {
+    pub user_id: String,
+    pub name: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +203,26 @@ pub enum RoomError {
     RoomStarted,
     RoomFull,
     UserNotFoundInRoom,
+    NotHost,
+    InvalidHandicap,
+    CastingNotAllowed,
+    CasterIsPlayer,
+    CasterNotAuthorized,                  // no valid caster token for the room
+    ServerBusy { retry_after_secs: u64 }, // at capacity, try again later
+    Maintenance { message: String },      // draining before a deploy, no new rooms
+    GameNotRunning,
+    NoAbortVote,                          // a no without a vote called
+    DealFailed { seed: u64, tries: u64 }, // none of the seeds tried from `seed` dealt a game
+    GameNotEnded,                         // a puzzle of a room still playing or gone
+    NoSuchTurn,                           // the seat played fewer moves
+    ReplayFailed,                         // the journal doesn't play again from the deal
+    SaveCodesDisabled,                    // the server has no save code secret
+    InvalidSaveCode,                      // unreadable, or signed by another secret
+    SaveCodeExpired,                      // issued longer ago than save codes last
+    SaveCodeUsed,                         // resumed already on this server
+    CompetitiveRoom,                      // a competitive game has no save code
+    SeedHidden,                           // a save code would give the seed away
+    PresetNotFound,                       // no preset of that name in the user's preferences
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +244,18 @@ pub enum OpError {
     ResearchContiuously,
 
     EndGameCanNotLocate,
+    GameFinished, // the game is over, the client is sent its end again
+
+    InvalidBatch, // empty, too long, nested or with a locate before its last step
+}
+
+impl From<MapError> for OpError {
+    fn from(e: MapError) -> Self {
+        match e {
+            MapError::IndexOutOfRange { .. } => OpError::InvalidIndex,
+            MapError::TokenNotReady => OpError::TokenNotEnough,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,6 +265,32 @@ pub enum RecommendError {
     GameNotFound,
 
     NotEnoughData,
+    HintsDisabled,
+    BotSeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TheaterError {
+    TheaterNotFound,
+    NotPresenter,
+    UnsupportedVersion,
+    TooManyTheaters, // the user presents `MAX_THEATERS_PER_USER` already
+    TheatersFull,    // the server holds `MAX_THEATERS`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatError {
+    RoomNotFound,
+    UserNotFoundInRoom,
+    NotHost,
+    Muted,
+    Empty,
+    TooLong,
+    Rejected, // refused by a chat filter
+    TargetNotFound,
+    TooFast, // reactions sent faster than the server allows
 }
 
 #[cfg(test)]
@@ -73,5 +307,10 @@ mod tests {
         let e2 = ServerResp::RejoinRoom("room_id".to_string());
         let s = serde_json::to_string(&e2).unwrap();
         assert_eq!(s, r#"{"rejoin_room":"room_id"}"#);
+
+        let e3 = ServerResp::bad_request("op", "x".repeat(500));
+        let s = serde_json::to_string(&e3).unwrap();
+        assert!(s.starts_with(r#"{"bad_request":{"event":"op","reason":"xxx"#));
+        assert!(s.len() < 300);
     }
 }
@@ -0,0 +1,407 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ids::Seat,
+    map::{ChoiceFilter, Clue, Map, MapType, Sector, SectorType, Sectors, Token},
+    operation::{Operation, OperationResult},
+    room::{
+        ConferenceResult, GameStage, GameState, GameStateResp, JournalEntry, ServerGameState,
+        UserLocationSequence, UserState,
+    },
+};
+
+/// a room as it was at some point of the game, with the choice filters compacted.
+#[derive(Debug, Clone)]
+pub struct RoomSnapshot {
+    pub info: SnapshotInfo,
+    key: SnapshotKey,
+    gs: GameStateResp,
+    ss: ServerGameState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SnapshotInfo {
+    pub id: u64,
+    pub taken_at: u64,
+    pub round: usize,
+    pub start_index: usize,
+    pub game_stage: GameStage,
+    pub moves: usize, // journal length
+}
+
+/// the parts of a room that change with the game, a new snapshot is taken when they do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnapshotKey {
+    status: GameState,
+    game_stage: GameStage,
+    round: usize,
+    start_index: usize,
+    moves: usize,
+}
+
+impl SnapshotKey {
+    fn of(gs: &GameStateResp, ss: &ServerGameState) -> Self {
+        SnapshotKey {
+            status: gs.status.clone(),
+            game_stage: gs.game_stage.clone(),
+            round: gs.round,
+            start_index: gs.start_index,
+            moves: ss.journal.len(),
+        }
+    }
+}
+
+/// the last snapshots of a room, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotRing {
+    next_id: u64,
+    snapshots: VecDeque<RoomSnapshot>,
+}
+
+impl SnapshotRing {
+    /// take a snapshot if the room changed since the last one, and return it for the storage.
+    pub fn autosave(
+        &mut self,
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        keep: usize,
+    ) -> Option<StoredRoom> {
+        let key = SnapshotKey::of(gs, ss);
+        if keep == 0 || self.snapshots.back().is_some_and(|s| s.key == key) {
+            return None;
+        }
+        self.next_id += 1;
+        let ss = ss.compacted();
+        self.snapshots.push_back(RoomSnapshot {
+            info: SnapshotInfo {
+                id: self.next_id,
+                taken_at: super::now_secs(),
+                round: gs.round,
+                start_index: gs.start_index,
+                game_stage: gs.game_stage.clone(),
+                moves: ss.journal.len(),
+            },
+            key,
+            gs: gs.clone(),
+            ss,
+        });
+        while self.snapshots.len() > keep {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.back().map(StoredRoom::new)
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn list(&self) -> Vec<SnapshotInfo> {
+        self.snapshots.iter().map(|s| s.info.clone()).collect()
+    }
+
+    /// every snapshot as the storage keeps it, oldest first.
+    pub fn stored(&self) -> Vec<StoredRoom> {
+        self.snapshots.iter().map(StoredRoom::new).collect()
+    }
+
+    /// the room at the snapshot, the later snapshots are dropped as they are no longer reachable.
+    #[cfg(feature = "admin")]
+    pub fn restore(&mut self, id: u64) -> Option<(GameStateResp, ServerGameState)> {
+        let pos = self.snapshots.iter().position(|s| s.info.id == id)?;
+        self.snapshots.truncate(pos + 1);
+        let snapshot = &self.snapshots[pos];
+        let mut gs = snapshot.gs.clone();
+        // timers restart from now, the players were not idle while the room was broken
+        gs.wait_since = None;
+        gs.deadline_warned = None;
+        gs.start_countdown = None;
+        let mut ss = snapshot.ss.clone();
+        for choice in ss.choices.values_mut() {
+            *choice = choice.rebuild();
+        }
+        Some((gs, ss))
+    }
+}
+
+/// the format of `StoredRoom`, stored along the room so an upgraded server can still resume
+/// the games saved by the previous release.
+/// - 1: the server game state fields next to `game_state`, without tag.
+/// - 2: the tag, and the server game state fields under `server_state`.
+pub const STORED_ROOM_VERSION: u32 = 2;
+
+/// a snapshot as persisted by the storage, the choice filters are kept as their operations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", try_from = "serde_json::Value")]
+pub struct StoredRoom {
+    pub version: u32,
+    pub info: SnapshotInfo,
+    game_state: GameStateResp,
+    server_state: StoredServerState,
+}
+
+/// the `ServerGameState` of a stored room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+struct StoredServerState {
+    map_type: MapType,
+    sectors: Vec<Sector>,
+    research_clues: Vec<Clue>,
+    x_clues: Vec<Clue>,
+    user_tokens: HashMap<String, Vec<Token>>,
+    terminator_location: Option<UserLocationSequence>,
+    revealed_sector_indexs: Vec<usize>,
+    operations: HashMap<String, Vec<(Operation, OperationResult)>>, // user_id -> choice filter ops
+    located_order: Vec<String>,
+    handicap_reveals: HashMap<String, Vec<(usize, SectorType)>>,
+    journal: Vec<JournalEntry>,
+    #[serde(default)]
+    hidden_seed: Option<u64>,
+    #[serde(default)]
+    conferences: Vec<ConferenceResult>,
+}
+
+impl TryFrom<serde_json::Value> for StoredRoom {
+    type Error = String;
+
+    /// read any supported version, the older ones are converted to the current one.
+    fn try_from(mut value: serde_json::Value) -> Result<Self, Self::Error> {
+        let version = value.get("version").map_or(Some(1), |v| v.as_u64());
+        match version {
+            Some(1) => nest_server_state(&mut value)?,
+            Some(v) if v == STORED_ROOM_VERSION as u64 => {}
+            _ => return Err(format!("unsupported stored room version {version:?}")),
+        }
+        let mut field = |name: &str| {
+            let field = value.get_mut(name).map(serde_json::Value::take);
+            field.ok_or_else(|| format!("stored room without {name}"))
+        };
+        let (info, game_state, server_state) =
+            (field("info")?, field("game_state")?, field("server_state")?);
+        Ok(StoredRoom {
+            version: STORED_ROOM_VERSION,
+            info: serde_json::from_value(info).map_err(|e| e.to_string())?,
+            game_state: serde_json::from_value(game_state).map_err(|e| e.to_string())?,
+            server_state: serde_json::from_value(server_state).map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+/// version 1 to 2: move the server game state fields under `server_state`.
+fn nest_server_state(value: &mut serde_json::Value) -> Result<(), String> {
+    const FIELDS: [&str; 11] = [
+        "map_type",
+        "sectors",
+        "research_clues",
+        "x_clues",
+        "user_tokens",
+        "terminator_location",
+        "revealed_sector_indexs",
+        "operations",
+        "located_order",
+        "handicap_reveals",
+        "journal",
+    ];
+    let room = value
+        .as_object_mut()
+        .ok_or("stored room is not an object")?;
+    let server_state = FIELDS
+        .iter()
+        .filter_map(|name| Some((name.to_string(), room.remove(*name)?)))
+        .collect();
+    room.insert(
+        "server_state".to_string(),
+        serde_json::Value::Object(server_state),
+    );
+    Ok(())
+}
+
+impl StoredRoom {
+    fn new(snapshot: &RoomSnapshot) -> Self {
+        let ss = &snapshot.ss;
+        StoredRoom {
+            version: STORED_ROOM_VERSION,
+            info: snapshot.info.clone(),
+            game_state: snapshot.gs.clone(),
+            server_state: StoredServerState {
+                map_type: ss.map.r#type.clone(),
+                sectors: ss.map.sectors.data.clone(),
+                research_clues: ss.research_clues.clone(),
+                x_clues: ss.x_clues.clone(),
+                user_tokens: ss.user_tokens.clone(),
+                terminator_location: ss.terminator_location.clone(),
+                revealed_sector_indexs: ss.revealed_sector_indexs.clone(),
+                operations: ss
+                    .choices
+                    .iter()
+                    .map(|(user_id, choice)| (user_id.clone(), choice.operations().to_vec()))
+                    .collect(),
+                located_order: ss.located_order.clone(),
+                handicap_reveals: ss.handicap_reveals.clone(),
+                journal: ss.journal.clone(),
+                hidden_seed: ss.hidden_seed,
+                conferences: ss.conferences.clone(),
+            },
+        }
+    }
+
+    /// the user played in the room, the snapshot holds their data.
+    #[cfg(feature = "admin")]
+    pub fn has_user(&self, user_id: &str) -> bool {
+        self.game_state.users.iter().any(|u| u.id == user_id)
+            || self.server_state.user_tokens.contains_key(user_id)
+    }
+
+    /// the room back, with the choice filters replayed.
+    pub fn restore(self) -> (GameStateResp, ServerGameState) {
+        let mut gs = self.game_state;
+        // the round is not on the wire
+        gs.round = self.info.round;
+        // the limits are not in the snapshots taken before them
+        gs.users.iter_mut().for_each(UserState::sync_limits);
+        let stored = self.server_state;
+        let choices = stored
+            .operations
+            .into_iter()
+            .map(|(user_id, ops)| {
+                let placed = stored
+                    .user_tokens
+                    .get(&user_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|t| t.placed && t.secret.r#type.is_some())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let is_bot = gs
+                    .users
+                    .iter()
+                    .any(|u| u.id == user_id && u.seat().is_bot());
+                let seat = Seat::of(&user_id, is_bot);
+                let choice = ChoiceFilter::replay(stored.map_type.clone(), seat, &placed, ops);
+                (user_id, choice)
+            })
+            .collect();
+        let ss = ServerGameState {
+            map: Map {
+                r#type: stored.map_type,
+                sectors: Sectors {
+                    data: stored.sectors,
+                },
+            },
+            research_clues: stored.research_clues,
+            x_clues: stored.x_clues,
+            user_tokens: stored.user_tokens,
+            terminator_location: stored.terminator_location,
+            revealed_sector_indexs: stored.revealed_sector_indexs,
+            choices,
+            located_order: stored.located_order,
+            handicap_reveals: stored.handicap_reveals,
+            journal: stored.journal,
+            hidden_seed: stored.hidden_seed,
+            conferences: stored.conferences,
+        };
+        (gs, ss)
+    }
+}
+
+impl ServerGameState {
+    /// a copy without the cached possibilities of the choice filters.
+    fn compacted(&self) -> Self {
+        let choices = self
+            .choices
+            .iter()
+            .map(|(user_id, choice)| {
+                let placed = self
+                    .user_tokens
+                    .get(user_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|t| t.placed && t.secret.r#type.is_some())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (user_id.clone(), choice.compact(&placed))
+            })
+            .collect();
+        ServerGameState {
+            map: self.map.clone(),
+            research_clues: self.research_clues.clone(),
+            x_clues: self.x_clues.clone(),
+            user_tokens: self.user_tokens.clone(),
+            terminator_location: self.terminator_location.clone(),
+            revealed_sector_indexs: self.revealed_sector_indexs.clone(),
+            choices,
+            located_order: self.located_order.clone(),
+            handicap_reveals: self.handicap_reveals.clone(),
+            journal: self.journal.clone(),
+            hidden_seed: self.hidden_seed,
+            conferences: self.conferences.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_snapshot_ring() {
+        let mut ring = SnapshotRing::default();
+        let mut gs = GameStateResp::empty();
+        let ss = ServerGameState::placeholder();
+
+        ring.autosave(&gs, &ss, 2);
+        ring.autosave(&gs, &ss, 2);
+        assert_eq!(ring.list().len(), 1);
+
+        gs.round = 2;
+        ring.autosave(&gs, &ss, 2);
+        gs.round = 3;
+        ring.autosave(&gs, &ss, 2);
+        let ids = ring.list().iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 3]);
+
+        let (restored, _) = ring.restore(2).unwrap();
+        assert_eq!(restored.round, 2);
+        assert_eq!(ring.list().len(), 1);
+        assert!(ring.restore(3).is_none());
+    }
+
+    #[test]
+    fn test_stored_room() {
+        let mut ring = SnapshotRing::default();
+        let mut gs = GameStateResp::empty();
+        gs.round = 3;
+        let mut ss = ServerGameState::placeholder();
+        ss.located_order.push("u1".to_string());
+
+        let stored = ring.autosave(&gs, &ss, 2).unwrap();
+        assert!(ring.autosave(&gs, &ss, 2).is_none());
+        let json = serde_json::to_string(&stored).unwrap();
+        let (gs, ss) = serde_json::from_str::<StoredRoom>(&json).unwrap().restore();
+        assert_eq!(gs.round, 3);
+        assert_eq!(ss.located_order, vec!["u1".to_string()]);
+    }
+
+    #[test]
+    fn test_stored_room_versions() {
+        let mut ring = SnapshotRing::default();
+        let mut ss = ServerGameState::placeholder();
+        ss.located_order.push("u1".to_string());
+        let stored = ring.autosave(&GameStateResp::empty(), &ss, 2).unwrap();
+
+        // the flat layout of version 1, as saved by the previous release
+        let mut v1 = serde_json::to_value(&stored).unwrap();
+        let room = v1.as_object_mut().unwrap();
+        room.remove("version");
+        let server_state = room.remove("server_state").unwrap();
+        room.extend(server_state.as_object().unwrap().clone());
+        let upgraded = serde_json::from_value::<StoredRoom>(v1).unwrap();
+        assert_eq!(upgraded.version, STORED_ROOM_VERSION);
+        assert_eq!(upgraded.restore().1.located_order, vec!["u1".to_string()]);
+
+        let mut newer = serde_json::to_value(&stored).unwrap();
+        newer["version"] = (STORED_ROOM_VERSION + 1).into();
+        assert!(serde_json::from_value::<StoredRoom>(newer).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{ChoiceFilter, Clue, MapType, SectorType},
+    operation::{Operation, OperationResult, ResearchOperation},
+};
+
+/// how much a dealt game asks to pin x down, computed once at the start from its clues.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Solvability {
+    pub min_clues: Option<usize>, // fewest of the dealt clues leaving x one sector, None if all don't
+    pub x_after_research: usize,  // sectors x can be in after every research clue
+    pub x_after_all: usize,       // and after the x clues too
+    pub badge: DifficultyBadge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DifficultyBadge {
+    Easy,   // two clues or less pin x
+    Medium, // three clues
+    Hard,   // more, or only surveys and targets pin it
+}
+
+impl Solvability {
+    /// run every map of the type through the clues, one pass: each distinct set of the clues a
+    /// map passes is kept with its x sector, the clue subsets are then checked against those.
+    pub fn new(map_type: &MapType, research_clues: &[Clue], x_clues: &[Clue]) -> Self {
+        let clues = research_clues.iter().chain(x_clues).collect::<Vec<_>>();
+        let ops = clues
+            .iter()
+            .map(|clue| {
+                let op = Operation::Research(ResearchOperation {
+                    index: clue.index.clone(),
+                });
+                (op, OperationResult::Research((*clue).clone()))
+            })
+            .collect::<Vec<_>>();
+        let passes = ChoiceFilter::enumerate(map_type)
+            .filter_map(|sectors| {
+                let x = sectors
+                    .data
+                    .iter()
+                    .find(|s| s.r#type == SectorType::X)?
+                    .index;
+                let mask = ops
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (op, opr))| ChoiceFilter::filter_op(&sectors, op, opr))
+                    .fold(0u32, |mask, (i, _)| mask | 1 << i);
+                Some((mask, x))
+            })
+            .collect::<HashSet<_>>();
+        let x_left = |subset: u32| {
+            passes
+                .iter()
+                .filter(|(mask, _)| mask & subset == subset)
+                .map(|(_, x)| *x)
+                .collect::<HashSet<_>>()
+                .len()
+        };
+
+        let all = (1u32 << clues.len()) - 1;
+        let research = (1u32 << research_clues.len()) - 1;
+        let min_clues = (0..=all)
+            .filter(|subset| x_left(*subset) == 1)
+            .map(|subset| subset.count_ones() as usize)
+            .min();
+        let badge = match min_clues {
+            Some(0..=2) => DifficultyBadge::Easy,
+            Some(3) => DifficultyBadge::Medium,
+            _ => DifficultyBadge::Hard,
+        };
+        Solvability {
+            min_clues,
+            x_after_research: x_left(research),
+            x_after_all: x_left(all),
+            badge,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::map::{ClueGenerator, Map};
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    #[test]
+    fn test_solvability() {
+        let map = Map::new(SmallRng::seed_from_u64(7), MapType::Standard).unwrap();
+        let (research_clues, x_clues) =
+            ClueGenerator::new(7, map.sectors.clone(), MapType::Standard)
+                .generate_clues()
+                .unwrap();
+        let solvability = Solvability::new(&MapType::Standard, &research_clues, &x_clues);
+        assert!(solvability.x_after_all >= 1);
+        assert!(solvability.x_after_all <= solvability.x_after_research);
+        let min_clues = solvability.min_clues.unwrap_or(usize::MAX);
+        assert_eq!(
+            solvability.x_after_all == 1,
+            min_clues <= research_clues.len() + x_clues.len()
+        );
+        if min_clues <= 2 {
+            assert_eq!(solvability.badge, DifficultyBadge::Easy);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast::board_tokens,
+    map::SecretToken,
+    room::{Announcement, ConferenceResult, GameStateResp, Maintenance, ServerGameState},
+};
+
+/// what a spectator arriving mid-game missed, sent as `spectator_summary` right after the room
+/// state on `sse/{room_id}`. only what the room was sent publicly, no seat's private data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SpectatorSummary {
+    pub room_id: String,
+    pub board_tokens: Vec<SecretToken>,
+    pub conferences: Vec<ConferenceResult>, // in the order they were held
+    pub announcement: Option<Announcement>, // the last one sent
+    pub maintenance: Option<Maintenance>,
+}
+
+impl SpectatorSummary {
+    pub fn new(
+        gs: &GameStateResp,
+        ss: &ServerGameState,
+        announcement: Option<&Announcement>,
+        maintenance: Option<&Maintenance>,
+    ) -> Self {
+        SpectatorSummary {
+            room_id: gs.id.clone(),
+            board_tokens: board_tokens(ss),
+            conferences: ss.conferences.clone(),
+            announcement: announcement.cloned(),
+            maintenance: maintenance.cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        map::{SectorType, Token},
+        room::GameStage,
+    };
+
+    #[test]
+    fn test_spectator_summary() {
+        let gs = GameStateResp::new("1234".to_string());
+        let mut ss = ServerGameState::placeholder();
+        let mut placed = Token::new(SectorType::Comet, "u1", 1);
+        placed.placed = true;
+        let kept = Token::new(SectorType::Asteroid, "u1", 1);
+        ss.user_tokens.insert("u1".to_string(), vec![placed, kept]);
+        ss.conferences.push(ConferenceResult {
+            room_id: gs.id.clone(),
+            stage: GameStage::MeetingPublish,
+            transitions: vec![],
+            verdicts: vec![],
+        });
+
+        let summary = SpectatorSummary::new(&gs, &ss, None, None);
+        // the tokens in hand stay private
+        assert_eq!(summary.board_tokens.len(), 1);
+        assert_eq!(summary.conferences.len(), 1);
+        assert!(summary.announcement.is_none());
+    }
+}
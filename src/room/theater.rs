@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{Clue, MapType, Sector},
+    room::{EXPORT_VERSION, GameExport, JournalEntry, PlayerExport, UserResultSummary},
+};
+
+/// theaters a user presents at once at most.
+pub const MAX_THEATERS_PER_USER: usize = 2;
+
+/// theaters open on the server at most, well below the 10000 ids so a free one is found fast.
+pub const MAX_THEATERS: usize = 1000;
+
+/// read-only playback of an exported game, stepped by the user who loaded it.
+#[derive(Debug, Clone)]
+pub struct Theater {
+    pub id: String,
+    pub presenter: String, // user_id of the loader, the only one who can step
+    pub presenter_socket: String, // the theater closes when this socket is gone
+    pub export: GameExport,
+    pub cursor: usize, // number of journal entries played
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TheaterOperation {
+    Load(Box<GameExport>),
+    Join(String),
+    Leave(String),
+    Step(TheaterStep),
+    Seek(TheaterSeek),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TheaterStep {
+    pub id: String,
+    pub delta: isize, // negative to step backward
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TheaterSeek {
+    pub id: String,
+    pub cursor: usize,
+}
+
+/// everything a client needs to draw the theater at the current cursor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TheaterFrame {
+    pub id: String,
+    pub presenter: String,
+    pub cursor: usize,
+    pub total: usize,
+    pub map_type: MapType,
+    pub sectors: Vec<Sector>,
+    pub clues: Vec<Clue>,
+    pub players: Vec<PlayerExport>,
+    pub events: Vec<JournalEntry>,
+    pub results: Option<Vec<UserResultSummary>>, // only at the end of the playback
+}
+
+impl Theater {
+    pub fn new(
+        id: String,
+        presenter: String,
+        presenter_socket: String,
+        export: GameExport,
+    ) -> Self {
+        Theater {
+            id,
+            presenter,
+            presenter_socket,
+            export,
+            cursor: 0,
+        }
+    }
+
+    pub fn is_supported(export: &GameExport) -> bool {
+        export.version <= EXPORT_VERSION
+    }
+
+    /// socket.io room of the viewers, kept apart from the game room ids.
+    pub fn channel(id: &str) -> String {
+        format!("theater-{id}")
+    }
+
+    pub fn total(&self) -> usize {
+        self.export.journal.len()
+    }
+
+    pub fn step(&mut self, delta: isize) {
+        self.seek(self.cursor.saturating_add_signed(delta));
+    }
+
+    pub fn seek(&mut self, cursor: usize) {
+        self.cursor = cursor.min(self.total());
+    }
+
+    pub fn frame(&self) -> TheaterFrame {
+        TheaterFrame {
+            id: self.id.clone(),
+            presenter: self.presenter.clone(),
+            cursor: self.cursor,
+            total: self.total(),
+            map_type: self.export.map_type.clone(),
+            sectors: self.export.sectors.clone(),
+            clues: self
+                .export
+                .research_clues
+                .iter()
+                .chain(self.export.x_clues.iter())
+                .cloned()
+                .collect(),
+            players: self.export.players.clone(),
+            events: self.export.journal[..self.cursor].to_vec(),
+            results: (self.cursor == self.total()).then(|| self.export.results.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    use crate::{
+        map::SectorType,
+        operation::{Operation, OperationResult, TargetOperation},
+        room::{GameStage, RoomRules},
+    };
+
+    fn export(moves: usize) -> GameExport {
+        let entry = JournalEntry {
+            user_id: "u1".to_string(),
+            round: 1,
+            stage: GameStage::UserMove,
+            op: Operation::Target(TargetOperation { index: 1 }),
+            result: OperationResult::Target(SectorType::Comet),
+            think_ms: None,
+        };
+        GameExport {
+            version: EXPORT_VERSION,
+            room_id: "1234".to_string(),
+            map_type: MapType::Standard,
+            map_seed: 0,
+            rules: RoomRules::default(),
+            competitive: false,
+            sectors: vec![],
+            research_clues: vec![],
+            x_clues: vec![],
+            solvability: None,
+            players: vec![],
+            journal: vec![entry; moves],
+            results: vec![],
+        }
+    }
+
+    #[test]
+    fn test_theater_step() {
+        let mut theater = Theater::new("0001".into(), "u1".into(), "s1".into(), export(3));
+        assert_eq!(theater.frame().events.len(), 0);
+        assert!(theater.frame().results.is_none());
+
+        theater.step(2);
+        assert_eq!(theater.frame().events.len(), 2);
+        theater.step(5);
+        assert_eq!(theater.cursor, 3);
+        assert!(theater.frame().results.is_some());
+        theater.step(-10);
+        assert_eq!(theater.cursor, 0);
+        theater.seek(1);
+        assert_eq!(theater.frame().events.len(), 1);
+    }
+}
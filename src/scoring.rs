@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{MapType, SectorType},
+    room::{BotPersona, GameStateResp, RoomRules, ServerGameState, UserResultSummary},
+};
+
+/// bonus for locating x at the end of the game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum XBonusCurve {
+    /// `terminator` points for locating at the terminator's step,
+    /// `per_step` points for each step a later locator was behind the terminator.
+    Distance { terminator: usize, per_step: usize },
+    /// points by the order x was located in, the first value goes to the terminator.
+    /// locators beyond the list get nothing.
+    ByOrder(Vec<usize>),
+}
+
+impl Default for XBonusCurve {
+    fn default() -> Self {
+        XBonusCurve::Distance {
+            terminator: 10,
+            per_step: 2,
+        }
+    }
+}
+
+/// how many points everything is worth in a room.
+#[derive(Debug, Clone)]
+pub struct ScoringTable {
+    pub map_type: MapType,
+    pub x_bonus: XBonusCurve,
+}
+
+impl ScoringTable {
+    pub fn new(map_type: &MapType, rules: &RoomRules) -> Self {
+        ScoringTable {
+            map_type: map_type.clone(),
+            x_bonus: rules.x_bonus.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn sector_points(&self, sector_type: &SectorType) -> usize {
+        sector_points(&self.map_type, sector_type)
+    }
+
+    /// `order` is the 0-based position of the player in the locate order.
+    pub fn x_bonus(&self, order: usize, terminator_step: usize, step: usize) -> usize {
+        match &self.x_bonus {
+            XBonusCurve::Distance {
+                terminator,
+                per_step,
+            } => {
+                if terminator_step == step {
+                    *terminator
+                } else {
+                    per_step * terminator_step.saturating_sub(step)
+                }
+            }
+            XBonusCurve::ByOrder(points) => points.get(order).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// points of one correct theory of the sector type.
+pub fn sector_points(map_type: &MapType, sector_type: &SectorType) -> usize {
+    match sector_type {
+        SectorType::Comet => 3,
+        SectorType::Asteroid => 2,
+        SectorType::DwarfPlanet => match map_type {
+            MapType::Standard => 4,
+            MapType::Expert => 2,
+        },
+        SectorType::Nebula => 4,
+        SectorType::X | SectorType::Space => 0,
+    }
+}
+
+/// interim score built from public information only, streamed during the last move.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ProjectedScore {
+    pub id: String,
+    pub name: String,
+    pub revealed_points: usize, // points of the theories already proven right
+    pub pending_theories: usize, // placed theories not revealed yet
+    pub step: usize,
+}
+
+pub fn projected_scores(gs: &GameStateResp, ss: &ServerGameState) -> Vec<ProjectedScore> {
+    gs.users
+        .iter()
+        .map(|user_state| {
+            let tokens = ss.user_tokens.get(&user_state.id);
+            let revealed_points = tokens.map_or(0, |tokens| {
+                tokens
+                    .iter()
+                    .filter(|t| t.is_success_located_any())
+                    .map(|t| sector_points(&gs.map_type, &t.r#type))
+                    .sum()
+            });
+            let pending_theories = tokens.map_or(0, |tokens| {
+                tokens
+                    .iter()
+                    .filter(|t| t.placed && t.secret.r#type.is_none())
+                    .count()
+            });
+            ProjectedScore {
+                id: user_state.id.clone(),
+                name: user_state.name.clone(),
+                revealed_points,
+                pending_theories,
+                step: user_state.location.step(),
+            }
+        })
+        .collect()
+}
+
+/// score every player of an ended game, best first.
+///
+/// a right theory earns the points of its type, and 1 first bonus to each player whose right
+/// theory on the sector has the lowest meeting index, all of them on a tie. a wrong theory
+/// earns nothing and takes no first bonus. locating x earns the x bonus of the room rules.
+///
+/// the higher sum ranks first, a tie goes to the player further behind on the time track, and
+/// the players still tied share the rank, listed in seat order.
+pub fn compute_results(gs: &GameStateResp, ss: &ServerGameState) -> Vec<UserResultSummary> {
+    let mut results = vec![];
+    let terminator_step = ss.terminator_location.as_ref().map_or(0, |t| t.step());
+    let table = ScoringTable::new(&ss.map.r#type, &gs.rules);
+    for user_state in gs.users.iter() {
+        let id = user_state.id.clone();
+        let located = |r#type: SectorType| {
+            ss.user_tokens.get(&id).map_or(0, |tokens| {
+                tokens
+                    .iter()
+                    .filter(|t| t.is_success_located(r#type.clone()))
+                    .count()
+            })
+        };
+        let comet = located(SectorType::Comet);
+        let asteroid = located(SectorType::Asteroid);
+        let dwarf_planet = located(SectorType::DwarfPlanet);
+        let nebula = located(SectorType::Nebula);
+        let mut first = 0;
+        for s_index in 1..=gs.map_type.sector_count() {
+            let mut sector_tokens = ss
+                .user_tokens
+                .iter()
+                .filter_map(|(_user_id, tokens)| {
+                    tokens
+                        .iter()
+                        .find(|t| t.secret.sector_index == s_index && t.is_success_located_any())
+                })
+                .collect::<Vec<_>>();
+            sector_tokens.sort_by_key(|t| t.secret.state.meeting_index());
+            let first_meeting_index = sector_tokens
+                .first()
+                .map(|t| t.secret.state.meeting_index())
+                .unwrap_or(0);
+            if sector_tokens.iter().any(|t| {
+                t.secret.state.meeting_index() == first_meeting_index && t.secret.user_id == id
+            }) {
+                first += 1;
+            }
+        }
+        let step = user_state.location.step();
+        let x = ss
+            .located_order
+            .iter()
+            .position(|located| *located == id)
+            .map_or(0, |order| table.x_bonus(order, terminator_step, step));
+        let bonus = user_state.handicap.bonus_points;
+
+        let sum = dwarf_planet * table.sector_points(&SectorType::DwarfPlanet)
+            + asteroid * table.sector_points(&SectorType::Asteroid)
+            + comet * table.sector_points(&SectorType::Comet)
+            + nebula * table.sector_points(&SectorType::Nebula)
+            + first
+            + x
+            + bonus;
+
+        results.push(UserResultSummary {
+            id: id.clone(),
+            name: user_state.name.clone(),
+            rank: 0, // once sorted
+            sum,
+            first,
+            comet,
+            asteroid,
+            dwarf_planet,
+            nebula,
+            x,
+            bonus,
+            step,
+            bot: user_state.persona.map(BotPersona::flair),
+        });
+    }
+
+    results.sort_by_key(tie_break);
+    // a tied player shares the rank of the first one of the tie
+    let keys = results.iter().map(tie_break).collect::<Vec<_>>();
+    for (result, key) in results.iter_mut().zip(keys.iter()) {
+        result.rank = keys.iter().position(|k| k == key).unwrap_or(0) + 1;
+    }
+    results
+}
+
+/// the order of the results, the lower first: the higher sum, then the lower step.
+fn tie_break(result: &UserResultSummary) -> (std::cmp::Reverse<usize>, usize) {
+    (std::cmp::Reverse(result.sum), result.step)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        map::{TheoryState, Token},
+        room::{UserLocationSequence, UserState},
+        server_state::User,
+    };
+
+    /// a placed theory revealed `steps_left` meetings after it was submitted, right or wrong.
+    fn theory(
+        user_id: &str,
+        r#type: SectorType,
+        sector: usize,
+        steps_left: usize,
+        right: bool,
+    ) -> Token {
+        let mut token = Token::new(r#type, user_id, 1);
+        token.placed = true;
+        token.secret.sector_index = sector;
+        token.secret.state = TheoryState::Submitted { steps_left };
+        match right {
+            true => token.verify(),
+            false => token.disprove(),
+        }
+        token
+    }
+
+    struct Case {
+        name: &'static str,
+        map_type: MapType,
+        seats: Vec<(&'static str, usize, usize)>, // id, step and handicap points
+        theories: Vec<Token>,
+        located: Vec<&'static str>, // in order, the terminator step is 10
+        expected: Vec<(&'static str, usize, usize, usize)>, // id, rank, sum and first
+    }
+
+    fn play(case: &Case) -> Vec<UserResultSummary> {
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_type = case.map_type.clone();
+        let mut ss = ServerGameState::placeholder();
+        ss.map.r#type = case.map_type.clone();
+        for (i, (id, step, bonus)) in case.seats.iter().enumerate() {
+            let user = User::test(id);
+            let mut user_state = UserState::placeholder(&user, i + 1, false);
+            user_state.location.index = *step;
+            user_state.handicap.bonus_points = *bonus;
+            gs.users.push(user_state);
+            ss.user_tokens.insert(id.to_string(), vec![]);
+        }
+        for token in case.theories.iter() {
+            ss.user_tokens
+                .get_mut(&token.secret.user_id)
+                .unwrap()
+                .push(token.clone());
+        }
+        ss.located_order = case.located.iter().map(|id| id.to_string()).collect();
+        ss.terminator_location = Some(UserLocationSequence::placeholder(10, 1));
+        compute_results(&gs, &ss)
+    }
+
+    #[test]
+    fn test_compute_results() {
+        use SectorType::*;
+        let two = || vec![("a", 1, 0), ("b", 1, 0)];
+        let cases = vec![
+            Case {
+                name: "the earlier right theory takes the first bonus",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![
+                    theory("a", Comet, 2, 1, true),
+                    theory("b", Comet, 2, 3, true),
+                ],
+                located: vec![],
+                expected: vec![("a", 1, 4, 1), ("b", 2, 3, 0)],
+            },
+            Case {
+                name: "a tie on the meeting index gives both the first bonus",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![
+                    theory("a", Comet, 2, 2, true),
+                    theory("b", Comet, 2, 2, true),
+                ],
+                located: vec![],
+                expected: vec![("a", 1, 4, 1), ("b", 1, 4, 1)],
+            },
+            Case {
+                name: "a wrong theory earns nothing and takes no first bonus",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![
+                    theory("a", Comet, 2, 1, false),
+                    theory("b", Comet, 2, 3, true),
+                ],
+                located: vec![],
+                expected: vec![("b", 1, 4, 1), ("a", 2, 0, 0)],
+            },
+            Case {
+                name: "a first bonus per sector",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![
+                    theory("a", Comet, 2, 1, true),
+                    theory("a", Asteroid, 5, 3, true),
+                    theory("b", Asteroid, 5, 1, true),
+                ],
+                located: vec![],
+                expected: vec![("a", 1, 6, 1), ("b", 2, 3, 1)],
+            },
+            Case {
+                name: "a tied sum goes to the player further behind, not the more first bonuses",
+                map_type: MapType::Standard,
+                seats: vec![("a", 8, 0), ("b", 5, 1)],
+                theories: vec![
+                    theory("a", Comet, 2, 2, true),
+                    theory("b", Comet, 2, 3, true),
+                ],
+                located: vec![],
+                expected: vec![("b", 1, 4, 0), ("a", 2, 4, 1)],
+            },
+            Case {
+                name: "players tied on the sum and the step share the rank",
+                map_type: MapType::Standard,
+                seats: vec![("a", 3, 0), ("b", 3, 3), ("c", 3, 3)],
+                theories: vec![theory("a", Asteroid, 2, 1, true)],
+                located: vec![],
+                expected: vec![("a", 1, 3, 1), ("b", 1, 3, 0), ("c", 1, 3, 0)],
+            },
+            Case {
+                name: "the rank after a shared one counts the tied players",
+                map_type: MapType::Standard,
+                seats: vec![("a", 1, 0), ("b", 1, 3), ("c", 1, 3)],
+                theories: vec![],
+                located: vec![],
+                expected: vec![("b", 1, 3, 0), ("c", 1, 3, 0), ("a", 3, 0, 0)],
+            },
+            Case {
+                name: "the x bonus by the distance to the terminator",
+                map_type: MapType::Standard,
+                seats: vec![("a", 10, 0), ("b", 7, 0)],
+                theories: vec![],
+                located: vec!["a", "b"],
+                expected: vec![("a", 1, 10, 0), ("b", 2, 6, 0)],
+            },
+            Case {
+                name: "a dwarf planet on the standard map",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![
+                    theory("a", DwarfPlanet, 3, 1, true),
+                    theory("b", Nebula, 4, 1, true),
+                ],
+                located: vec![],
+                expected: vec![("a", 1, 5, 1), ("b", 1, 5, 1)],
+            },
+            Case {
+                name: "a dwarf planet on the expert map",
+                map_type: MapType::Expert,
+                seats: two(),
+                theories: vec![
+                    theory("a", DwarfPlanet, 3, 1, true),
+                    theory("b", Nebula, 4, 1, true),
+                ],
+                located: vec![],
+                expected: vec![("b", 1, 5, 1), ("a", 2, 3, 1)],
+            },
+            Case {
+                name: "a sector past the standard map scores no first bonus",
+                map_type: MapType::Standard,
+                seats: two(),
+                theories: vec![theory("a", Comet, 15, 1, true)],
+                located: vec![],
+                expected: vec![("a", 1, 3, 0), ("b", 2, 0, 0)],
+            },
+            Case {
+                name: "the same sector on the expert map does",
+                map_type: MapType::Expert,
+                seats: two(),
+                theories: vec![theory("a", Comet, 15, 1, true)],
+                located: vec![],
+                expected: vec![("a", 1, 4, 1), ("b", 2, 0, 0)],
+            },
+        ];
+        for case in cases.iter() {
+            let results = play(case)
+                .into_iter()
+                .map(|r| (r.id, r.rank, r.sum, r.first))
+                .collect::<Vec<_>>();
+            let expected = case
+                .expected
+                .iter()
+                .map(|(id, rank, sum, first)| (id.to_string(), *rank, *sum, *first))
+                .collect::<Vec<_>>();
+            assert_eq!(results, expected, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn test_sector_points() {
+        assert_eq!(
+            sector_points(&MapType::Standard, &SectorType::DwarfPlanet),
+            4
+        );
+        assert_eq!(sector_points(&MapType::Expert, &SectorType::DwarfPlanet), 2);
+        assert_eq!(sector_points(&MapType::Expert, &SectorType::Comet), 3);
+        assert_eq!(sector_points(&MapType::Expert, &SectorType::Space), 0);
+    }
+
+    #[test]
+    fn test_x_bonus() {
+        let table = ScoringTable::new(&MapType::Standard, &RoomRules::default());
+        assert_eq!(table.x_bonus(0, 20, 20), 10);
+        assert_eq!(table.x_bonus(1, 20, 17), 6);
+
+        let rules = RoomRules {
+            x_bonus: Some(XBonusCurve::ByOrder(vec![10, 7, 4])),
+            ..Default::default()
+        };
+        let table = ScoringTable::new(&MapType::Expert, &rules);
+        assert_eq!(table.x_bonus(0, 20, 20), 10);
+        assert_eq!(table.x_bonus(2, 20, 15), 4);
+        assert_eq!(table.x_bonus(3, 20, 15), 0);
+
+        let rules: RoomRules =
+            serde_json::from_str(r#"{"x_bonus":{"distance":{"terminator":8,"per_step":1}}}"#)
+                .unwrap();
+        let table = ScoringTable::new(&MapType::Expert, &rules);
+        assert_eq!(table.x_bonus(1, 20, 18), 2);
+    }
+}
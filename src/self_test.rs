@@ -0,0 +1,82 @@
+//! the deployment smoke check run by `planetx_server --self-test`: deal the maps and clues of a
+//! few seeds on both map types, play a short bot game, and exit non-zero when any of it fails.
+
+use anyhow::Context;
+use rand::{SeedableRng, rngs::SmallRng};
+use tracing::info;
+
+use crate::{
+    config::ServerConfig,
+    map::{ChoiceFilter, ClueGenerator, ClueWeights, Map, MapType},
+    operation::{Operation, OperationResult, ResearchOperation},
+    simulation::{SimulationConfig, simulate_games},
+};
+
+/// seeds dealt for each map type.
+const SELF_TEST_SEEDS: u64 = 16;
+
+/// check the config file, the dealing of maps and clues and a bot game, as the server would
+/// run them.
+pub fn self_test() -> anyhow::Result<()> {
+    let path = ServerConfig::path();
+    let config = match std::fs::exists(&path)? {
+        true => ServerConfig::reload().with_context(|| format!("config {path}"))?,
+        false => ServerConfig::default(),
+    };
+    run_checks(&config.clue_weights, SELF_TEST_SEEDS)?;
+    info!("self test passed");
+    Ok(())
+}
+
+fn run_checks(clue_weights: &ClueWeights, seeds: u64) -> anyhow::Result<()> {
+    for map_type in [MapType::Standard, MapType::Expert] {
+        for seed in 0..seeds {
+            deal(seed, map_type.clone(), clue_weights)
+                .with_context(|| format!("dealing seed {seed} ({map_type:?})"))?;
+        }
+        info!("{seeds} seeds dealt on {map_type:?}");
+
+        let report = simulate_games(&SimulationConfig {
+            games: 1,
+            players: 2,
+            map_type: map_type.clone(),
+            seed: 0,
+        });
+        anyhow::ensure!(
+            report.finished == 1 && report.illegal_moves == 0,
+            "bot game on {map_type:?}: {report:?}"
+        );
+        info!("bot game played on {map_type:?}");
+    }
+    Ok(())
+}
+
+/// the map and clues of the seed, every clue true of the map.
+fn deal(seed: u64, map_type: MapType, clue_weights: &ClueWeights) -> anyhow::Result<()> {
+    let map = Map::new(SmallRng::seed_from_u64(seed), map_type.clone())?;
+    let (research_clues, x_clues) = ClueGenerator::new(seed, map.sectors.clone(), map_type)
+        .with_weights(clue_weights.clone())
+        .generate_clues()?;
+    for clue in research_clues.iter().chain(x_clues.iter()) {
+        let op = Operation::Research(ResearchOperation {
+            index: clue.index.clone(),
+        });
+        let opr = OperationResult::Research(clue.clone());
+        anyhow::ensure!(
+            ChoiceFilter::filter_op(&map.sectors, &op, &opr),
+            "clue {clue:?} is false of the map"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_self_test_checks() {
+        run_checks(&ClueWeights::default(), 2).unwrap();
+    }
+}
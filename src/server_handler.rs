@@ -1,58 +1,96 @@
-use std::{collections::HashMap, vec};
+use std::vec;
 
 use crate::{
-    map::{ChoiceFilter, MapType, SectorType},
-    operation::{Operation, OperationResult, ResearchOperation},
-    recommendation::{BestMoveInfo, RecommendOperation, SectorIndex, best_move},
+    broadcast::{Outbox, board_tokens},
+    engine::{self, EngineEvent},
+    operation::Operation,
+    recommendation::RecommendOperation,
+    redact::Secret,
     room::{
-        GameStage, GameState, GameStateResp, RoomUserOperation, ServerGameState, ServerResp,
-        UserLocationSequence, UserResultSummary, UserState,
+        BugReportFiled, BugReportRequest, CastOperation, CasterView, ChatMessage, ChatOperation,
+        GameStage, GameStateResp, LobbyQuery, OpError, Penalty, PreferencesOperation, ReactionSend,
+        RoomUserOperation, SaveCodeIssued, SaveCodeRequest, ServerResp, SyncBundle, Theater,
+        TheaterOperation, now_secs, revealed_x_clues,
     },
-    server_state::{StateRef, User},
+    scoring::projected_scores,
+    server_state::{State as ServerState, StateRef, User},
+    sink::{Capabilities, EventSink},
 };
-use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
 use socketioxide::{
     SocketIo,
-    extract::{Data, SocketRef, State},
+    extract::{Data, Extension, SocketRef, TryData},
 };
 use tracing::info;
 
-pub async fn handle_on_connect(_io: SocketIo, socket: SocketRef, _state: State<StateRef>) {
-    // let client_id = uuid::Uuid::new_v4().to_string();
-    // state
-    //     .lock()
-    //     .await
-    //     .users
-    //     .insert(socket.id.to_string(), client_id.clone());
-    // socket.extensions.insert::<String>(client_id);
+/// `state` is the state of the namespace the socket connected to, its handlers extract it.
+pub async fn handle_on_connect(_io: SocketIo, socket: SocketRef, state: StateRef) {
+    socket.extensions.insert(state);
 
     info!(ns = "socket.io", ?socket.id, "new client connected");
 
     socket.on(
         "auth",
-        |socket: SocketRef, state: State<StateRef>, user: Data<User>| async move {
-            state
-                .0
-                .lock()
-                .await
-                .upsert_user(socket.id.to_string(), user.0.clone(), socket.clone());
+        |socket: SocketRef, state: Extension<StateRef>, user: Data<User>| async move {
+            let mut state = state.0.lock().await;
+            state.upsert_user(socket.id.to_string(), user.0.clone(), socket.clone());
             info!(ns = "socket.io", ?socket.id, "auth {:?}", user.0);
+            // the broadcasts of the events the client lacks a flag for skip these rooms
+            let capabilities = user.0.capabilities;
+            socket.leave(Capabilities::default().lacking_rooms());
+            socket.join(capabilities.lacking_rooms());
             socket
                 .emit("server_resp", &ServerResp::auth_success_version())
                 .ok();
+            let maintenance = state.maintenance.as_ref();
+            if let Some(maintenance) = maintenance.filter(|_| capabilities.accepts("maintenance")) {
+                socket.emit("maintenance", maintenance).ok();
+            }
         },
     );
 
-    socket.on_disconnect(|socket: SocketRef, state: State<StateRef>| async move {
-        state.0.lock().await.users.remove(socket.id.as_str());
+    socket.on_disconnect(|socket: SocketRef, state: Extension<StateRef>| async move {
+        let mut state = state.0.lock().await;
+        let unreadied = state.drop_socket(socket.id.as_str());
+        for gs in unreadied.iter() {
+            let state_event = gs.state_event();
+            state
+                .relay
+                .to_room(&gs.id, state_event.event(), &state_event);
+        }
+        let closed_theaters = std::mem::take(&mut state.closed_theaters);
+        drop(state);
+        for gs in unreadied {
+            let state_event = gs.state_event();
+            let room = socket.to(gs.id.clone());
+            room.emit(state_event.event(), &state_event).await.ok();
+        }
+        // the presenter is gone, the viewers stop the playback
+        for id in closed_theaters {
+            let viewers = socket.to(Theater::channel(&id));
+            viewers.emit("theater_closed", &id).await.ok();
+        }
         info!(ns = "socket.io", ?socket.id, "disconnected");
     });
 
+    // clients that may die silently, e.g. a backgrounded phone, ping every few seconds. once a
+    // socket pinged, it is dropped when its pings stop for `heartbeat_timeout_secs`.
+    socket.on(
+        "ping",
+        |socket: SocketRef, state: Extension<StateRef>| async move {
+            let mut state = state.lock().await;
+            if state.check_auth(socket.id.as_str()).is_none() {
+                return;
+            }
+            state.heartbeat(socket.id.as_str());
+            socket.emit("pong", &now_secs()).ok();
+        },
+    );
+
     socket.on(
         "recommend",
         |io: SocketIo,
          socket: SocketRef,
-         State::<StateRef>(state),
+         Extension::<StateRef>(state),
          Data::<RecommendOperation>(op)| async move {
             handle_recommend(io, socket, state, op).await;
         },
@@ -60,8 +98,14 @@ pub async fn handle_on_connect(_io: SocketIo, socket: SocketRef, _state: State<S
 
     socket.on(
         "op",
-        |io: SocketIo,socket: SocketRef, State::<StateRef>(state), Data::<Operation>(op)| async move {
-            handle_op(io, socket, state, op).await;
+        |io: SocketIo,
+         socket: SocketRef,
+         Extension::<StateRef>(state),
+         TryData::<Operation>(op)| async move {
+            match op {
+                Ok(op) => handle_op(io, socket, state, op).await,
+                Err(e) => reject_payload(&socket, &state, "op", e).await,
+            }
         },
     );
 
@@ -69,65 +113,213 @@ pub async fn handle_on_connect(_io: SocketIo, socket: SocketRef, _state: State<S
         "room",
         |io: SocketIo,
          socket: SocketRef,
-         State::<StateRef>(state),
-         Data::<RoomUserOperation>(op)| async move {
-            handle_room(io, socket, state, op).await;
+         Extension::<StateRef>(state),
+         TryData::<RoomUserOperation>(op)| async move {
+            match op {
+                Ok(op) => handle_room(io, socket, state, op).await,
+                Err(e) => reject_payload(&socket, &state, "room", e).await,
+            }
+        },
+    );
+
+    socket.on(
+        "chat",
+        |io: SocketIo,
+         socket: SocketRef,
+         Extension::<StateRef>(state),
+         Data::<ChatOperation>(op)| async move {
+            handle_chat(io, socket, state, op).await;
         },
     );
 
     socket.on(
-        "sync",
-        |_io: SocketIo, socket: SocketRef, state: State<StateRef>| async move {
+        "reaction",
+        |io: SocketIo,
+         socket: SocketRef,
+         Extension::<StateRef>(state),
+         Data::<ReactionSend>(send)| async move {
+            handle_reaction(io, socket, state, send).await;
+        },
+    );
+
+    socket.on(
+        "cast",
+        |socket: SocketRef, Extension::<StateRef>(state), Data::<CastOperation>(op)| async move {
             let user = state.lock().await.check_auth(socket.id.as_str()).cloned();
             let Some(user) = user else {
+                info!(ns = "socket.io", ?socket.id, "unauthorized cast op");
+                return;
+            };
+            let resp = state.lock().await.handle_cast_op(socket.clone(), user, op);
+            match resp {
+                Ok(Some(view)) => {
+                    socket.emit("caster_view", &view).ok();
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    info!(ns = "socket.io", ?socket.id, ?e, "cast op error");
+                    socket.emit("server_resp", &ServerResp::RoomErrors(e)).ok();
+                }
+            }
+        },
+    );
+
+    socket.on(
+        "theater",
+        |socket: SocketRef, Extension::<StateRef>(state), Data::<TheaterOperation>(op)| async move {
+            handle_theater(socket, state, op).await;
+        },
+    );
+
+    socket.on(
+        "lobby",
+        |socket: SocketRef, state: Extension<StateRef>, Data::<LobbyQuery>(query)| async move {
+            let state = state.lock().await;
+            if state.check_auth(socket.id.as_str()).is_none() {
+                info!(ns = "socket.io", ?socket.id, "unauthorized lobby query");
+                return;
+            }
+            socket.emit("lobby", &state.query_lobby(&query)).ok();
+        },
+    );
+
+    socket.on(
+        "preferences",
+        |socket: SocketRef, state: Extension<StateRef>, Data::<PreferencesOperation>(op)| async move {
+            let mut state = state.lock().await;
+            let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+                info!(ns = "socket.io", ?socket.id, "unauthorized preferences op");
+                return;
+            };
+            let preferences = state.handle_preferences(&user.id, op);
+            socket.emit("preferences", &preferences).ok();
+        },
+    );
+
+    socket.on(
+        "sync",
+        |_io: SocketIo, socket: SocketRef, state: Extension<StateRef>| async move {
+            let state = state.lock().await;
+            let Some(user) = state.check_auth(socket.id.as_str()) else {
                 info!(ns = "socket.io", ?socket.id, "unauthorized sync");
                 return;
             };
-            for (_room_id, (gs, ss)) in state.lock().await.iter_all() {
-                for user_state in gs.users.iter() {
-                    if user_state.id != user.id {
-                        continue;
-                    }
+            // only the room the user sits in, found from the index
+            let Some(room_id) = state.find_user_room(&user.id) else {
+                return;
+            };
+            let Some((gs, ss)) = state.state_data.get(&room_id) else {
+                return;
+            };
+            let Some(user_state) = gs.users.iter().find(|u| u.id == user.id) else {
+                return;
+            };
+            // one atomic snapshot for the clients that take it
+            if user.capabilities.contains(Capabilities::SYNC_BUNDLE) && !gs.in_lobby() {
+                if let Some(bundle) = SyncBundle::new(gs, ss, &user.id, user.capabilities) {
+                    info!(ns = "socket.io", ?socket.id, "sync bundle {:?}", Secret(&bundle));
+                    socket.emit("sync_bundle", &bundle).ok();
+                }
+                return;
+            }
 
-                    socket.emit("game_start", &ss.clue_secret()).ok();
+            socket.emit("game_start", &ss.clue_secret()).ok();
 
-                    info!(ns = "socket.io", ?socket.id, "sync game state {:?}", gs);
-                    socket.emit("game_state", &gs).ok();
+            info!(ns = "socket.io", ?socket.id, "sync game state {:?}", Secret(gs));
+            let state_event = gs.state_event();
+            socket.emit(state_event.event(), &state_event).ok();
 
-                    for re in user_state.moves_result.iter() {
-                        socket.emit("op_result", re).ok();
-                    }
+            for re in user_state.moves_result.iter() {
+                socket.emit("op_result", re).ok();
+            }
 
-                    // emit xclue to user if after xclue point
-                    gs.map_type
-                        .xclue_points()
-                        .iter()
-                        .enumerate()
-                        .for_each(|(i, (index, _))| {
-                            if gs.round > 1 || gs.start_index > *index {
-                                socket.emit("xclue", &vec![ss.x_clues[i].clone()]).ok();
-                            }
-                        });
+            // emit xclue to user if after xclue point
+            for clue in revealed_x_clues(gs, ss) {
+                socket.emit("xclue", &vec![clue]).ok();
+            }
 
-                    let Some(tokens) = ss.user_tokens.get(&user.id) else {
-                        continue;
-                    };
-                    info!(ns = "socket.io", ?socket.id, "sync tokens {:?}", tokens);
-                    socket.emit("token", &tokens).ok();
+            if let Some(reveals) = ss.handicap_reveals.get(&user.id) {
+                socket.emit("handicap_reveal", reveals).ok();
+            }
 
-                    let tokens = ss
-                        .user_tokens
-                        .iter()
-                        .flat_map(|(_user_id, tokens)| tokens.iter())
-                        .filter(|t| t.placed)
-                        .map(|t| &t.secret)
-                        .cloned()
-                        .collect::<Vec<_>>();
-                    socket.emit("board_tokens", &tokens).ok();
+            let Some(tokens) = ss.user_tokens.get(&user.id) else {
+                return;
+            };
+            info!(ns = "socket.io", ?socket.id, "sync tokens {:?}", Secret(tokens));
+            socket.emit("token", &tokens).ok();
+
+            socket.emit("board_tokens", &board_tokens(ss)).ok();
+        },
+    );
+
+    // a player reporting a bug in their room gets the id of the bundle to give along
+    socket.on(
+        "bug_report",
+        |socket: SocketRef, state: Extension<StateRef>, Data::<BugReportRequest>(request)| async move {
+            let mut state = state.lock().await;
+            let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+                info!(ns = "socket.io", ?socket.id, "unauthorized bug report");
+                return;
+            };
+            if !state.take_rate_limit(&user) {
+                socket.emit("server_resp", &ServerResp::RateLimited).ok();
+                return;
+            }
+            match state.file_bug_report(&request.room_id, Some(&user.id), request.note) {
+                Ok(id) => {
+                    socket.emit("bug_report", &BugReportFiled { id }).ok();
+                }
+                Err(e) => {
+                    socket.emit("server_resp", &ServerResp::RoomErrors(e)).ok();
+                }
+            }
+        },
+    );
+
+    // the host of a running room keeps its save code to resume the game later, here or elsewhere
+    socket.on(
+        "save_code",
+        |socket: SocketRef, state: Extension<StateRef>, Data::<SaveCodeRequest>(request)| async move {
+            let mut state = state.lock().await;
+            let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+                info!(ns = "socket.io", ?socket.id, "unauthorized save code");
+                return;
+            };
+            if !state.take_rate_limit(&user) {
+                socket.emit("server_resp", &ServerResp::RateLimited).ok();
+                return;
+            }
+            match state.save_code(&request.room_id, &user.id) {
+                Ok(code) => {
+                    let issued = SaveCodeIssued {
+                        room_id: request.room_id,
+                        code,
+                    };
+                    socket.emit("save_code", &issued).ok();
+                }
+                Err(e) => {
+                    socket.emit("server_resp", &ServerResp::RoomErrors(e)).ok();
                 }
             }
         },
     );
+
+    // one machine readable message with everything the seat knows, for scripted clients
+    socket.on(
+        "catch_up",
+        |socket: SocketRef, state: Extension<StateRef>| async move {
+            let mut state = state.lock().await;
+            let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+                info!(ns = "socket.io", ?socket.id, "unauthorized catch up");
+                return;
+            };
+            if !state.take_rate_limit(&user) {
+                socket.emit("server_resp", &ServerResp::RateLimited).ok();
+                return;
+            }
+            socket.emit("catch_up", &state.catch_up(&user.id)).ok();
+        },
+    );
 }
 
 async fn handle_recommend(
@@ -141,12 +333,16 @@ async fn handle_recommend(
         info!(ns = "socket.io", ?socket.id, "unauthorized recommend op {:?}", op);
         return;
     };
+    if !state.lock().await.take_rate_limit(&user) {
+        socket.emit("server_resp", &ServerResp::RateLimited).ok();
+        return;
+    }
 
     info!(?op, ?socket.id, "received recommend op {:?}", op);
 
     match state.lock().await.handle_recommend_op(user, op) {
         Ok(resp) => {
-            info!(ns = "socket.io", ?socket.id, ?resp, "recommend success");
+            info!(ns = "socket.io", ?socket.id, resp = ?Secret(&resp), "recommend success");
             socket.emit("recommend_result", &resp).ok();
         }
         Err(e) => {
@@ -158,7 +354,7 @@ async fn handle_recommend(
     }
 }
 
-async fn handle_op(_io: SocketIo, socket: SocketRef, state: StateRef, op: Operation) {
+async fn handle_op(io: SocketIo, socket: SocketRef, state: StateRef, op: Operation) {
     let user = state.lock().await.check_auth(socket.id.as_str()).cloned();
     let Some(user) = user else {
         info!(ns = "socket.io", ?socket.id, "unauthorized room op {:?}", op);
@@ -167,23 +363,103 @@ async fn handle_op(_io: SocketIo, socket: SocketRef, state: StateRef, op: Operat
 
     info!(?op, ?socket.id, "received op {:?}", op);
 
-    match state.lock().await.handle_action_op(user, &op) {
+    let mut state = state.lock().await;
+    match state.play(&user, &op) {
         Ok(resp) => {
             // to the user
-            info!(ns = "socket.io", ?socket.id, ?resp, "op success");
-            socket.emit("op_result", &resp).ok();
+            info!(ns = "socket.io", ?socket.id, resp = ?Secret(&resp), "op success");
+            socket
+                .emit("op_result", &user.capabilities.op_result(resp))
+                .ok();
             // to other users in the room
             // the automove will do the broadcast
             // socket.to("room_id").emit("op", &op).await.ok();
+            send_op_effects(&io, &mut state).await;
+            broadcast_projected_scores(&state.sink(&io), &state, &user.id).await;
         }
         Err(e) => {
             info!(ns = "socket.io", ?socket.id, ?e, "op error");
-            socket.emit("server_resp", &ServerResp::OpErrors(e)).ok();
+            socket.emit("server_resp", &e).ok();
+            // a client still showing the game in play, its end again
+            let finished = matches!(e, ServerResp::OpErrors(OpError::GameFinished));
+            if let Some((gs, tokens)) = state.final_state(&user.id).filter(|_| finished) {
+                let state_event = gs.state_event();
+                socket.emit(state_event.event(), &state_event).ok();
+                socket.emit("board_tokens", &tokens).ok();
+            }
+        }
+    }
+}
+
+/// tell the rooms why their players moved, for the penalties queued by the operations.
+pub async fn send_penalties(sink: &impl EventSink, penalties: Vec<Penalty>) {
+    for penalty in penalties {
+        sink.to_room(&penalty.room_id, "penalty", &penalty).await;
+    }
+}
+
+/// send what an operation queued: its penalties, then the phases it ran as the tick would.
+pub async fn send_op_effects(io: &SocketIo, state: &mut ServerState) {
+    let penalties = std::mem::take(&mut state.penalties);
+    let events = std::mem::take(&mut state.engine_events);
+    let sink = state.sink(io);
+    send_penalties(&sink, penalties).await;
+    let mut outbox = Outbox::default();
+    let mut room_events = vec![];
+    queue_engine_events(state, events, &mut outbox, &mut room_events);
+    engine::send(&sink, outbox, room_events).await;
+}
+
+/// the room states and boards go to the outbox, the other events keep their order.
+fn queue_engine_events(
+    state: &ServerState,
+    events: Vec<(String, EngineEvent)>,
+    outbox: &mut Outbox,
+    room_events: &mut Vec<(String, EngineEvent)>,
+) {
+    for (room_id, event) in events {
+        let Some((gs, ss)) = state.state_data.get(&room_id) else {
+            continue;
+        };
+        match event {
+            EngineEvent::GameState => outbox.game_state(gs),
+            EngineEvent::BoardTokens => outbox.board_tokens(&room_id, ss),
+            event => room_events.push((room_id, event)),
         }
     }
 }
 
-async fn handle_room(_io: SocketIo, socket: SocketRef, state: StateRef, op: RoomUserOperation) {
+/// stream the interim scores while the last moves resolve.
+pub async fn broadcast_projected_scores(
+    sink: &impl EventSink,
+    state: &crate::server_state::State,
+    user_id: &str,
+) {
+    let Some(room_id) = state.find_user_room(user_id) else {
+        return;
+    };
+    let Some((gs, ss)) = state.state_data.get(&room_id) else {
+        return;
+    };
+    if gs.game_stage == GameStage::LastMove {
+        sink.to_room(&room_id, "projected_score", &projected_scores(gs, ss))
+            .await;
+    }
+}
+
+/// answer a payload that doesn't deserialize, so the client learns what is wrong with it.
+async fn reject_payload(
+    socket: &SocketRef,
+    state: &StateRef,
+    event: &str,
+    error: impl std::fmt::Display,
+) {
+    let resp = state.lock().await.bad_request(event, error);
+    info!(ns = "socket.io", ?socket.id, ?resp, "bad payload");
+    socket.emit("server_resp", &resp).ok();
+}
+
+async fn handle_room(io: SocketIo, socket: SocketRef, state: StateRef, op: RoomUserOperation) {
     let user = state.lock().await.check_auth(socket.id.as_str()).cloned();
     let Some(user) = user else {
         info!(ns = "socket.io", ?socket.id, "unauthorized room op {:?}", op);
@@ -191,20 +467,39 @@ async fn handle_room(_io: SocketIo, socket: SocketRef, state: StateRef, op: Room
     };
 
     info!(?op, ?socket.id, "received room op {:?}", op);
+    let dealt = matches!(
+        op,
+        RoomUserOperation::Puzzle(_) | RoomUserOperation::Resume(_)
+    );
 
-    match state
-        .lock()
-        .await
-        .handle_room_op(socket.clone(), user.clone(), op)
-    {
+    let result = {
+        let mut state = state.lock().await;
+        let result = state.handle_room_op(&socket, user.clone(), op);
+        for gs in result.iter().flatten() {
+            state.touch_room(&gs.id);
+            let state_event = gs.state_event();
+            state
+                .relay
+                .to_room(&gs.id, state_event.event(), &state_event);
+        }
+        result
+    };
+    match result {
         Ok(resp) => {
             let mut do_resp = false;
+            // a practice or resumed room starts dealt, its player needs their clues and tokens too
+            let dealt_room = resp
+                .iter()
+                .find(|gs| dealt && gs.is_playing() && gs.users.iter().any(|u| u.id == user.id))
+                .map(|gs| gs.id.clone());
             for gs in resp {
-                info!(ns = "socket.io", ?socket.id, ?gs, "room op success");
+                info!(ns = "socket.io", ?socket.id, gs = ?Secret(&gs), "room op success");
 
-                socket.to(gs.id.clone()).emit("game_state", &gs).await.ok();
+                let state_event = gs.state_event();
+                let room = socket.to(gs.id.clone());
+                room.emit(state_event.event(), &state_event).await.ok();
                 if gs.users.iter().any(|u| u.id == user.id) {
-                    socket.emit("game_state", &gs).ok();
+                    socket.emit(state_event.event(), &state_event).ok();
                     do_resp = true;
                 }
             }
@@ -212,6 +507,10 @@ async fn handle_room(_io: SocketIo, socket: SocketRef, state: StateRef, op: Room
                 // no game state to response, empty client game state
                 socket.emit("game_state", &GameStateResp::empty()).ok();
             }
+            if let Some(room_id) = dealt_room {
+                let state = state.lock().await;
+                broadcast_full_state(&state.sink(&io), &state, &room_id).await;
+            }
         }
 
         Err(e) => {
@@ -221,804 +520,308 @@ async fn handle_room(_io: SocketIo, socket: SocketRef, state: StateRef, op: Room
     }
 }
 
+async fn handle_chat(io: SocketIo, socket: SocketRef, state: StateRef, op: ChatOperation) {
+    let mut state = state.lock().await;
+    let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+        info!(ns = "socket.io", ?socket.id, "unauthorized chat op");
+        return;
+    };
+    match state.handle_chat_op(&user, op) {
+        Ok(Some(message)) => send_chat(&state.sink(&io), &state, &message),
+        Ok(None) => {}
+        Err(e) => {
+            info!(ns = "socket.io", ?socket.id, ?e, "chat op error");
+            socket.emit("server_resp", &ServerResp::ChatErrors(e)).ok();
+        }
+    }
+}
+
+/// a chat message to each player of its room, skipping the ones who blocked the sender.
+pub fn send_chat(sink: &impl EventSink, state: &ServerState, message: &ChatMessage) {
+    for user_id in state.chat_recipients(message) {
+        sink.to_user(&user_id, "chat", message);
+    }
+}
+
+async fn handle_reaction(io: SocketIo, socket: SocketRef, state: StateRef, send: ReactionSend) {
+    let mut state = state.lock().await;
+    let Some(user) = state.check_auth(socket.id.as_str()).cloned() else {
+        info!(ns = "socket.io", ?socket.id, "unauthorized reaction");
+        return;
+    };
+    match state.react(&user, send) {
+        Ok(reaction) => {
+            let sink = state.sink(&io);
+            sink.to_room(&reaction.room_id, "reaction", &reaction).await;
+        }
+        Err(e) => {
+            socket.emit("server_resp", &ServerResp::ChatErrors(e)).ok();
+        }
+    }
+}
+
+async fn handle_theater(socket: SocketRef, state: StateRef, op: TheaterOperation) {
+    let user = state.lock().await.check_auth(socket.id.as_str()).cloned();
+    let Some(user) = user else {
+        info!(ns = "socket.io", ?socket.id, "unauthorized theater op");
+        return;
+    };
+    let left = match &op {
+        TheaterOperation::Leave(id) => Some(id.clone()),
+        _ => None,
+    };
+
+    let resp = state
+        .lock()
+        .await
+        .handle_theater_op(socket.clone(), user, op);
+    match resp {
+        Ok(Some(frame)) => {
+            socket
+                .to(Theater::channel(&frame.id))
+                .emit("theater_frame", &frame)
+                .await
+                .ok();
+            socket.emit("theater_frame", &frame).ok();
+        }
+        Ok(None) => {
+            // the presenter closed the theater, the viewers stop the playback
+            let Some(id) = left else { return };
+            if !state.lock().await.theaters.contains_key(&id) {
+                socket
+                    .to(Theater::channel(&id))
+                    .emit("theater_closed", &id)
+                    .await
+                    .ok();
+            }
+        }
+        Err(e) => {
+            info!(ns = "socket.io", ?socket.id, ?e, "theater op error");
+            socket
+                .emit("server_resp", &ServerResp::TheaterErrors(e))
+                .ok();
+        }
+    }
+}
+
+/// reload the config file on SIGHUP, the rooms and games in memory are kept.
+#[cfg(unix)]
+pub fn register_reload_on_hangup(state: StateRef) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+        tracing::warn!("can't listen to SIGHUP, reload the config with the admin api");
+        return;
+    };
+    tokio::task::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match crate::config::ServerConfig::reload() {
+                Ok(config) => {
+                    state.lock().await.reload_config(config);
+                }
+                Err(e) => tracing::warn!(?e, "config reload failed, the running config is kept"),
+            }
+        }
+    });
+}
+
 pub fn register_state_manager(state: StateRef, io: SocketIo) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+    let (writes, batches) = tokio::sync::mpsc::unbounded_channel();
+    let (events, event_batches) = tokio::sync::mpsc::unbounded_channel();
     tokio::task::spawn(async move {
+        let (storage, analytics) = {
+            let state = state.lock().await;
+            (state.storage.clone(), state.config.analytics.clone())
+        };
+        tokio::task::spawn(crate::storage::write_behind(storage, batches));
+        if let Some(config) = analytics {
+            match crate::analytics::connect(&config) {
+                Ok(sink) => {
+                    tokio::task::spawn(crate::analytics::ship(sink, event_batches));
+                }
+                Err(e) => tracing::warn!(?e, "invalid analytics sink, no analytics are sent"),
+            }
+        }
         loop {
             interval.tick().await;
             let mut state = state.lock().await;
+            let mut outbox = Outbox::default();
+
+            // 0. turn timeouts, the server plays the turn of idle seats
+            let timers = state.expire_turns();
+            for (user_id, deadline) in &timers.warnings {
+                state.sink(&io).to_user(user_id, "turn_deadline", deadline);
+            }
+            for (room_id, timeout) in &timers.timeouts {
+                let sink = state.sink(&io);
+                sink.to_room(room_id, "turn_timeout", timeout).await;
+            }
+            for (room_id, kick) in timers.kicks {
+                info!("idle kick at room: {} {:?}", room_id, kick);
+                state.sink(&io).to_room(&room_id, "idle_kick", &kick).await;
+                if let Some(gs) = state.get_game_state(&room_id) {
+                    outbox.game_state(gs);
+                }
+            }
 
-            // 0. act for bot
+            // 0.5 act for bots, bot controlled seats and timed out seats
             let mut bot_ops = vec![];
+            #[cfg(feature = "metrics")]
+            let shadow_weights = state.config.shadow_bot.clone();
             for (room_id, (gs, ss)) in state.iter_mut_all() {
-                let bot_id = format!("bot-{}", room_id);
-                if gs.status == GameState::Wait(vec![bot_id.clone()]) {
-                    info!("bot at room: {}", room_id);
-
-                    let map_type = gs.map_type.clone();
-                    let start_index = SectorIndex::new(gs.start_index, gs.map_type.sector_count());
-                    let end_index = SectorIndex::new(gs.end_index, gs.map_type.sector_count());
-                    let Some(bot_state) = gs.users.iter().find(|u| u.id == bot_id) else {
-                        continue;
-                    };
-                    let Some(tokens) = ss.user_tokens.get(&bot_id) else {
-                        continue;
-                    };
-                    let Some(choices) = ss.choices.get(&bot_id) else {
-                        continue;
-                    };
-                    let info = BestMoveInfo {
-                        stage: gs.game_stage.clone(),
-                        map_type,
-                        start_index,
-                        end_index,
-                        revealed_sectors: ss.revealed_sector_indexs.clone(),
+                let timeouts = &timers.timeouts;
+                let is_timed_out = |id: &str| {
+                    timeouts
+                        .iter()
+                        .any(|(r, t)| r == room_id && t.user_id == id)
+                };
+                for (bot, op) in engine::bot_moves(gs, ss, is_timed_out) {
+                    #[cfg(feature = "metrics")]
+                    let op = {
+                        let shadow = shadow_weights
+                            .as_ref()
+                            .and_then(|weights| engine::shadow_move(gs, ss, &bot.id, weights));
+                        (op, shadow)
                     };
-                    let op = best_move(info, ss.research_clues.clone(), bot_state, tokens, choices);
-                    bot_ops.push((
-                        User {
-                            id: bot_id.clone(),
-                            name: "protocol".to_string(),
-                        },
-                        op,
-                    ));
+                    bot_ops.push((room_id.clone(), bot, op));
                 }
             }
-            for (bot, op) in bot_ops {
+            for (room_id, bot, op) in bot_ops {
+                #[cfg(feature = "metrics")]
+                let result = state.play_server_move(&room_id, bot, &op.0, op.1);
+                #[cfg(not(feature = "metrics"))]
                 let result = state.handle_action_op(bot, &op);
-                info!("bot result: {:?}", result);
+                info!("bot result at room {}: {:?}", room_id, Secret(&result));
                 if let Err(e) = result {
                     tracing::error!("bot error: {:?}", e);
                     continue;
                 }
             }
-
-            // 1. clean empty game rooms
-            let mut clean_room_ids = Vec::new();
-            for (room_id, gs) in state.iter_game_state() {
-                // todo add clean logic for bots and long time rooms maybe
-                if gs.users.is_empty() {
-                    clean_room_ids.push(room_id.clone());
+            let penalties = std::mem::take(&mut state.penalties);
+            send_penalties(&state.sink(&io), penalties).await;
+
+            // 0.8 drop the sockets whose heartbeat stopped, as if they disconnected
+            for socket_id in state.stale_sockets() {
+                info!(ns = "socket.io", ?socket_id, "heartbeat lost");
+                let socket = state
+                    .users
+                    .get(&socket_id)
+                    .map(|(socket, _)| socket.clone());
+                for gs in state.drop_socket(&socket_id) {
+                    outbox.game_state(&gs);
+                }
+                if let Some(socket) = socket {
+                    socket.disconnect().ok();
                 }
             }
-            for room_id in clean_room_ids {
-                state.state_data.remove(&room_id);
+            for id in std::mem::take(&mut state.closed_theaters) {
+                let sink = state.sink(&io);
+                sink.to_room(&Theater::channel(&id), "theater_closed", &id)
+                    .await;
             }
 
-            // 2 check if all users in a room are ready, and start the game
-            let mut updated_tokens = Vec::new();
-            for (room_id, (gs, ss)) in state.iter_mut_all() {
-                if gs.status == GameState::NotStarted && gs.users.iter().all(|u| u.ready) {
-                    gs.status = GameState::Starting;
-                    // gs.hint = Some("Game is starting".to_string());
-                    // broadcast_room_game_state(&io, gs).await;
-                    gs.start_index = 1;
-                    gs.round = 1;
-                    gs.end_index = gs.map_type.sector_count() / 2;
-                    gs.users.shuffle(&mut SmallRng::seed_from_u64(gs.map_seed));
-                    let mut user_tokens = HashMap::new();
-                    let mut choices = HashMap::new();
-                    for (index, user) in gs.users.iter_mut().enumerate() {
-                        user.location = UserLocationSequence::new(
-                            gs.start_index,
-                            index + 1,
-                            gs.map_type.sector_count(),
-                        );
-                        let tokens = gs.map_type.generate_tokens(user.id.clone(), index + 1);
-                        user_tokens.insert(user.id.clone(), tokens);
-                        choices.insert(
-                            user.id.clone(),
-                            ChoiceFilter::new(gs.map_type.clone(), user.id.clone()),
-                        );
-                    }
-
-                    gs.hint = Some("Game is starting".to_string());
-                    broadcast_room_game_state(&io, gs).await;
+            // 1. clean empty game rooms, put aside the games nobody is connected to
+            state.archive_rooms();
 
-                    let rng = SmallRng::seed_from_u64(gs.map_seed);
-                    let Ok(map) = crate::map::Map::new(rng, gs.map_type.clone()) else {
-                        gs.status = GameState::End;
-                        gs.hint = Some("Map generation failed".to_string());
-                        broadcast_room_game_state(&io, gs).await;
-                        continue;
-                    };
-                    info!(?map, "map generated");
-                    let Ok((research_clues, x_clues)) = crate::map::ClueGenerator::new(
-                        gs.map_seed,
-                        map.sectors.clone(),
-                        map.r#type.clone(),
-                    )
-                    .generate_clues() else {
-                        gs.status = GameState::End;
-                        gs.hint = Some("Clue generation failed".to_string());
-                        broadcast_room_game_state(&io, gs).await;
-                        continue;
-                    };
-                    let server_game_state = ServerGameState {
-                        map,
-                        research_clues,
-                        x_clues,
-                        user_tokens,
-                        terminator_location: None,
-                        revealed_sector_indexs: vec![],
-                        choices,
-                    };
-                    io.of("/xplanet")
-                        .unwrap()
-                        .to(room_id.clone())
-                        .emit("game_start", &server_game_state.clue_secret())
-                        .await
-                        .ok();
-                    // distrubute tokens emiting to users
-                    updated_tokens.push(server_game_state.user_tokens.clone());
+            // 1.2 remove users who left a not started room for too long
+            for (room_id, kick) in state.remove_idle_users() {
+                info!("idle kick at room: {} {:?}", room_id, kick);
+                state.sink(&io).to_room(&room_id, "idle_kick", &kick).await;
+                if let Some(gs) = state.get_game_state(&room_id) {
+                    outbox.game_state(gs);
+                }
+            }
 
-                    *ss = server_game_state;
+            // 1.3 the announcement due now, one per interval
+            if let Some(announcement) = state.due_announcement(now_secs()) {
+                info!(?announcement, "announcement");
+                state.sink(&io).to_all("announcement", &announcement).await;
+            }
 
-                    gs.status = GameState::AutoMove;
-                    gs.hint = Some("Game started".to_string());
-                    broadcast_room_game_state(&io, gs).await;
-                }
+            // 1.5 expire ready flags held for too long, and the abort votes
+            for gs in state.expire_ready().iter_mut() {
+                outbox.game_state(gs);
             }
-            // send each token to user
-            for tokens in &updated_tokens {
-                send_each_token(&state, tokens);
+            for gs in state.expire_abort_votes().iter() {
+                outbox.game_state(gs);
             }
 
+            // 2 check if all users in a room are ready, count down and start the game
             // 3. autoMove as server
-            updated_tokens.clear();
+            let countdown = tokio::time::Duration::from_secs(state.config.start_countdown_secs);
+            let clue_weights = state.config.clue_weights.clone();
+            let mut room_events = vec![];
+            // the phases the bot moves ran first
+            let queued = std::mem::take(&mut state.engine_events);
+            queue_engine_events(&state, queued, &mut outbox, &mut room_events);
             for (room_id, (gs, ss)) in state.iter_mut_all() {
-                if gs.status == GameState::AutoMove && gs.game_stage == GameStage::UserMove {
-                    // find the first point from gs.start_index, move to it.
-
-                    let Some(next_point) = find_next_point(gs, false) else {
-                        gs.status = GameState::End;
-                        gs.hint = Some("No more points".to_string());
-                        io.of("/xplanet")
-                            .unwrap()
-                            .to(room_id.clone())
-                            .emit("game_state", &gs)
-                            .await
-                            .ok();
-                        continue;
-                    };
-                    gs.round += if next_point.index < gs.start_index {
-                        1
-                    } else {
-                        0
-                    };
-                    gs.start_index = next_point.index;
-                    gs.end_index = next_point.index + gs.map_type.sector_count() / 2 - 1;
-                    if gs.end_index > gs.map_type.sector_count() {
-                        gs.end_index -= gs.map_type.sector_count();
-                    }
-                    match next_point.r#type {
-                        PointType::User(id) => {
-                            let name = gs
-                                .users
-                                .iter()
-                                .find(|u| u.id == id)
-                                .map(|u| u.name.clone())
-                                .unwrap_or_else(|| "Unknown".to_string());
-                            gs.status = GameState::Wait(vec![id]);
-                            gs.game_stage = GameStage::UserMove;
-                            gs.hint = Some(format!("{} should move", name));
-                        }
-                        PointType::Meeting => {
-                            info!("should start a meeting");
-                            gs.status =
-                                GameState::Wait(gs.users.iter().map(|u| u.id.clone()).collect());
-                            gs.game_stage = GameStage::MeetingProposal;
-                            gs.hint = Some("Meeting proposal, Everyone should move".to_string());
-                        }
-                        PointType::XClue => {
-                            info!("should broadcast xclue");
-                            let index = gs
-                                .map_type
-                                .xclue_points()
-                                .iter()
-                                .position(|(i, c)| {
-                                    *i == next_point.index && *c == next_point.child_index
-                                })
-                                .unwrap_or(0);
-                            let xclue = ss.x_clues.get(index).map_or(vec![], |x| vec![x.clone()]);
-                            io.of("/xplanet")
-                                .unwrap()
-                                .to(room_id.clone())
-                                .emit("xclue", &xclue)
-                                .await
-                                .ok();
-                            let Some(second_point) = find_next_point(gs, true) else {
-                                gs.status = GameState::End;
-                                gs.hint = Some("No more points".to_string());
-                                io.of("/xplanet")
-                                    .unwrap()
-                                    .to(room_id.clone())
-                                    .emit("game_state", &gs)
-                                    .await
-                                    .ok();
-                                continue;
-                            };
-                            gs.hint = Some("X clue time".to_string());
-                            gs.round += if second_point.index < gs.start_index {
-                                1
-                            } else {
-                                0
-                            };
-                            gs.start_index = second_point.index;
-                            gs.end_index = second_point.index + gs.map_type.sector_count() / 2 - 1;
-                            if gs.end_index > gs.map_type.sector_count() {
-                                gs.end_index -= gs.map_type.sector_count();
-                            }
-                            gs.game_stage = GameStage::UserMove;
-                            gs.status = GameState::AutoMove;
-
-                            for (_user_id, filter) in ss.choices.iter_mut() {
-                                filter.add_operation(
-                                    Operation::Research(ResearchOperation {
-                                        index: xclue[0].index.clone(),
-                                    }),
-                                    OperationResult::Research(xclue[0].clone()),
-                                );
-                            }
-                        }
-                    }
-                    broadcast_room_game_state(&io, gs).await;
-                }
-
-                // meeting check phase
-                if gs.status == GameState::AutoMove && gs.game_stage == GameStage::MeetingCheck {
-                    let mut result = vec![];
-                    let mut checked_tokens = ss
-                        .user_tokens
-                        .iter_mut()
-                        .flat_map(|(user_id, tokens)| {
-                            tokens
-                                .iter_mut()
-                                .filter(|t| t.any_ready_checked())
-                                .map(|t| (user_id.clone(), t))
-                        })
-                        .collect::<Vec<(String, &mut crate::map::Token)>>();
-                    // we need to sort the tokens by sector_index, and then check them one by one
-                    checked_tokens.sort_by(|(_user_id_a, token_a), (_user_id_b, token_b)| {
-                        token_a
-                            .secret
-                            .sector_index
-                            .cmp(&token_b.secret.sector_index)
-                    });
-
-                    for (user_id, token) in checked_tokens {
-                        let all_users_location = gs
-                            .users
-                            .iter()
-                            .map(|u| u.location.clone())
-                            .collect::<Vec<_>>();
-                        let user = gs
-                            .users
-                            .iter_mut()
-                            .find(|u| u.id == user_id)
-                            .unwrap_or_else(|| panic!("user not found: {user_id}"));
-                        if ss
-                            .map
-                            .meeting_check(token.secret.sector_index, &token.r#type)
-                        {
-                            // right, reveal the token
-                            token.secret.r#type = Some(token.r#type.clone());
-                            result.push(format!(
-                                "{}'s token at {}, {} is right",
-                                user.name, token.secret.sector_index, token.r#type
-                            ));
-                            ss.revealed_sector_indexs.push(token.secret.sector_index);
-                        } else {
-                            // punish the user move 1 step, token reveal and move outside the map
-                            token.secret.r#type = Some(token.r#type.clone());
-                            token.secret.meeting_index = 4;
-                            user.location = user.location.next(1, &all_users_location);
-                            result.push(format!(
-                                "{}'s token at {}, {} is wrong, user move 1 step",
-                                user.name, token.secret.sector_index, token.r#type
-                            ));
-                        }
-                    }
-                    // next checked tokens
-                    let mut double_check_tokens = ss
-                        .user_tokens
-                        .iter_mut()
-                        .flat_map(|(user_id, tokens)| {
-                            tokens
-                                .iter_mut()
-                                .filter(|t| {
-                                    t.secret.r#type.is_none()
-                                        && t.placed
-                                        && ss
-                                            .revealed_sector_indexs
-                                            .contains(&t.secret.sector_index)
-                                })
-                                .map(|t| (user_id.clone(), t))
-                        })
-                        .collect::<Vec<(String, &mut crate::map::Token)>>();
-                    double_check_tokens.sort_by(|(_user_id_a, token_a), (_user_id_b, token_b)| {
-                        token_a
-                            .secret
-                            .sector_index
-                            .cmp(&token_b.secret.sector_index)
-                    });
-
-                    for (user_id, token) in double_check_tokens {
-                        let all_users_location = gs
-                            .users
-                            .iter()
-                            .map(|u| u.location.clone())
-                            .collect::<Vec<_>>();
-                        let user = gs
-                            .users
-                            .iter_mut()
-                            .find(|u| u.id == user_id)
-                            .unwrap_or_else(|| panic!("user not found: {user_id}"));
-                        if ss
-                            .map
-                            .meeting_check(token.secret.sector_index, &token.r#type)
-                        {
-                            // right, reveal the token
-                            token.secret.r#type = Some(token.r#type.clone());
-                            result.push(format!(
-                                "{}'s token at {}, {} is right",
-                                user.name, token.secret.sector_index, token.r#type
-                            ));
-                        } else {
-                            // punish the user move 1 step, token reveal and move outside the map
-                            token.secret.r#type = Some(token.r#type.clone());
-                            token.secret.meeting_index = 4;
-                            user.location = user.location.next(1, &all_users_location);
-                            result.push(format!(
-                                "{}'s token at {}, {} is wrong, user move 1 step",
-                                user.name, token.secret.sector_index, token.r#type
-                            ));
-                        }
-                    }
-
-                    info!("meeting check result: {:?}", result);
-                    // no one need to publish, go to next user
-                    // make waiting next user move
-                    gs.status = GameState::AutoMove;
-                    gs.game_stage = GameStage::UserMove;
-                    gs.hint = Some("Push forward".to_string());
-                    // need to find next user to move
-                    let Some(second_point) = find_next_point(gs, true) else {
-                        gs.status = GameState::End;
-                        gs.hint = Some("No more points".to_string());
-                        io.of("/xplanet")
-                            .unwrap()
-                            .to(room_id.clone())
-                            .emit("game_state", &gs)
-                            .await
-                            .ok();
-                        continue;
-                    };
-                    gs.round += if second_point.index < gs.start_index {
-                        1
-                    } else {
-                        0
-                    };
-                    gs.start_index = second_point.index;
-                    gs.end_index = second_point.index + gs.map_type.sector_count() / 2 - 1;
-                    if gs.end_index > gs.map_type.sector_count() {
-                        gs.end_index -= gs.map_type.sector_count();
-                    }
-                    broadcast_room_game_state(&io, gs).await;
-                    broadcast_room_board_token(&io, &gs.id, ss).await;
-
-                    // update tokens to choices
-                    for (user_id, tokens) in ss.user_tokens.iter_mut() {
-                        let Some(choice) = ss.choices.get_mut(user_id) else {
-                            continue;
-                        };
-                        let placed = tokens
-                            .iter()
-                            .filter(|t| t.placed && t.secret.r#type.is_some())
-                            .cloned()
-                            .collect::<Vec<_>>();
-                        choice.update_tokens(&placed);
-                    }
+                let mut events =
+                    engine::start_game(gs, ss, countdown, &clue_weights, std::time::Instant::now());
+                events.extend(engine::advance(gs, ss));
+                if !gs.in_lobby() {
+                    gs.stamp_checksum(ss);
                 }
-
-                // each users should publish tokens
-                // check publish first then proposal, we could update tokens after proposal
-                if gs.status == GameState::AutoMove && gs.game_stage == GameStage::MeetingPublish {
-                    let mut need_publish = false;
-                    for id in sort_users_points(gs).iter().filter_map(|p| {
-                        if let PointType::User(id) = &p.r#type {
-                            Some(id.clone())
-                        } else {
-                            None
-                        }
-                    }) {
-                        if ss
-                            .user_tokens
-                            .get(&id)
-                            .is_some_and(|tokens| tokens.iter().any(|t| t.any_ready_published()))
-                        {
-                            gs.status = GameState::Wait(vec![id.clone()]);
-                            let name = gs
-                                .users
-                                .iter()
-                                .find(|u| u.id == id)
-                                .map(|u| u.name.clone())
-                                .unwrap_or_else(|| "Unknown".to_string());
-                            gs.hint = Some(format!("{} should publish", name));
-                            need_publish = true;
-                            break;
-                        }
+                for event in events {
+                    match event {
+                        EngineEvent::GameState => outbox.game_state(gs),
+                        EngineEvent::BoardTokens => outbox.board_tokens(room_id, ss),
+                        event => room_events.push((room_id.clone(), event)),
                     }
-
-                    if !need_publish {
-                        // push tokens forword at any none revealed sector
-                        // first we need to get revealed sector index
-                        let revealed_sectors = ss
-                            .user_tokens
-                            .iter()
-                            .flat_map(|(_user_id, tokens)| {
-                                tokens.iter().filter_map(|t| {
-                                    t.is_revealed_checked().then_some(t.secret.sector_index)
-                                })
-                            })
-                            .collect::<Vec<_>>();
-
-                        ss.user_tokens.iter_mut().for_each(|(_user_id, tokens)| {
-                            tokens
-                                .iter_mut()
-                                .for_each(|t| t.push_at_meeting(&revealed_sectors));
-                        });
-
-                        // check if need to go to meeting check phase
-                        if ss
-                            .user_tokens
-                            .iter()
-                            .any(|(_user_id, tokens)| tokens.iter().any(|t| t.any_ready_checked()))
-                        {
-                            gs.status = GameState::AutoMove;
-                            gs.game_stage = GameStage::MeetingCheck;
-                            gs.hint = Some(
-                                "Push forward triggle Meeting check, Wait Checking...".to_string(),
-                            );
-                        } else {
-                            // no one need to publish, go to next user
-                            gs.status = GameState::AutoMove;
-                            gs.game_stage = GameStage::UserMove;
-                            gs.hint = Some("Push forward".to_string());
-                            // need to find next user to move
-                            let Some(second_point) = find_next_point(gs, true) else {
-                                gs.status = GameState::End;
-                                gs.hint = Some("No more points".to_string());
-                                io.of("/xplanet")
-                                    .unwrap()
-                                    .to(room_id.clone())
-                                    .emit("game_state", &gs)
-                                    .await
-                                    .ok();
-                                continue;
-                            };
-                            gs.round += if second_point.index < gs.start_index {
-                                1
-                            } else {
-                                0
-                            };
-                            gs.start_index = second_point.index;
-                            gs.end_index = second_point.index + gs.map_type.sector_count() / 2 - 1;
-                            if gs.end_index > gs.map_type.sector_count() {
-                                gs.end_index -= gs.map_type.sector_count();
-                            }
-                        }
-                    }
-
-                    // make waiting next user move
-                    broadcast_room_game_state(&io, gs).await;
-                    broadcast_room_board_token(&io, &gs.id, ss).await;
-                }
-
-                // proposal finished, and waiting for each user publish
-                if gs.status == GameState::AutoMove && gs.game_stage == GameStage::MeetingProposal {
-                    info!("server MeetingPublish");
-                    gs.game_stage = GameStage::MeetingPublish;
-                    gs.hint = Some("Gathering all tokens, ready for Meeting publish".to_string());
-                    broadcast_room_game_state(&io, gs).await;
-                    broadcast_room_board_token(&io, &gs.id, ss).await;
-                    updated_tokens.push(ss.user_tokens.clone());
-                }
-
-                if gs.status == GameState::AutoMove && gs.game_stage == GameStage::LastMove {
-                    // in the last move, everyone before the winner will have one chance to move
-                    // and then the game will end
-
-                    let mut need_wait_last_move = false;
-                    for id in sort_users_points(gs).iter().filter_map(|p| {
-                        if let PointType::User(id) = &p.r#type {
-                            Some(id.clone())
-                        } else {
-                            None
-                        }
-                    }) {
-                        let Some(user) = gs.users.iter_mut().find(|u| u.id == id) else {
-                            continue;
-                        };
-                        if !user.last_move {
-                            continue;
-                        }
-                        gs.status = GameState::Wait(vec![id.clone()]);
-                        let name = gs
-                            .users
-                            .iter()
-                            .find(|u| u.id == id)
-                            .map(|u| u.name.clone())
-                            .unwrap_or_else(|| "Unknown".to_string());
-                        gs.hint = Some(format!("{} should make last move", name));
-                        need_wait_last_move = true;
-                        break;
-                    }
-                    if !need_wait_last_move {
-                        // no one need to move, end the game
-                        gs.status = GameState::End;
-                        gs.game_stage = GameStage::GameEnd;
-                        gs.hint = Some("Game Over!".to_string());
-
-                        // reveal all tokens
-                        ss.user_tokens.iter_mut().for_each(|(_user_id, tokens)| {
-                            tokens.iter_mut().for_each(|t| {
-                                if t.reveal_in_the_end()
-                                    && !ss.map.meeting_check(t.secret.sector_index, &t.r#type)
-                                {
-                                    // wrong, move to 4
-                                    t.secret.meeting_index = 4;
-                                }
-                            });
-                        });
-
-                        let mut results = vec![];
-                        let terminator_step =
-                            ss.terminator_location.as_ref().map_or(0, |t| t.step());
-                        let map_type = ss.map.r#type.clone();
-                        for user_state in gs.users.iter() {
-                            let id = user_state.id.clone();
-                            let comet = ss.user_tokens.get(&id).map_or(0, |tokens| {
-                                tokens
-                                    .iter()
-                                    .filter(|t| t.is_success_located(SectorType::Comet))
-                                    .count()
-                            });
-                            let asteroid = ss.user_tokens.get(&id).map_or(0, |tokens| {
-                                tokens
-                                    .iter()
-                                    .filter(|t| t.is_success_located(SectorType::Asteroid))
-                                    .count()
-                            });
-                            let dwarf_planet = ss.user_tokens.get(&id).map_or(0, |tokens| {
-                                tokens
-                                    .iter()
-                                    .filter(|t| t.is_success_located(SectorType::DwarfPlanet))
-                                    .count()
-                            });
-                            let nebula = ss.user_tokens.get(&id).map_or(0, |tokens| {
-                                tokens
-                                    .iter()
-                                    .filter(|t| t.is_success_located(SectorType::Nebula))
-                                    .count()
-                            });
-                            let mut first = 0;
-                            for s_index in 1..=gs.map_type.sector_count() {
-                                let mut sector_tokens = ss
-                                    .user_tokens
-                                    .iter()
-                                    .filter_map(|(_user_id, tokens)| {
-                                        tokens.iter().find(|t| {
-                                            t.secret.sector_index == s_index
-                                                && t.is_success_located_any()
-                                        })
-                                    })
-                                    .collect::<Vec<_>>();
-                                sector_tokens.sort_by(|a, b| {
-                                    a.secret.meeting_index.cmp(&b.secret.meeting_index)
-                                });
-                                let first_meeting_index = sector_tokens
-                                    .first()
-                                    .map(|t| t.secret.meeting_index)
-                                    .unwrap_or(0);
-                                if sector_tokens
-                                    .iter()
-                                    .find(|t| {
-                                        t.secret.meeting_index == first_meeting_index
-                                            && t.secret.user_id == id
-                                    })
-                                    .is_some()
-                                {
-                                    first += 1;
-                                }
-                            }
-                            let step = user_state.location.step();
-                            let x = user_state.moves_result.last().map_or(0, |r| match r {
-                                &OperationResult::Locate(true) => {
-                                    if terminator_step == step {
-                                        10
-                                    } else {
-                                        2 * (terminator_step.saturating_sub(step))
-                                    }
-                                }
-                                _rest => 0,
-                            });
-
-                            let sum = match map_type {
-                                MapType::Standard => dwarf_planet * 4,
-                                MapType::Expert => dwarf_planet * 2,
-                            } + asteroid * 2
-                                + comet * 3
-                                + nebula * 4
-                                + first
-                                + x;
-
-                            results.push(UserResultSummary {
-                                id: id.clone(),
-                                name: user_state.name.clone(),
-                                sum,
-                                first,
-                                comet,
-                                asteroid,
-                                dwarf_planet,
-                                nebula,
-                                x,
-                                step,
-                            });
-                        }
-
-                        results
-                            .sort_by(|a, b| a.sum.cmp(&b.sum).then_with(|| a.first.cmp(&b.first)));
-                        results.reverse();
-                        info!("game result: {:?}", results);
-                        gs.game_result = Some(results);
-                    }
-
-                    broadcast_room_game_state(&io, gs).await;
-                    broadcast_room_board_token(&io, &gs.id, ss).await;
                 }
             }
-            for tokens in &updated_tokens {
-                send_each_token(&state, tokens);
+            #[cfg(feature = "metrics")]
+            {
+                state.observe_pacing();
+                state.observe_shadow();
+            }
+            state.observe_stages();
+            state.observe_analytics();
+            // only the last state of each room goes out, then the private data that follows it
+            #[cfg(feature = "metrics")]
+            outbox.record(&mut state.broadcast_metrics);
+            engine::send(&state.sink(&io), outbox, room_events).await;
+
+            // 3.5 autosave the rooms changed in this tick, the storage is written in the background
+            state.autosave();
+            let batch = std::mem::take(&mut state.pending_writes);
+            if !batch.is_empty() {
+                writes.send(batch).ok();
+            }
+            let batch = state.analytics.take();
+            if !batch.is_empty() {
+                events.send(batch).ok();
             }
-        }
-    });
-}
-
-fn find_next_point(gs: &mut GameStateResp, next_next: bool) -> Option<PointInfo> {
-    let index = if next_next { 1 } else { 0 };
-    let mut all_points: Vec<PointInfo> = gs
-        .users
-        .iter()
-        .map(Into::into)
-        .chain(
-            gs.map_type
-                .meeting_points()
-                .into_iter()
-                .map(|(index, child_index)| PointInfo {
-                    r#type: PointType::Meeting,
-                    index,
-                    child_index,
-                    round: 0, // not used
-                }),
-        )
-        .chain(
-            gs.map_type
-                .xclue_points()
-                .into_iter()
-                .map(|(index, child_index)| PointInfo {
-                    r#type: PointType::XClue,
-                    index,
-                    child_index,
-                    round: 1, // not used
-                }),
-        )
-        .collect::<Vec<_>>();
-    // sort by start_index, index, child_index
-    all_points.sort_by(|a, b| {
-        a.index
-            .cmp(&b.index)
-            .then_with(|| a.child_index.cmp(&b.child_index))
-    });
-    info!(?all_points, "all points");
-
-    all_points
-        .iter()
-        .cycle()
-        .skip_while(|p| p.index < gs.start_index || (gs.round > 1 && p.round == 1))
-        .nth(index)
-        .cloned()
-}
 
-fn sort_users_points(gs: &mut GameStateResp) -> Vec<PointInfo> {
-    let mut all_user_points: Vec<PointInfo> = gs.users.iter().map(Into::into).collect::<Vec<_>>();
-    all_user_points.sort_by(|a, b| {
-        // round , then index, then child_index
-        a.round.cmp(&b.round).then_with(|| {
-            if a.index == b.index {
-                a.child_index.cmp(&b.child_index)
-            } else {
-                a.index.cmp(&b.index)
+            // 4. full picture for the casters of consenting rooms
+            for (room_id, view) in state.caster_views() {
+                let channel = CasterView::channel(&room_id);
+                state
+                    .sink(&io)
+                    .to_room(&channel, "caster_view", &view)
+                    .await;
             }
-        })
+        }
     });
-    info!(?all_user_points, "all user points");
-    all_user_points
-}
-
-async fn broadcast_room_game_state(io: &SocketIo, gs: &mut GameStateResp) {
-    // let mut gs = gs.clone();
-    // gs.users.iter_mut().for_each(|u| {
-    //     u.moves_result.clear();
-    // });
-
-    io.of("/xplanet")
-        .unwrap()
-        .to(gs.id.clone())
-        .emit("game_state", &gs)
-        .await
-        .ok();
 }
 
-async fn broadcast_room_board_token(io: &SocketIo, room_id: &str, ss: &ServerGameState) {
-    let tokens = ss
-        .user_tokens
-        .iter()
-        .flat_map(|(_user_id, tokens)| tokens.iter())
-        .filter(|t| t.placed)
-        .map(|t| &t.secret)
-        .cloned()
-        .collect::<Vec<_>>();
-    io.of("/xplanet")
-        .unwrap()
-        .to(room_id.to_owned())
-        .emit("board_tokens", &tokens)
-        .await
-        .ok();
-}
-
-fn send_each_token(
-    state: &tokio::sync::MutexGuard<'_, crate::server_state::State>,
-    tokens: &HashMap<String, Vec<crate::map::Token>>,
+/// resend everything a room shows after it changed out of the game flow, e.g. an admin restore.
+/// clients are asked to rejoin, which syncs their private results again.
+pub async fn broadcast_full_state(
+    sink: &impl EventSink,
+    state: &crate::server_state::State,
+    room_id: &str,
 ) {
-    for (user_id, token) in tokens {
-        if user_id.starts_with("bot-") {
-            continue;
-        }
-        let s = state
-            .users
-            .iter()
-            .find_map(|(_sid, (s, u))| (u.id == *user_id).then_some(s.clone()));
-        let Some(user_socket) = s else {
-            tracing::error!("user not found, user_id: {}", user_id);
-            continue;
-        };
-        user_socket.emit("token", token).ok();
-    }
-}
-
-#[derive(Debug, Clone)]
-struct PointInfo {
-    r#type: PointType,
-    index: usize,
-    child_index: usize,
-    round: usize,
-}
-
-#[derive(Debug, Clone)]
-enum PointType {
-    User(String),
-    Meeting,
-    XClue,
-}
-
-impl From<&UserState> for PointInfo {
-    fn from(user: &UserState) -> Self {
-        PointInfo {
-            r#type: PointType::User(user.id.clone()),
-            index: user.location.index,
-            child_index: user.location.child_index,
-            round: user.location.round,
-        }
+    let Some((gs, ss)) = state.state_data.get(room_id) else {
+        return;
+    };
+    let state_event = gs.state_event();
+    sink.to_room(room_id, state_event.event(), &state_event)
+        .await;
+    sink.to_room(room_id, "board_tokens", &board_tokens(ss))
+        .await;
+    for (user_id, tokens) in ss.user_tokens.iter() {
+        sink.to_user(user_id, "token", tokens);
     }
+    let resp = ServerResp::rejoin_room(room_id.to_owned());
+    sink.to_room(room_id, "server_resp", &resp).await;
 }
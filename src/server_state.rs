@@ -1,46 +1,407 @@
-use std::collections::HashMap;
+#[cfg(feature = "admin")]
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
-use socketioxide::extract::SocketRef;
+use socketioxide::{SocketIo, extract::SocketRef};
 use tokio::sync::Mutex;
 use tracing::{info, warn};
 
+#[cfg(feature = "integrations")]
+use crate::room::{SAVE_CODE_TTL_SECS, SaveGame};
 use crate::{
-    map::{SectorType, validate_index_in_range},
-    operation::{Operation, OperationResult},
-    recommendation::{RecommendOperation, RecommendOperationResult},
+    analytics::Analytics,
+    broadcast::board_tokens,
+    config::{DEFAULT_NAMESPACE, ServerConfig},
+    engine::{self, EngineEvent},
+    map::{SecretToken, SectorIndex, SectorRange, SectorType, SkyWindow, check_index},
+    operation::{
+        LocateMiss, MAX_BATCH_STEPS, Operation, OperationResult, PASS_STEPS, PassResult,
+        SurveyOperatoin,
+    },
+    ratelimit::RateBucket,
+    recommendation::{
+        BestMoveInfo, PlayQuality, RecommendOperation, RecommendOperationResult, rank_move,
+    },
+    redact,
+    room::{
+        AbortBallot, AbortVote, Announcement, Appearance, BotPersona, BugReport, BugReports,
+        CastJoin, CastOperation, CasterView, CatchUp, ChatError, ChatFilter, ChatMessage,
+        ChatOperation, FailedLocatePolicy, FilterVerdict, GameExport, GameStage, GameState,
+        GameStateResp, IdleKick, IdleKickAction, JournalEntry, LobbyPage, LobbyQuery, MAX_CHAT_LEN,
+        MAX_THEATERS, MAX_THEATERS_PER_USER, Maintenance, ModerationAction, ModerationEntry,
+        OpError, Penalty, PenaltyReason, Preferences, PreferencesOperation, Reaction, ReactionSend,
+        RecommendError, RoomChat, RoomError, RoomUserOperation, ServerGameState, ServerResp,
+        SnapshotRing, StageLog, StoredRoom, Theater, TheaterError, TheaterFrame, TheaterOperation,
+        TurnDeadline, TurnTimeout, UserState, WordFilter, build_puzzle, now_secs,
+    },
+    sink::{Capabilities, Membership, Relay, SocketSink},
+    storage::{MemoryStorage, Storage, StorageWrite},
+};
+#[cfg(feature = "metrics")]
+use crate::{
+    broadcast::BroadcastMetrics,
+    map::Clue,
+    recommendation::{Divergence, ShadowLog},
+    telemetry::{BadRequestMetrics, ClueMetrics, PacingMetrics, RejectionMetrics},
+};
+#[cfg(feature = "admin")]
+use crate::{
+    recommendation::QualityReport,
     room::{
-        GameStage, GameState, GameStateResp, OpError, RecommendError, RoomError, RoomUserOperation,
-        ServerGameState, ServerResp, UserState,
+        AnnouncementKind, CasterToken, Dashboard, RoomStats, SnapshotInfo, UserSeat,
+        UserWhereabouts,
     },
 };
 
 type RoomId = String;
 
+/// moderation entries kept for the admin api.
+const MODERATION_LOG_LEN: usize = 500;
+
+/// seconds left when a waiting player is warned of the turn timeout, the ones not shorter
+/// than the timeout are skipped.
+const DEADLINE_WARNINGS: [u64; 3] = [60, 30, 10];
+
+/// what the turn timers did this tick.
+#[derive(Debug, Default)]
+pub struct TurnTimers {
+    pub warnings: Vec<(String, TurnDeadline)>, // user_id, private
+    pub timeouts: Vec<(RoomId, TurnTimeout)>,
+    pub kicks: Vec<(RoomId, IdleKick)>,
+}
+
 pub struct State {
+    pub config: ServerConfig,
+    pub namespace: String, // the socket.io namespace the state serves, isolated from the others
     pub users: HashMap<String, (SocketRef, User)>, // socket_id -> User
+    pub last_seen: HashMap<String, std::time::Instant>, // socket_id -> last ping, once it pinged
+    pub user_sockets: HashMap<String, Vec<SocketRef>>, // user_id -> sockets
     pub state_data: HashMap<RoomId, (GameStateResp, ServerGameState)>, // room_id -> game_data
+    pub play_quality: HashMap<String, PlayQuality>, // user_id -> decision quality in competitive rooms
+    pub preferences: HashMap<String, Preferences>,  // user_id -> settings kept across devices
+    pub theaters: HashMap<String, Theater>,         // theater_id -> replay of an exported game
+    pub closed_theaters: Vec<String>,               // closed by their presenter's disconnect
+    pub casters: HashMap<RoomId, HashSet<String>>,  // room_id -> caster socket ids
+    pub caster_tokens: HashMap<RoomId, String>,     // room_id -> token issued to its casters
+    pub snapshots: HashMap<RoomId, SnapshotRing>,   // room_id -> autosaved snapshots
+    #[cfg(feature = "metrics")]
+    pub broadcast_metrics: BroadcastMetrics,
+    pub rate_limits: HashMap<String, RateBucket>, // user_id -> request budget of a service account
+    pub relay: Relay,                             // events for the websocket and sse connections
+    pub storage: Arc<dyn Storage>,
+    pub pending_writes: Vec<StorageWrite>, // written by the storage writer after the tick
+    pub chats: HashMap<RoomId, RoomChat>,
+    pub blocks: HashMap<String, HashSet<String>>, // user_id -> blocked user ids, in every room
+    pub chat_filters: Vec<Box<dyn ChatFilter>>,   // run on every message, in order
+    pub moderation: VecDeque<ModerationEntry>,    // latest last
+    pub reaction_limits: HashMap<String, RateBucket>, // user_id -> reaction budget
+    #[cfg(feature = "metrics")]
+    pub rejections: RejectionMetrics, // player operations refused
+    #[cfg(feature = "metrics")]
+    pub bad_requests: BadRequestMetrics, // inbound payloads that didn't deserialize
+    #[cfg(feature = "metrics")]
+    pub pacing: PacingMetrics, // turn, stage and game durations
+    #[cfg(feature = "metrics")]
+    pub clue_usage: ClueMetrics, // what the researched clues narrowed
+    #[cfg(feature = "metrics")]
+    pub shadow: ShadowLog, // the shadow bot's picks against the live bot
+    pub stage_log: StageLog,                      // the stages each room went through
+    pub bug_reports: BugReports,
+    pub maintenance: Option<Maintenance>,
+    pub announcements: Vec<Announcement>, // scheduled, the earliest first
+    pub last_announcement: Option<Announcement>, // the last one sent, for late spectators
+    pub announced_at: Option<u64>,        // unix secs of the last one sent
+    #[cfg(feature = "admin")]
+    pub next_announcement_id: u64,
+    pub dormant: HashMap<RoomId, DormantRoom>, // running games nobody is connected to
+    pub restored: HashMap<RoomId, std::time::Instant>, // rooms back after a restart -> grace end
+    pub last_actions: HashMap<String, u64>, // user_id -> unix secs of the last accepted operation
+    pub penalties: Vec<Penalty>,            // queued by the operations, sent after them
+    pub engine_events: Vec<(RoomId, EngineEvent)>, // phases run by an operation, sent after it
+    pub analytics: Analytics,               // anonymized gameplay events, shipped after the tick
+    pub user_rooms: HashMap<String, RoomId>, // user_id -> the room they sit in, checked on read
+    #[cfg(feature = "integrations")]
+    pub resumed_codes: HashMap<String, u64>, // save code nonce -> issued at, until it expires
+}
+
+/// draws of a free theater id before the load is refused.
+const THEATER_ID_ATTEMPTS: usize = 16;
+
+/// users the admin user search answers at most.
+#[cfg(feature = "admin")]
+const MAX_USER_SEARCH_RESULTS: usize = 50;
+
+/// announcements waiting to go out at most, the admin api refuses more.
+#[cfg(feature = "admin")]
+const MAX_SCHEDULED_ANNOUNCEMENTS: usize = 100;
+
+/// a room out of the tick until one of its players comes back or `dormant_ttl_secs` pass.
+pub struct DormantRoom {
+    pub gs: GameStateResp,
+    pub ss: ServerGameState,
+    pub since: std::time::Instant,
+}
+
+/// an operation played in a room, its effects on the server kept until the turn is handed on.
+struct PlayedStep {
+    result: OperationResult,
+    penalty: Option<Penalty>,
+    #[cfg(feature = "metrics")]
+    turn: Option<std::time::Duration>, // the think time, on the first operation of a turn
+    #[cfg(feature = "metrics")]
+    clue_use: Option<(Clue, Option<usize>, Option<usize>)>, // the clue, the maps before and after
+}
+
+/// the range of a survey, refused outside the sky window, for the x, or for comets off the
+/// prime sectors.
+fn check_survey(
+    gs: &GameStateResp,
+    ss: &ServerGameState,
+    s: &SurveyOperatoin,
+) -> Result<SectorRange, OpError> {
+    let range = SectorRange::new(s.start, s.end, ss.map.size())?;
+    if !gs.window().contains_range(s.start, s.end) {
+        return Err(OpError::InvalidIndex);
+    }
+    if s.sector_type == SectorType::X {
+        return Err(OpError::InvalidSectorType);
+    }
+    if s.sector_type == SectorType::Comet
+        && (!matches!(s.start, 2 | 3 | 5 | 7 | 11 | 13 | 17)
+            || !matches!(s.end, 2 | 3 | 5 | 7 | 11 | 13 | 17))
+    {
+        return Err(OpError::InvalidIndexOfPrime);
+    }
+    Ok(range)
 }
 
 enum InnerRoomOp<'a> {
     Enter(&'a String),
     Leave(&'a String),
     LeaveAll,
-    EnableBot(&'a String),
+    EnableBot(&'a String, BotPersona),
     DisableBot(&'a String),
 }
 impl State {
-    fn new() -> Self {
+    pub fn new(config: ServerConfig) -> Self {
         State {
             users: HashMap::new(),
+            last_seen: HashMap::new(),
+            user_sockets: HashMap::new(),
             state_data: HashMap::new(),
+            play_quality: HashMap::new(),
+            preferences: HashMap::new(),
+            theaters: HashMap::new(),
+            closed_theaters: vec![],
+            casters: HashMap::new(),
+            caster_tokens: HashMap::new(),
+            snapshots: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            broadcast_metrics: BroadcastMetrics::default(),
+            rate_limits: HashMap::new(),
+            relay: Relay::default(),
+            storage: Arc::new(MemoryStorage::default()),
+            pending_writes: vec![],
+            chats: HashMap::new(),
+            blocks: HashMap::new(),
+            chat_filters: vec![Box::new(WordFilter::new(&config.chat_banned_words))],
+            moderation: VecDeque::new(),
+            reaction_limits: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            rejections: RejectionMetrics::default(),
+            #[cfg(feature = "metrics")]
+            bad_requests: BadRequestMetrics::default(),
+            #[cfg(feature = "metrics")]
+            pacing: PacingMetrics::default(),
+            #[cfg(feature = "metrics")]
+            clue_usage: ClueMetrics::default(),
+            #[cfg(feature = "metrics")]
+            shadow: ShadowLog::default(),
+            stage_log: StageLog::default(),
+            bug_reports: BugReports::default(),
+            maintenance: None,
+            announcements: vec![],
+            last_announcement: None,
+            announced_at: None,
+            #[cfg(feature = "admin")]
+            next_announcement_id: 1,
+            dormant: HashMap::new(),
+            restored: HashMap::new(),
+            last_actions: HashMap::new(),
+            penalties: vec![],
+            engine_events: vec![],
+            user_rooms: HashMap::new(),
+            #[cfg(feature = "integrations")]
+            resumed_codes: HashMap::new(),
+            analytics: Analytics::new(config.analytics.is_some(), config.analytics_burst),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            config,
+        }
+    }
+
+    /// time the stages and games of every room after the tick moved them.
+    #[cfg(feature = "metrics")]
+    pub fn observe_pacing(&mut self) {
+        let now = std::time::Instant::now();
+        for (gs, _) in self.state_data.values() {
+            self.pacing.observe(gs, now);
+        }
+        let (rooms, dormant) = (&self.state_data, &self.dormant);
+        self.pacing
+            .retain_rooms(|id| rooms.contains_key(id) || dormant.contains_key(id));
+    }
+
+    /// log the stage transitions of the rooms for the bug reports.
+    pub fn observe_stages(&mut self) {
+        let now = now_secs();
+        for (gs, ss) in self.state_data.values() {
+            self.stage_log.observe(gs, ss, now);
+        }
+        let (rooms, dormant) = (&self.state_data, &self.dormant);
+        self.stage_log
+            .retain_rooms(|id| rooms.contains_key(id) || dormant.contains_key(id));
+    }
+
+    /// record the games that ended in the tick for the analytics.
+    pub fn observe_analytics(&mut self) {
+        let limits = self.analytics_limits();
+        for (gs, ss) in self.state_data.values() {
+            self.analytics.observe_end(gs, ss, limits);
+        }
+        let (rooms, dormant) = (&self.state_data, &self.dormant);
+        self.analytics
+            .retain_rooms(|id| rooms.contains_key(id) || dormant.contains_key(id));
+    }
+
+    fn analytics_limits(&self) -> (f64, usize) {
+        (
+            self.config.analytics_events_per_sec,
+            self.config.analytics_burst,
+        )
+    }
+
+    /// settle the shadow bot divergences of the games that ended in the tick.
+    #[cfg(feature = "metrics")]
+    pub fn observe_shadow(&mut self) {
+        for (gs, _) in self.state_data.values() {
+            if gs.status == GameState::End {
+                self.shadow.settle(gs);
+            }
+        }
+        let (rooms, dormant) = (&self.state_data, &self.dormant);
+        self.shadow
+            .retain_rooms(|id| rooms.contains_key(id) || dormant.contains_key(id));
+    }
+
+    /// play a move the server picked for a seat. `shadow` is the pick of the shadow bot for the
+    /// same turn, logged with what the played move narrowed.
+    #[cfg(feature = "metrics")]
+    pub fn play_server_move(
+        &mut self,
+        room_id: &str,
+        bot: User,
+        op: &Operation,
+        shadow: Option<Operation>,
+    ) -> Result<OperationResult, OpError> {
+        let maps = |state: &Self| {
+            let (_, ss) = state.state_data.get(room_id)?;
+            ss.choices.get(&bot.id)?.count()
+        };
+        let maps_before = maps(self);
+        let turn = self
+            .state_data
+            .get(room_id)
+            .map(|(gs, _)| (gs.round, gs.game_stage.clone()));
+        let seat_id = bot.id.clone();
+        let result = self.handle_action_op(bot, op)?;
+        if let (Some(shadow), Some((round, stage))) = (shadow, turn) {
+            let maps_after = self
+                .state_data
+                .get(room_id)
+                .and_then(|(_, ss)| ss.choices.get(&seat_id)?.count());
+            self.shadow.record(Divergence {
+                room_id: room_id.to_string(),
+                seat_id,
+                round,
+                stage,
+                live: op.clone(),
+                shadow,
+                maps_before,
+                maps_after,
+                final_score: None,
+                final_rank: None,
+            });
+        }
+        Ok(result)
+    }
+
+    /// queue an announcement for `at`, now if None. None when too many are scheduled.
+    #[cfg(feature = "admin")]
+    pub fn schedule_announcement(
+        &mut self,
+        message: String,
+        kind: AnnouncementKind,
+        at: Option<u64>,
+    ) -> Option<Announcement> {
+        if self.announcements.len() >= MAX_SCHEDULED_ANNOUNCEMENTS {
+            return None;
+        }
+        let announcement = Announcement {
+            id: self.next_announcement_id,
+            message,
+            kind,
+            at: at.unwrap_or_else(now_secs),
+        };
+        self.next_announcement_id += 1;
+        let index = self
+            .announcements
+            .partition_point(|a| a.at <= announcement.at);
+        self.announcements.insert(index, announcement.clone());
+        Some(announcement)
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn cancel_announcement(&mut self, id: u64) -> bool {
+        let before = self.announcements.len();
+        self.announcements.retain(|a| a.id != id);
+        self.announcements.len() != before
+    }
+
+    /// the earliest due announcement, at most one per `announce_interval_secs`.
+    pub fn due_announcement(&mut self, now: u64) -> Option<Announcement> {
+        let interval = self.config.announce_interval_secs;
+        if self.announced_at.is_some_and(|at| now < at + interval) {
+            return None;
         }
+        if self.announcements.first()?.at > now {
+            return None;
+        }
+        self.announced_at = Some(now);
+        let announcement = self.announcements.remove(0);
+        self.last_announcement = Some(announcement.clone());
+        Some(announcement)
+    }
+
+    /// apply a reloaded config to the running server, return the fields that changed.
+    pub fn reload_config(&mut self, config: ServerConfig) -> Vec<&'static str> {
+        let changed = self.config.apply_live(config);
+        if changed.contains(&"log_secrets") {
+            redact::set_log_secrets(self.config.log_secrets);
+        }
+        if changed.contains(&"chat_banned_words") {
+            // the word filter of the config is the first one
+            self.chat_filters[0] = Box::new(WordFilter::new(&self.config.chat_banned_words));
+        }
+        info!(?changed, "config reloaded");
+        changed
     }
 
     pub fn iter_game_state(&self) -> impl Iterator<Item = (&String, &GameStateResp)> {
         self.state_data.iter().map(|(k, v)| (k, &v.0))
     }
+    #[cfg(feature = "admin")]
     pub fn iter_all(&self) -> impl Iterator<Item = (&String, (&GameStateResp, &ServerGameState))> {
         self.state_data.iter().map(|(k, v)| (k, (&v.0, &v.1)))
     }
@@ -79,6 +440,7 @@ impl State {
     }
 
     pub fn upsert_user(&mut self, socket_id: String, user: User, socket: SocketRef) {
+        self.wake_rooms(&user.id);
         self.iter_game_state().for_each(|(room_id, gs)| {
             if gs.users.iter().any(|u| u.id == user.id) {
                 info!("upsert user: {} in room: {}", user.id, room_id);
@@ -87,78 +449,383 @@ impl State {
                     .emit("server_resp", &ServerResp::rejoin_room(room_id.clone()))
                     .ok();
                 socket.join(room_id.clone());
+                if self.in_restore_grace(room_id) {
+                    self.resend_room(&socket, gs, &user.id);
+                }
+            }
+        });
+        self.reconnect(&user.id);
+        let sockets = self.user_sockets.entry(user.id.clone()).or_default();
+        sockets.retain(|s| s.id != socket.id);
+        sockets.push(socket.clone());
+        let user_id = user.id.clone();
+        if let Some((old_socket, old_user)) = self.users.insert(socket_id, (socket, user)) {
+            // the same socket re-authenticated as another user
+            if old_user.id != user_id {
+                self.unregister_socket(&old_user.id, &old_socket);
             }
+        }
+    }
+
+    /// the user is back, return the rooms they are seated in.
+    pub fn reconnect(&mut self, user_id: &str) -> Vec<RoomId> {
+        self.wake_rooms(user_id);
+        let mut rooms = vec![];
+        for (room_id, gs) in self.iter_mut_game_state() {
+            if let Some(u) = gs.users.iter_mut().find(|u| u.id == user_id) {
+                u.disconnected_at = None;
+                rooms.push(room_id.clone());
+            }
+        }
+        rooms
+    }
+
+    /// the room is back from a restart and its players still have time to reconnect.
+    fn in_restore_grace(&self, room_id: &str) -> bool {
+        self.restored
+            .get(room_id)
+            .is_some_and(|until| *until > std::time::Instant::now())
+    }
+
+    /// the state of the room and the user's turn, for a player back after a restart whose client
+    /// lost track of the room.
+    fn resend_room(&self, socket: &SocketRef, gs: &GameStateResp, user_id: &str) {
+        info!("resend room {} to {} after the restart", gs.id, user_id);
+        let state_event = gs.state_event();
+        socket.emit(state_event.event(), &state_event).ok();
+        let Some(EngineEvent::YourTurn(turns)) = engine::your_turn(gs) else {
+            return;
+        };
+        for (_, turn) in turns.iter().filter(|(id, _)| id == user_id) {
+            socket.emit("your_turn", turn).ok();
+        }
+    }
+
+    /// bring back the running games of the last snapshots stored before a restart. their players
+    /// are all gone, the rooms wait dormant for them to come back.
+    pub fn restore_rooms(&mut self, rooms: Vec<(RoomId, StoredRoom)>) {
+        let now = std::time::Instant::now();
+        let grace = std::time::Duration::from_secs(self.config.restore_grace_secs);
+        for (room_id, room) in rooms {
+            let (mut gs, ss) = room.restore();
+            let running = matches!(gs.status, GameState::Wait(_) | GameState::AutoMove);
+            if !running || self.state_data.contains_key(&room_id) {
+                continue;
+            }
+            for user in gs.users.iter_mut().filter(|u| !u.seat().is_bot()) {
+                user.disconnected_at = Some(now);
+            }
+            info!("room {} restored after the restart", room_id);
+            self.restored.insert(room_id.clone(), now + grace);
+            self.dormant
+                .insert(room_id, DormantRoom { gs, ss, since: now });
+        }
+    }
+
+    /// put the running games with no player connected aside, their last state saved, and
+    /// drop the empty rooms not playing. dormant rooms past their ttl are dropped.
+    pub fn archive_rooms(&mut self) {
+        let ttl = std::time::Duration::from_secs(self.config.dormant_ttl_secs);
+        let mut empty = vec![];
+        let mut idle = vec![];
+        for (room_id, gs) in self.iter_game_state() {
+            let playing = !matches!(gs.status, GameState::NotStarted | GameState::End);
+            // players of the control plane never connect, they are not disconnected either
+            let connected = gs
+                .users
+                .iter()
+                .any(|u| !u.seat().is_bot() && u.disconnected_at.is_none());
+            if gs.users.is_empty() && (!playing || ttl.is_zero()) {
+                empty.push(room_id.clone());
+            } else if playing && !connected && !ttl.is_zero() {
+                idle.push(room_id.clone());
+            }
+        }
+        for room_id in empty {
+            self.state_data.remove(&room_id);
+            self.chats.remove(&room_id);
+            self.casters.remove(&room_id);
+            self.caster_tokens.remove(&room_id);
+        }
+        let rooms = &self.state_data;
+        self.user_rooms
+            .retain(|_, room_id| rooms.contains_key(room_id));
+        let keep = self.config.snapshot_keep;
+        for room_id in idle {
+            let Some((gs, ss)) = self.state_data.remove(&room_id) else {
+                continue;
+            };
+            info!("room {} is dormant", room_id);
+            let ring = self.snapshots.entry(room_id.clone()).or_default();
+            if let Some(room) = ring.autosave(&gs, &ss, keep) {
+                let room_id = room_id.clone();
+                self.pending_writes.push(StorageWrite::Snapshot {
+                    room_id,
+                    room: Box::new(room),
+                    keep,
+                });
+            }
+            let since = std::time::Instant::now();
+            self.dormant.insert(room_id, DormantRoom { gs, ss, since });
+        }
+        let expired = self
+            .dormant
+            .iter()
+            .filter(|(_, room)| room.since.elapsed() > ttl)
+            .map(|(room_id, _)| room_id.clone())
+            .collect::<Vec<_>>();
+        for room_id in expired {
+            info!("dormant room {} dropped", room_id);
+            self.dormant.remove(&room_id);
+            self.chats.remove(&room_id);
+            self.snapshots.remove(&room_id);
+            self.casters.remove(&room_id);
+            self.caster_tokens.remove(&room_id);
+        }
+        let now = std::time::Instant::now();
+        self.restored.retain(|_, until| *until > now);
+    }
+
+    /// bring back the dormant rooms the user plays in, their timers restart from now, or once
+    /// the grace of a room back from a restart ends.
+    fn wake_rooms(&mut self, user_id: &str) {
+        let now = std::time::Instant::now();
+        let woken = self
+            .dormant
+            .iter()
+            .filter(|(_, room)| room.gs.users.iter().any(|u| u.id == user_id))
+            .map(|(room_id, _)| room_id.clone())
+            .collect::<Vec<_>>();
+        for room_id in woken {
+            let Some(DormantRoom { mut gs, ss, .. }) = self.dormant.remove(&room_id) else {
+                continue;
+            };
+            info!("dormant room {} is back", room_id);
+            // a timer started in the future stays at its full length until then
+            gs.wait_since = self
+                .restored
+                .get(&room_id)
+                .filter(|until| **until > now)
+                .copied();
+            gs.deadline_warned = None;
+            self.state_data.insert(room_id, (gs, ss));
+        }
+    }
+
+    /// the socket is gone, by its disconnect or a lost heartbeat. once the user has no connection
+    /// left their seats are marked disconnected, return the rooms they were unreadied in.
+    pub fn drop_socket(&mut self, socket_id: &str) -> Vec<GameStateResp> {
+        match self.remove_socket(socket_id) {
+            // the last socket of the user is gone, nobody is there to start the game
+            Some(user) if self.is_offline(&user.id) => {
+                self.mark_disconnected(&user.id);
+                self.unready_user(&user.id)
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn heartbeat(&mut self, socket_id: &str) {
+        self.last_seen
+            .insert(socket_id.to_string(), std::time::Instant::now());
+    }
+
+    /// the sockets that pinged once and then stayed silent past `heartbeat_timeout_secs`.
+    pub fn stale_sockets(&self) -> Vec<String> {
+        let timeout = std::time::Duration::from_secs(self.config.heartbeat_timeout_secs);
+        if timeout.is_zero() {
+            return vec![];
+        }
+        self.last_seen
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() > timeout)
+            .map(|(socket_id, _)| socket_id.clone())
+            .collect()
+    }
+
+    /// no socket nor plain connection of the user is left.
+    pub fn is_offline(&self, user_id: &str) -> bool {
+        self.user_sockets(user_id).next().is_none() && !self.relay.is_connected(user_id)
+    }
+
+    pub fn remove_socket(&mut self, socket_id: &str) -> Option<User> {
+        self.last_seen.remove(socket_id);
+        // the viewers of the presenter's theaters are told by `closed_theaters`
+        let closed = &mut self.closed_theaters;
+        self.theaters.retain(|id, theater| {
+            let presenting = theater.presenter_socket == socket_id;
+            if presenting {
+                closed.push(id.clone());
+            }
+            !presenting
+        });
+        let (socket, user) = self.users.remove(socket_id)?;
+        self.unregister_socket(&user.id, &socket);
+        self.casters.retain(|_, sockets| {
+            sockets.remove(socket_id);
+            !sockets.is_empty()
+        });
+        Some(user)
+    }
+
+    fn unregister_socket(&mut self, user_id: &str, socket: &SocketRef) {
+        if let Some(sockets) = self.user_sockets.get_mut(user_id) {
+            sockets.retain(|s| s.id != socket.id);
+            if sockets.is_empty() {
+                self.user_sockets.remove(user_id);
+            }
+        }
+    }
+
+    pub fn user_sockets(&self, user_id: &str) -> impl Iterator<Item = &SocketRef> {
+        self.user_sockets.get(user_id).into_iter().flatten()
+    }
+
+    /// the socket.io sink reaching the rooms and the connected users.
+    pub fn sink<'a>(&'a self, io: &'a SocketIo) -> SocketSink<'a> {
+        SocketSink::new(
+            io,
+            &self.namespace,
+            &self.users,
+            &self.user_sockets,
+            &self.relay,
+        )
+    }
+
+    /// the room state and board of the user's finished game, sent again to a client acting in it.
+    pub fn final_state(&self, user_id: &str) -> Option<(&GameStateResp, Vec<SecretToken>)> {
+        let room_id = self.find_user_room(user_id)?;
+        let (gs, ss) = self.state_data.get(&room_id)?;
+        (gs.game_stage == GameStage::GameEnd).then(|| (gs, board_tokens(ss)))
+    }
+
+    /// the room the user sits in, from the index when it is still right, else by looking through
+    /// every room.
+    pub fn find_user_room(&self, user_id: &str) -> Option<RoomId> {
+        let indexed = self.user_rooms.get(user_id).filter(|room_id| {
+            self.state_data
+                .get(*room_id)
+                .is_some_and(|(gs, _)| gs.users.iter().any(|u| u.id == user_id))
         });
-        self.users.insert(socket_id, (socket, user));
+        if let Some(room_id) = indexed {
+            return Some(room_id.clone());
+        }
+        self.iter_game_state()
+            .find_map(|(id, gs)| gs.users.iter().any(|u| u.id == user_id).then_some(id))
+            .cloned()
     }
 
     pub fn check_auth(&self, socket_id: &str) -> Option<&User> {
         self.users.get(socket_id).map(|(_, user)| user)
     }
 
+    /// take one request from the budget of a service account, false when it is used up.
+    /// other users are never limited.
+    pub fn take_rate_limit(&mut self, user: &User) -> bool {
+        let rate = self.config.service_ops_per_sec;
+        if !user.service || rate <= 0.0 {
+            return true;
+        }
+        let burst = self.config.service_burst as f64;
+        let now = std::time::Instant::now();
+        self.rate_limits
+            .entry(user.id.clone())
+            .or_insert_with(|| RateBucket::new(burst, now))
+            .try_take(now, rate, burst)
+    }
+
+    pub fn catch_up(&self, user_id: &str) -> Option<CatchUp> {
+        let room_id = self.find_user_room(user_id)?;
+        let (gs, ss) = self.state_data.get(&room_id)?;
+        CatchUp::new(gs, ss, user_id)
+    }
+
+    /// a player operation from any transport, rated and rate limited.
+    pub fn play(&mut self, user: &User, op: &Operation) -> Result<OperationResult, ServerResp> {
+        if !self.take_rate_limit(user) {
+            return Err(ServerResp::RateLimited);
+        }
+        let quality = self.rate_move(&user.id, op);
+        let stage = self
+            .find_user_room(&user.id)
+            .and_then(|room_id| self.state_data.get(&room_id))
+            .map(|(gs, _)| (gs.game_stage.clone(), gs.map_type.clone()));
+        let resp = self.handle_action_op(user.clone(), op);
+        if let Some(stage) = stage {
+            let limits = self.analytics_limits();
+            let error = resp.as_ref().err();
+            self.analytics.record_action(stage, op, error, limits);
+        }
+        let resp = resp.map_err(|e| {
+            #[cfg(feature = "metrics")]
+            self.rejections
+                .record(op, &e, user.client_version.as_deref());
+            ServerResp::OpErrors(e)
+        })?;
+        self.mark_active(&user.id);
+        if let Some(accuracy) = quality {
+            self.record_move_quality(&user.id, accuracy);
+        }
+        Ok(resp)
+    }
+
     pub fn handle_action_op(
         &mut self,
         user: User,
         operation: &Operation,
     ) -> Result<OperationResult, OpError> {
         // ) -> anyhow::Result<OperationResult> {
+        if let Operation::Batch(steps) = operation {
+            return self.handle_batch(user, steps);
+        }
         let room_id = self
-            .iter_game_state()
-            .find_map(|(id, gs)| gs.users.iter().any(|u| u.id == user.id).then_some(id))
-            .cloned()
+            .find_user_room(&user.id)
             .ok_or(OpError::UserNotFoundInRoom)?;
-        let (gs, ss) = self.get_state(&room_id).ok_or(OpError::GameNotFound)?;
+        let played = self.play_step(&room_id, &user, operation, true)?;
+        let op_result = played.result.clone();
+        self.end_turn(&room_id, &user.id, vec![played]);
+        Ok(op_result)
+    }
 
-        if !gs.check_waiting_for(&user.id) {
+    /// play the operation in the room, the turn is not handed on. `timed` charges the player the
+    /// time the room waited on them, once a turn.
+    fn play_step(
+        &mut self,
+        room_id: &RoomId,
+        user: &User,
+        operation: &Operation,
+        timed: bool,
+    ) -> Result<PlayedStep, OpError> {
+        let self_check = self.config.choice_self_check;
+        let (gs, ss) = self.get_state(room_id).ok_or(OpError::GameNotFound)?;
+
+        if gs.game_stage == GameStage::GameEnd {
+            return Err(OpError::GameFinished);
+        }
+        if !gs.check_waiting(&user.id) {
             return Err(OpError::NotUsersTurn);
         }
 
-        match (operation, &gs.game_stage) {
-            (
-                Operation::Survey(_)
-                | Operation::Target(_)
-                | Operation::Research(_)
-                | Operation::Locate(_),
-                GameStage::UserMove,
-            ) => {}
-            (Operation::ReadyPublish(_), GameStage::MeetingProposal) => {}
-            (Operation::DoPublish(_), GameStage::MeetingPublish) => {}
-            (Operation::DoPublish(_) | Operation::Locate(_), GameStage::LastMove) => {}
-            _rest => {
-                warn!(
-                    "invalid operation in stage{:?} {:?}",
-                    gs.game_stage, operation
-                );
-                return Err(OpError::InvalidMoveInStage);
-            }
+        if !gs.game_stage.operations().contains(&operation.kind()) {
+            warn!(
+                "invalid operation in stage{:?} {:?}",
+                gs.game_stage, operation
+            );
+            return Err(OpError::InvalidMoveInStage);
         }
 
+        let mut penalty = None;
         let op_result = match operation {
             Operation::Survey(s) => {
-                if !validate_index_in_range(
-                    gs.start_index,
-                    gs.end_index,
-                    s.start,
-                    Some(s.end),
-                    ss.map.size(),
-                ) {
-                    return Err(OpError::InvalidIndex);
-                }
-                if s.sector_type == SectorType::X {
-                    return Err(OpError::InvalidSectorType);
-                }
-                if s.sector_type == SectorType::Comet
-                    && (!matches!(s.start, 2 | 3 | 5 | 7 | 11 | 13 | 17)
-                        || !matches!(s.end, 2 | 3 | 5 | 7 | 11 | 13 | 17))
-                {
-                    return Err(OpError::InvalidIndexOfPrime);
-                }
-                let range_size = if s.start <= s.end {
-                    s.end - s.start
-                } else {
-                    s.end + ss.map.size() - s.start
-                };
-                gs.user_move(&user.id, 4 - range_size / 3)?;
+                let range = check_survey(gs, ss, s)?;
+                let cost = gs
+                    .users
+                    .iter()
+                    .find(|u| u.id == user.id)
+                    .ok_or(OpError::UserNotFoundInRoom)?
+                    .handicap
+                    .survey_cost(4_usize.saturating_sub(range.span() / 3));
+                gs.user_move(&user.id, cost)?;
                 OperationResult::Survey(ss.map.survey_sector(s.start, s.end, &s.sector_type))
             }
             Operation::Target(t) => {
@@ -167,26 +834,14 @@ impl State {
                     .iter_mut()
                     .find(|u| u.id == user.id)
                     .ok_or(OpError::UserNotFoundInRoom)?;
-                if user_state
-                    .moves
-                    .iter()
-                    .filter(|op| matches!(op, Operation::Target(_)))
-                    .count()
-                    >= 2
-                {
+                if user_state.targets_left() == 0 {
                     return Err(OpError::TargetTimeExhausted);
                 }
-                if !validate_index_in_range(
-                    gs.start_index,
-                    gs.end_index,
-                    t.index,
-                    None,
-                    ss.map.size(),
-                ) {
+                if !gs.window().contains(t.index) {
                     return Err(OpError::InvalidIndex);
                 }
                 gs.user_move(&user.id, 4)?;
-                OperationResult::Target(ss.map.target_sector(t.index))
+                OperationResult::Target(ss.map.target_sector(t.index)?)
             }
             Operation::Research(r) => {
                 let user_state = gs
@@ -194,11 +849,7 @@ impl State {
                     .iter_mut()
                     .find(|u| u.id == user.id)
                     .ok_or(OpError::UserNotFoundInRoom)?;
-                if user_state
-                    .moves
-                    .last()
-                    .is_some_and(|op| matches!(op, Operation::Research(_)))
-                {
+                if user_state.research_blocked() {
                     return Err(OpError::ResearchContiuously);
                 }
                 gs.user_move(&user.id, 1)?;
@@ -221,20 +872,57 @@ impl State {
                     if !user_state.can_locate {
                         return Err(OpError::EndGameCanNotLocate);
                     }
+                    // a refused index doesn't spend the last locate
+                    check_index(l.index, ss.map.size())?;
                     user_state.can_locate = false;
                     user_state.last_move = false;
-                    OperationResult::Locate(ss.map.locate_x(
-                        l.index,
-                        &l.pre_sector_type,
-                        &l.next_sector_type,
-                    ))
+                    let located =
+                        ss.map
+                            .locate_x(l.index, &l.pre_sector_type, &l.next_sector_type)?;
+                    if located {
+                        ss.located_order.push(user.id.clone());
+                    }
+                    OperationResult::Locate(located)
                 } else {
-                    gs.user_move(&user.id, 5)?;
-                    let r = OperationResult::Locate(ss.map.locate_x(
-                        l.index,
-                        &l.pre_sector_type,
-                        &l.next_sector_type,
-                    ));
+                    check_index(l.index, ss.map.size())?;
+                    let located =
+                        ss.map
+                            .locate_x(l.index, &l.pre_sector_type, &l.next_sector_type)?;
+                    let r = match (located, gs.rules.failed_locate.clone()) {
+                        (true, _) | (false, FailedLocatePolicy::RevealNothing) => {
+                            OperationResult::Locate(located)
+                        }
+                        (false, FailedLocatePolicy::RevealAdjacent) => {
+                            OperationResult::LocateMiss(LocateMiss {
+                                adjacent_wrong: Some(
+                                    ss.map.meeting_check(l.index, &SectorType::X)?,
+                                ),
+                                extra_time: 0,
+                            })
+                        }
+                        (false, FailedLocatePolicy::TimePenalty(extra_time)) => {
+                            OperationResult::LocateMiss(LocateMiss {
+                                adjacent_wrong: None,
+                                extra_time,
+                            })
+                        }
+                    };
+                    let extra_time = match &r {
+                        OperationResult::LocateMiss(miss) => miss.extra_time,
+                        _ => 0,
+                    };
+                    // the locate and its extra time in one move
+                    gs.user_move(&user.id, 5 + extra_time)?;
+                    if !located {
+                        penalty = Some(Penalty {
+                            room_id: room_id.to_string(),
+                            user_id: user.id.clone(),
+                            name: user.name.clone(),
+                            reason: PenaltyReason::FailedLocate,
+                            sector_index: l.index,
+                            steps: 5 + extra_time,
+                        });
+                    }
 
                     if matches!(r, OperationResult::Locate(true)) {
                         gs.game_stage = GameStage::LastMove;
@@ -249,6 +937,7 @@ impl State {
                             user.last_move = user.location.index_lt(&terminator_location);
                         });
                         ss.terminator_location = Some(terminator_location);
+                        ss.located_order.push(user.id.clone());
                     }
                     r
                 }
@@ -258,6 +947,7 @@ impl State {
                 OperationResult::ReadyPublish(rp.sectors.len())
             }
             Operation::DoPublish(dp) => {
+                check_index(dp.index, ss.map.size())?;
                 if ss.revealed_sector_indexs.contains(&dp.index) {
                     return Err(OpError::SectorAlreadyRevealed);
                 }
@@ -286,28 +976,231 @@ impl State {
 
                 OperationResult::DoPublish((dp.index, dp.sector_type.clone()))
             }
+            // played by `handle_batch`, never a step of one
+            Operation::Batch(_) => return Err(OpError::InvalidBatch),
+            Operation::Pass => {
+                let mut pass = PassResult {
+                    steps: 0,
+                    withdrawn: 0,
+                    last_move: false,
+                };
+                match gs.game_stage {
+                    GameStage::UserMove => {
+                        gs.user_move(&user.id, PASS_STEPS)?;
+                        pass.steps = PASS_STEPS;
+                    }
+                    // the players not asked again are the ones without picked tokens
+                    GameStage::MeetingPublish => {
+                        pass.withdrawn = ss.withdraw_ready_tokens(&user.id)?;
+                    }
+                    GameStage::LastMove => {
+                        let user_state = gs
+                            .users
+                            .iter_mut()
+                            .find(|u| u.id == user.id)
+                            .ok_or(OpError::UserNotFoundInRoom)?;
+                        user_state.can_locate = false;
+                        user_state.last_move = false;
+                        pass.last_move = true;
+                    }
+                    // proposing nothing
+                    _ => {}
+                }
+                OperationResult::Pass(pass)
+            }
         };
 
-        ss.choices
+        // a refused operation keeps the player in the waiting list
+        let is_bot = gs.seat_of(&user.id).is_some_and(|s| s.is_bot());
+        let turn = gs
+            .wait_since
+            .filter(|_| timed && !is_bot)
+            .map(|since| since.elapsed());
+        let choices = ss
+            .choices
             .get_mut(&user.id)
-            .ok_or(OpError::UserNotFoundInRoom)?
-            .add_operation(operation.clone(), op_result.clone());
+            .ok_or(OpError::UserNotFoundInRoom)?;
+        #[cfg(feature = "metrics")]
+        let before = choices.count();
+        choices.add_operation(operation.clone(), op_result.clone());
+        #[cfg(feature = "metrics")]
+        let clue_use = match &op_result {
+            OperationResult::Research(clue) if !is_bot => {
+                Some((clue.clone(), before, choices.count()))
+            }
+            _ => None,
+        };
+        if let Some(dump) = self_check.then(|| ss.choice_divergence(&user.id)).flatten() {
+            tracing::error!(
+                "room {room_id}: choice filter lost the true map after {operation:?}: {dump}"
+            );
+            if cfg!(debug_assertions) {
+                panic!("choice filter of {} diverged from the rules", user.id);
+            }
+        }
+        ss.journal.push(JournalEntry {
+            user_id: user.id.clone(),
+            round: gs.round,
+            stage: gs.game_stage.clone(),
+            op: operation.clone(),
+            result: op_result.clone(),
+            think_ms: turn.map(|t| t.as_millis() as u64),
+        });
         let user_state = gs
             .users
             .iter_mut()
             .find(|u| u.id == user.id)
             .ok_or(OpError::UserNotFoundInRoom)?;
+        if let Some(turn) = turn {
+            user_state.think_time += turn;
+            user_state.timed_turns += 1;
+        }
+        user_state.last_think_ms = turn.map(|t| t.as_millis() as u64);
         match operation {
             Operation::ReadyPublish(_) | Operation::DoPublish(_) => {
                 user_state.moves_result.push(op_result.clone());
             }
+            // kept in the journal only, a pass doesn't lift the research block
+            Operation::Pass => {}
             op => {
                 user_state.moves.push(op.clone());
                 user_state.moves_result.push(op_result.clone());
+                user_state.sync_limits();
             }
         }
+        Ok(PlayedStep {
+            result: op_result,
+            penalty,
+            #[cfg(feature = "metrics")]
+            turn,
+            #[cfg(feature = "metrics")]
+            clue_use,
+        })
+    }
 
-        Ok(op_result)
+    /// hand the turn on once the player's operations are played and play on, then keep their
+    /// effects on the server.
+    fn end_turn(&mut self, room_id: &RoomId, user_id: &str, played: Vec<PlayedStep>) {
+        if let Some((gs, ss)) = self.get_state(room_id) {
+            gs.check_waiting_for(user_id);
+            if gs.game_stage == GameStage::MeetingProposal && gs.status == GameState::AutoMove {
+                ss.order_proposals(&engine::user_ids_in_track_order(gs));
+            }
+            // the last awaited player acted, play on now rather than at the next tick
+            let events = engine::settle(gs, ss);
+            gs.stamp_checksum(ss);
+            self.engine_events
+                .extend(events.into_iter().map(|e| (room_id.clone(), e)));
+        }
+        for step in played {
+            self.penalties.extend(step.penalty);
+            #[cfg(feature = "metrics")]
+            {
+                if let Some(turn) = step.turn {
+                    self.pacing.record_turn(turn);
+                }
+                if let Some((clue, before, after)) = step.clue_use {
+                    self.clue_usage.record(&clue, before, after);
+                }
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if let Some((gs, _)) = self.state_data.get(room_id) {
+            self.pacing.observe(gs, std::time::Instant::now());
+        }
+    }
+
+    /// play the steps as one turn. every step is checked against the room before the batch, so a
+    /// refused step plays none of them, and the turn is handed on once they are all played.
+    fn handle_batch(
+        &mut self,
+        user: User,
+        steps: &[Operation],
+    ) -> Result<OperationResult, OpError> {
+        let nested = steps.iter().any(|s| matches!(s, Operation::Batch(_)));
+        if steps.is_empty() || steps.len() > MAX_BATCH_STEPS || nested {
+            return Err(OpError::InvalidBatch);
+        }
+        let room_id = self
+            .find_user_room(&user.id)
+            .ok_or(OpError::UserNotFoundInRoom)?;
+        self.check_batch(&room_id, &user.id, steps)?;
+        let mut played = vec![];
+        for (i, step) in steps.iter().enumerate() {
+            match self.play_step(&room_id, &user, step, i == 0) {
+                Ok(step) => played.push(step),
+                Err(e) => {
+                    // `check_batch` let through a refused step, the steps before it stay played
+                    tracing::error!("room {room_id}: batch step {step:?} refused after its check");
+                    self.end_turn(&room_id, &user.id, played);
+                    return Err(e);
+                }
+            }
+        }
+        let results = played.iter().map(|step| step.result.clone()).collect();
+        self.end_turn(&room_id, &user.id, played);
+        Ok(OperationResult::Batch(results))
+    }
+
+    /// refuse the batch unless each step is legal in the room as it is before the batch, with
+    /// the steps before it counted in the limits of the player. a batch is moves along the
+    /// track, a locate can only be its last step.
+    fn check_batch(
+        &self,
+        room_id: &RoomId,
+        user_id: &str,
+        steps: &[Operation],
+    ) -> Result<(), OpError> {
+        let (gs, ss) = self.state_data.get(room_id).ok_or(OpError::GameNotFound)?;
+        if gs.game_stage == GameStage::GameEnd {
+            return Err(OpError::GameFinished);
+        }
+        if !gs.check_waiting(user_id) {
+            return Err(OpError::NotUsersTurn);
+        }
+        if gs.game_stage != GameStage::UserMove {
+            return Err(OpError::InvalidMoveInStage);
+        }
+        let mut seat = gs
+            .users
+            .iter()
+            .find(|u| u.id == user_id)
+            .cloned()
+            .ok_or(OpError::UserNotFoundInRoom)?;
+        for (i, step) in steps.iter().enumerate() {
+            match step {
+                Operation::Survey(s) => {
+                    check_survey(gs, ss, s)?;
+                }
+                Operation::Target(t) => {
+                    if seat.targets_left() == 0 {
+                        return Err(OpError::TargetTimeExhausted);
+                    }
+                    if !gs.window().contains(t.index) {
+                        return Err(OpError::InvalidIndex);
+                    }
+                }
+                Operation::Research(r) => {
+                    if seat.research_blocked() {
+                        return Err(OpError::ResearchContiuously);
+                    }
+                    if !ss.research_clues.iter().any(|c| c.index == r.index) {
+                        return Err(OpError::InvalidClue);
+                    }
+                }
+                // a found x ends the moves along the track
+                Operation::Locate(_) if i + 1 < steps.len() => return Err(OpError::InvalidBatch),
+                Operation::Locate(l) => {
+                    check_index(l.index, ss.map.size())?;
+                }
+                Operation::Pass => {}
+                _ => return Err(OpError::InvalidMoveInStage),
+            }
+            if !matches!(step, Operation::Pass) {
+                seat.moves.push(step.clone());
+            }
+        }
+        Ok(())
     }
 
     fn _room_op(&mut self, user: User, op: InnerRoomOp) -> Vec<GameStateResp> {
@@ -318,7 +1211,9 @@ impl State {
                     if !gs.users.iter().any(|u| u.id == user.id) && gs.users.len() < 4 {
                         let room_user = UserState::placeholder(&user, gs.users.len() + 1, false);
                         gs.users.push(room_user);
+                        gs.assign_color(&user.id);
                         res.push(gs.clone());
+                        self.user_rooms.insert(user.id.clone(), id.clone());
                     } else {
                         info!("room full or user already in room");
                     }
@@ -331,6 +1226,7 @@ impl State {
                     if gs.users.iter().any(|u| u.id == user.id) {
                         gs.users.retain(|u| u.id != user.id);
                         res.push(gs.clone());
+                        self.user_rooms.remove(&user.id);
                     }
                 } else {
                     info!("room not found");
@@ -343,17 +1239,15 @@ impl State {
                         res.push(gs.clone());
                     }
                 }
+                self.user_rooms.remove(&user.id);
             }
-            InnerRoomOp::EnableBot(id) => {
+            InnerRoomOp::EnableBot(id, persona) => {
                 if let Some(gs) = self.get_game_state(id) {
-                    if !gs.users.iter().any(|u| u.is_bot) && gs.users.len() < 4 {
-                        let bot_user = User {
-                            id: format!("bot-{}", &id),
-                            name: "protocol".to_string(),
-                        };
-                        let room_bot_user =
-                            UserState::placeholder(&bot_user, gs.users.len() + 1, true);
-                        gs.users.push(room_bot_user);
+                    if !gs.users.iter().any(|u| u.seat().is_bot()) && gs.users.len() < 4 {
+                        let bot = UserState::bot(id, persona, gs.users.len() + 1);
+                        let bot_id = bot.id.clone();
+                        gs.users.push(bot);
+                        gs.assign_color(&bot_id);
                         res.push(gs.clone());
                     } else {
                         info!("room full or bot already in room");
@@ -364,8 +1258,8 @@ impl State {
             }
             InnerRoomOp::DisableBot(id) => {
                 if let Some(gs) = self.get_game_state(id) {
-                    if gs.users.iter().any(|u| u.is_bot) {
-                        gs.users.retain(|u| !u.is_bot);
+                    if gs.users.iter().any(|u| u.seat().is_bot()) {
+                        gs.users.retain(|u| !u.seat().is_bot());
                         res.push(gs.clone());
                     }
                 } else {
@@ -378,26 +1272,16 @@ impl State {
 
     pub fn handle_room_op(
         &mut self,
-        socket: SocketRef,
+        conn: &impl Membership,
         user: User,
         room_op: RoomUserOperation,
     ) -> Result<Vec<GameStateResp>, RoomError> {
         match room_op {
             RoomUserOperation::Create => {
+                self.check_capacity(true)?;
                 let mut results = self._room_op(user.clone(), InnerRoomOp::LeaveAll);
-                socket.leave_all();
-                let rand_new_id = loop {
-                    // maybe a pure number id is better
-                    let rand_id: String = uuid::Uuid::new_v4()
-                        .to_string()
-                        .chars()
-                        .filter(|c| c.is_ascii_digit())
-                        .take(4)
-                        .collect();
-                    if rand_id.len() == 4 && !self.iter_game_state().any(|(id, _)| id == &rand_id) {
-                        break rand_id;
-                    }
-                };
+                conn.exit_all();
+                let rand_new_id = self.new_room_id();
                 info!("new room id: {}", rand_new_id);
 
                 self.state_data.insert(
@@ -408,7 +1292,34 @@ impl State {
                     ),
                 );
                 results.extend(self._room_op(user, InnerRoomOp::Enter(&rand_new_id)));
-                socket.join(rand_new_id);
+                conn.enter(rand_new_id);
+                Ok(results)
+            }
+            RoomUserOperation::FromPreset(name) => {
+                let preset = self
+                    .preferences
+                    .get(&user.id)
+                    .and_then(|p| p.presets.iter().find(|p| p.name == name))
+                    .cloned()
+                    .ok_or(RoomError::PresetNotFound)?;
+                let mut results =
+                    self.handle_room_op(conn, user.clone(), RoomUserOperation::Create)?;
+                let room_id = self
+                    .find_user_room(&user.id)
+                    .ok_or(RoomError::RoomNotFound)?;
+                // the room is sent once, with the preset applied
+                results.retain(|gs| gs.id != room_id);
+                let gs = self
+                    .get_game_state(&room_id)
+                    .ok_or(RoomError::RoomNotFound)?;
+                preset.apply(gs);
+                match preset.bot {
+                    Some(persona) => {
+                        let bot = InnerRoomOp::EnableBot(&room_id, persona);
+                        results.extend(self._room_op(user, bot));
+                    }
+                    None => results.push(gs.clone()),
+                }
                 Ok(results)
             }
             RoomUserOperation::SwitchBot(id) => {
@@ -416,57 +1327,135 @@ impl State {
                 if gs.status != GameState::NotStarted {
                     return Err(RoomError::RoomStarted);
                 }
-                let res = if gs.users.iter().any(|u| u.is_bot) {
+                let res = if gs.users.iter().any(|u| u.seat().is_bot()) {
                     self._room_op(user, InnerRoomOp::DisableBot(&id))
                 } else {
                     if gs.users.len() >= 4 {
                         return Err(RoomError::RoomFull);
                     }
-                    self._room_op(user, InnerRoomOp::EnableBot(&id))
+                    self._room_op(user, InnerRoomOp::EnableBot(&id, BotPersona::random()))
                 };
                 Ok(res)
             }
-            RoomUserOperation::Edit(new_info) => {
+            RoomUserOperation::BotPersona(info) => {
                 let gs = self
-                    .get_game_state(&new_info.room_id)
+                    .get_game_state(&info.room_id)
                     .ok_or(RoomError::RoomNotFound)?;
-                gs.map_seed = new_info.map_seed;
-                gs.map_type = new_info.map_type;
-                gs.end_index = gs.map_type.sector_count() / 2;
-                Ok(vec![gs.clone()])
-            }
-            RoomUserOperation::Join(id) => {
-                let gs = self.get_game_state(&id).ok_or(RoomError::RoomNotFound)?;
-                if gs.status != GameState::NotStarted && !gs.users.iter().any(|u| u.id == user.id) {
+                if gs.status != GameState::NotStarted {
                     return Err(RoomError::RoomStarted);
                 }
-                if gs.users.iter().any(|u| u.id == user.id) {
-                    socket.join(id);
+                if let Some(bot) = gs.users.iter_mut().find(|u| u.seat().is_bot()) {
+                    bot.set_persona(info.persona);
                     return Ok(vec![gs.clone()]);
                 }
                 if gs.users.len() >= 4 {
                     return Err(RoomError::RoomFull);
                 }
-                let mut results = self._room_op(user.clone(), InnerRoomOp::LeaveAll);
-                socket.leave_all();
-                results.extend(self._room_op(user, InnerRoomOp::Enter(&id)));
-                socket.join(id);
-                Ok(results)
-            }
-            RoomUserOperation::Leave(id) => {
-                socket.leave(id.clone());
-                Ok(self._room_op(user, InnerRoomOp::Leave(&id)))
+                let res = self._room_op(user, InnerRoomOp::EnableBot(&info.room_id, info.persona));
+                Ok(res)
             }
-            RoomUserOperation::Prepare(id) => {
-                let gs = self.get_game_state(&id).ok_or(RoomError::RoomNotFound)?;
-                let user = gs
-                    .users
+            RoomUserOperation::Edit(new_info) => {
+                let gs = self
+                    .get_game_state(&new_info.room_id)
+                    .ok_or(RoomError::RoomNotFound)?;
+                if gs.status != GameState::NotStarted {
+                    // the settings, hint policy included, are locked once the game starts
+                    return Err(RoomError::RoomStarted);
+                }
+                gs.map_type = new_info.map_type;
+                if let Some(rules) = new_info.rules {
+                    gs.rules = rules;
+                }
+                // the server draws the seed at the start
+                gs.map_seed = match gs.rules.server_seed {
+                    true => 0,
+                    false => new_info.map_seed,
+                };
+                gs.set_window(SkyWindow::first(&gs.map_type));
+                Ok(vec![gs.clone()])
+            }
+            RoomUserOperation::SetHandicap(info) => {
+                let gs = self
+                    .get_game_state(&info.room_id)
+                    .ok_or(RoomError::RoomNotFound)?;
+                if gs.status != GameState::NotStarted {
+                    return Err(RoomError::RoomStarted);
+                }
+                if gs.host_id() != Some(user.id.as_str()) {
+                    return Err(RoomError::NotHost);
+                }
+                if !info.handicap.is_valid() {
+                    return Err(RoomError::InvalidHandicap);
+                }
+                let target = gs
+                    .users
+                    .iter_mut()
+                    .find(|u| u.id == info.user_id)
+                    .ok_or(RoomError::UserNotFoundInRoom)?;
+                target.handicap = info.handicap;
+                Ok(vec![gs.clone()])
+            }
+            RoomUserOperation::Join(id) => {
+                let gs = self.get_game_state(&id).ok_or(RoomError::RoomNotFound)?;
+                if gs.status != GameState::NotStarted && !gs.users.iter().any(|u| u.id == user.id) {
+                    return Err(RoomError::RoomStarted);
+                }
+                if gs.users.iter().any(|u| u.id == user.id) {
+                    conn.enter(id);
+                    return Ok(vec![gs.clone()]);
+                }
+                if gs.users.len() >= 4 {
+                    return Err(RoomError::RoomFull);
+                }
+                self.check_capacity(false)?;
+                let mut results = self._room_op(user.clone(), InnerRoomOp::LeaveAll);
+                conn.exit_all();
+                results.extend(self._room_op(user, InnerRoomOp::Enter(&id)));
+                conn.enter(id);
+                Ok(results)
+            }
+            RoomUserOperation::Leave(id) => {
+                conn.exit(id.clone());
+                Ok(self._room_op(user, InnerRoomOp::Leave(&id)))
+            }
+            RoomUserOperation::Prepare(id) => {
+                let gs = self.get_game_state(&id).ok_or(RoomError::RoomNotFound)?;
+                let user = gs
+                    .users
                     .iter_mut()
                     .find(|u| u.id == user.id)
                     .ok_or(RoomError::UserNotFoundInRoom)?;
                 user.ready = true;
+                user.ready_at = Some(std::time::Instant::now());
                 Ok(vec![gs.clone()])
             }
+            RoomUserOperation::AbortVote(ballot) => self.vote_abort(&user.id, ballot),
+            RoomUserOperation::Resume(code) => self.resume(conn, user, &code),
+            RoomUserOperation::Puzzle(info) => {
+                self.check_capacity(true)?;
+                let export = self
+                    .export_game(&info.room_id)
+                    .ok_or(RoomError::GameNotEnded)?;
+                if !export.players.iter().any(|p| p.id == user.id) {
+                    return Err(RoomError::UserNotFoundInRoom);
+                }
+                let new_id = self.new_room_id();
+                let (gs, ss) = build_puzzle(&export, &info, &new_id, &user)?;
+                info!(
+                    "puzzle room {} from room {}, seat {} turn {}",
+                    new_id, info.room_id, info.seat_id, info.turn
+                );
+                let user_id = user.id.clone();
+                let mut results = self._room_op(user, InnerRoomOp::LeaveAll);
+                conn.exit_all();
+                results.push(gs.clone());
+                self.state_data.insert(new_id.clone(), (gs, ss));
+                self.user_rooms.insert(user_id, new_id.clone());
+                conn.enter(new_id);
+                Ok(results)
+            }
+            RoomUserOperation::ConsentCast(id) => self.set_cast_consent(&id, &user.id, true),
+            RoomUserOperation::WithdrawCast(id) => self.set_cast_consent(&id, &user.id, false),
             RoomUserOperation::Unprepare(id) => {
                 let gs = self.get_game_state(&id).ok_or(RoomError::RoomNotFound)?;
                 let user = gs
@@ -475,38 +1464,1083 @@ impl State {
                     .find(|u| u.id == user.id)
                     .ok_or(RoomError::UserNotFoundInRoom)?;
                 user.ready = false;
+                user.ready_at = None;
                 Ok(vec![gs.clone()])
             }
         }
     }
 
+    pub fn mark_disconnected(&mut self, user_id: &str) {
+        for (_, gs) in self.iter_mut_game_state() {
+            if let Some(u) = gs.users.iter_mut().find(|u| u.id == user_id) {
+                u.disconnected_at = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    /// the user acted by itself, take the seat back from the bot if it was handed over.
+    pub fn mark_active(&mut self, user_id: &str) {
+        self.last_actions.insert(user_id.to_string(), now_secs());
+        for (_, gs) in self.iter_mut_game_state() {
+            if let Some(u) = gs.users.iter_mut().find(|u| u.id == user_id) {
+                u.missed_turns = 0;
+                u.bot_controlled = false;
+                gs.last_activity = now_secs();
+            }
+        }
+    }
+
+    /// a free room id, four digits.
+    fn new_room_id(&self) -> RoomId {
+        loop {
+            // maybe a pure number id is better
+            let rand_id: String = uuid::Uuid::new_v4()
+                .to_string()
+                .chars()
+                .filter(|c| c.is_ascii_digit())
+                .take(4)
+                .collect();
+            if rand_id.len() == 4 && !self.room_id_taken(&rand_id) {
+                break rand_id;
+            }
+        }
+    }
+
+    /// a room with the id runs or waits dormant for its players.
+    fn room_id_taken(&self, room_id: &str) -> bool {
+        self.state_data.contains_key(room_id) || self.dormant.contains_key(room_id)
+    }
+
+    /// refuse new rooms and seats once the server is at capacity, so the running games
+    /// keep their pace. rejoining a seat is never refused.
+    fn check_capacity(&self, creating: bool) -> Result<(), RoomError> {
+        if let Some(maintenance) = self.maintenance.as_ref().filter(|_| creating) {
+            return Err(RoomError::Maintenance {
+                message: maintenance.message.clone(),
+            });
+        }
+        let config = &self.config;
+        let games = self
+            .iter_game_state()
+            .filter(|(_, gs)| !matches!(gs.status, GameState::NotStarted | GameState::End))
+            .count();
+        // the asking user is connected already, so the connection count includes them
+        let busy = (config.max_connections > 0 && self.users.len() > config.max_connections)
+            || creating && config.max_rooms > 0 && self.state_data.len() >= config.max_rooms
+            || creating && config.max_games > 0 && games >= config.max_games;
+        if busy {
+            return Err(RoomError::ServerBusy {
+                retry_after_secs: config.busy_retry_secs,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn touch_room(&mut self, room_id: &str) {
+        if let Some(gs) = self.get_game_state(room_id) {
+            gs.last_activity = now_secs();
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn dashboard(&self) -> Dashboard {
+        let now = now_secs();
+        let mut rooms = self
+            .iter_all()
+            .map(|(_, (gs, ss))| RoomStats::new(gs, ss, now))
+            .collect::<Vec<_>>();
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        Dashboard {
+            connections: self.users.len(),
+            rooms,
+        }
+    }
+
+    /// the users whose id is `query` or whose name contains it, case insensitive, with their
+    /// connections and seats. dormant rooms included.
+    #[cfg(feature = "admin")]
+    pub fn find_users(&self, query: &str) -> Vec<UserWhereabouts> {
+        let needle = query.to_lowercase();
+        let hit = |id: &str, name: &str| id == query || name.to_lowercase().contains(&needle);
+        let rooms = self
+            .state_data
+            .values()
+            .map(|(gs, ss)| (gs, ss, false))
+            .chain(self.dormant.values().map(|room| (&room.gs, &room.ss, true)));
+        let mut found: BTreeMap<String, String> = BTreeMap::new(); // id -> name
+        for (gs, _, _) in rooms.clone() {
+            for u in gs.users.iter().filter(|u| hit(&u.id, &u.name)) {
+                found.insert(u.id.clone(), u.name.clone());
+            }
+        }
+        for (_, user) in self.users.values().filter(|(_, u)| hit(&u.id, &u.name)) {
+            found.insert(user.id.clone(), user.name.clone());
+        }
+        found
+            .into_iter()
+            .take(MAX_USER_SEARCH_RESULTS)
+            .map(|(id, name)| UserWhereabouts {
+                sockets: self.user_sockets(&id).map(|s| s.id.to_string()).collect(),
+                plain_connected: self.relay.is_connected(&id),
+                seats: rooms
+                    .clone()
+                    .filter_map(|(gs, ss, dormant)| UserSeat::new(gs, ss, &id, dormant))
+                    .collect(),
+                last_action: self.last_actions.get(&id).copied(),
+                id,
+                name,
+            })
+            .collect()
+    }
+
+    /// count a missed turn for every idle waiting seat, return the timed out seats and the
+    /// seats which got handed over to the bot.
+    pub fn expire_turns(&mut self) -> TurnTimers {
+        let mut timers = TurnTimers::default();
+        let timeout_secs = self.config.turn_timeout_secs;
+        if timeout_secs == 0 {
+            return timers;
+        }
+        let timeout = std::time::Duration::from_secs(timeout_secs);
+        for (room_id, gs) in self.iter_mut_game_state() {
+            let GameState::Wait(waiting) = &gs.status else {
+                continue;
+            };
+            let since = *gs.wait_since.get_or_insert_with(std::time::Instant::now);
+            if since.elapsed() <= timeout {
+                let secs_left = timeout.saturating_sub(since.elapsed()).as_secs();
+                let warning = DEADLINE_WARNINGS
+                    .into_iter()
+                    .filter(|&w| w < timeout_secs && secs_left < w)
+                    .min();
+                if let Some(w) = warning.filter(|&w| gs.deadline_warned.is_none_or(|d| w < d)) {
+                    gs.deadline_warned = Some(w);
+                    for user in gs
+                        .users
+                        .iter()
+                        .filter(|u| waiting.contains(&u.id) && !u.is_server_driven())
+                    {
+                        let deadline = TurnDeadline {
+                            room_id: room_id.clone(),
+                            secs_left: w,
+                        };
+                        timers.warnings.push((user.id.clone(), deadline));
+                    }
+                }
+                continue;
+            }
+            // restart the timer, in case the server move is rejected
+            gs.wait_since = Some(std::time::Instant::now());
+            gs.deadline_warned = None;
+            for user in gs
+                .users
+                .iter_mut()
+                .filter(|u| waiting.contains(&u.id) && !u.is_server_driven())
+            {
+                user.missed_turns += 1;
+                info!("turn timeout: {} in room: {}", user.id, room_id);
+                let timeout = TurnTimeout {
+                    user_id: user.id.clone(),
+                    name: user.name.clone(),
+                };
+                timers.timeouts.push((room_id.clone(), timeout));
+                if gs.rules.idle_kick_turns > 0 && user.missed_turns >= gs.rules.idle_kick_turns {
+                    user.bot_controlled = true;
+                    timers.kicks.push((
+                        room_id.clone(),
+                        IdleKick {
+                            user_id: user.id.clone(),
+                            name: user.name.clone(),
+                            action: IdleKickAction::BotControl,
+                        },
+                    ));
+                }
+            }
+        }
+        timers
+    }
+
+    /// remove users who have been disconnected for a whole turn timeout from not started rooms.
+    pub fn remove_idle_users(&mut self) -> Vec<(RoomId, IdleKick)> {
+        let mut kicks = vec![];
+        if self.config.turn_timeout_secs == 0 {
+            return kicks;
+        }
+        let timeout = std::time::Duration::from_secs(self.config.turn_timeout_secs);
+        for (room_id, gs) in self.iter_mut_game_state() {
+            if gs.status != GameState::NotStarted || gs.rules.idle_kick_turns == 0 {
+                continue;
+            }
+            gs.users.retain(|u| {
+                let idle =
+                    !u.seat().is_bot() && u.disconnected_at.is_some_and(|t| t.elapsed() > timeout);
+                if idle {
+                    kicks.push((
+                        room_id.clone(),
+                        IdleKick {
+                            user_id: u.id.clone(),
+                            name: u.name.clone(),
+                            action: IdleKickAction::Removed,
+                        },
+                    ));
+                }
+                !idle
+            });
+        }
+        kicks
+    }
+
+    /// clear the ready flag of the user in every not started room, return the changed rooms.
+    pub fn unready_user(&mut self, user_id: &str) -> Vec<GameStateResp> {
+        let mut res = vec![];
+        for (_, gs) in self.iter_mut_game_state() {
+            if gs.status != GameState::NotStarted {
+                continue;
+            }
+            if let Some(user) = gs.users.iter_mut().find(|u| u.id == user_id && u.ready) {
+                user.ready = false;
+                user.ready_at = None;
+                res.push(gs.clone());
+            }
+        }
+        res
+    }
+
+    /// clear the ready flags which lasted longer than the timeout while the room is still waiting.
+    pub fn expire_ready(&mut self) -> Vec<GameStateResp> {
+        if self.config.ready_timeout_secs == 0 {
+            return vec![];
+        }
+        let timeout = std::time::Duration::from_secs(self.config.ready_timeout_secs);
+        let mut res = vec![];
+        for (_, gs) in self.iter_mut_game_state() {
+            if gs.status != GameState::NotStarted || gs.users.iter().all(|u| u.ready) {
+                continue;
+            }
+            let mut changed = false;
+            for user in gs
+                .users
+                .iter_mut()
+                .filter(|u| u.ready && !u.seat().is_bot())
+            {
+                if user.ready_at.is_some_and(|t| t.elapsed() > timeout) {
+                    info!("ready timeout: {} in room: {}", user.id, gs.id);
+                    user.ready = false;
+                    user.ready_at = None;
+                    changed = true;
+                }
+            }
+            if changed {
+                res.push(gs.clone());
+            }
+        }
+        res
+    }
+
+    /// rate the move against the bot's evaluation before it is applied, competitive rooms only.
+    pub fn rate_move(&self, user_id: &str, op: &Operation) -> Option<f64> {
+        let room_id = self.find_user_room(user_id)?;
+        let (gs, ss) = self.state_data.get(&room_id)?;
+        if !gs.rules.is_competitive() || !gs.check_waiting(user_id) || gs.seat_of(user_id)?.is_bot()
+        {
+            return None;
+        }
+        let user_state = gs.users.iter().find(|u| u.id == user_id)?;
+        let info = BestMoveInfo {
+            stage: gs.game_stage.clone(),
+            map_type: gs.map_type.clone(),
+            start_index: SectorIndex::new(gs.start_index, gs.map_type.sector_count()).ok()?,
+            end_index: SectorIndex::new(gs.end_index, gs.map_type.sector_count()).ok()?,
+            revealed_sectors: ss.revealed_sector_indexs.clone(),
+        };
+        rank_move(
+            info,
+            &ss.research_clues,
+            user_state,
+            ss.user_tokens.get(user_id)?,
+            ss.choices.get(user_id)?,
+            op,
+        )
+    }
+
+    /// count a payload of `event` that didn't deserialize, and tell the client why.
+    pub fn bad_request(&mut self, event: &str, reason: impl std::fmt::Display) -> ServerResp {
+        #[cfg(feature = "metrics")]
+        self.bad_requests.record(event);
+        ServerResp::bad_request(event, reason)
+    }
+
+    pub fn record_move_quality(&mut self, user_id: &str, accuracy: f64) {
+        self.play_quality
+            .entry(user_id.to_string())
+            .or_default()
+            .record(accuracy);
+    }
+
+    /// drop the stats of the user, including the ones waiting to be stored.
+    #[cfg(any(feature = "admin", feature = "integrations"))]
+    pub fn forget_stats(&mut self, user_id: &str) {
+        self.play_quality.remove(user_id);
+        self.pending_writes
+            .retain(|w| !matches!(w, StorageWrite::Stats(id, _) if id == user_id));
+    }
+
+    /// fold the stats of a guest id into the account that claimed it.
+    #[cfg(feature = "integrations")]
+    pub fn merge_stats(&mut self, guest_id: &str, user_id: &str) {
+        if guest_id == user_id {
+            return;
+        }
+        let Some(quality) = self.play_quality.get(guest_id).cloned() else {
+            return;
+        };
+        self.forget_stats(guest_id);
+        let merged = self.play_quality.entry(user_id.to_string()).or_default();
+        merged.merge(&quality);
+    }
+
+    /// the preferences of the user after the operation, a change is queued for the storage.
+    pub fn handle_preferences(&mut self, user_id: &str, op: PreferencesOperation) -> Preferences {
+        match op {
+            PreferencesOperation::Get => self.preferences.get(user_id).cloned().unwrap_or_default(),
+            PreferencesOperation::Set(preferences) => {
+                let preferences = preferences.validated();
+                self.preferences
+                    .insert(user_id.to_string(), preferences.clone());
+                self.pending_writes.push(StorageWrite::Preferences(
+                    user_id.to_string(),
+                    preferences.clone(),
+                ));
+                preferences
+            }
+        }
+    }
+
+    /// drop the preferences of the user, including the ones waiting to be stored.
+    #[cfg(any(feature = "admin", feature = "integrations"))]
+    pub fn forget_preferences(&mut self, user_id: &str) {
+        self.preferences.remove(user_id);
+        self.pending_writes
+            .retain(|w| !matches!(w, StorageWrite::Preferences(id, _) if id == user_id));
+    }
+
+    /// the account keeps its own preferences, else it takes the ones of the guest id it claimed.
+    #[cfg(feature = "integrations")]
+    pub fn merge_preferences(&mut self, guest_id: &str, user_id: &str) {
+        if guest_id == user_id {
+            return;
+        }
+        let Some(preferences) = self.preferences.get(guest_id).cloned() else {
+            return;
+        };
+        self.forget_preferences(guest_id);
+        if !self.preferences.contains_key(user_id) {
+            self.handle_preferences(user_id, PreferencesOperation::Set(preferences));
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn suspicious_players(&self) -> Vec<QualityReport> {
+        self.play_quality
+            .iter()
+            .filter(|(_, q)| {
+                q.is_suspicious(
+                    self.config.anticheat_min_samples,
+                    self.config.anticheat_best_move_rate,
+                )
+            })
+            .map(|(id, q)| QualityReport::new(id, q))
+            .collect()
+    }
+
+    /// snapshot every started room that changed since its last snapshot, and queue the
+    /// snapshots, the finished games and their players' stats for the storage.
+    pub fn autosave(&mut self) {
+        let keep = self.config.snapshot_keep;
+        for (room_id, (gs, ss)) in self.state_data.iter() {
+            if gs.status == GameState::NotStarted {
+                continue;
+            }
+            let Some(room) = self
+                .snapshots
+                .entry(room_id.clone())
+                .or_default()
+                .autosave(gs, ss, keep)
+            else {
+                continue;
+            };
+            self.pending_writes.push(StorageWrite::Snapshot {
+                room_id: room_id.clone(),
+                room: Box::new(room),
+                keep,
+            });
+            // a practice game is not kept with the played ones
+            if gs.status != GameState::End || gs.puzzle.is_some() {
+                continue;
+            }
+            self.pending_writes
+                .push(StorageWrite::Game(GameExport::new(gs, ss)));
+            // only the competitive games rate the moves
+            if !gs.rules.is_competitive() {
+                continue;
+            }
+            for user in gs.users.iter().filter(|u| !u.seat().is_bot()) {
+                let quality = self.play_quality.entry(user.id.clone()).or_default();
+                quality.competitive_games += 1;
+                let write = StorageWrite::Stats(user.id.clone(), quality.clone());
+                self.pending_writes.push(write);
+            }
+        }
+        self.snapshots.retain(|room_id, _| {
+            self.state_data.contains_key(room_id) || self.dormant.contains_key(room_id)
+        });
+    }
+
+    /// the game of a save code dealt again for its host, who leaves their rooms for it.
+    #[cfg(feature = "integrations")]
+    fn resume(
+        &mut self,
+        conn: &impl Membership,
+        user: User,
+        code: &str,
+    ) -> Result<Vec<GameStateResp>, RoomError> {
+        self.check_capacity(true)?;
+        let secret = self
+            .config
+            .save_code_secret
+            .as_deref()
+            .ok_or(RoomError::SaveCodesDisabled)?;
+        let save = SaveGame::decode(code, secret)?;
+        if save.host_id() != Some(user.id.as_str()) {
+            return Err(RoomError::NotHost);
+        }
+        let now = now_secs();
+        if save.is_expired(now) {
+            return Err(RoomError::SaveCodeExpired);
+        }
+        self.resumed_codes
+            .retain(|_, issued_at| now <= *issued_at + SAVE_CODE_TTL_SECS);
+        if self.resumed_codes.contains_key(&save.nonce) {
+            return Err(RoomError::SaveCodeUsed);
+        }
+        // the game still runs here, a second copy would split its players
+        let playing = |id: &str| {
+            self.iter_game_state()
+                .any(|(_, gs)| gs.is_playing() && gs.users.iter().any(|u| u.id == id))
+                || self
+                    .dormant
+                    .values()
+                    .any(|r| r.gs.users.iter().any(|u| u.id == id))
+        };
+        if save.seats.iter().any(|s| !s.is_bot && playing(&s.id)) {
+            return Err(RoomError::RoomStarted);
+        }
+        let new_id = match self.room_id_taken(&save.room_id) {
+            true => self.new_room_id(),
+            false => save.room_id.clone(),
+        };
+        let (mut gs, ss) = save.resume(&new_id)?;
+        info!(
+            "room {} resumed from a save of room {}",
+            new_id, save.room_id
+        );
+        self.resumed_codes.insert(save.nonce, save.issued_at);
+        // the others rejoin when they connect, the server plays for them meanwhile
+        let now = std::time::Instant::now();
+        for u in gs
+            .users
+            .iter_mut()
+            .filter(|u| !u.seat().is_bot() && u.id != user.id)
+        {
+            u.disconnected_at = Some(now);
+        }
+        let user_id = user.id.clone();
+        let mut results = self._room_op(user, InnerRoomOp::LeaveAll);
+        conn.exit_all();
+        results.push(gs.clone());
+        self.state_data.insert(new_id.clone(), (gs, ss));
+        self.user_rooms.insert(user_id, new_id.clone());
+        conn.enter(new_id);
+        Ok(results)
+    }
+
+    #[cfg(not(feature = "integrations"))]
+    fn resume(
+        &mut self,
+        _conn: &impl Membership,
+        _user: User,
+        _code: &str,
+    ) -> Result<Vec<GameStateResp>, RoomError> {
+        Err(RoomError::SaveCodesDisabled)
+    }
+
+    /// the save code of a running room, for its host.
+    #[cfg(feature = "integrations")]
+    pub fn save_code(&self, room_id: &str, user_id: &str) -> Result<String, RoomError> {
+        let secret = self
+            .config
+            .save_code_secret
+            .as_deref()
+            .ok_or(RoomError::SaveCodesDisabled)?;
+        let (gs, ss) = self
+            .state_data
+            .get(room_id)
+            .ok_or(RoomError::RoomNotFound)?;
+        if gs.host_id() != Some(user_id) {
+            return Err(RoomError::NotHost);
+        }
+        let save = SaveGame::new(gs, ss, &self.config.clue_weights)?;
+        Ok(save.encode(secret))
+    }
+
+    #[cfg(not(feature = "integrations"))]
+    pub fn save_code(&self, _room_id: &str, _user_id: &str) -> Result<String, RoomError> {
+        Err(RoomError::SaveCodesDisabled)
+    }
+
+    /// bundle what it takes to replay a room locally and keep it for the admins. `reporter` is
+    /// the player asking for it, who must sit in the room, or None for an admin.
+    pub fn file_bug_report(
+        &mut self,
+        room_id: &str,
+        reporter: Option<&str>,
+        note: Option<String>,
+    ) -> Result<String, RoomError> {
+        let (gs, ss) = match self.state_data.get(room_id) {
+            Some((gs, ss)) => (gs, ss),
+            None => self
+                .dormant
+                .get(room_id)
+                .map(|room| (&room.gs, &room.ss))
+                .ok_or(RoomError::RoomNotFound)?,
+        };
+        if reporter.is_some_and(|user_id| !gs.users.iter().any(|u| u.id == user_id)) {
+            return Err(RoomError::UserNotFoundInRoom);
+        }
+        let snapshots = self
+            .snapshots
+            .get(room_id)
+            .map(|ring| ring.stored())
+            .unwrap_or_default();
+        let report = BugReport::new(
+            gs,
+            ss,
+            self.stage_log.transitions(room_id),
+            snapshots,
+            reporter.map(str::to_string),
+            note,
+        );
+        let id = self.bug_reports.file(report);
+        info!(
+            "room {}: bug report {} filed by {:?}",
+            room_id, id, reporter
+        );
+        Ok(id)
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn room_snapshots(&self, room_id: &str) -> Option<Vec<SnapshotInfo>> {
+        self.snapshots.get(room_id).map(|ring| ring.list())
+    }
+
+    /// rewind the room to the snapshot, false if the room or the snapshot is gone.
+    #[cfg(feature = "admin")]
+    pub fn restore_snapshot(&mut self, room_id: &str, snapshot_id: u64) -> bool {
+        let Some(restored) = self
+            .snapshots
+            .get_mut(room_id)
+            .and_then(|ring| ring.restore(snapshot_id))
+        else {
+            return false;
+        };
+        let Some(data) = self.state_data.get_mut(room_id) else {
+            return false;
+        };
+        warn!("room {} restored to snapshot {}", room_id, snapshot_id);
+        *data = restored;
+        true
+    }
+
+    /// put a stored snapshot back as the room, e.g. after a restart lost it.
+    #[cfg(feature = "admin")]
+    pub fn restore_stored(&mut self, room_id: &str, room: StoredRoom) {
+        warn!(
+            "room {} restored to stored snapshot {}",
+            room_id, room.info.id
+        );
+        self.state_data.insert(room_id.to_owned(), room.restore());
+    }
+
+    /// export of a finished game, None if the room is not found or still playing.
+    pub fn export_game(&self, room_id: &str) -> Option<GameExport> {
+        let (gs, ss) = self.state_data.get(room_id)?;
+        (gs.status == GameState::End).then(|| GameExport::new(gs, ss))
+    }
+
+    fn vote_abort(
+        &mut self,
+        user_id: &str,
+        ballot: AbortBallot,
+    ) -> Result<Vec<GameStateResp>, RoomError> {
+        let ratio = self.config.abort_vote_ratio;
+        let deadline = now_secs() + self.config.abort_vote_secs;
+        let (gs, ss) = self
+            .get_state(&ballot.room_id)
+            .ok_or(RoomError::RoomNotFound)?;
+        if !gs.is_playing() {
+            return Err(RoomError::GameNotRunning);
+        }
+        if !gs
+            .users
+            .iter()
+            .any(|u| u.id == user_id && !u.seat().is_bot())
+        {
+            return Err(RoomError::UserNotFoundInRoom);
+        }
+        if gs.abort_vote.is_none() {
+            if !ballot.agree {
+                return Err(RoomError::NoAbortVote);
+            }
+            info!("abort vote called by {} in room {}", user_id, gs.id);
+        }
+        gs.abort_vote
+            .get_or_insert_with(|| AbortVote::new(user_id, deadline))
+            .cast(user_id, ballot.agree);
+        gs.settle_abort_vote(ss, ratio, now_secs());
+        Ok(vec![gs.clone()])
+    }
+
+    /// close the abort votes past their deadline, return the rooms changed.
+    pub fn expire_abort_votes(&mut self) -> Vec<GameStateResp> {
+        let ratio = self.config.abort_vote_ratio;
+        let now = now_secs();
+        self.state_data
+            .values_mut()
+            .filter_map(|(gs, ss)| gs.settle_abort_vote(ss, ratio, now).then(|| gs.clone()))
+            .collect()
+    }
+
+    fn set_cast_consent(
+        &mut self,
+        room_id: &str,
+        user_id: &str,
+        consent: bool,
+    ) -> Result<Vec<GameStateResp>, RoomError> {
+        let gs = self
+            .get_game_state(room_id)
+            .ok_or(RoomError::RoomNotFound)?;
+        if gs.status != GameState::NotStarted {
+            return Err(RoomError::RoomStarted);
+        }
+        let user = gs
+            .users
+            .iter_mut()
+            .find(|u| u.id == user_id)
+            .ok_or(RoomError::UserNotFoundInRoom)?;
+        user.cast_consent = consent;
+        Ok(vec![gs.clone()])
+    }
+
+    /// Ok(None) when the caster left.
+    pub fn handle_cast_op(
+        &mut self,
+        socket: SocketRef,
+        user: User,
+        op: CastOperation,
+    ) -> Result<Option<CasterView>, RoomError> {
+        match op {
+            CastOperation::Join(CastJoin { room_id, token }) => {
+                self.check_caster(&room_id, &user.id, &token)?;
+                let (gs, ss) = self.get_state(&room_id).ok_or(RoomError::RoomNotFound)?;
+                let view = CasterView::new(gs, ss);
+                self.casters
+                    .entry(room_id.clone())
+                    .or_default()
+                    .insert(socket.id.to_string());
+                socket.join(CasterView::channel(&room_id));
+                Ok(Some(view))
+            }
+            CastOperation::Leave(room_id) => {
+                if let Some(sockets) = self.casters.get_mut(&room_id) {
+                    sockets.remove(socket.id.as_str());
+                }
+                socket.leave(CasterView::channel(&room_id));
+                Ok(None)
+            }
+        }
+    }
+
+    /// a user may cast a room with the caster token of the room or the admin token, and only
+    /// once every player consented. user ids are chosen by the clients, the token is the proof.
+    fn check_caster(&self, room_id: &str, user_id: &str, token: &str) -> Result<(), RoomError> {
+        let (gs, _) = self
+            .state_data
+            .get(room_id)
+            .ok_or(RoomError::RoomNotFound)?;
+        let issued = self.caster_tokens.get(room_id).is_some_and(|t| t == token);
+        let admin = self.config.admin_token.as_deref() == Some(token);
+        if !issued && !admin {
+            return Err(RoomError::CasterNotAuthorized);
+        }
+        if gs.users.iter().any(|u| u.id == user_id) {
+            return Err(RoomError::CasterIsPlayer);
+        }
+        if !gs.casting_allowed() {
+            return Err(RoomError::CastingNotAllowed);
+        }
+        Ok(())
+    }
+
+    /// the caster token of a room allowing casting, issued once and kept until the room is gone.
+    #[cfg(feature = "admin")]
+    pub fn issue_caster_token(&mut self, room_id: &str) -> Result<CasterToken, RoomError> {
+        let gs = match self.state_data.get(room_id) {
+            Some((gs, _)) => gs,
+            None => &self.dormant.get(room_id).ok_or(RoomError::RoomNotFound)?.gs,
+        };
+        if !gs.rules.casting {
+            return Err(RoomError::CastingNotAllowed);
+        }
+        let token = self
+            .caster_tokens
+            .entry(room_id.to_string())
+            .or_insert_with(|| uuid::Uuid::new_v4().to_string())
+            .clone();
+        Ok(CasterToken {
+            room_id: room_id.to_string(),
+            token,
+        })
+    }
+
+    /// views to push to the casters of started rooms.
+    pub fn caster_views(&self) -> Vec<(RoomId, CasterView)> {
+        self.casters
+            .iter()
+            .filter(|(_, sockets)| !sockets.is_empty())
+            .filter_map(|(room_id, _)| {
+                let (gs, ss) = self.state_data.get(room_id)?;
+                (gs.status != GameState::NotStarted && gs.casting_allowed())
+                    .then(|| (room_id.clone(), CasterView::new(gs, ss)))
+            })
+            .collect()
+    }
+
+    /// Ok(None) when the user left, the theater is closed if it was the presenter.
+    /// Ok(Some) with a message to deliver, see `chat_recipients`.
+    pub fn handle_chat_op(
+        &mut self,
+        user: &User,
+        op: ChatOperation,
+    ) -> Result<Option<ChatMessage>, ChatError> {
+        match op {
+            ChatOperation::Send(send) => {
+                let text = send.text.trim();
+                if text.is_empty() {
+                    return Err(ChatError::Empty);
+                }
+                if text.chars().count() > MAX_CHAT_LEN {
+                    return Err(ChatError::TooLong);
+                }
+                let (gs, _) = self
+                    .state_data
+                    .get(&send.room_id)
+                    .ok_or(ChatError::RoomNotFound)?;
+                let name = gs
+                    .users
+                    .iter()
+                    .find(|u| u.id == user.id)
+                    .ok_or(ChatError::UserNotFoundInRoom)?
+                    .name
+                    .clone();
+                let chat = self.chats.get(&send.room_id);
+                if chat.is_some_and(|c| c.muted.contains(&user.id)) {
+                    return Err(ChatError::Muted);
+                }
+                let mut text = text.to_string();
+                let mut action = None;
+                for filter in self.chat_filters.iter() {
+                    match filter.check(&text) {
+                        FilterVerdict::Pass => {}
+                        FilterVerdict::Replace(replaced) => {
+                            text = replaced;
+                            action = Some(ModerationAction::Masked);
+                        }
+                        FilterVerdict::Reject => {
+                            action = Some(ModerationAction::Rejected);
+                            break;
+                        }
+                    }
+                }
+                if let Some(action) = action {
+                    let rejected = action == ModerationAction::Rejected;
+                    self.moderate(Some(&send.room_id), "filter", &user.id, action);
+                    if rejected {
+                        return Err(ChatError::Rejected);
+                    }
+                }
+                Ok(Some(ChatMessage {
+                    room_id: send.room_id,
+                    user_id: user.id.clone(),
+                    name,
+                    text,
+                    sent_at: now_secs(),
+                }))
+            }
+            ChatOperation::Mute(target) => {
+                self.check_chat_host(user, &target.room_id, &target.user_id)?;
+                self.chats
+                    .entry(target.room_id.clone())
+                    .or_default()
+                    .muted
+                    .insert(target.user_id.clone());
+                let room_id = Some(target.room_id.as_str());
+                self.moderate(room_id, &user.id, &target.user_id, ModerationAction::Mute);
+                Ok(None)
+            }
+            ChatOperation::Unmute(target) => {
+                self.check_chat_host(user, &target.room_id, &target.user_id)?;
+                if let Some(chat) = self.chats.get_mut(&target.room_id) {
+                    chat.muted.remove(&target.user_id);
+                }
+                let room_id = Some(target.room_id.as_str());
+                self.moderate(room_id, &user.id, &target.user_id, ModerationAction::Unmute);
+                Ok(None)
+            }
+            ChatOperation::Block(target) => {
+                let blocked = self.blocks.entry(user.id.clone()).or_default();
+                blocked.insert(target.clone());
+                self.moderate(None, &user.id, &target, ModerationAction::Block);
+                Ok(None)
+            }
+            ChatOperation::Unblock(target) => {
+                if let Some(blocked) = self.blocks.get_mut(&user.id) {
+                    blocked.remove(&target);
+                }
+                self.moderate(None, &user.id, &target, ModerationAction::Unblock);
+                Ok(None)
+            }
+        }
+    }
+
+    fn check_chat_host(&self, user: &User, room_id: &str, target: &str) -> Result<(), ChatError> {
+        let (gs, _) = self
+            .state_data
+            .get(room_id)
+            .ok_or(ChatError::RoomNotFound)?;
+        if gs.host_id() != Some(user.id.as_str()) {
+            return Err(ChatError::NotHost);
+        }
+        if !gs.users.iter().any(|u| u.id == target) {
+            return Err(ChatError::UserNotFoundInRoom);
+        }
+        Ok(())
+    }
+
+    fn moderate(
+        &mut self,
+        room_id: Option<&str>,
+        actor: &str,
+        target: &str,
+        action: ModerationAction,
+    ) {
+        info!(?room_id, actor, target, ?action, "chat moderation");
+        self.moderation.push_back(ModerationEntry {
+            at: now_secs(),
+            room_id: room_id.map(str::to_string),
+            actor: actor.to_string(),
+            target: target.to_string(),
+            action,
+        });
+        while self.moderation.len() > MODERATION_LOG_LEN {
+            self.moderation.pop_front();
+        }
+    }
+
+    /// a reaction of a player of the room, muted players can't react either.
+    pub fn react(&mut self, user: &User, send: ReactionSend) -> Result<Reaction, ChatError> {
+        let (gs, _) = self
+            .state_data
+            .get(&send.room_id)
+            .ok_or(ChatError::RoomNotFound)?;
+        if !gs.users.iter().any(|u| u.id == user.id) {
+            return Err(ChatError::UserNotFoundInRoom);
+        }
+        let in_room = |id: &String| gs.users.iter().any(|u| &u.id == id);
+        if send.target.as_ref().is_some_and(|t| !in_room(t)) {
+            return Err(ChatError::TargetNotFound);
+        }
+        let chat = self.chats.get(&send.room_id);
+        if chat.is_some_and(|c| c.muted.contains(&user.id)) {
+            return Err(ChatError::Muted);
+        }
+        let rate = self.config.reactions_per_sec;
+        if rate > 0.0 {
+            let burst = self.config.reaction_burst as f64;
+            let now = std::time::Instant::now();
+            let taken = self
+                .reaction_limits
+                .entry(user.id.clone())
+                .or_insert_with(|| RateBucket::new(burst, now))
+                .try_take(now, rate, burst);
+            if !taken {
+                return Err(ChatError::TooFast);
+            }
+        }
+        Ok(Reaction {
+            room_id: send.room_id,
+            user_id: user.id.clone(),
+            emote: send.emote,
+            target: send.target,
+            sent_at: now_secs(),
+        })
+    }
+
+    /// the players of the room who get the message, all but the ones blocking its sender.
+    pub fn chat_recipients(&self, message: &ChatMessage) -> Vec<String> {
+        let Some((gs, _)) = self.state_data.get(&message.room_id) else {
+            return vec![];
+        };
+        gs.users
+            .iter()
+            .filter(|u| !u.seat().is_bot())
+            .filter(|u| {
+                self.blocks
+                    .get(&u.id)
+                    .is_none_or(|blocked| !blocked.contains(&message.user_id))
+            })
+            .map(|u| u.id.clone())
+            .collect()
+    }
+
+    pub fn handle_theater_op(
+        &mut self,
+        socket: SocketRef,
+        user: User,
+        op: TheaterOperation,
+    ) -> Result<Option<TheaterFrame>, TheaterError> {
+        match op {
+            TheaterOperation::Load(export) => {
+                if !Theater::is_supported(&export) {
+                    return Err(TheaterError::UnsupportedVersion);
+                }
+                let id = self.new_theater_id(&user.id)?;
+                info!("new theater id: {}", id);
+                let theater = Theater::new(id.clone(), user.id, socket.id.to_string(), *export);
+                let frame = theater.frame();
+                self.theaters.insert(id.clone(), theater);
+                socket.join(Theater::channel(&id));
+                Ok(Some(frame))
+            }
+            TheaterOperation::Join(id) => {
+                let theater = self
+                    .theaters
+                    .get(&id)
+                    .ok_or(TheaterError::TheaterNotFound)?;
+                socket.join(Theater::channel(&id));
+                Ok(Some(theater.frame()))
+            }
+            TheaterOperation::Leave(id) => {
+                let theater = self
+                    .theaters
+                    .get(&id)
+                    .ok_or(TheaterError::TheaterNotFound)?;
+                if theater.presenter == user.id {
+                    self.theaters.remove(&id);
+                }
+                socket.leave(Theater::channel(&id));
+                Ok(None)
+            }
+            TheaterOperation::Step(step) => {
+                let theater = self.presenting_theater(&step.id, &user.id)?;
+                theater.step(step.delta);
+                Ok(Some(theater.frame()))
+            }
+            TheaterOperation::Seek(seek) => {
+                let theater = self.presenting_theater(&seek.id, &user.id)?;
+                theater.seek(seek.cursor);
+                Ok(Some(theater.frame()))
+            }
+        }
+    }
+
+    /// a free 4 digit id for a theater the user loads, within the theater limits. refused as
+    /// full when a few draws all hit taken ids.
+    fn new_theater_id(&self, user_id: &str) -> Result<String, TheaterError> {
+        let presenting = self.theaters.values().filter(|t| t.presenter == user_id);
+        if presenting.count() >= MAX_THEATERS_PER_USER {
+            return Err(TheaterError::TooManyTheaters);
+        }
+        if self.theaters.len() >= MAX_THEATERS {
+            return Err(TheaterError::TheatersFull);
+        }
+        (0..THEATER_ID_ATTEMPTS)
+            .map(|_| format!("{:04}", rand::random_range(0..10000)))
+            .find(|id| !self.theaters.contains_key(id))
+            .ok_or(TheaterError::TheatersFull)
+    }
+
+    fn presenting_theater(
+        &mut self,
+        id: &str,
+        user_id: &str,
+    ) -> Result<&mut Theater, TheaterError> {
+        let theater = self
+            .theaters
+            .get_mut(id)
+            .ok_or(TheaterError::TheaterNotFound)?;
+        if theater.presenter != user_id {
+            return Err(TheaterError::NotPresenter);
+        }
+        Ok(theater)
+    }
+
+    pub fn query_lobby(&self, query: &LobbyQuery) -> LobbyPage {
+        query.apply(self.iter_game_state().map(|(_id, gs)| gs))
+    }
+
     pub fn handle_recommend_op(
         &mut self,
         user: User,
         op: RecommendOperation,
     ) -> Result<RecommendOperationResult, RecommendError> {
         let room_id = self
-            .iter_game_state()
-            .find_map(|(id, gs)| gs.users.iter().any(|u| u.id == user.id).then_some(id))
-            .cloned()
+            .find_user_room(&user.id)
             .ok_or(RecommendError::UserNotFoundInRoom)?;
-        let (_gs, ss) = self
+        let (gs, ss) = self
             .get_state(&room_id)
             .ok_or(RecommendError::GameNotFound)?;
-        let choice = ss
-            .choices
-            .get(&user.id)
-            .ok_or(RecommendError::UserNotFoundInRoom)?;
+        // the server plays the bot seats, there is no one to advise
+        if gs.seat_of(&user.id).is_some_and(|s| s.is_bot()) {
+            return Err(RecommendError::BotSeat);
+        }
+        // rule info, not a hint
+        let hint = !matches!(op, RecommendOperation::LegalActions);
+        if hint && gs.rules.no_hints {
+            return Err(RecommendError::HintsDisabled);
+        }
+        let choice = || {
+            ss.choices
+                .get(&user.id)
+                .ok_or(RecommendError::UserNotFoundInRoom)
+        };
         match op {
+            RecommendOperation::LegalActions => {
+                let legal = gs
+                    .legal_actions(&user.id)
+                    .ok_or(RecommendError::UserNotFoundInRoom)?;
+                Ok(RecommendOperationResult::LegalActions(legal))
+            }
             RecommendOperation::Count => {
-                if !choice.initialized {
+                let choice = choice()?;
+                // counted from the cache or, above the cache threshold, the streamed pass
+                let Some(count) = choice.count() else {
                     return Err(RecommendError::NotEnoughData);
-                } else {
-                    return Ok(RecommendOperationResult::Count(choice.all.len()));
-                }
+                };
+                return Ok(RecommendOperationResult::Count(count));
             }
             RecommendOperation::CanLocate => {
-                if !choice.initialized {
+                let choice = choice()?;
+                if choice.count().is_none() {
                     return Err(RecommendError::NotEnoughData);
                 } else {
                     let can_locate = choice.can_locate();
@@ -517,14 +2551,1210 @@ impl State {
     }
 }
 
-pub fn create_state() -> Arc<Mutex<State>> {
-    Arc::new(Mutex::new(State::new()))
+/// the state of a namespace on its rows of the storage, the player stats, the preferences and
+/// the recent rooms are loaded back from them.
+pub async fn create_state(
+    config: ServerConfig,
+    namespace: &str,
+    storage: &dyn Storage,
+) -> anyhow::Result<Arc<Mutex<State>>> {
+    let storage = storage.scoped(namespace);
+    let mut state = State::new(config);
+    state.namespace = namespace.to_string();
+    state.play_quality = storage.load_stats().await?;
+    state.preferences = storage.load_preferences().await?;
+    let since = now_secs().saturating_sub(state.config.dormant_ttl_secs);
+    let rooms = storage.load_latest_snapshots(since).await?;
+    state.restore_rooms(rooms);
+    state.storage = storage;
+    Ok(Arc::new(Mutex::new(state)))
 }
 
 pub type StateRef = Arc<Mutex<State>>;
 
+/// the states of every namespace, for the requests about a player rather than a room. only the
+/// admin and control routes read them.
+#[derive(Clone)]
+#[cfg_attr(
+    not(any(feature = "admin", feature = "integrations")),
+    allow(dead_code)
+)]
+pub struct Namespaces(pub Vec<StateRef>);
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct User {
     pub id: String, // some rand uuid for each device.
     pub name: String,
+    /// a scripted client, rate limited by the server. bots of third parties authenticate with it.
+    #[serde(default)]
+    pub service: bool,
+    /// the build of the client, sent with `auth`, to tell the rejected operations apart.
+    #[serde(default)]
+    pub client_version: Option<String>,
+    /// the events the client understands beyond the original ones, sent with `auth`.
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(default)]
+    pub appearance: Appearance, // validated when seated
+}
+
+#[cfg(test)]
+impl User {
+    /// a human user named after its id.
+    pub fn test(id: &str) -> Self {
+        crate::ids::Seat::of(id, false).user(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::map::ClueEnum;
+
+    #[test]
+    fn test_check_capacity() {
+        let mut state = State::new(ServerConfig::default());
+        let room = |id: &str| {
+            (
+                GameStateResp::new(id.to_string()),
+                ServerGameState::placeholder(),
+            )
+        };
+        state.state_data.insert("1".to_string(), room("1"));
+        assert!(state.check_capacity(true).is_ok());
+
+        state.config.max_rooms = 2;
+        assert!(state.check_capacity(true).is_ok());
+        state.state_data.insert("2".to_string(), room("2"));
+        assert!(state.check_capacity(true).is_err());
+        assert!(state.check_capacity(false).is_ok());
+
+        state.maintenance = Some(Maintenance {
+            message: "back soon".to_string(),
+            since: now_secs(),
+        });
+        assert!(matches!(
+            state.check_capacity(true),
+            Err(RoomError::Maintenance { .. })
+        ));
+        assert!(state.check_capacity(false).is_ok());
+        state.maintenance = None;
+
+        state.config.max_rooms = 0;
+        state.config.max_games = 1;
+        assert!(state.check_capacity(true).is_ok());
+        state.get_game_state("1").unwrap().status = GameState::AutoMove;
+        assert!(matches!(
+            state.check_capacity(true),
+            Err(RoomError::ServerBusy {
+                retry_after_secs: 30
+            })
+        ));
+    }
+
+    #[test]
+    fn test_stale_sockets() {
+        let mut state = State::new(ServerConfig::default());
+        state.heartbeat("s1");
+        state.heartbeat("s2");
+        assert!(state.stale_sockets().is_empty());
+
+        let silent = std::time::Duration::from_secs(state.config.heartbeat_timeout_secs + 1);
+        state.last_seen.insert(
+            "s1".to_string(),
+            std::time::Instant::now().checked_sub(silent).unwrap(),
+        );
+        assert_eq!(state.stale_sockets(), vec!["s1".to_string()]);
+        assert!(state.drop_socket("s1").is_empty());
+        assert!(state.stale_sockets().is_empty());
+
+        state.heartbeat("s1");
+        state.last_seen.insert(
+            "s1".to_string(),
+            std::time::Instant::now().checked_sub(silent).unwrap(),
+        );
+        state.config.heartbeat_timeout_secs = 0;
+        assert!(state.stale_sockets().is_empty());
+    }
+
+    #[test]
+    fn test_theater_limits() {
+        let mut state = State::new(ServerConfig::default());
+        let export = GameExport::new(
+            &GameStateResp::new("1".to_string()),
+            &ServerGameState::placeholder(),
+        );
+        for (user_id, socket_id) in [("u1", "s1"), ("u1", "s1"), ("u2", "s2")] {
+            let id = state.new_theater_id(user_id).unwrap();
+            let theater =
+                Theater::new(id.clone(), user_id.into(), socket_id.into(), export.clone());
+            state.theaters.insert(id, theater);
+        }
+        assert!(matches!(
+            state.new_theater_id("u1"),
+            Err(TheaterError::TooManyTheaters)
+        ));
+        assert!(state.new_theater_id("u2").is_ok());
+
+        // the theaters close with the socket that loaded them
+        assert!(state.drop_socket("s1").is_empty());
+        assert_eq!(state.closed_theaters.len(), 2);
+        assert_eq!(state.theaters.len(), 1);
+        assert!(state.new_theater_id("u1").is_ok());
+    }
+
+    #[test]
+    fn test_ready_expires_and_disconnect_unreadies() {
+        let mut state = State::new(ServerConfig::default());
+        let mut gs = GameStateResp::new("1".to_string());
+        for (i, id) in ["u1", "u2", "u3"].into_iter().enumerate() {
+            let user = User::test(id);
+            gs.users.push(UserState::placeholder(&user, i + 1, false));
+        }
+        gs.users.push(UserState::bot("1", BotPersona::default(), 4));
+        let timeout = std::time::Duration::from_secs(state.config.ready_timeout_secs);
+        let now = std::time::Instant::now();
+        gs.users[0].ready = true;
+        gs.users[0].ready_at = now.checked_sub(timeout * 2);
+        gs.users[1].ready = true;
+        gs.users[1].ready_at = Some(now);
+        state
+            .state_data
+            .insert("1".to_string(), (gs, ServerGameState::placeholder()));
+        let ready = |state: &mut State| {
+            let gs = state.get_game_state("1").unwrap();
+            gs.users.iter().map(|u| u.ready).collect::<Vec<_>>()
+        };
+
+        // only the flag older than the timeout goes, the bot stays ready
+        assert_eq!(state.expire_ready().len(), 1);
+        assert_eq!(ready(&mut state), vec![false, true, false, true]);
+        assert!(state.expire_ready().is_empty());
+
+        // the last socket of u2 is gone, as `drop_socket` handles it
+        assert!(state.is_offline("u2"));
+        state.mark_disconnected("u2");
+        assert_eq!(state.unready_user("u2").len(), 1);
+        assert_eq!(ready(&mut state), vec![false, false, false, true]);
+        assert!(state.unready_user("u2").is_empty());
+
+        // a started room keeps its flags
+        state.get_game_state("1").unwrap().users[2].ready = true;
+        state.get_game_state("1").unwrap().status = GameState::AutoMove;
+        assert!(state.unready_user("u3").is_empty());
+
+        // a timeout of 0 never expires
+        state.get_game_state("1").unwrap().status = GameState::NotStarted;
+        state.get_game_state("1").unwrap().users[2].ready_at = now.checked_sub(timeout * 2);
+        state.config.ready_timeout_secs = 0;
+        assert!(state.expire_ready().is_empty());
+    }
+
+    #[test]
+    fn test_unready_aborts_the_countdown() {
+        let mut state = State::new(ServerConfig::default());
+        let mut gs = GameStateResp::new("1".to_string());
+        let user = User::test("u1");
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        gs.users.push(UserState::bot("1", BotPersona::default(), 2));
+        gs.users[0].ready = true;
+        gs.users[0].ready_at = Some(std::time::Instant::now());
+        state
+            .state_data
+            .insert("1".to_string(), (gs, ServerGameState::placeholder()));
+        let tick = |state: &mut State| {
+            let (gs, ss) = state.get_state("1").unwrap();
+            engine::start_game(
+                gs,
+                ss,
+                std::time::Duration::from_secs(5),
+                &crate::map::ClueWeights::default(),
+                std::time::Instant::now(),
+            )
+        };
+
+        assert!(matches!(
+            tick(&mut state)[..],
+            [EngineEvent::StartingIn(Some(_))]
+        ));
+        // the player's last socket is gone while the countdown runs
+        state.mark_disconnected("u1");
+        assert_eq!(state.unready_user("u1").len(), 1);
+        assert!(matches!(
+            tick(&mut state)[..],
+            [EngineEvent::StartingIn(None)]
+        ));
+        let gs = state.get_game_state("1").unwrap();
+        assert!(gs.start_countdown.is_none());
+        assert_eq!(gs.status, GameState::NotStarted);
+    }
+
+    #[test]
+    fn test_archive_rooms() {
+        let mut state = State::new(ServerConfig::default());
+        let user = User::test("u1");
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.users.push(UserState::placeholder(&user, 0, false));
+        gs.status = GameState::AutoMove;
+        let room = (gs, ServerGameState::placeholder());
+        state.state_data.insert("1".to_string(), room);
+        let empty = (
+            GameStateResp::new("2".to_string()),
+            ServerGameState::placeholder(),
+        );
+        state.state_data.insert("2".to_string(), empty);
+
+        state.archive_rooms();
+        assert!(state.state_data.contains_key("1"));
+        assert!(!state.state_data.contains_key("2"));
+
+        state.mark_disconnected("u1");
+        state.archive_rooms();
+        assert!(state.state_data.is_empty());
+        assert!(state.dormant.contains_key("1"));
+        assert_eq!(state.pending_writes.len(), 1);
+
+        assert_eq!(state.reconnect("u1"), vec!["1".to_string()]);
+        assert!(state.dormant.is_empty());
+
+        state.config.dormant_ttl_secs = 0;
+        state.mark_disconnected("u1");
+        state.archive_rooms();
+        assert!(state.state_data.contains_key("1"));
+    }
+
+    #[test]
+    fn test_room_id_taken_by_dormant_room() {
+        let mut state = State::new(ServerConfig::default());
+        let room = (
+            GameStateResp::new("1".to_string()),
+            ServerGameState::placeholder(),
+        );
+        state.state_data.insert("1".to_string(), room);
+        assert!(state.room_id_taken("1"));
+        assert!(!state.room_id_taken("2"));
+
+        let (gs, ss) = state.state_data.remove("1").unwrap();
+        let since = std::time::Instant::now();
+        state
+            .dormant
+            .insert("1".to_string(), DormantRoom { gs, ss, since });
+        // a new room must not take the id of a room waiting for its players
+        assert!(state.room_id_taken("1"));
+    }
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_caster_token() {
+        let mut state = State::new(ServerConfig {
+            admin_token: Some("admin".to_string()),
+            ..ServerConfig::default()
+        });
+        let user = User::test("u1");
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.users.push(UserState::placeholder(&user, 0, false));
+        let room = (gs, ServerGameState::placeholder());
+        state.state_data.insert("1".to_string(), room);
+        assert!(matches!(
+            state.issue_caster_token("1"),
+            Err(RoomError::CastingNotAllowed)
+        ));
+
+        state.get_game_state("1").unwrap().rules.casting = true;
+        state.set_cast_consent("1", "u1", true).unwrap();
+        let token = state.issue_caster_token("1").unwrap().token;
+        assert_eq!(state.issue_caster_token("1").unwrap().token, token);
+        // any user id is taken at its word, the token is what lets it in
+        assert!(matches!(
+            state.check_caster("1", "c1", "guess"),
+            Err(RoomError::CasterNotAuthorized)
+        ));
+        assert!(state.check_caster("1", "c1", &token).is_ok());
+        assert!(state.check_caster("1", "c1", "admin").is_ok());
+        assert!(matches!(
+            state.check_caster("1", "u1", &token),
+            Err(RoomError::CasterIsPlayer)
+        ));
+
+        state.state_data.get_mut("1").unwrap().0.users.clear();
+        state
+            .casters
+            .insert("1".to_string(), HashSet::from(["s1".to_string()]));
+        state.archive_rooms();
+        assert!(state.casters.is_empty());
+        assert!(state.caster_tokens.is_empty());
+    }
+
+    #[test]
+    fn test_restore_rooms() {
+        let mut state = State::new(ServerConfig::default());
+        let user = User::test("u1");
+        let ss = ServerGameState::placeholder();
+        let stored = |id: &str, status: GameState| {
+            let mut gs = GameStateResp::new(id.to_string());
+            gs.users.push(UserState::placeholder(&user, 0, false));
+            gs.status = status;
+            let room = SnapshotRing::default().autosave(&gs, &ss, 1).unwrap();
+            (id.to_string(), room)
+        };
+        state.restore_rooms(vec![
+            stored("1", GameState::Wait(vec!["u1".to_string()])),
+            stored("2", GameState::End),
+        ]);
+        assert_eq!(state.dormant.len(), 1);
+        assert!(state.dormant["1"].gs.users[0].disconnected_at.is_some());
+
+        assert_eq!(state.reconnect("u1"), vec!["1".to_string()]);
+        let (gs, _) = &state.state_data["1"];
+        assert!(gs.wait_since.is_some_and(|t| t > std::time::Instant::now()));
+        assert!(state.in_restore_grace("1"));
+    }
+
+    #[test]
+    fn test_turn_deadline_warnings() {
+        let mut state = State::new(ServerConfig::default());
+        let user = User::test("u1");
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.users.push(UserState::placeholder(&user, 0, false));
+        gs.status = GameState::Wait(vec!["u1".to_string()]);
+        let ago = |secs| Some(std::time::Instant::now() - std::time::Duration::from_secs(secs));
+        gs.wait_since = ago(95);
+        let room = (gs, ServerGameState::placeholder());
+        state.state_data.insert("1".to_string(), room);
+
+        let timers = state.expire_turns();
+        assert_eq!(timers.warnings.len(), 1);
+        assert_eq!(timers.warnings[0].1.secs_left, 30);
+        // warned once per threshold
+        assert!(state.expire_turns().warnings.is_empty());
+
+        state.get_game_state("1").unwrap().wait_since = ago(121);
+        let timers = state.expire_turns();
+        assert!(timers.warnings.is_empty());
+        assert_eq!(timers.timeouts[0].1.user_id, "u1");
+    }
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_announcements() {
+        let mut state = State::new(ServerConfig::default());
+        let now = now_secs();
+        let later =
+            state.schedule_announcement("v2".into(), AnnouncementKind::NewVersion, Some(now + 5));
+        state.schedule_announcement("hello".into(), AnnouncementKind::Info, None);
+        state.schedule_announcement("bye".into(), AnnouncementKind::Info, None);
+        assert_eq!(state.announcements[2], later.unwrap());
+
+        assert_eq!(state.due_announcement(now).unwrap().message, "hello");
+        // rate limited, the next one waits for the interval
+        assert!(state.due_announcement(now + 1).is_none());
+        assert_eq!(state.due_announcement(now + 60).unwrap().message, "bye");
+        assert!(state.cancel_announcement(1));
+        assert!(state.announcements.is_empty());
+    }
+
+    /// the recorded moves of a scripted opponent, played in order and again from the start.
+    #[derive(Debug, Clone)]
+    enum Scripted {
+        Research(ClueEnum),
+        SurveyWindow(SectorType), // the whole visible half of the track, no comets
+    }
+
+    /// steps a scripted game may take before it counts as stuck.
+    const MAX_SCRIPTED_STEPS: usize = 1000;
+
+    /// the move of the scripted opponent the room waits for, by stage.
+    fn scripted_move<'a>(
+        gs: &GameStateResp,
+        script: &mut impl Iterator<Item = &'a Scripted>,
+    ) -> Operation {
+        use crate::operation::{
+            LocateOperation, ReadyPublishOperation, ResearchOperation, SurveyOperatoin,
+        };
+
+        let survey = |sector_type| {
+            Operation::Survey(SurveyOperatoin {
+                sector_type,
+                start: gs.start_index,
+                end: gs.end_index,
+            })
+        };
+        match gs.game_stage {
+            GameStage::MeetingProposal => {
+                Operation::ReadyPublish(ReadyPublishOperation { sectors: vec![] })
+            }
+            GameStage::LastMove => Operation::Locate(LocateOperation {
+                index: 1,
+                pre_sector_type: SectorType::Space,
+                next_sector_type: SectorType::Space,
+            }),
+            _ => match script.next() {
+                Some(Scripted::Research(_))
+                    if gs
+                        .users
+                        .iter()
+                        .any(|u| u.id == "u1" && u.research_blocked()) =>
+                {
+                    survey(SectorType::Space)
+                }
+                Some(Scripted::Research(index)) => Operation::Research(ResearchOperation {
+                    index: index.clone(),
+                }),
+                Some(Scripted::SurveyWindow(sector_type)) => survey(sector_type.clone()),
+                None => survey(SectorType::Space),
+            },
+        }
+    }
+
+    /// play a standard game of the bot against a scripted opponent, return the bot's operations.
+    fn play_scripted(seed: u64, script: &[Scripted]) -> Vec<Operation> {
+        let [human, bot] = ["u1", "bot-1"].map(User::test);
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_seed = seed;
+        let mut seat = UserState::placeholder(&human, 1, false);
+        seat.ready = true;
+        gs.users.push(seat);
+        gs.users.push(UserState::placeholder(&bot, 2, true));
+        let mut ss = ServerGameState::placeholder();
+        let now = std::time::Instant::now();
+        engine::start_game(
+            &mut gs,
+            &mut ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            now,
+        );
+        let mut state = State::new(ServerConfig::default());
+        state.state_data.insert("1".to_string(), (gs, ss));
+        drive_room(&mut state, "1", &human, script)
+    }
+
+    /// play a started room to its end, the server plays the bot and `human` follows the script.
+    fn drive_room(
+        state: &mut State,
+        room_id: &str,
+        human: &User,
+        script: &[Scripted],
+    ) -> Vec<Operation> {
+        let mut script = script.iter().cycle();
+        let mut bot_ops = vec![];
+        for _ in 0..MAX_SCRIPTED_STEPS {
+            let (gs, ss) = state.get_state(room_id).unwrap();
+            if gs.status == GameState::End {
+                return bot_ops;
+            }
+            engine::advance(gs, ss);
+            let moves = engine::bot_moves(gs, ss, |_| false);
+            let scripted = gs
+                .check_waiting(&human.id)
+                .then(|| scripted_move(gs, &mut script));
+            for (seat, op) in moves {
+                state
+                    .handle_action_op(seat, &op)
+                    .expect("the bot plays legal moves");
+                bot_ops.push(op);
+            }
+            if let Some(op) = scripted {
+                let played = state.handle_action_op(human.clone(), &op);
+                assert!(played.is_ok(), "scripted {op:?} refused: {played:?}");
+            }
+        }
+        panic!("the scripted game got stuck");
+    }
+
+    #[test]
+    #[cfg(feature = "admin")]
+    fn test_find_users() {
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let user = User {
+            name: "Alice".to_string(),
+            ..User::test("u1")
+        };
+        state
+            .handle_room_op(&Detached, user.clone(), RoomUserOperation::Create)
+            .unwrap();
+        state.mark_active("u1");
+
+        let [found] = &state.find_users("ali")[..] else {
+            panic!("the name should match in part");
+        };
+        assert_eq!(found.id, "u1");
+        assert!(found.sockets.is_empty());
+        assert_eq!(found.seats.len(), 1);
+        assert!(found.last_action.is_some());
+        assert_eq!(state.find_users("u1").len(), 1);
+        assert!(state.find_users("bob").is_empty());
+    }
+
+    #[test]
+    fn test_user_room_index() {
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let user = User::test("u1");
+        let created = state
+            .handle_room_op(&Detached, user.clone(), RoomUserOperation::Create)
+            .unwrap();
+        let room_id = created.last().unwrap().id.clone();
+        assert_eq!(state.user_rooms.get("u1"), Some(&room_id));
+        assert_eq!(state.find_user_room("u1"), Some(room_id.clone()));
+
+        // a stale entry is not trusted
+        state
+            .user_rooms
+            .insert("u1".to_string(), "gone".to_string());
+        assert_eq!(state.find_user_room("u1"), Some(room_id.clone()));
+
+        state
+            .handle_room_op(&Detached, user, RoomUserOperation::Leave(room_id))
+            .unwrap();
+        assert!(!state.user_rooms.contains_key("u1"));
+        assert_eq!(state.find_user_room("u1"), None);
+    }
+
+    #[test]
+    fn test_room_from_preset() {
+        use crate::map::MapType;
+        use crate::room::{RoomPreset, RoomRules};
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let host = User::test("u1");
+        let from_preset = |state: &mut State, name: &str| {
+            let op = RoomUserOperation::FromPreset(name.to_string());
+            state.handle_room_op(&Detached, host.clone(), op)
+        };
+        assert!(matches!(
+            from_preset(&mut state, "expert"),
+            Err(RoomError::PresetNotFound)
+        ));
+
+        let preset = RoomPreset {
+            name: "expert".to_string(),
+            map_type: MapType::Expert,
+            rules: RoomRules {
+                no_hints: true,
+                ..Default::default()
+            },
+            bot: Some(BotPersona::Stargazer),
+        };
+        let preferences = Preferences {
+            presets: vec![preset],
+            ..Default::default()
+        };
+        state.handle_preferences("u1", PreferencesOperation::Set(preferences));
+        let rooms = from_preset(&mut state, "expert").unwrap();
+        let [gs] = &rooms[..] else {
+            panic!("the new room should be sent once");
+        };
+        assert_eq!(gs.map_type, MapType::Expert);
+        assert!(gs.rules.no_hints);
+        let bot = gs.users.iter().find(|u| u.seat().is_bot()).unwrap();
+        assert_eq!(bot.persona, Some(BotPersona::Stargazer));
+    }
+
+    #[test]
+    fn test_competitive_room() {
+        use crate::map::MapType;
+        use crate::room::{EditRoomInfo, RoomRules};
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let host = User::test("u1");
+        let room_op = |state: &mut State, op| state.handle_room_op(&Detached, host.clone(), op);
+        room_op(&mut state, RoomUserOperation::Create).unwrap();
+        let room_id = state.find_user_room("u1").unwrap();
+        let edit = |no_hints| {
+            RoomUserOperation::Edit(EditRoomInfo {
+                room_id: room_id.clone(),
+                map_type: MapType::Standard,
+                map_seed: 42,
+                rules: Some(RoomRules {
+                    no_hints,
+                    ..Default::default()
+                }),
+            })
+        };
+        room_op(&mut state, edit(true)).unwrap();
+        room_op(&mut state, RoomUserOperation::Prepare(room_id.clone())).unwrap();
+        let (gs, ss) = state.get_state(&room_id).unwrap();
+        engine::start_game(
+            gs,
+            ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            std::time::Instant::now(),
+        );
+
+        // the hint policy is locked with the rest of the settings
+        assert!(matches!(
+            room_op(&mut state, edit(false)),
+            Err(RoomError::RoomStarted)
+        ));
+        assert!(state.get_game_state(&room_id).unwrap().rules.no_hints);
+        assert!(matches!(
+            state.handle_recommend_op(host.clone(), RecommendOperation::Count),
+            Err(RecommendError::HintsDisabled)
+        ));
+        assert!(
+            state
+                .handle_recommend_op(host.clone(), RecommendOperation::LegalActions)
+                .is_ok()
+        );
+
+        // the finished game and the stats of its player are stored as competitive
+        let (gs, _) = state.get_state(&room_id).unwrap();
+        gs.status = GameState::End;
+        gs.game_stage = GameStage::GameEnd;
+        state.autosave();
+        state.autosave();
+        let games: Vec<_> = state
+            .pending_writes
+            .iter()
+            .filter_map(|w| match w {
+                StorageWrite::Game(export) => Some(export),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(&games[..], [export] if export.competitive));
+        let stats: Vec<_> = state
+            .pending_writes
+            .iter()
+            .filter_map(|w| match w {
+                StorageWrite::Stats(id, quality) => Some((id, quality)),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(&stats[..], [(id, q)] if *id == "u1" && q.competitive_games == 1));
+    }
+
+    #[test]
+    fn test_room_bot_plays_to_the_end() {
+        use crate::room::{BotPersonaInfo, PuzzleInfo};
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let human = User::test("u1");
+        let room_op = |state: &mut State, op| {
+            state.handle_room_op(&Detached, human.clone(), op).unwrap();
+        };
+        room_op(&mut state, RoomUserOperation::Create);
+        let room_id = state.find_user_room("u1").unwrap();
+        let persona = BotPersonaInfo {
+            room_id: room_id.clone(),
+            persona: BotPersona::Navigator,
+        };
+        room_op(&mut state, RoomUserOperation::BotPersona(persona));
+        room_op(&mut state, RoomUserOperation::Prepare(room_id.clone()));
+
+        let (gs, ss) = state.get_state(&room_id).unwrap();
+        let bot = gs.users.iter().find(|u| u.seat().is_bot()).unwrap();
+        assert_eq!(bot.seat(), crate::ids::Seat::bot(&room_id));
+        assert_eq!(bot.name, "navigator");
+        assert_eq!(bot.appearance.avatar.as_deref(), Some("bot-navigator"));
+        let now = std::time::Instant::now();
+        engine::start_game(
+            gs,
+            ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            now,
+        );
+        assert_eq!(gs.status, GameState::AutoMove);
+
+        let script = [Scripted::SurveyWindow(SectorType::Space)];
+        assert!(!drive_room(&mut state, &room_id, &human, &script).is_empty());
+        let gs = state.get_game_state(&room_id).unwrap();
+        assert_eq!(gs.status, GameState::End);
+        let results = gs.game_result.as_ref().unwrap();
+        let flair = results.iter().find_map(|r| r.bot.as_ref()).unwrap();
+        assert_eq!(flair.persona, BotPersona::Navigator);
+
+        // the finished game as a practice room, at the second move of the player
+        let (_, ss) = state.get_state(&room_id).unwrap();
+        let before = ss
+            .journal
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.user_id == "u1")
+            .nth(1)
+            .unwrap()
+            .0;
+        let puzzle = |turn| {
+            RoomUserOperation::Puzzle(PuzzleInfo {
+                room_id: room_id.clone(),
+                seat_id: "u1".to_string(),
+                turn,
+            })
+        };
+        let too_far = state.handle_room_op(&Detached, human.clone(), puzzle(1000));
+        assert!(matches!(too_far, Err(RoomError::NoSuchTurn)));
+        room_op(&mut state, puzzle(1));
+        let puzzle_id = state.find_user_room("u1").unwrap();
+        assert_ne!(puzzle_id, room_id);
+        let (gs, ss) = state.get_state(&puzzle_id).unwrap();
+        assert!(gs.check_waiting("u1"));
+        assert_eq!(ss.journal.len(), before);
+        assert_eq!(gs.puzzle.as_ref().unwrap().room_id, room_id);
+        assert!(!gs.rules.is_competitive());
+    }
+
+    #[test]
+    #[cfg(feature = "integrations")]
+    fn test_resume_save_code() {
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig {
+            save_code_secret: Some("secret".to_string()),
+            ..ServerConfig::default()
+        });
+        let human = User::test("u1");
+        let room_op = |state: &mut State, op| state.handle_room_op(&Detached, human.clone(), op);
+        room_op(&mut state, RoomUserOperation::Create).unwrap();
+        let room_id = state.find_user_room("u1").unwrap();
+        room_op(&mut state, RoomUserOperation::Prepare(room_id.clone())).unwrap();
+        let (gs, ss) = state.get_state(&room_id).unwrap();
+        engine::start_game(
+            gs,
+            ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            std::time::Instant::now(),
+        );
+        // a few moves in, of the bot and the player
+        let mut script = [Scripted::SurveyWindow(SectorType::Space)].iter().cycle();
+        for _ in 0..MAX_SCRIPTED_STEPS {
+            let (gs, ss) = state.get_state(&room_id).unwrap();
+            if ss.journal.len() >= 4 {
+                break;
+            }
+            engine::advance(gs, ss);
+            let moves = engine::bot_moves(gs, ss, |_| false);
+            let scripted = gs
+                .check_waiting("u1")
+                .then(|| scripted_move(gs, &mut script));
+            for (seat, op) in moves {
+                state.handle_action_op(seat, &op).unwrap();
+            }
+            if let Some(op) = scripted {
+                state.handle_action_op(human.clone(), &op).unwrap();
+            }
+        }
+
+        let code = state.save_code(&room_id, "u1").unwrap();
+        // the server restarted without the room
+        let (gs, ss) = state.state_data.remove(&room_id).unwrap();
+        assert!(gs.is_playing());
+        room_op(&mut state, RoomUserOperation::Resume(code.clone())).unwrap();
+        let resumed_id = state.find_user_room("u1").unwrap();
+        let (resumed, resumed_ss) = state.get_state(&resumed_id).unwrap();
+        assert_eq!(resumed.map_seed, gs.map_seed);
+        assert_eq!(
+            (resumed.round, &resumed.game_stage),
+            (gs.round, &gs.game_stage)
+        );
+        assert_eq!(resumed.board_checksum, gs.board_checksum);
+        let moves = |ss: &ServerGameState| {
+            let moves = ss.journal.iter().map(|e| (e.user_id.clone(), e.op.clone()));
+            moves.collect::<Vec<_>>()
+        };
+        assert!(moves(&ss).len() >= 4);
+        assert_eq!(moves(resumed_ss), moves(&ss));
+
+        // a code is resumed once
+        state.state_data.remove(&resumed_id);
+        assert!(matches!(
+            room_op(&mut state, RoomUserOperation::Resume(code)),
+            Err(RoomError::SaveCodeUsed)
+        ));
+
+        state.state_data.insert(room_id.clone(), (gs, ss));
+        state.get_game_state(&room_id).unwrap().rules.no_hints = true;
+        assert!(matches!(
+            state.save_code(&room_id, "u1"),
+            Err(RoomError::CompetitiveRoom)
+        ));
+    }
+
+    /// the move of a human of the smoke test: `u1` knows the map, publishes one right theory and
+    /// locates x once it is verified, `u2` researches and surveys and guesses on its last move.
+    fn smoke_move(gs: &GameStateResp, ss: &ServerGameState, user: &UserState) -> Operation {
+        use crate::operation::{
+            DoPublishOperation, LocateOperation, ReadyPublishOperation, ResearchOperation,
+            SurveyOperatoin,
+        };
+
+        let oracle = user.id == "u1";
+        let tokens = &ss.user_tokens[&user.id];
+        let proposed = tokens.iter().any(|t| t.placed);
+        let verified = tokens.iter().any(|t| t.is_success_located_any());
+        let sectors = &ss.map.sectors;
+        let asteroid = sectors
+            .data
+            .iter()
+            .find(|s| {
+                s.r#type == SectorType::Asteroid && !ss.revealed_sector_indexs.contains(&s.index)
+            })
+            .unwrap()
+            .index;
+        let x = sectors
+            .data
+            .iter()
+            .find(|s| s.r#type == SectorType::X)
+            .unwrap()
+            .index;
+        let locate_x = Operation::Locate(LocateOperation {
+            index: x,
+            pre_sector_type: sectors.prev(x).r#type.clone(),
+            next_sector_type: sectors.next(x).r#type.clone(),
+        });
+        match gs.game_stage {
+            GameStage::MeetingProposal => Operation::ReadyPublish(ReadyPublishOperation {
+                sectors: match oracle && !proposed {
+                    true => vec![SectorType::Asteroid],
+                    false => vec![],
+                },
+            }),
+            GameStage::MeetingPublish => Operation::DoPublish(DoPublishOperation {
+                index: asteroid,
+                sector_type: SectorType::Asteroid,
+            }),
+            GameStage::LastMove if oracle => locate_x,
+            GameStage::LastMove => Operation::Locate(LocateOperation {
+                index: 1,
+                pre_sector_type: SectorType::Space,
+                next_sector_type: SectorType::Space,
+            }),
+            _ if oracle && verified => locate_x,
+            _ if !oracle && !user.research_blocked() => {
+                let clues = [ClueEnum::A, ClueEnum::B, ClueEnum::C, ClueEnum::D];
+                Operation::Research(ResearchOperation {
+                    index: clues[user.moves.len() % clues.len()].clone(),
+                })
+            }
+            _ => Operation::Survey(SurveyOperatoin {
+                sector_type: SectorType::Space,
+                start: gs.start_index,
+                end: gs.end_index,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_standard_game_smoke() {
+        use crate::sink::Detached;
+
+        let mut state = State::new(ServerConfig::default());
+        let [u1, u2] = ["u1", "u2"].map(User::test);
+        let room_op = |state: &mut State, user: &User, op| {
+            state.handle_room_op(&Detached, user.clone(), op).unwrap();
+        };
+        room_op(&mut state, &u1, RoomUserOperation::Create);
+        let room_id = state.find_user_room("u1").unwrap();
+        room_op(&mut state, &u2, RoomUserOperation::Join(room_id.clone()));
+        room_op(
+            &mut state,
+            &u1,
+            RoomUserOperation::SwitchBot(room_id.clone()),
+        );
+        for user in [&u1, &u2] {
+            room_op(
+                &mut state,
+                user,
+                RoomUserOperation::Prepare(room_id.clone()),
+            );
+        }
+        let (gs, ss) = state.get_state(&room_id).unwrap();
+        assert_eq!(gs.users.len(), 3);
+        let now = std::time::Instant::now();
+        engine::start_game(
+            gs,
+            ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            now,
+        );
+
+        let mut conferences = 0;
+        for _ in 0..MAX_SCRIPTED_STEPS {
+            let (gs, ss) = state.get_state(&room_id).unwrap();
+            if gs.status == GameState::End {
+                break;
+            }
+            let events = engine::advance(gs, ss);
+            conferences += events
+                .iter()
+                .filter(|e| matches!(e, EngineEvent::Conference(_)))
+                .count();
+            let mut moves = engine::bot_moves(gs, ss, |_| false);
+            for user in [&u1, &u2] {
+                let Some(seat) = gs.users.iter().find(|u| u.id == user.id) else {
+                    continue;
+                };
+                if gs.check_waiting(&user.id) {
+                    moves.push((user.clone(), smoke_move(gs, ss, seat)));
+                }
+            }
+            for (user, op) in moves {
+                let played = state.handle_action_op(user, &op);
+                assert!(played.is_ok(), "{op:?} refused: {played:?}");
+            }
+            conferences += state
+                .engine_events
+                .drain(..)
+                .filter(|(_, e)| matches!(e, EngineEvent::Conference(_)))
+                .count();
+        }
+
+        let (gs, ss) = state.get_state(&room_id).unwrap();
+        assert_eq!(gs.status, GameState::End, "the smoke game got stuck");
+        assert!(conferences > 0);
+        assert!(ss.located_order.iter().any(|id| id == "u1"));
+        let results = gs.game_result.as_ref().unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.windows(2).all(|w| w[0].sum >= w[1].sum));
+        assert_eq!(results[0].rank, 1);
+        assert!(results.windows(2).all(|w| w[0].rank <= w[1].rank));
+        let oracle = results.iter().find(|r| r.id == "u1").unwrap();
+        assert!(oracle.x > 0);
+        assert_eq!(oracle.asteroid, 1);
+    }
+
+    #[test]
+    fn test_bot_against_script() {
+        let script = [
+            Scripted::Research(ClueEnum::A),
+            Scripted::SurveyWindow(SectorType::Asteroid),
+            Scripted::Research(ClueEnum::B),
+            Scripted::SurveyWindow(SectorType::DwarfPlanet),
+            Scripted::SurveyWindow(SectorType::Space),
+        ];
+        let ops = play_scripted(42, &script);
+        assert!(!ops.is_empty());
+        assert_eq!(ops, play_scripted(42, &script), "the bot is deterministic");
+
+        // the recorded moves of the bot, `BLESS=1` records them again after a wanted change
+        let recorded = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/bot_scripted_42.json");
+        if std::env::var_os("BLESS").is_some() {
+            std::fs::create_dir_all(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata")).unwrap();
+            std::fs::write(recorded, serde_json::to_string_pretty(&ops).unwrap()).unwrap();
+            return;
+        }
+        let json = std::fs::read_to_string(recorded)
+            .unwrap_or_else(|e| panic!("{recorded}: {e}, record it with BLESS=1"));
+        let expected: Vec<Operation> = serde_json::from_str(&json).unwrap();
+        assert_eq!(ops, expected, "the bot changed its moves");
+    }
+
+    #[test]
+    fn test_batch_is_all_or_nothing() {
+        use crate::operation::ResearchOperation;
+
+        let mut state = State::new(ServerConfig::default());
+        let users = ["u1", "u2"].map(User::test);
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_seed = 42;
+        for user in users.iter() {
+            let mut user_state = UserState::placeholder(user, gs.users.len() + 1, false);
+            user_state.ready = true;
+            gs.users.push(user_state);
+        }
+        let mut ss = ServerGameState::placeholder();
+        engine::start_game(
+            &mut gs,
+            &mut ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            std::time::Instant::now(),
+        );
+        gs.status = GameState::Wait(vec!["u1".to_string()]);
+        state.state_data.insert("1".to_string(), (gs, ss));
+        let research = |index| Operation::Research(ResearchOperation { index });
+
+        let empty = state.handle_action_op(users[0].clone(), &Operation::Batch(vec![]));
+        assert!(matches!(empty, Err(OpError::InvalidBatch)));
+
+        // the second research is refused, none is played
+        let twice = Operation::Batch(vec![research(ClueEnum::A), research(ClueEnum::B)]);
+        let refused = state.handle_action_op(users[0].clone(), &twice);
+        assert!(matches!(refused, Err(OpError::ResearchContiuously)));
+        let (gs, ss) = state.get_state("1").unwrap();
+        assert!(ss.journal.is_empty());
+        assert!(gs.check_waiting("u1"));
+        assert!(!gs.users[0].research_locked);
+        let (window, size) = (gs.window(), ss.map.size());
+        assert!(state.engine_events.is_empty());
+
+        // alone, the research would move u1 past u2 and hand the turn on before the survey
+        let survey = Operation::Survey(SurveyOperatoin {
+            sector_type: SectorType::Asteroid,
+            start: window.start,
+            end: window.start % size + 1,
+        });
+        let moves = Operation::Batch(vec![research(ClueEnum::A), survey]);
+        let played = state.handle_action_op(users[0].clone(), &moves).unwrap();
+        assert!(matches!(&played, OperationResult::Batch(results) if results.len() == 2));
+        let (gs, ss) = state.get_state("1").unwrap();
+        assert_eq!(ss.journal.len(), 2);
+        assert!(gs.check_waiting("u2"));
+        // the limits the clients read off the seat
+        assert!(!gs.users[0].research_locked);
+        assert_eq!(gs.users[0].targets_remaining, crate::room::MAX_TARGETS);
+    }
+
+    #[test]
+    fn test_pass() {
+        let mut state = State::new(ServerConfig::default());
+        let users = ["u1", "u2"].map(User::test);
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_seed = 42;
+        for user in users.iter() {
+            let mut user_state = UserState::placeholder(user, gs.users.len() + 1, false);
+            user_state.ready = true;
+            gs.users.push(user_state);
+        }
+        let mut ss = ServerGameState::placeholder();
+        engine::start_game(
+            &mut gs,
+            &mut ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            std::time::Instant::now(),
+        );
+        gs.status = GameState::Wait(vec!["u1".to_string()]);
+        let step = gs.users[0].location.step();
+        state.state_data.insert("1".to_string(), (gs, ss));
+
+        let passed = state.handle_action_op(users[0].clone(), &Operation::Pass);
+        assert!(matches!(passed, Ok(OperationResult::Pass(p)) if p.steps == PASS_STEPS));
+        let (gs, ss) = state.get_state("1").unwrap();
+        assert_eq!(gs.users[0].location.step(), step + PASS_STEPS);
+        assert!(gs.users[0].moves.is_empty());
+        assert_eq!(ss.journal.len(), 1);
+
+        // the last move is given up
+        gs.game_stage = GameStage::LastMove;
+        gs.status = GameState::Wait(vec!["u2".to_string()]);
+        let passed = state.handle_action_op(users[1].clone(), &Operation::Pass);
+        assert!(matches!(passed, Ok(OperationResult::Pass(p)) if p.last_move && p.steps == 0));
+        let u2 = &state.get_state("1").unwrap().0.users[1];
+        assert!(!u2.last_move && !u2.can_locate);
+    }
+
+    #[test]
+    fn test_last_locate_out_of_range() {
+        use crate::{operation::LocateOperation, room::UserLocationSequence};
+
+        let mut state = State::new(ServerConfig::default());
+        let users = ["u1", "u2"].map(User::test);
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_seed = 42;
+        for user in users.iter() {
+            let mut user_state = UserState::placeholder(user, gs.users.len() + 1, false);
+            user_state.ready = true;
+            gs.users.push(user_state);
+        }
+        let mut ss = ServerGameState::placeholder();
+        engine::start_game(
+            &mut gs,
+            &mut ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            std::time::Instant::now(),
+        );
+        gs.game_stage = GameStage::LastMove;
+        gs.status = GameState::Wait(vec!["u1".to_string()]);
+        ss.terminator_location = Some(UserLocationSequence::placeholder(10, 1));
+        state.state_data.insert("1".to_string(), (gs, ss));
+
+        let locate = Operation::Locate(LocateOperation {
+            index: 99,
+            pre_sector_type: SectorType::Space,
+            next_sector_type: SectorType::Space,
+        });
+        let refused = state.handle_action_op(users[0].clone(), &locate);
+        assert!(matches!(refused, Err(OpError::InvalidIndex)));
+        let u1 = &state.get_state("1").unwrap().0.users[0];
+        assert!(u1.can_locate && u1.last_move);
+    }
+
+    #[test]
+    fn test_game_finished() {
+        let mut state = State::new(ServerConfig::default());
+        let user = User::test("u1");
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.users.push(UserState::placeholder(&user, 1, false));
+        gs.status = GameState::End;
+        gs.game_stage = GameStage::GameEnd;
+        state
+            .state_data
+            .insert("1".to_string(), (gs, ServerGameState::placeholder()));
+
+        let late = state.play(&user, &Operation::Pass);
+        assert!(matches!(
+            late,
+            Err(ServerResp::OpErrors(OpError::GameFinished))
+        ));
+        assert!(state.final_state(&user.id).is_some());
+    }
+
+    #[test]
+    fn test_meeting_proposals() {
+        use crate::operation::ReadyPublishOperation;
+
+        let mut state = State::new(ServerConfig::default());
+        let users = ["u1", "u2"].map(User::test);
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.map_seed = 42;
+        for user in users.iter() {
+            let mut user_state = UserState::placeholder(user, gs.users.len() + 1, false);
+            user_state.ready = true;
+            gs.users.push(user_state);
+        }
+        let mut ss = ServerGameState::placeholder();
+        let now = std::time::Instant::now();
+        engine::start_game(
+            &mut gs,
+            &mut ss,
+            std::time::Duration::ZERO,
+            &crate::map::ClueWeights::default(),
+            now,
+        );
+        gs.game_stage = GameStage::MeetingProposal;
+        gs.status = GameState::Wait(vec!["u1".to_string(), "u2".to_string()]);
+        let track_order = engine::user_ids_in_track_order(&gs);
+        state.state_data.insert("1".to_string(), (gs, ss));
+        let propose = |sectors| Operation::ReadyPublish(ReadyPublishOperation { sectors });
+
+        // a refused proposal keeps the player waited for
+        let x = propose(vec![SectorType::X]);
+        assert!(state.handle_action_op(users[1].clone(), &x).is_err());
+        assert!(state.get_game_state("1").unwrap().check_waiting("u2"));
+
+        // the last proposal settles the meeting at once, the journal follows the track
+        for user in users.iter().rev() {
+            assert!(
+                state
+                    .handle_action_op(user.clone(), &propose(vec![]))
+                    .is_ok()
+            );
+        }
+        let (gs, ss) = state.get_state("1").unwrap();
+        assert!(matches!(gs.status, GameState::Wait(_)));
+        let journaled = ss
+            .journal
+            .iter()
+            .map(|e| e.user_id.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(journaled, track_order);
+        assert!(
+            state
+                .engine_events
+                .iter()
+                .any(|(_, e)| matches!(e, EngineEvent::Tokens(_)))
+        );
+    }
 }
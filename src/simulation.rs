@@ -0,0 +1,210 @@
+//! bot only games played through the same checks as the clients, to rate a change to the bot
+//! heuristics before merging it: `cargo test --release -- --ignored bot_quality`.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{
+    config::ServerConfig,
+    engine,
+    ids::Seat,
+    map::MapType,
+    room::{GameState, GameStateResp, ServerGameState, UserState},
+    server_state::State,
+};
+
+/// steps a simulated game may take before it counts as stuck, a standard game takes ~100.
+const MAX_GAME_STEPS: usize = 2000;
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    /// games to play, each on its own seed.
+    pub games: usize,
+    /// bots seated in every game.
+    pub players: usize,
+    pub map_type: MapType,
+    /// seed of the first game, the next games count up from it.
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            games: 100,
+            players: 4,
+            map_type: MapType::Standard,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SimulationReport {
+    pub games: usize,
+    /// games that reached their end, the rates and averages are over these.
+    pub finished: usize,
+    /// share of the finished games won by each seat, by join order.
+    pub win_rates: Vec<f64>,
+    /// average final score of each seat, by join order.
+    pub avg_scores: Vec<f64>,
+    /// bot operations the server refused.
+    pub illegal_moves: usize,
+    /// games that stopped moving, or ran out of steps, before their end.
+    pub stuck_games: usize,
+    /// seeds of the stuck games, to replay them.
+    pub stuck_seeds: Vec<u64>,
+}
+
+enum Outcome {
+    /// the score of each seat by join order, and the seats ranked first.
+    Finished(Vec<usize>, Vec<usize>),
+    Stuck,
+}
+
+/// play `config.games` bot only games one after another and sum them up.
+pub fn simulate_games(config: &SimulationConfig) -> SimulationReport {
+    let mut report = SimulationReport {
+        games: config.games,
+        win_rates: vec![0.0; config.players],
+        avg_scores: vec![0.0; config.players],
+        ..Default::default()
+    };
+    for game in 0..config.games {
+        let seed = config.seed.wrapping_add(game as u64);
+        let (outcome, illegal_moves) = play_game(config, seed);
+        report.illegal_moves += illegal_moves;
+        match outcome {
+            Outcome::Finished(scores, winners) => {
+                report.finished += 1;
+                // a shared first place splits the win
+                for winner in winners.iter() {
+                    report.win_rates[*winner] += 1.0 / winners.len() as f64;
+                }
+                for (avg, score) in report.avg_scores.iter_mut().zip(scores) {
+                    *avg += score as f64;
+                }
+            }
+            Outcome::Stuck => {
+                report.stuck_games += 1;
+                report.stuck_seeds.push(seed);
+            }
+        }
+    }
+    if report.finished > 0 {
+        let finished = report.finished as f64;
+        report.win_rates.iter_mut().for_each(|r| *r /= finished);
+        report.avg_scores.iter_mut().for_each(|s| *s /= finished);
+    }
+    report
+}
+
+/// one game of bots, the outcome and the operations the server refused.
+fn play_game(config: &SimulationConfig, seed: u64) -> (Outcome, usize) {
+    let seats = (1..=config.players)
+        .map(|i| Seat::bot(&i.to_string()).user(&format!("bot-{i}")))
+        .collect::<Vec<_>>();
+    let mut gs = GameStateResp::new(format!("sim-{seed}"));
+    gs.map_type = config.map_type.clone();
+    gs.map_seed = seed;
+    for (i, seat) in seats.iter().enumerate() {
+        gs.users.push(UserState::placeholder(seat, i + 1, true));
+    }
+    let mut ss = ServerGameState::placeholder();
+    engine::start_game(
+        &mut gs,
+        &mut ss,
+        Duration::ZERO,
+        &crate::map::ClueWeights::default(),
+        Instant::now(),
+    );
+    let room_id = gs.id.clone();
+    let mut state = State::new(ServerConfig::default());
+    state.state_data.insert(room_id.clone(), (gs, ss));
+
+    let mut illegal_moves = 0;
+    for _ in 0..MAX_GAME_STEPS {
+        let Some((gs, ss)) = state.get_state(&room_id) else {
+            break;
+        };
+        if gs.status == GameState::End {
+            let results = gs.game_result.clone().unwrap_or_default();
+            let scores = seats
+                .iter()
+                .map(|seat| {
+                    results
+                        .iter()
+                        .find(|r| r.id == seat.id)
+                        .map_or(0, |r| r.sum)
+                })
+                .collect();
+            let winners = results
+                .iter()
+                .filter(|r| r.rank == 1)
+                .filter_map(|r| seats.iter().position(|seat| seat.id == r.id))
+                .collect();
+            return (Outcome::Finished(scores, winners), illegal_moves);
+        }
+        let before = gs.status.clone();
+        engine::advance(gs, ss);
+        let moves = engine::bot_moves(gs, ss, |_| false);
+        if moves.is_empty() && gs.status == before {
+            // nothing plays and nothing moves on, the next steps would be the same
+            break;
+        }
+        let mut played = moves.is_empty();
+        for (seat, op) in moves {
+            match state.handle_action_op(seat, &op) {
+                Ok(_) => played = true,
+                Err(e) => {
+                    tracing::warn!("simulation seed {seed}: refused {op:?}: {e:?}");
+                    illegal_moves += 1;
+                }
+            }
+        }
+        if !played {
+            // the bots would pick the same refused moves again
+            break;
+        }
+    }
+    (Outcome::Stuck, illegal_moves)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_simulate_games() {
+        let config = SimulationConfig {
+            games: 2,
+            players: 2,
+            ..Default::default()
+        };
+        let report = simulate_games(&config);
+        assert_eq!(report.games, 2);
+        assert_eq!(report.finished + report.stuck_games, 2);
+        assert_eq!(report.win_rates.len(), 2);
+        if report.finished > 0 {
+            assert!((report.win_rates.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+    }
+
+    /// the gate before merging a heuristic change, run with `--release -- --ignored bot_quality`.
+    #[test]
+    #[ignore]
+    fn bot_quality() {
+        let report = simulate_games(&SimulationConfig::default());
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        assert_eq!(report.illegal_moves, 0);
+        assert_eq!(
+            report.stuck_games, 0,
+            "stuck seeds: {:?}",
+            report.stuck_seeds
+        );
+        // the seats play the same bot, no seat should be far ahead of a fair share
+        let fair = 1.0 / report.win_rates.len() as f64;
+        assert!(report.win_rates.iter().all(|r| (r - fair).abs() < 0.2));
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use socketioxide::{SocketIo, extract::SocketRef};
+use tokio::sync::broadcast;
+
+use crate::{operation::OperationResult, server_state::User};
+
+/// what a client understands beyond the original events, a bitmap sent with `auth`. the events
+/// gated by a flag only reach the connections that advertised it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const TURN_EVENTS: Self = Self(1); // your_turn, turn_deadline, turn_timeout
+    pub const CONFERENCE: Self = Self(1 << 1); // conference_result, penalty, pacing_summary
+    pub const CHAT: Self = Self(1 << 2); // chat, reaction
+    pub const ANNOUNCEMENTS: Self = Self(1 << 3); // announcement, maintenance
+    pub const LOCATE_MISS: Self = Self(1 << 4); // else a missed locate answers `locate: false`
+    pub const SYNC_BUNDLE: Self = Self(1 << 5); // else `sync` answers the events one by one
+    const GATED: [Self; 4] = [
+        Self::TURN_EVENTS,
+        Self::CONFERENCE,
+        Self::CHAT,
+        Self::ANNOUNCEMENTS,
+    ];
+
+    pub fn contains(self, flag: Self) -> bool {
+        self.0 & flag.0 == flag.0
+    }
+
+    /// the flag an event needs, None for the events every client knows.
+    pub fn required_for(event: &str) -> Option<Self> {
+        match event {
+            "your_turn" | "turn_deadline" | "turn_timeout" => Some(Self::TURN_EVENTS),
+            "conference_result" | "penalty" | "pacing_summary" => Some(Self::CONFERENCE),
+            "chat" | "reaction" => Some(Self::CHAT),
+            "announcement" | "maintenance" => Some(Self::ANNOUNCEMENTS),
+            _ => None,
+        }
+    }
+
+    pub fn accepts(self, event: &str) -> bool {
+        Self::required_for(event).is_none_or(|flag| self.contains(flag))
+    }
+
+    /// the result of an operation as the client reads it.
+    pub fn op_result(self, result: OperationResult) -> OperationResult {
+        match result {
+            OperationResult::LocateMiss(_) if !self.contains(Self::LOCATE_MISS) => {
+                OperationResult::Locate(false)
+            }
+            OperationResult::Batch(results) => {
+                OperationResult::Batch(results.into_iter().map(|r| self.op_result(r)).collect())
+            }
+            result => result,
+        }
+    }
+
+    /// the socket.io room of the sockets lacking the flag, the broadcasts it gates skip it.
+    fn lacking_room(self) -> String {
+        format!("lacks-capability-{}", self.0)
+    }
+
+    /// the rooms a socket is in for the flags it lacks.
+    pub fn lacking_rooms(self) -> Vec<String> {
+        Self::GATED
+            .into_iter()
+            .filter(|flag| !self.contains(*flag))
+            .map(Self::lacking_room)
+            .collect()
+    }
+}
+
+/// where the server sends its events: a broadcast to a room or a unicast to every connection of a user.
+pub trait EventSink {
+    fn to_room<T: ?Sized + Serialize + Sync>(
+        &self,
+        room_id: &str,
+        event: &'static str,
+        data: &T,
+    ) -> impl Future<Output = ()> + Send;
+
+    /// return the count of connections reached.
+    fn to_user<T: ?Sized + Serialize>(&self, user_id: &str, event: &'static str, data: &T)
+    -> usize;
+}
+
+/// the socket.io sink of a namespace, relayed to the plain transports as well.
+pub struct SocketSink<'a> {
+    io: &'a SocketIo,
+    namespace: &'a str,
+    users: &'a HashMap<String, (SocketRef, User)>, // socket_id -> user
+    user_sockets: &'a HashMap<String, Vec<SocketRef>>, // user_id -> sockets
+    relay: &'a Relay,
+}
+
+impl<'a> SocketSink<'a> {
+    pub fn new(
+        io: &'a SocketIo,
+        namespace: &'a str,
+        users: &'a HashMap<String, (SocketRef, User)>,
+        user_sockets: &'a HashMap<String, Vec<SocketRef>>,
+        relay: &'a Relay,
+    ) -> Self {
+        Self {
+            io,
+            namespace,
+            users,
+            user_sockets,
+            relay,
+        }
+    }
+
+    fn accepts(&self, socket: &SocketRef, event: &str) -> bool {
+        let capabilities = self
+            .users
+            .get(socket.id.as_str())
+            .map(|(_, u)| u.capabilities);
+        capabilities.unwrap_or_default().accepts(event)
+    }
+}
+
+impl SocketSink<'_> {
+    /// every connection of the namespace, in a room or not.
+    pub async fn to_all<T: ?Sized + Serialize + Sync>(&self, event: &'static str, data: &T) {
+        if let Some(mut ns) = self.io.of(self.namespace) {
+            if let Some(flag) = Capabilities::required_for(event) {
+                ns = ns.except(flag.lacking_room());
+            }
+            ns.emit(event, data).await.ok();
+        }
+        self.relay.to_all(event, data);
+    }
+}
+
+impl EventSink for SocketSink<'_> {
+    async fn to_room<T: ?Sized + Serialize + Sync>(
+        &self,
+        room_id: &str,
+        event: &'static str,
+        data: &T,
+    ) {
+        if let Some(ns) = self.io.of(self.namespace) {
+            let mut room = ns.to(room_id.to_owned());
+            if let Some(flag) = Capabilities::required_for(event) {
+                room = room.except(flag.lacking_room());
+            }
+            room.emit(event, data).await.ok();
+        }
+        self.relay.to_room(room_id, event, data);
+    }
+
+    fn to_user<T: ?Sized + Serialize>(
+        &self,
+        user_id: &str,
+        event: &'static str,
+        data: &T,
+    ) -> usize {
+        self.user_sockets
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter(|s| self.accepts(s, event) && s.emit(event, data).is_ok())
+            .count()
+            + self.relay.to_user(user_id, event, data)
+    }
+}
+
+/// the rooms a connection receives the broadcasts of.
+pub trait Membership {
+    fn enter(&self, room_id: String);
+    fn exit(&self, room_id: String);
+    fn exit_all(&self);
+}
+
+impl Membership for SocketRef {
+    fn enter(&self, room_id: String) {
+        self.join(room_id);
+    }
+
+    fn exit(&self, room_id: String) {
+        self.leave(room_id);
+    }
+
+    fn exit_all(&self) {
+        self.leave_all();
+    }
+}
+
+/// a request without a connection, e.g. the http control plane, it receives no broadcast.
+#[cfg(any(feature = "integrations", test))]
+pub struct Detached;
+
+#[cfg(any(feature = "integrations", test))]
+impl Membership for Detached {
+    fn enter(&self, _room_id: String) {}
+
+    fn exit(&self, _room_id: String) {}
+
+    fn exit_all(&self) {}
+}
+
+/// the rooms of a plain websocket connection.
+#[derive(Debug, Default)]
+pub struct Rooms(std::sync::Mutex<HashSet<String>>);
+
+impl Rooms {
+    pub fn contains(&self, room_id: &str) -> bool {
+        self.0.lock().unwrap().contains(room_id)
+    }
+}
+
+impl Membership for Rooms {
+    fn enter(&self, room_id: String) {
+        self.0.lock().unwrap().insert(room_id);
+    }
+
+    fn exit(&self, room_id: String) {
+        self.0.lock().unwrap().remove(&room_id);
+    }
+
+    fn exit_all(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+/// the wire frame of the plain transports: a socket.io event name and its payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Frame {
+    pub event: String,
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+impl Frame {
+    pub fn new<T: ?Sized + Serialize>(event: &str, data: &T) -> Self {
+        Frame {
+            event: event.to_owned(),
+            data: serde_json::to_value(data).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Room(String),
+    User(String),
+    All, // every connection
+}
+
+/// fans the events out to the websocket and sse connections, which pick their own.
+#[derive(Debug)]
+pub struct Relay {
+    tx: broadcast::Sender<(Target, Frame)>,
+    users: HashMap<String, usize>, // user_id -> connections
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Relay {
+            tx: broadcast::channel(256).0,
+            users: HashMap::new(),
+        }
+    }
+}
+
+impl Relay {
+    pub fn subscribe(&self) -> broadcast::Receiver<(Target, Frame)> {
+        self.tx.subscribe()
+    }
+
+    pub fn connect(&mut self, user_id: &str) {
+        *self.users.entry(user_id.to_owned()).or_default() += 1;
+    }
+
+    /// return true if it was the last connection of the user.
+    pub fn disconnect(&mut self, user_id: &str) -> bool {
+        let Some(count) = self.users.get_mut(user_id) else {
+            return true;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        self.users.remove(user_id);
+        true
+    }
+
+    pub fn is_connected(&self, user_id: &str) -> bool {
+        self.users.contains_key(user_id)
+    }
+
+    pub fn to_room<T: ?Sized + Serialize>(&self, room_id: &str, event: &str, data: &T) {
+        // nobody listens, skip the serialization
+        if self.tx.receiver_count() > 0 {
+            let target = Target::Room(room_id.to_owned());
+            self.tx.send((target, Frame::new(event, data))).ok();
+        }
+    }
+
+    pub fn to_all<T: ?Sized + Serialize>(&self, event: &str, data: &T) {
+        if self.tx.receiver_count() > 0 {
+            self.tx.send((Target::All, Frame::new(event, data))).ok();
+        }
+    }
+
+    pub fn to_user<T: ?Sized + Serialize>(&self, user_id: &str, event: &str, data: &T) -> usize {
+        let Some(count) = self.users.get(user_id) else {
+            return 0;
+        };
+        let target = Target::User(user_id.to_owned());
+        self.tx.send((target, Frame::new(event, data))).ok();
+        *count
+    }
+}
+
+/// keeps every event in order, for the tests of the code sending them.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingSink {
+    pub sent: std::sync::Mutex<Vec<Sent>>,
+    pub offline: Vec<String>, // user ids with no connection
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sent {
+    Room(String, &'static str, serde_json::Value),
+    User(String, &'static str, serde_json::Value),
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    /// the event names, in the order they were sent.
+    pub fn events(&self) -> Vec<&'static str> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| match s {
+                Sent::Room(_, event, _) | Sent::User(_, event, _) => *event,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl EventSink for RecordingSink {
+    async fn to_room<T: ?Sized + Serialize + Sync>(
+        &self,
+        room_id: &str,
+        event: &'static str,
+        data: &T,
+    ) {
+        let data = serde_json::to_value(data).unwrap();
+        let sent = Sent::Room(room_id.to_owned(), event, data);
+        self.sent.lock().unwrap().push(sent);
+    }
+
+    fn to_user<T: ?Sized + Serialize>(
+        &self,
+        user_id: &str,
+        event: &'static str,
+        data: &T,
+    ) -> usize {
+        if self.offline.iter().any(|id| id == user_id) {
+            return 0;
+        }
+        let data = serde_json::to_value(data).unwrap();
+        let sent = Sent::User(user_id.to_owned(), event, data);
+        self.sent.lock().unwrap().push(sent);
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_relay() {
+        let mut relay = Relay::default();
+        let mut rx = relay.subscribe();
+        assert_eq!(relay.to_user("u1", "token", "t"), 0);
+        relay.connect("u1");
+        relay.connect("u1");
+        relay.to_room("1234", "xclue", &vec![1]);
+        assert_eq!(relay.to_user("u1", "token", "t"), 2);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            (
+                Target::Room("1234".to_string()),
+                Frame::new("xclue", &vec![1])
+            )
+        );
+        assert_eq!(rx.try_recv().unwrap().0, Target::User("u1".to_string()));
+        assert!(!relay.disconnect("u1"));
+        assert!(relay.disconnect("u1"));
+        assert!(!relay.is_connected("u1"));
+
+        let frame: Frame = serde_json::from_str(r#"{"event":"sync"}"#).unwrap();
+        assert_eq!(frame.data, serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let user: User =
+            serde_json::from_str(r#"{"id":"u1","name":"u1","capabilities":6}"#).unwrap();
+        let capabilities = user.capabilities;
+        assert!(capabilities.accepts("game_state"));
+        assert!(capabilities.accepts("penalty") && capabilities.accepts("chat"));
+        assert!(!capabilities.accepts("your_turn") && !capabilities.accepts("announcement"));
+        assert_eq!(capabilities.lacking_rooms().len(), 2);
+        assert!(Capabilities(0b11111).lacking_rooms().is_empty());
+
+        let miss = OperationResult::LocateMiss(crate::operation::LocateMiss {
+            adjacent_wrong: None,
+            extra_time: 2,
+        });
+        assert_eq!(
+            capabilities.op_result(miss.clone()),
+            OperationResult::Locate(false)
+        );
+        assert_eq!(Capabilities::LOCATE_MISS.op_result(miss.clone()), miss);
+    }
+
+    #[tokio::test]
+    async fn test_recording_sink() {
+        let sink = RecordingSink {
+            offline: vec!["u2".to_string()],
+            ..Default::default()
+        };
+        sink.to_room("1234", "xclue", &vec![1, 2]).await;
+        assert_eq!(sink.to_user("u1", "token", "t"), 1);
+        assert_eq!(sink.to_user("u2", "token", "t"), 0);
+        assert_eq!(sink.events(), vec!["xclue", "token"]);
+        assert_eq!(
+            sink.sent.lock().unwrap()[0],
+            Sent::Room("1234".to_string(), "xclue", serde_json::json!([1, 2]))
+        );
+    }
+}
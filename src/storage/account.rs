@@ -0,0 +1,107 @@
+use serde::{Deserialize, Serialize};
+
+/// a registered player, an identity verified by a login integration (e.g. `email` and the
+/// address, or an oauth provider and its subject). the account plays as its `user_id`, the
+/// first guest id it claimed, so the device it registered from keeps its history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Account {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: String,
+    pub guest_ids: Vec<String>, // claimed, oldest first
+}
+
+#[cfg(feature = "integrations")]
+#[derive(Debug)]
+pub enum ClaimError {
+    /// the guest id belongs to another account.
+    Taken,
+    Storage(anyhow::Error),
+}
+
+#[cfg(feature = "integrations")]
+impl From<anyhow::Error> for ClaimError {
+    fn from(e: anyhow::Error) -> Self {
+        ClaimError::Storage(e)
+    }
+}
+
+#[cfg(all(
+    feature = "integrations",
+    any(feature = "sqlite", feature = "postgres")
+))]
+impl From<sqlx::Error> for ClaimError {
+    fn from(e: sqlx::Error) -> Self {
+        ClaimError::Storage(e.into())
+    }
+}
+
+/// what a claim of a guest id does, decided before anything is written.
+#[cfg(feature = "integrations")]
+#[derive(Debug, PartialEq)]
+pub(super) enum Claim {
+    /// the account already holds the guest id.
+    Claimed(Account),
+    /// attach the guest id to the account, new on its first claim.
+    Attach(Account),
+}
+
+/// decide the claim of a guest id by an identity from its account and the current owner of the
+/// guest id. the backends call it inside the transaction that writes the claim.
+#[cfg(feature = "integrations")]
+pub(super) fn plan_claim(
+    account: Option<Account>,
+    owner: Option<String>,
+    provider: String,
+    subject: String,
+    guest_id: &str,
+) -> Result<Claim, ClaimError> {
+    match (account, owner) {
+        (Some(account), Some(owner)) if owner == account.user_id => Ok(Claim::Claimed(account)),
+        (_, Some(_)) => Err(ClaimError::Taken),
+        (account, None) => Ok(Claim::Attach(account.unwrap_or_else(|| Account {
+            provider,
+            subject,
+            user_id: guest_id.to_string(),
+            guest_ids: vec![],
+        }))),
+    }
+}
+
+#[cfg(all(test, feature = "integrations"))]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        recommendation::PlayQuality,
+        storage::{MemoryStorage, Storage},
+    };
+
+    #[tokio::test]
+    async fn test_claim_guest() {
+        let storage = MemoryStorage::default();
+        let claim = |subject: &str, guest_id: &str| {
+            storage.claim_guest("email".into(), subject.into(), guest_id.into())
+        };
+        let mut quality = PlayQuality::default();
+        quality.record(1.0);
+        storage
+            .save_stats("g1".into(), quality.clone())
+            .await
+            .unwrap();
+        storage.save_stats("g2".into(), quality).await.unwrap();
+
+        let account = claim("a@b.c", "g1").await.unwrap();
+        assert_eq!(account.user_id, "g1");
+        let account = claim("a@b.c", "g2").await.unwrap();
+        assert_eq!(account.guest_ids, vec!["g1".to_string(), "g2".to_string()]);
+        // claiming again changes nothing
+        assert_eq!(claim("a@b.c", "g2").await.unwrap(), account);
+        assert!(matches!(claim("x@y.z", "g2").await, Err(ClaimError::Taken)));
+
+        let stats = storage.load_stats().await.unwrap();
+        assert_eq!(stats["g1"].samples, 2);
+        assert!(!stats.contains_key("g2"));
+    }
+}
@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+
+use crate::{
+    config::DEFAULT_NAMESPACE,
+    recommendation::PlayQuality,
+    room::{GameExport, Preferences, StoredRoom},
+};
+
+#[cfg(any(feature = "admin", feature = "integrations"))]
+use super::Account;
+use super::Storage;
+#[cfg(feature = "integrations")]
+use super::{Claim, ClaimError, plan_claim};
+#[cfg(feature = "admin")]
+use super::{UserData, UserDeletion};
+
+/// keeps everything in the process, for the tests and the servers without a database.
+#[derive(Debug, Clone)]
+pub struct MemoryStorage {
+    namespace: String,
+    tables: Arc<Tables>, // shared by the handles of every namespace
+}
+
+type Key = (String, String); // namespace, then the room or user id
+
+#[derive(Debug, Default)]
+struct Tables {
+    snapshots: Mutex<HashMap<Key, Vec<StoredRoom>>>, // room_id -> snapshots, oldest first
+    games: Mutex<HashMap<Key, GameExport>>,          // room_id -> last finished game
+    stats: Mutex<HashMap<Key, PlayQuality>>,         // user_id -> decision quality
+    preferences: Mutex<HashMap<Key, Preferences>>,   // user_id -> settings
+    #[cfg(any(feature = "admin", feature = "integrations"))]
+    accounts: Mutex<Vec<Account>>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        MemoryStorage {
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            tables: Arc::default(),
+        }
+    }
+}
+
+impl MemoryStorage {
+    fn key(&self, id: String) -> Key {
+        (self.namespace.clone(), id)
+    }
+
+    /// the values of the namespace by id.
+    fn of_namespace<T: Clone>(&self, table: &Mutex<HashMap<Key, T>>) -> HashMap<String, T> {
+        table
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((namespace, _), _)| *namespace == self.namespace)
+            .map(|((_, id), value)| (id.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn scoped(&self, namespace: &str) -> Arc<dyn Storage> {
+        Arc::new(MemoryStorage {
+            namespace: namespace.to_string(),
+            tables: self.tables.clone(),
+        })
+    }
+
+    fn save_snapshot(
+        &self,
+        room_id: String,
+        room: StoredRoom,
+        keep: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let mut snapshots = self.tables.snapshots.lock().unwrap();
+        let ring = snapshots.entry(self.key(room_id)).or_default();
+        let id = room.info.id;
+        ring.retain(|s| s.info.id < id && s.info.id + keep as u64 > id);
+        ring.push(room);
+        async { Ok(()) }.boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn load_snapshots(&self, room_id: String) -> BoxFuture<'_, anyhow::Result<Vec<StoredRoom>>> {
+        let snapshots = self.tables.snapshots.lock().unwrap();
+        let ring = snapshots.get(&self.key(room_id)).cloned();
+        async { Ok(ring.unwrap_or_default()) }.boxed()
+    }
+
+    fn load_latest_snapshots(
+        &self,
+        since: u64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<(String, StoredRoom)>>> {
+        let latest = self
+            .of_namespace(&self.tables.snapshots)
+            .into_iter()
+            .filter_map(|(room_id, ring)| Some((room_id, ring.last()?.clone())))
+            .filter(|(_, room)| room.info.taken_at >= since)
+            .collect::<Vec<_>>();
+        async { Ok(latest) }.boxed()
+    }
+
+    fn save_game(&self, game: GameExport) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.tables
+            .games
+            .lock()
+            .unwrap()
+            .insert(self.key(game.room_id.clone()), game);
+        async { Ok(()) }.boxed()
+    }
+
+    fn load_game(&self, room_id: String) -> BoxFuture<'_, anyhow::Result<Option<GameExport>>> {
+        let game = self
+            .tables
+            .games
+            .lock()
+            .unwrap()
+            .get(&self.key(room_id))
+            .cloned();
+        async { Ok(game) }.boxed()
+    }
+
+    fn save_stats(
+        &self,
+        user_id: String,
+        quality: PlayQuality,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.tables
+            .stats
+            .lock()
+            .unwrap()
+            .insert(self.key(user_id), quality);
+        async { Ok(()) }.boxed()
+    }
+
+    fn load_stats(&self) -> BoxFuture<'_, anyhow::Result<HashMap<String, PlayQuality>>> {
+        let stats = self.of_namespace(&self.tables.stats);
+        async { Ok(stats) }.boxed()
+    }
+
+    fn save_preferences(
+        &self,
+        user_id: String,
+        preferences: Preferences,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.tables
+            .preferences
+            .lock()
+            .unwrap()
+            .insert(self.key(user_id), preferences);
+        async { Ok(()) }.boxed()
+    }
+
+    fn load_preferences(&self) -> BoxFuture<'_, anyhow::Result<HashMap<String, Preferences>>> {
+        let preferences = self.of_namespace(&self.tables.preferences);
+        async { Ok(preferences) }.boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn load_user_data(&self, user_id: String) -> BoxFuture<'_, anyhow::Result<UserData>> {
+        let games = self
+            .of_namespace(&self.tables.games)
+            .into_values()
+            .filter(|g| g.players.iter().any(|p| p.id == user_id))
+            .collect();
+        let accounts = self.tables.accounts.lock().unwrap();
+        let account = accounts.iter().find(|a| a.user_id == user_id).cloned();
+        let key = self.key(user_id.clone());
+        let data = UserData {
+            account,
+            stats: self.tables.stats.lock().unwrap().get(&key).cloned(),
+            preferences: self.tables.preferences.lock().unwrap().get(&key).cloned(),
+            games,
+            user_id,
+        };
+        async { Ok(data) }.boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn delete_user_data(&self, user_id: String) -> BoxFuture<'_, anyhow::Result<UserDeletion>> {
+        let mut accounts = self.tables.accounts.lock().unwrap();
+        let before = accounts.len();
+        accounts.retain(|a| a.user_id != user_id);
+        let mut deletion = UserDeletion {
+            account: accounts.len() < before,
+            ..Default::default()
+        };
+        let of_user = |(_, id): &Key| *id == user_id;
+        self.tables.stats.lock().unwrap().retain(|key, _| {
+            deletion.stats |= of_user(key);
+            !of_user(key)
+        });
+        self.tables.preferences.lock().unwrap().retain(|key, _| {
+            deletion.preferences |= of_user(key);
+            !of_user(key)
+        });
+        for ring in self.tables.snapshots.lock().unwrap().values_mut() {
+            let before = ring.len();
+            ring.retain(|s| !s.has_user(&user_id));
+            deletion.snapshots += before - ring.len();
+        }
+        for game in self.tables.games.lock().unwrap().values_mut() {
+            if game.anonymize(&user_id) {
+                deletion.games += 1;
+            }
+        }
+        async { Ok(deletion) }.boxed()
+    }
+
+    #[cfg(feature = "integrations")]
+    fn load_account(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> BoxFuture<'_, anyhow::Result<Option<Account>>> {
+        let accounts = self.tables.accounts.lock().unwrap();
+        let account = accounts
+            .iter()
+            .find(|a| a.provider == provider && a.subject == subject)
+            .cloned();
+        async { Ok(account) }.boxed()
+    }
+
+    #[cfg(feature = "integrations")]
+    fn claim_guest(
+        &self,
+        provider: String,
+        subject: String,
+        guest_id: String,
+    ) -> BoxFuture<'_, Result<Account, ClaimError>> {
+        // the accounts lock is held throughout, so the claim is atomic
+        let mut accounts = self.tables.accounts.lock().unwrap();
+        let account = accounts
+            .iter()
+            .find(|a| a.provider == provider && a.subject == subject)
+            .cloned();
+        let owner = accounts
+            .iter()
+            .find(|a| a.guest_ids.contains(&guest_id))
+            .map(|a| a.user_id.clone());
+        let result = plan_claim(account, owner, provider, subject, &guest_id).map(|claim| {
+            let mut account = match claim {
+                Claim::Claimed(account) => return account,
+                Claim::Attach(account) => account,
+            };
+            if guest_id != account.user_id {
+                self.merge_user(&guest_id, &account.user_id);
+            }
+            accounts.retain(|a| a.provider != account.provider || a.subject != account.subject);
+            account.guest_ids.push(guest_id);
+            accounts.push(account.clone());
+            account
+        });
+        async { result }.boxed()
+    }
+}
+
+#[cfg(feature = "integrations")]
+impl MemoryStorage {
+    /// fold the stats and the finished games of a user id into another in every namespace,
+    /// return the count of games moved. the preferences of `from` are kept only if `into` has
+    /// none.
+    fn merge_user(&self, from: &str, into: &str) -> usize {
+        let mut stats = self.tables.stats.lock().unwrap();
+        let moved = stats
+            .keys()
+            .filter(|(_, id)| id == from)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in moved {
+            let quality = stats.remove(&key).unwrap_or_default();
+            stats
+                .entry((key.0, into.to_string()))
+                .or_default()
+                .merge(&quality);
+        }
+        let mut preferences = self.tables.preferences.lock().unwrap();
+        let moved = preferences
+            .keys()
+            .filter(|(_, id)| id == from)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in moved {
+            let kept = preferences.remove(&key).unwrap_or_default();
+            preferences.entry((key.0, into.to_string())).or_insert(kept);
+        }
+        let mut games = 0;
+        for game in self.tables.games.lock().unwrap().values_mut() {
+            if game.rename_player(from, into) {
+                games += 1;
+            }
+        }
+        games
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::room::{GameStateResp, ServerGameState, SnapshotRing};
+
+    #[tokio::test]
+    async fn test_memory_storage() {
+        let storage = MemoryStorage::default();
+        let mut ring = SnapshotRing::default();
+        let mut gs = GameStateResp::empty();
+        let ss = ServerGameState::placeholder();
+        for round in 1..=3 {
+            gs.round = round;
+            let room = ring.autosave(&gs, &ss, 2).unwrap();
+            storage.save_snapshot("r1".into(), room, 2).await.unwrap();
+        }
+        #[cfg(feature = "admin")]
+        {
+            let stored = storage.load_snapshots("r1".into()).await.unwrap();
+            let rounds = stored.iter().map(|s| s.info.round).collect::<Vec<_>>();
+            assert_eq!(rounds, vec![2, 3]);
+        }
+
+        let mut quality = PlayQuality::default();
+        quality.record(1.0);
+        storage.save_stats("u1".into(), quality).await.unwrap();
+        assert_eq!(storage.load_stats().await.unwrap()["u1"].best_moves, 1);
+        assert!(storage.load_game("r1".into()).await.unwrap().is_none());
+        let preferences = Preferences {
+            hints_opt_out: true,
+            ..Default::default()
+        };
+        storage
+            .save_preferences("u1".into(), preferences.clone())
+            .await
+            .unwrap();
+        assert_eq!(storage.load_preferences().await.unwrap()["u1"], preferences);
+
+        #[cfg(feature = "admin")]
+        {
+            let deletion = storage.delete_user_data("u1".into()).await.unwrap();
+            assert_eq!(
+                deletion,
+                UserDeletion {
+                    stats: true,
+                    preferences: true,
+                    ..Default::default()
+                }
+            );
+            let data = storage.load_user_data("u1".into()).await.unwrap();
+            assert!(data.stats.is_none() && data.preferences.is_none() && data.games.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_namespaces() {
+        let storage = MemoryStorage::default();
+        let beta = storage.scoped("/beta");
+        let mut quality = PlayQuality::default();
+        quality.record(1.0);
+        storage
+            .save_stats("g1".into(), quality.clone())
+            .await
+            .unwrap();
+        beta.save_stats("g1".into(), quality).await.unwrap();
+        let gs = GameStateResp::empty();
+        let ss = ServerGameState::placeholder();
+        let room = SnapshotRing::default().autosave(&gs, &ss, 1).unwrap();
+        beta.save_snapshot("r1".into(), room, 1).await.unwrap();
+        assert!(storage.load_latest_snapshots(0).await.unwrap().is_empty());
+        assert_eq!(beta.load_latest_snapshots(0).await.unwrap().len(), 1);
+
+        // an account is the same player in every namespace
+        #[cfg(all(feature = "admin", feature = "integrations"))]
+        {
+            for guest_id in ["a1", "g1"] {
+                storage
+                    .claim_guest("email".into(), "a@b.c".into(), guest_id.into())
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(storage.load_stats().await.unwrap()["a1"].samples, 1);
+            assert_eq!(beta.load_stats().await.unwrap()["a1"].samples, 1);
+            let deletion = storage.delete_user_data("a1".into()).await.unwrap();
+            assert!(deletion.stats);
+            assert!(beta.load_stats().await.unwrap().is_empty());
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+#[cfg(any(feature = "admin", feature = "integrations"))]
+use sqlx::{
+    Transaction,
+    sqlite::{Sqlite, SqliteConnection},
+};
+
+use crate::{
+    config::DEFAULT_NAMESPACE,
+    recommendation::PlayQuality,
+    room::{GameExport, Preferences, StoredRoom, now_secs},
+};
+
+#[cfg(any(feature = "admin", feature = "integrations"))]
+use super::{Account, json_needle};
+#[cfg(feature = "integrations")]
+use super::{Claim, ClaimError, plan_claim};
+use super::{MIGRATOR, STATE_VERSION, Storage, decode};
+#[cfg(feature = "admin")]
+use super::{UserData, UserDeletion};
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    namespace: String,
+}
+
+impl SqliteStorage {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        MIGRATOR.run(&pool).await?;
+        Ok(SqliteStorage {
+            pool,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn scoped(&self, namespace: &str) -> Arc<dyn Storage> {
+        Arc::new(SqliteStorage {
+            pool: self.pool.clone(),
+            namespace: namespace.to_string(),
+        })
+    }
+
+    fn save_snapshot(
+        &self,
+        room_id: String,
+        room: StoredRoom,
+        keep: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let id = room.info.id as i64;
+            let data = serde_json::to_string(&room)?;
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(
+                "DELETE FROM snapshots WHERE namespace = ?1 AND room_id = ?2 \
+                 AND (id >= ?3 OR id <= ?4)",
+            )
+            .bind(&self.namespace)
+            .bind(&room_id)
+            .bind(id)
+            .bind(id - keep as i64)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "INSERT INTO snapshots (namespace, room_id, id, taken_at, version, data) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .bind(&self.namespace)
+            .bind(&room_id)
+            .bind(id)
+            .bind(room.info.taken_at as i64)
+            .bind(STATE_VERSION)
+            .bind(data)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn load_snapshots(&self, room_id: String) -> BoxFuture<'_, anyhow::Result<Vec<StoredRoom>>> {
+        async move {
+            let rows: Vec<(i64, String)> = sqlx::query_as(
+                "SELECT version, data FROM snapshots WHERE namespace = ?1 AND room_id = ?2 \
+                 ORDER BY id",
+            )
+            .bind(&self.namespace)
+            .bind(room_id)
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .iter()
+                .filter_map(|(version, data)| decode(*version, data))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn load_latest_snapshots(
+        &self,
+        since: u64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<(String, StoredRoom)>>> {
+        async move {
+            let rows: Vec<(String, i64, String)> = sqlx::query_as(
+                "SELECT room_id, version, data FROM snapshots s \
+                 WHERE namespace = ?1 AND taken_at >= ?2 AND id = (SELECT MAX(id) FROM snapshots \
+                 WHERE namespace = s.namespace AND room_id = s.room_id)",
+            )
+            .bind(&self.namespace)
+            .bind(since as i64)
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .iter()
+                .filter_map(|(room_id, version, data)| {
+                    Some((room_id.clone(), decode(*version, data)?))
+                })
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn save_game(&self, game: GameExport) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let data = serde_json::to_string(&game)?;
+            sqlx::query(
+                "INSERT INTO games (namespace, room_id, finished_at, version, data) \
+                 VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (namespace, room_id, finished_at) \
+                 DO UPDATE SET version = excluded.version, data = excluded.data",
+            )
+            .bind(&self.namespace)
+            .bind(&game.room_id)
+            .bind(now_secs() as i64)
+            .bind(STATE_VERSION)
+            .bind(data)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_game(&self, room_id: String) -> BoxFuture<'_, anyhow::Result<Option<GameExport>>> {
+        async move {
+            let row: Option<(i64, String)> = sqlx::query_as(
+                "SELECT version, data FROM games WHERE namespace = ?1 AND room_id = ?2 \
+                 ORDER BY finished_at DESC LIMIT 1",
+            )
+            .bind(&self.namespace)
+            .bind(room_id)
+            .fetch_optional(&self.pool)
+            .await?;
+            Ok(row.and_then(|(version, data)| decode(version, &data)))
+        }
+        .boxed()
+    }
+
+    fn save_stats(
+        &self,
+        user_id: String,
+        quality: PlayQuality,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let data = serde_json::to_string(&quality)?;
+            sqlx::query(
+                "INSERT INTO stats (namespace, user_id, version, data) VALUES (?1, ?2, ?3, ?4) \
+                 ON CONFLICT (namespace, user_id) \
+                 DO UPDATE SET version = excluded.version, data = excluded.data",
+            )
+            .bind(&self.namespace)
+            .bind(user_id)
+            .bind(STATE_VERSION)
+            .bind(data)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_stats(&self) -> BoxFuture<'_, anyhow::Result<HashMap<String, PlayQuality>>> {
+        async move {
+            let rows: Vec<(String, i64, String)> =
+                sqlx::query_as("SELECT user_id, version, data FROM stats WHERE namespace = ?1")
+                    .bind(&self.namespace)
+                    .fetch_all(&self.pool)
+                    .await?;
+            Ok(rows
+                .into_iter()
+                .filter_map(|(user_id, version, data)| Some((user_id, decode(version, &data)?)))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn save_preferences(
+        &self,
+        user_id: String,
+        preferences: Preferences,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let data = serde_json::to_string(&preferences)?;
+            sqlx::query(
+                "INSERT INTO preferences (namespace, user_id, version, data) VALUES (?1, ?2, ?3, ?4) \
+                 ON CONFLICT (namespace, user_id) \
+                 DO UPDATE SET version = excluded.version, data = excluded.data",
+            )
+            .bind(&self.namespace)
+            .bind(user_id)
+            .bind(STATE_VERSION)
+            .bind(data)
+            .execute(&self.pool)
+            .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_preferences(&self) -> BoxFuture<'_, anyhow::Result<HashMap<String, Preferences>>> {
+        async move {
+            let rows: Vec<(String, i64, String)> = sqlx::query_as(
+                "SELECT user_id, version, data FROM preferences WHERE namespace = ?1",
+            )
+            .bind(&self.namespace)
+            .fetch_all(&self.pool)
+            .await?;
+            Ok(rows
+                .into_iter()
+                .filter_map(|(user_id, version, data)| Some((user_id, decode(version, &data)?)))
+                .collect())
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn load_user_data(&self, user_id: String) -> BoxFuture<'_, anyhow::Result<UserData>> {
+        async move {
+            let stats: Option<(i64, String)> = sqlx::query_as(
+                "SELECT version, data FROM stats WHERE namespace = ?1 AND user_id = ?2",
+            )
+            .bind(&self.namespace)
+            .bind(&user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+            let preferences: Option<(i64, String)> = sqlx::query_as(
+                "SELECT version, data FROM preferences WHERE namespace = ?1 AND user_id = ?2",
+            )
+            .bind(&self.namespace)
+            .bind(&user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+            let rows: Vec<(i64, String)> = sqlx::query_as(
+                "SELECT version, data FROM games WHERE namespace = ?1 AND instr(data, ?2) > 0 \
+                 ORDER BY finished_at",
+            )
+            .bind(&self.namespace)
+            .bind(json_needle(&user_id)?)
+            .fetch_all(&self.pool)
+            .await?;
+            let games = rows
+                .iter()
+                .filter_map(|(version, data)| decode::<GameExport>(*version, data))
+                .filter(|g| g.players.iter().any(|p| p.id == user_id))
+                .collect();
+            let account: Option<(String, String)> =
+                sqlx::query_as("SELECT provider, subject FROM accounts WHERE user_id = ?1")
+                    .bind(&user_id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+            let account = match account {
+                Some((provider, subject)) => {
+                    let mut conn = self.pool.acquire().await?;
+                    load_account(&mut conn, provider, subject).await?
+                }
+                None => None,
+            };
+            Ok(UserData {
+                account,
+                stats: stats.and_then(|(version, data)| decode(version, &data)),
+                preferences: preferences.and_then(|(version, data)| decode(version, &data)),
+                games,
+                user_id,
+            })
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "admin")]
+    fn delete_user_data(&self, user_id: String) -> BoxFuture<'_, anyhow::Result<UserDeletion>> {
+        async move {
+            let needle = json_needle(&user_id)?;
+            let mut tx = self.pool.begin().await?;
+            let stats = sqlx::query("DELETE FROM stats WHERE user_id = ?1")
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+            let preferences = sqlx::query("DELETE FROM preferences WHERE user_id = ?1")
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+            // snapshots are restore points of the running games, they go as a whole
+            let snapshots = sqlx::query("DELETE FROM snapshots WHERE instr(data, ?1) > 0")
+                .bind(&needle)
+                .execute(&mut *tx)
+                .await?;
+            let games = rewrite_games(&mut tx, &user_id, |g| g.anonymize(&user_id)).await?;
+            let account = sqlx::query("DELETE FROM accounts WHERE user_id = ?1")
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM account_claims WHERE user_id = ?1")
+                .bind(&user_id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(UserDeletion {
+                account: account.rows_affected() > 0,
+                stats: stats.rows_affected() > 0,
+                preferences: preferences.rows_affected() > 0,
+                snapshots: snapshots.rows_affected() as usize,
+                games,
+            })
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "integrations")]
+    fn load_account(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> BoxFuture<'_, anyhow::Result<Option<Account>>> {
+        async move {
+            let mut conn = self.pool.acquire().await?;
+            load_account(&mut conn, provider, subject).await
+        }
+        .boxed()
+    }
+
+    #[cfg(feature = "integrations")]
+    fn claim_guest(
+        &self,
+        provider: String,
+        subject: String,
+        guest_id: String,
+    ) -> BoxFuture<'_, Result<Account, ClaimError>> {
+        async move {
+            let mut tx = self.pool.begin().await?;
+            let account = load_account(&mut tx, provider.clone(), subject.clone()).await?;
+            let owner =
+                sqlx::query_scalar("SELECT user_id FROM account_claims WHERE guest_id = ?1")
+                    .bind(&guest_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            let mut account = match plan_claim(account, owner, provider, subject, &guest_id)? {
+                Claim::Claimed(account) => return Ok(account),
+                Claim::Attach(account) => account,
+            };
+            if guest_id != account.user_id {
+                merge_user(&mut tx, &guest_id, &account.user_id).await?;
+            }
+            let now = now_secs() as i64;
+            let created = sqlx::query(
+                "INSERT INTO accounts (provider, subject, user_id, created_at) \
+                 VALUES (?1, ?2, ?3, ?4) ON CONFLICT (provider, subject) DO NOTHING",
+            )
+            .bind(&account.provider)
+            .bind(&account.subject)
+            .bind(&account.user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            // a new account registered by another request meanwhile owns another user id
+            if account.guest_ids.is_empty() && created.rows_affected() == 0 {
+                return Err(anyhow::anyhow!("account created concurrently").into());
+            }
+            let claimed = sqlx::query(
+                "INSERT INTO account_claims (guest_id, user_id, claimed_at) \
+                 VALUES (?1, ?2, ?3) ON CONFLICT (guest_id) DO NOTHING",
+            )
+            .bind(&guest_id)
+            .bind(&account.user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            // claimed by another request meanwhile, dropping the transaction undoes the merge
+            if claimed.rows_affected() == 0 {
+                return Err(ClaimError::Taken);
+            }
+            tx.commit().await?;
+            account.guest_ids.push(guest_id);
+            Ok(account)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(any(feature = "admin", feature = "integrations"))]
+async fn load_account(
+    conn: &mut SqliteConnection,
+    provider: String,
+    subject: String,
+) -> anyhow::Result<Option<Account>> {
+    let user_id: Option<String> =
+        sqlx::query_scalar("SELECT user_id FROM accounts WHERE provider = ?1 AND subject = ?2")
+            .bind(&provider)
+            .bind(&subject)
+            .fetch_optional(&mut *conn)
+            .await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+    let guest_ids = sqlx::query_scalar(
+        "SELECT guest_id FROM account_claims WHERE user_id = ?1 ORDER BY claimed_at, guest_id",
+    )
+    .bind(&user_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(Some(Account {
+        provider,
+        subject,
+        user_id,
+        guest_ids,
+    }))
+}
+
+/// fold the stats and the finished games of a user id into another in every namespace, return
+/// the count of games moved. the preferences of `from` are kept only if `into` has none.
+#[cfg(feature = "integrations")]
+async fn merge_user(
+    tx: &mut Transaction<'_, Sqlite>,
+    from: &str,
+    into: &str,
+) -> anyhow::Result<usize> {
+    let rows: Vec<(String, i64, String)> =
+        sqlx::query_as("SELECT namespace, version, data FROM stats WHERE user_id IN (?1, ?2)")
+            .bind(from)
+            .bind(into)
+            .fetch_all(&mut **tx)
+            .await?;
+    let mut merged = HashMap::<String, PlayQuality>::new();
+    for (namespace, version, data) in rows {
+        if let Some(quality) = decode::<PlayQuality>(version, &data) {
+            merged.entry(namespace).or_default().merge(&quality);
+        }
+    }
+    for (namespace, merged) in merged {
+        sqlx::query(
+            "INSERT INTO stats (namespace, user_id, version, data) VALUES (?1, ?2, ?3, ?4) \
+             ON CONFLICT (namespace, user_id) \
+             DO UPDATE SET version = excluded.version, data = excluded.data",
+        )
+        .bind(namespace)
+        .bind(into)
+        .bind(STATE_VERSION)
+        .bind(serde_json::to_string(&merged)?)
+        .execute(&mut **tx)
+        .await?;
+    }
+    sqlx::query("DELETE FROM stats WHERE user_id = ?1")
+        .bind(from)
+        .execute(&mut **tx)
+        .await?;
+    // the account's own preferences win over the guest's
+    sqlx::query(
+        "INSERT INTO preferences (namespace, user_id, version, data) \
+         SELECT namespace, ?1, version, data FROM preferences WHERE user_id = ?2 \
+         ON CONFLICT (namespace, user_id) DO NOTHING",
+    )
+    .bind(into)
+    .bind(from)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query("DELETE FROM preferences WHERE user_id = ?1")
+        .bind(from)
+        .execute(&mut **tx)
+        .await?;
+    rewrite_games(tx, from, |g| g.rename_player(from, into)).await
+}
+
+/// rewrite the finished games mentioning the user in every namespace, return how many `change`
+/// changed.
+#[cfg(any(feature = "admin", feature = "integrations"))]
+async fn rewrite_games(
+    tx: &mut Transaction<'_, Sqlite>,
+    user_id: &str,
+    change: impl Fn(&mut GameExport) -> bool,
+) -> anyhow::Result<usize> {
+    let rows: Vec<(String, String, i64, i64, String)> = sqlx::query_as(
+        "SELECT namespace, room_id, finished_at, version, data FROM games \
+         WHERE instr(data, ?1) > 0",
+    )
+    .bind(json_needle(user_id)?)
+    .fetch_all(&mut **tx)
+    .await?;
+    let mut changed = 0;
+    for (namespace, room_id, finished_at, version, data) in rows {
+        let Some(mut game) = decode::<GameExport>(version, &data) else {
+            continue;
+        };
+        if !change(&mut game) {
+            continue;
+        }
+        sqlx::query(
+            "UPDATE games SET version = ?1, data = ?2 \
+             WHERE namespace = ?3 AND room_id = ?4 AND finished_at = ?5",
+        )
+        .bind(STATE_VERSION)
+        .bind(serde_json::to_string(&game)?)
+        .bind(namespace)
+        .bind(room_id)
+        .bind(finished_at)
+        .execute(&mut **tx)
+        .await?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_storage() {
+        let path = std::env::temp_dir().join(format!("planetx-{}.db", uuid::Uuid::new_v4()));
+        let url = format!("sqlite://{}", path.display());
+        let storage = SqliteStorage::connect(&url).await.unwrap();
+        let mut quality = PlayQuality::default();
+        quality.record(0.5);
+        storage
+            .save_stats("u1".into(), quality.clone())
+            .await
+            .unwrap();
+        quality.record(1.0);
+        storage.save_stats("u1".into(), quality).await.unwrap();
+        // a row of a newer server is skipped, not an error at startup
+        sqlx::query(
+            "INSERT INTO stats (namespace, user_id, version, data) VALUES (?1, 'u2', ?2, '{}')",
+        )
+        .bind(&storage.namespace)
+        .bind(STATE_VERSION + 1)
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+        let stats = storage.load_stats().await.unwrap();
+        assert_eq!(stats["u1"].samples, 2);
+        assert!(!stats.contains_key("u2"));
+        // another namespace reads its own rows
+        let beta = storage.scoped("/beta");
+        assert!(beta.load_stats().await.unwrap().is_empty());
+        assert!(storage.load_game("r1".into()).await.unwrap().is_none());
+
+        #[cfg(feature = "admin")]
+        {
+            let snapshots = storage.load_snapshots("r1".into()).await.unwrap();
+            assert!(snapshots.is_empty());
+            let deletion = storage.delete_user_data("u1".into()).await.unwrap();
+            assert!(deletion.stats);
+            let data = storage.load_user_data("u1".into()).await.unwrap();
+            assert!(data.stats.is_none());
+        }
+
+        #[cfg(feature = "integrations")]
+        check_claims(&storage).await;
+        std::fs::remove_file(path).ok();
+    }
+
+    #[cfg(feature = "integrations")]
+    async fn check_claims(storage: &SqliteStorage) {
+        let claim =
+            |guest_id: &str| storage.claim_guest("email".into(), "a@b.c".into(), guest_id.into());
+        claim("g1").await.unwrap();
+        let account = claim("g2").await.unwrap();
+        assert_eq!(account.user_id, "g1");
+        let account = storage.load_account("email".into(), "a@b.c".into());
+        assert_eq!(account.await.unwrap().unwrap().guest_ids, vec!["g1", "g2"]);
+
+        // another request claims g3 between the ownership check and the insert
+        let mut quality = PlayQuality::default();
+        quality.record(1.0);
+        storage.save_stats("g3".into(), quality).await.unwrap();
+        sqlx::query(
+            "CREATE TRIGGER race BEFORE INSERT ON account_claims WHEN NEW.guest_id = 'g3' \
+             BEGIN INSERT INTO account_claims VALUES ('g3', 'x1', 0); END",
+        )
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+        assert!(matches!(claim("g3").await, Err(ClaimError::Taken)));
+        // the merge is rolled back with the claim
+        let stats = storage.load_stats().await.unwrap();
+        assert_eq!(stats["g3"].samples, 1);
+        assert!(!stats.contains_key("g1"));
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{
+    analytics::connection_kind,
+    map::Clue,
+    operation::{Operation, OperationKind},
+    room::{GameStage, GameState, GameStateResp, OpError},
+};
+
+/// distinct (error, operation, client version) counters kept, later ones count as `other`.
+const MAX_REJECTION_KEYS: usize = 1000;
+/// client versions are cut to this length.
+const MAX_VERSION_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RejectionKey {
+    error: String,
+    operation: OperationKind,
+    client_version: String,
+}
+
+/// the player operations the server refused, by error, operation and client build.
+#[derive(Debug, Clone, Default)]
+pub struct RejectionMetrics {
+    counts: HashMap<RejectionKey, u64>,
+    total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RejectionReport {
+    pub total: u64,
+    pub counts: Vec<RejectionCount>, // most frequent first
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct RejectionCount {
+    pub error: String,
+    pub operation: OperationKind,
+    pub client_version: String,
+    pub count: u64,
+}
+
+impl RejectionMetrics {
+    pub fn record(&mut self, op: &Operation, error: &OpError, client_version: Option<&str>) {
+        self.total += 1;
+        let error = serde_json::to_value(error)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_else(|| format!("{error:?}"));
+        let mut key = RejectionKey {
+            error,
+            operation: op.kind(),
+            client_version: client_version
+                .unwrap_or("unknown")
+                .chars()
+                .take(MAX_VERSION_LEN)
+                .collect(),
+        };
+        if !self.counts.contains_key(&key) && self.counts.len() >= MAX_REJECTION_KEYS {
+            key.client_version = "other".to_string();
+        }
+        *self.counts.entry(key).or_default() += 1;
+    }
+
+    pub fn report(&self) -> RejectionReport {
+        let mut counts = self
+            .counts
+            .iter()
+            .map(|(key, count)| RejectionCount {
+                error: key.error.clone(),
+                operation: key.operation,
+                client_version: key.client_version.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then(a.error.cmp(&b.error)));
+        RejectionReport {
+            total: self.total,
+            counts,
+        }
+    }
+}
+
+/// the inbound payloads that didn't deserialize, by event.
+#[derive(Debug, Clone, Default)]
+pub struct BadRequestMetrics {
+    counts: HashMap<String, u64>,
+    total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct BadRequestReport {
+    pub total: u64,
+    pub events: Vec<(String, u64)>, // most frequent first
+}
+
+impl BadRequestMetrics {
+    pub fn record(&mut self, event: &str) {
+        self.total += 1;
+        *self.counts.entry(event.to_string()).or_default() += 1;
+    }
+
+    pub fn report(&self) -> BadRequestReport {
+        let mut events = self
+            .counts
+            .iter()
+            .map(|(event, count)| (event.clone(), *count))
+            .collect::<Vec<_>>();
+        events.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        BadRequestReport {
+            total: self.total,
+            events,
+        }
+    }
+}
+
+/// the clues the players research, by letter and connection, no player kept. what a research
+/// leaves of the maps a player's filter still had shows which connections tell the most.
+#[derive(Debug, Clone, Default)]
+pub struct ClueMetrics {
+    counts: HashMap<(String, &'static str), ClueCounter>,
+    total: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClueCounter {
+    researches: u64,
+    measured: u64, // researches with a count of the maps before and after
+    kept_sum: f64, // sum of the share of the maps each measured research kept
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ClueUsageReport {
+    pub total: u64,
+    pub clues: Vec<ClueUsage>, // by letter, then connection
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ClueUsage {
+    pub clue: String,
+    pub connection: String,
+    pub researches: u64,
+    pub measured: u64,
+    pub avg_kept: Option<f64>, // 1 for a research that narrowed nothing
+}
+
+impl ClueMetrics {
+    /// `before` and `after` are the maps the filter of the player had, None before its first pass.
+    pub fn record(&mut self, clue: &Clue, before: Option<usize>, after: Option<usize>) {
+        self.total += 1;
+        let key = (format!("{:?}", clue.index), connection_kind(&clue.conn));
+        let counter = self.counts.entry(key).or_default();
+        counter.researches += 1;
+        if let (Some(before @ 1..), Some(after)) = (before, after) {
+            counter.measured += 1;
+            counter.kept_sum += after as f64 / before as f64;
+        }
+    }
+
+    pub fn report(&self) -> ClueUsageReport {
+        let mut clues = self
+            .counts
+            .iter()
+            .map(|((clue, connection), counter)| ClueUsage {
+                clue: clue.clone(),
+                connection: connection.to_string(),
+                researches: counter.researches,
+                measured: counter.measured,
+                avg_kept: (counter.measured > 0)
+                    .then(|| counter.kept_sum / counter.measured as f64),
+            })
+            .collect::<Vec<_>>();
+        clues.sort_by(|a, b| a.clue.cmp(&b.clue).then(a.connection.cmp(&b.connection)));
+        ClueUsageReport {
+            total: self.total,
+            clues,
+        }
+    }
+}
+
+/// latest durations kept per series, the oldest are dropped.
+const MAX_SAMPLES: usize = 4096;
+
+/// how long turns, stages and games take, to pick the turn timer defaults and spot slow stages.
+#[derive(Debug, Default)]
+pub struct PacingMetrics {
+    turns: Samples, // from the start of a wait to the operation of a human
+    stages: HashMap<GameStage, Samples>,
+    games: Samples,                     // from the start to the end of a game
+    clocks: HashMap<String, RoomClock>, // room_id -> the running stage
+}
+
+#[derive(Debug)]
+struct RoomClock {
+    stage: GameStage,
+    stage_since: Instant,
+    started: Instant,
+    ended: bool,
+}
+
+#[derive(Debug, Default)]
+struct Samples(VecDeque<u64>); // millis
+
+impl Samples {
+    fn push(&mut self, duration: Duration) {
+        if self.0.len() >= MAX_SAMPLES {
+            self.0.pop_front();
+        }
+        self.0.push_back(duration.as_millis() as u64);
+    }
+
+    fn percentiles(&self) -> Percentiles {
+        let mut sorted = self.0.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let at = |p: usize| {
+            let index = (sorted.len() * p).div_ceil(100).saturating_sub(1);
+            sorted.get(index).copied().unwrap_or(0)
+        };
+        Percentiles {
+            count: sorted.len(),
+            p50_ms: at(50),
+            p90_ms: at(90),
+            p99_ms: at(99),
+            max_ms: sorted.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub struct PacingReport {
+    pub turns: Percentiles,
+    pub stages: Vec<(GameStage, Percentiles)>, // slowest p90 first
+    pub games: Percentiles,
+}
+
+impl PacingMetrics {
+    pub fn record_turn(&mut self, duration: Duration) {
+        self.turns.push(duration);
+    }
+
+    /// look at a room after it may have changed, a stage is timed from one look to the next.
+    pub fn observe(&mut self, gs: &GameStateResp, now: Instant) {
+        if matches!(gs.status, GameState::NotStarted | GameState::Starting) {
+            self.clocks.remove(&gs.id);
+            return;
+        }
+        let clock = self
+            .clocks
+            .entry(gs.id.clone())
+            .or_insert_with(|| RoomClock {
+                stage: gs.game_stage.clone(),
+                stage_since: now,
+                started: now,
+                ended: false,
+            });
+        if clock.stage != gs.game_stage {
+            let stage = std::mem::replace(&mut clock.stage, gs.game_stage.clone());
+            self.stages
+                .entry(stage)
+                .or_default()
+                .push(now - clock.stage_since);
+            clock.stage_since = now;
+        }
+        if gs.status == GameState::End && !clock.ended {
+            clock.ended = true;
+            self.games.push(now - clock.started);
+        }
+    }
+
+    /// forget the clocks of the rooms that are gone.
+    pub fn retain_rooms(&mut self, alive: impl Fn(&str) -> bool) {
+        self.clocks.retain(|id, _| alive(id));
+    }
+
+    pub fn report(&self) -> PacingReport {
+        let mut stages = self
+            .stages
+            .iter()
+            .map(|(stage, samples)| (stage.clone(), samples.percentiles()))
+            .collect::<Vec<_>>();
+        stages.sort_by_key(|(_, p)| Reverse(p.p90_ms));
+        PacingReport {
+            turns: self.turns.percentiles(),
+            stages,
+            games: self.games.percentiles(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    use crate::{
+        map::{ClueConnection, ClueEnum, SectorType},
+        operation::TargetOperation,
+    };
+
+    #[test]
+    fn test_rejection_metrics() {
+        let mut metrics = RejectionMetrics::default();
+        let op = Operation::Target(TargetOperation { index: 40 });
+        metrics.record(&op, &OpError::InvalidIndex, Some("1.2.0"));
+        metrics.record(&op, &OpError::InvalidIndex, Some("1.2.0"));
+        metrics.record(&op, &OpError::NotUsersTurn, None);
+
+        let report = metrics.report();
+        assert_eq!(report.total, 3);
+        assert_eq!(
+            report.counts[0],
+            RejectionCount {
+                error: "invalid_index".to_string(),
+                operation: OperationKind::Target,
+                client_version: "1.2.0".to_string(),
+                count: 2,
+            }
+        );
+        assert_eq!(report.counts[1].client_version, "unknown");
+
+        let mut bad = BadRequestMetrics::default();
+        bad.record("room");
+        bad.record("op");
+        bad.record("op");
+        let report = bad.report();
+        assert_eq!(report.total, 3);
+        assert_eq!(report.events[0], ("op".to_string(), 2));
+    }
+
+    #[test]
+    fn test_clue_metrics() {
+        let clue = |index, conn| Clue {
+            index,
+            subject: SectorType::Comet,
+            object: SectorType::Asteroid,
+            conn,
+        };
+        let mut metrics = ClueMetrics::default();
+        metrics.record(
+            &clue(ClueEnum::B, ClueConnection::AllInRange(3)),
+            Some(100),
+            Some(25),
+        );
+        metrics.record(
+            &clue(ClueEnum::B, ClueConnection::AllInRange(4)),
+            Some(50),
+            Some(25),
+        );
+        metrics.record(
+            &clue(ClueEnum::B, ClueConnection::AllInRange(2)),
+            None,
+            Some(25),
+        );
+        metrics.record(
+            &clue(ClueEnum::A, ClueConnection::OneOpposite),
+            Some(10),
+            Some(10),
+        );
+
+        let report = metrics.report();
+        assert_eq!(report.total, 4);
+        assert_eq!(report.clues[0].clue, "A");
+        assert_eq!(report.clues[0].avg_kept, Some(1.0));
+        assert_eq!(
+            report.clues[1],
+            ClueUsage {
+                clue: "B".to_string(),
+                connection: "all_in_range".to_string(),
+                researches: 3,
+                measured: 2,
+                avg_kept: Some(0.375),
+            }
+        );
+    }
+
+    #[test]
+    fn test_pacing_metrics() {
+        let mut metrics = PacingMetrics::default();
+        for secs in 1..=10 {
+            metrics.record_turn(Duration::from_secs(secs));
+        }
+        let turns = metrics.report().turns;
+        assert_eq!((turns.count, turns.p50_ms, turns.p90_ms), (10, 5000, 9000));
+
+        let start = Instant::now();
+        let mut gs = GameStateResp::new("1".to_string());
+        gs.status = GameState::AutoMove;
+        metrics.observe(&gs, start);
+        gs.game_stage = GameStage::MeetingProposal;
+        metrics.observe(&gs, start + Duration::from_secs(3));
+        gs.status = GameState::End;
+        gs.game_stage = GameStage::GameEnd;
+        metrics.observe(&gs, start + Duration::from_secs(4));
+        metrics.observe(&gs, start + Duration::from_secs(5));
+
+        let report = metrics.report();
+        assert_eq!(report.stages[0].0, GameStage::UserMove);
+        assert_eq!(report.stages[0].1.max_ms, 3000);
+        assert_eq!((report.games.count, report.games.max_ms), (1, 4000));
+
+        metrics.retain_rooms(|_| false);
+        assert!(metrics.clocks.is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+//! plain transports, for the clients that can't use socket.io.
+//!
+//! a `ws` connection exchanges json `Frame`s named after the socket.io events: `auth`, `room`,
+//! `op`, `recommend`, `chat`, `reaction`, `lobby`, `preferences` and `catch_up` in, the events
+//! of the `/xplanet` namespace out. casting and theaters stay socket.io only. `sse/{room_id}` streams the public events of a
+//! room to receive-only spectators.
+
+use std::convert::Infallible;
+
+use futures_util::StreamExt;
+use salvo::{
+    affix_state,
+    http::StatusCode,
+    prelude::*,
+    sse::{SseEvent, SseKeepAlive},
+    websocket::{Message, WebSocket, WebSocketUpgrade},
+};
+use serde::de::DeserializeOwned;
+use socketioxide::SocketIo;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+
+use crate::{
+    api::obtain_state,
+    room::{GameStateResp, OpError, RoomUserOperation, ServerResp, SpectatorSummary},
+    server_handler::{
+        broadcast_full_state, broadcast_projected_scores, send_chat, send_op_effects,
+    },
+    server_state::{State, StateRef, User},
+    sink::{Capabilities, EventSink, Frame, Membership, Rooms, Target},
+};
+
+pub fn router(state: StateRef, io: SocketIo) -> Router {
+    Router::new()
+        .hoop(affix_state::inject(state).inject(io))
+        .push(Router::with_path("ws").goal(connect))
+        .push(Router::with_path("sse/{room_id}").get(spectate))
+}
+
+#[handler]
+async fn connect(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    let state = obtain_state(depot);
+    let io = depot
+        .obtain::<SocketIo>()
+        .expect("socket io injected by the ws router")
+        .clone();
+    WebSocketUpgrade::new()
+        .upgrade(req, res, |ws| serve(ws, state, io))
+        .await
+}
+
+/// one websocket connection, it receives the rooms it entered and the events of its user.
+#[derive(Default)]
+struct Conn {
+    user: Option<User>,
+    rooms: Rooms,
+}
+
+impl Conn {
+    fn wants(&self, target: &Target) -> bool {
+        match target {
+            Target::Room(id) => self.rooms.contains(id),
+            Target::User(id) => self.user.as_ref().is_some_and(|u| u.id == *id),
+            Target::All => true,
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.user
+            .as_ref()
+            .map(|u| u.capabilities)
+            .unwrap_or_default()
+    }
+}
+
+async fn serve(mut ws: WebSocket, state: StateRef, io: SocketIo) {
+    let mut relayed = state.lock().await.relay.subscribe();
+    let mut conn = Conn::default();
+    info!(ns = "ws", "new client connected");
+    'conn: loop {
+        let frames = tokio::select! {
+            msg = ws.recv() => {
+                let Some(Ok(msg)) = msg else { break };
+                if msg.is_close() {
+                    break;
+                }
+                let Ok(text) = msg.as_str() else { continue };
+                match serde_json::from_str::<Frame>(text) {
+                    Ok(frame) => handle_frame(&state, &io, &mut conn, frame).await,
+                    Err(e) => {
+                        info!(ns = "ws", ?e, "bad frame");
+                        continue;
+                    }
+                }
+            }
+            event = relayed.recv() => match event {
+                Ok((target, frame))
+                    if conn.wants(&target) && conn.capabilities().accepts(&frame.event) =>
+                {
+                    vec![frame]
+                }
+                Ok(_) => continue,
+                // a slow connection missed events, catch_up gets it back in sync
+                Err(RecvError::Lagged(skipped)) => {
+                    info!(ns = "ws", skipped, "connection lagged");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        };
+        for frame in frames {
+            let Ok(text) = serde_json::to_string(&frame) else {
+                continue;
+            };
+            if ws.send(Message::text(text)).await.is_err() {
+                break 'conn;
+            }
+        }
+    }
+
+    info!(ns = "ws", "disconnected");
+    let Some(user) = conn.user else {
+        return;
+    };
+    let mut state = state.lock().await;
+    state.relay.disconnect(&user.id);
+    if state.is_offline(&user.id) {
+        // the last connection of the user is gone, nobody is there to start the game
+        state.mark_disconnected(&user.id);
+        for gs in state.unready_user(&user.id) {
+            let state_event = gs.state_event();
+            let sink = state.sink(&io);
+            sink.to_room(&gs.id, state_event.event(), &state_event)
+                .await;
+        }
+    }
+}
+
+/// the payload of the frame, or the `bad_request` answer telling the client what is wrong with it.
+fn decode<T: DeserializeOwned>(state: &mut State, frame: Frame) -> Result<T, Frame> {
+    serde_json::from_value(frame.data).map_err(|e| {
+        info!(ns = "ws", ?e, "bad {} data", frame.event);
+        Frame::new("server_resp", &state.bad_request(&frame.event, e))
+    })
+}
+
+/// answer one inbound frame, the replies go to this connection only.
+async fn handle_frame(
+    state: &StateRef,
+    io: &SocketIo,
+    conn: &mut Conn,
+    frame: Frame,
+) -> Vec<Frame> {
+    if frame.event == "auth" {
+        let mut state = state.lock().await;
+        let user = match decode::<User>(&mut state, frame) {
+            Ok(user) => user,
+            Err(bad) => return vec![bad],
+        };
+        info!(ns = "ws", "auth {:?}", user);
+        if let Some(old) = conn.user.replace(user.clone()) {
+            state.relay.disconnect(&old.id);
+        }
+        state.relay.connect(&user.id);
+        conn.rooms.exit_all();
+        let mut replies = vec![Frame::new(
+            "server_resp",
+            &ServerResp::auth_success_version(),
+        )];
+        for room_id in state.reconnect(&user.id) {
+            let resp = ServerResp::rejoin_room(room_id.clone());
+            replies.push(Frame::new("server_resp", &resp));
+            conn.rooms.enter(room_id);
+        }
+        let maintenance = state.maintenance.as_ref();
+        if let Some(maintenance) = maintenance.filter(|_| user.capabilities.accepts("maintenance"))
+        {
+            replies.push(Frame::new("maintenance", maintenance));
+        }
+        return replies;
+    }
+    let Some(user) = conn.user.clone() else {
+        info!(ns = "ws", "unauthorized {}", frame.event);
+        return vec![];
+    };
+
+    let mut state = state.lock().await;
+    let event = frame.event.clone();
+    match event.as_str() {
+        "room" => {
+            let op = match decode::<RoomUserOperation>(&mut state, frame) {
+                Ok(op) => op,
+                Err(bad) => return vec![bad],
+            };
+            let dealt = matches!(
+                op,
+                RoomUserOperation::Puzzle(_) | RoomUserOperation::Resume(_)
+            );
+            match state.handle_room_op(&conn.rooms, user.clone(), op) {
+                Ok(resp) => {
+                    for gs in resp.iter() {
+                        state.touch_room(&gs.id);
+                        let state_event = gs.state_event();
+                        let sink = state.sink(io);
+                        sink.to_room(&gs.id, state_event.event(), &state_event)
+                            .await;
+                    }
+                    for gs in resp.iter().filter(|gs| dealt && gs.is_playing()) {
+                        broadcast_full_state(&state.sink(io), &state, &gs.id).await;
+                    }
+                    if resp
+                        .iter()
+                        .any(|gs| gs.users.iter().any(|u| u.id == user.id))
+                    {
+                        return vec![];
+                    }
+                    // no game state to response, empty client game state
+                    vec![Frame::new("game_state", &GameStateResp::empty())]
+                }
+                Err(e) => vec![Frame::new("server_resp", &ServerResp::RoomErrors(e))],
+            }
+        }
+        "op" => {
+            let op = match decode(&mut state, frame) {
+                Ok(op) => op,
+                Err(bad) => return vec![bad],
+            };
+            match state.play(&user, &op) {
+                Ok(resp) => {
+                    send_op_effects(io, &mut state).await;
+                    broadcast_projected_scores(&state.sink(io), &state, &user.id).await;
+                    vec![Frame::new("op_result", &user.capabilities.op_result(resp))]
+                }
+                Err(e) => {
+                    let mut frames = vec![Frame::new("server_resp", &e)];
+                    // a client still showing the game in play, its end again
+                    let finished = matches!(e, ServerResp::OpErrors(OpError::GameFinished));
+                    if let Some((gs, tokens)) = state.final_state(&user.id).filter(|_| finished) {
+                        let state_event = gs.state_event();
+                        frames.push(Frame::new(state_event.event(), &state_event));
+                        frames.push(Frame::new("board_tokens", &tokens));
+                    }
+                    frames
+                }
+            }
+        }
+        "recommend" => {
+            let op = match decode(&mut state, frame) {
+                Ok(op) => op,
+                Err(bad) => return vec![bad],
+            };
+            if !state.take_rate_limit(&user) {
+                return vec![Frame::new("server_resp", &ServerResp::RateLimited)];
+            }
+            match state.handle_recommend_op(user, op) {
+                Ok(resp) => vec![Frame::new("recommend_result", &resp)],
+                Err(e) => vec![Frame::new("server_resp", &ServerResp::RecommendErrors(e))],
+            }
+        }
+        "chat" => {
+            let op = match decode(&mut state, frame) {
+                Ok(op) => op,
+                Err(bad) => return vec![bad],
+            };
+            match state.handle_chat_op(&user, op) {
+                Ok(Some(message)) => {
+                    send_chat(&state.sink(io), &state, &message);
+                    vec![]
+                }
+                Ok(None) => vec![],
+                Err(e) => vec![Frame::new("server_resp", &ServerResp::ChatErrors(e))],
+            }
+        }
+        "reaction" => {
+            let send = match decode(&mut state, frame) {
+                Ok(send) => send,
+                Err(bad) => return vec![bad],
+            };
+            match state.react(&user, send) {
+                Ok(reaction) => {
+                    let sink = state.sink(io);
+                    sink.to_room(&reaction.room_id, "reaction", &reaction).await;
+                    vec![]
+                }
+                Err(e) => vec![Frame::new("server_resp", &ServerResp::ChatErrors(e))],
+            }
+        }
+        "lobby" => {
+            let query = match decode(&mut state, frame) {
+                Ok(query) => query,
+                Err(bad) => return vec![bad],
+            };
+            vec![Frame::new("lobby", &state.query_lobby(&query))]
+        }
+        "preferences" => {
+            let op = match decode(&mut state, frame) {
+                Ok(op) => op,
+                Err(bad) => return vec![bad],
+            };
+            vec![Frame::new(
+                "preferences",
+                &state.handle_preferences(&user.id, op),
+            )]
+        }
+        "catch_up" => {
+            if !state.take_rate_limit(&user) {
+                return vec![Frame::new("server_resp", &ServerResp::RateLimited)];
+            }
+            vec![Frame::new("catch_up", &state.catch_up(&user.id))]
+        }
+        event => {
+            info!(ns = "ws", "unsupported event {}", event);
+            vec![]
+        }
+    }
+}
+
+/// the public events of a room as server-sent events, starting with its current state.
+#[handler]
+async fn spectate(req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    let room_id = req.param::<String>("room_id").unwrap_or_default();
+    let state = obtain_state(depot);
+    let state = state.lock().await;
+    // only game rooms, the caster and theater channels are not public
+    let Some((gs, ss)) = state.state_data.get(&room_id) else {
+        res.status_code(StatusCode::NOT_FOUND);
+        return;
+    };
+    let state_event = gs.state_event();
+    let mut current = vec![sse_event(Frame::new(state_event.event(), &state_event))];
+    // what was said in the room before, for a spectator arriving mid-game
+    if !gs.in_lobby() {
+        let announcement = state.last_announcement.as_ref();
+        let summary = SpectatorSummary::new(gs, ss, announcement, state.maintenance.as_ref());
+        current.push(sse_event(Frame::new("spectator_summary", &summary)));
+    }
+    let relayed = state.relay.subscribe();
+    drop(state);
+
+    let events = futures_util::stream::unfold(relayed, move |mut relayed| {
+        let room_id = room_id.clone();
+        async move {
+            loop {
+                match relayed.recv().await {
+                    Ok((Target::Room(id), frame)) if id == room_id => {
+                        return Some((Ok::<_, Infallible>(sse_event(frame)), relayed));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    let current = futures_util::stream::iter(current.into_iter().map(Ok));
+    SseKeepAlive::new(current.chain(events)).stream(res);
+}
+
+fn sse_event(frame: Frame) -> SseEvent {
+    SseEvent::default()
+        .name(frame.event)
+        .text(frame.data.to_string())
+}